@@ -0,0 +1,2631 @@
+use crate::api::dto;
+use crate::api::params;
+use crate::api::user;
+use crate::prelude::axum::*;
+use crate::state::AppState;
+use crate::state::CAPTCHA_STATS_MINUTES;
+use crate::webhook::Webhook;
+use axum::body::Body;
+use axum::body::Bytes;
+use axum::extract::rejection::JsonRejection;
+use axum::extract::Query;
+use axum::http::header;
+use axum::http::HeaderMap;
+use axum::http::StatusCode;
+use axum::response::sse::Event;
+use axum::response::sse::KeepAlive;
+use axum::response::sse::Sse;
+use axum::response::IntoResponse;
+use axum::Json;
+use database::migrations::Migrator;
+use database::migrations::MigratorTrait;
+use futures::SinkExt;
+use futures::Stream;
+use futures::StreamExt;
+use proto::admin::agent::AgentErrorListReq;
+use proto::admin::agent::AgentErrorResp;
+use proto::admin::alert::AlertListReq;
+use proto::admin::alert::AlertResp;
+use proto::admin::alert::HostAlertThresholdReq;
+use proto::admin::alert::HostAlertThresholdResp;
+use proto::admin::backup::BackupResp;
+use proto::admin::command::CommandBroadcastResp;
+use proto::admin::command::HostCommandResp;
+use proto::admin::config::EffectiveConfigResp;
+use proto::admin::enrollment::EnrollmentCreateReq;
+use proto::admin::enrollment::EnrollmentCreateResp;
+use proto::admin::event::EventListReq;
+use proto::admin::event::EventResp;
+use proto::admin::hardware::HardwareChangeListReq;
+use proto::admin::hardware::HardwareChangeResp;
+use proto::admin::host::HostByHardwareReq;
+use proto::admin::host::HostDisconnectResp;
+use proto::admin::host::HostDisplayNameReq;
+use proto::admin::host::HostExportReq;
+use proto::admin::host::HostImportResp;
+use proto::admin::host::HostListReq;
+use proto::admin::host::HostMaintenanceReq;
+use proto::admin::host::HostMergeReq;
+use proto::admin::host::HostResp;
+use proto::admin::label::HostLabelByFilterResp;
+use proto::admin::label::HostLabelReq;
+use proto::admin::log::HostLogResp;
+use proto::admin::schema::SchemaResp;
+use proto::admin::stats::CaptchaStatsReq;
+use proto::admin::stats::CaptchaStatsResp;
+use proto::admin::stats::LatencyStatsResp;
+use proto::admin::user::InitializedResp;
+use proto::admin::user::UserCreateReq;
+use proto::admin::user::UserResp;
+use proto::admin::webhook::WebhookTestResp;
+use proto::webhook::WebhookChange;
+use sea_orm::prelude::Uuid;
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Lists hosts.
+///
+/// This endpoint accepts the following query parameters:
+///
+/// - `page`: The zero-based page index (default: 0).
+/// - `size`: The page size (default: 20, max: 100).
+/// - `pending`: If `true`, only hosts that never reported OS information are returned,
+///   which helps spotting installs that are not phoning home. If `false`, only hosts
+///   that did report are returned.
+/// - `q`: Only hosts whose machine ID or display name contains this text are returned.
+/// - `os_family`: Only hosts of this OS family are returned.
+pub async fn hosts(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<HostListReq>,
+) -> Result<Json<Vec<HostResp>>, AxumError> {
+    let hosts = internal::hosts(&state, &query).await?;
+
+    Ok(Json(hosts.into_iter().map(dto::host).collect()))
+}
+
+/// Lists hosts sharing a hardware fingerprint, e.g. to spot cloned machines.
+///
+/// This endpoint accepts the following query parameters, at least one is required:
+///
+/// - `hashed_cpu`, `hashed_gpu`, `hashed_memory`, `hashed_disk`, `hashed_network`: The
+///   hardware hash the host must have, as returned in host responses.
+///
+/// Only hosts matching all given hashes are returned, at most 1000. Soft-deleted hosts
+/// are excluded.
+///
+/// # Errors
+///
+/// Returns `400 Bad Request` if no hash is given.
+pub async fn hosts_by_hardware(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<HostByHardwareReq>,
+) -> Result<Json<Vec<HostResp>>, AxumError> {
+    let hosts = internal::hosts_by_hardware(&state, &query).await?;
+
+    Ok(Json(hosts.into_iter().map(dto::host).collect()))
+}
+
+/// Exports the full host inventory.
+///
+/// This endpoint accepts the following query parameters:
+///
+/// - `format`: The export format, only `ndjson` is supported (default: `ndjson`).
+///
+/// The response streams one host JSON object per line. Hosts are read in batches, so
+/// memory stays flat regardless of the fleet size. Soft-deleted hosts are excluded.
+///
+/// # Errors
+///
+/// Returns `400 Bad Request` if the format is not supported.
+pub async fn host_export(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<HostExportReq>,
+) -> Result<impl IntoResponse, AxumError> {
+    if query
+        .format
+        .as_deref()
+        .is_some_and(|format| format != "ndjson")
+    {
+        return Err(StatusError::new(
+            StatusCode::BAD_REQUEST,
+            "unsupported_format",
+            "only the ndjson format is supported",
+        )
+        .into());
+    }
+
+    // scan hosts batch by batch, resuming after the last exported id
+    let stream = futures::stream::try_unfold(None, move |after| {
+        let state = state.clone();
+        async move {
+            let hosts = internal::hosts_after(&state, after).await?;
+            let Some(last) = hosts.last().map(|host| host.id) else {
+                return anyhow::Ok(None);
+            };
+
+            let mut lines = Vec::new();
+            for host in hosts {
+                serde_json::to_writer(&mut lines, &dto::host(host))?;
+                lines.push(b'\n');
+            }
+
+            Ok(Some((lines, Some(last))))
+        }
+    });
+
+    Ok((
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(stream),
+    ))
+}
+
+/// Imports hosts from a CSV or JSON upload.
+///
+/// The body is either a JSON array (`application/json`) or a CSV file with a header
+/// row (`text/csv`) of host definitions with the following fields:
+///
+/// - `machine_id`: The machine ID of the host (required).
+/// - `machine_ip`: The IP address of the host.
+/// - `machine_country`: The country code of the host (up to 3 characters).
+/// - `os_family`, `os_name`, `os_version`: The operating system of the host.
+///
+/// Valid hosts whose machine ID is not known yet are inserted in a single transaction.
+/// Invalid rows and machine IDs which already exist (or repeat within the upload) are
+/// skipped. The response reports the counts and the result of every row.
+///
+/// # Errors
+///
+/// Returns `400 Bad Request` if the upload cannot be parsed, or `415 Unsupported Media
+/// Type` if it is neither CSV nor JSON.
+pub async fn host_import(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<HostImportResp>, AxumError> {
+    let hosts = internal::parse_import(&headers, &body)?;
+    let result = internal::import_hosts(&state, hosts).await?;
+
+    Ok(Json(result))
+}
+
+/// Exports everything known about the host with the given `id` as a single JSON document.
+///
+/// The document is a JSON object with the following fields:
+///
+/// - `host`: The host itself, even if it is soft-deleted.
+/// - `events`: All events received from the host, oldest first.
+/// - `metrics`: All metrics samples of the host, oldest first.
+/// - `commands`: All commands queued for the host, oldest first.
+/// - `labels`: The labels of the host, an object of names to values.
+/// - `hardware_changes`: All hardware changes of the host, oldest first.
+/// - `logs`: The log snippets uploaded by the agent, newest first.
+///
+/// The document is streamed as it is read from the database and offered as a file
+/// download.
+///
+/// # Errors
+///
+/// Returns `404 Not Found` if the host does not exist.
+pub async fn host_export_bundle(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, AxumError> {
+    let target = internal::host(&state, id).await?;
+
+    // write the bundle on a separate task, aborting the body if anything fails
+    let (mut tx, rx) = futures::channel::mpsc::channel(4);
+    tokio::spawn(async move {
+        if let Err(err) = internal::write_bundle(&state, target, &mut tx).await {
+            tracing::warn!("host export failed: {}", err);
+            _ = tx.send(Err(err)).await;
+        }
+    });
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/json".to_owned()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"host-{}.json\"", id),
+            ),
+        ],
+        Body::from_stream(rx),
+    ))
+}
+
+/// Lists the events received from all hosts, newest first.
+///
+/// This endpoint accepts the following query parameters:
+///
+/// - `event_type`: Only events of this type are returned (e.g. `EvtOsEmit`).
+/// - `host_id`: Only events of this host are returned.
+/// - `from`: The RFC 3339 start of the range.
+/// - `to`: The RFC 3339 end of the range.
+/// - `page`: The zero-based page index (default: 0).
+/// - `size`: The page size (default: 20, max: 100).
+///
+/// # Errors
+///
+/// Returns `400 Bad Request` if the host id or the range cannot be parsed.
+pub async fn events(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<EventListReq>,
+) -> Result<Json<Vec<EventResp>>, AxumError> {
+    let host_id = params::parse_uuid(query.host_id.as_deref())?;
+    let from = params::parse_time(query.from.as_deref())?;
+    let to = params::parse_time(query.to.as_deref())?;
+
+    let events = internal::events(&state, &query, host_id, from, to).await?;
+
+    Ok(Json(events.into_iter().map(dto::event).collect()))
+}
+
+/// Lists the recorded hardware fingerprint changes, newest first.
+///
+/// This endpoint accepts the following query parameters:
+///
+/// - `host_id`: Only changes of this host are returned.
+/// - `limit`: The maximum number of changes (default: 100, max: 1000).
+///
+/// # Errors
+///
+/// Returns `400 Bad Request` if the host id cannot be parsed.
+pub async fn hardware_changes(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<HardwareChangeListReq>,
+) -> Result<Json<Vec<HardwareChangeResp>>, AxumError> {
+    let host_id = params::parse_uuid(query.host_id.as_deref())?;
+    let limit = query.limit.unwrap_or(100).clamp(1, 1000);
+
+    let changes = internal::hardware_changes(&state, host_id, limit).await?;
+
+    Ok(Json(
+        changes.into_iter().map(dto::hardware_change).collect(),
+    ))
+}
+
+/// Streams the server log as server-sent events.
+///
+/// The stream starts with the last `--log-tail-lines` buffered lines, then sends every
+/// new line as a `data` event. A subscriber falling too far behind misses lines, which
+/// is announced by a `lagged` event carrying the number of missed lines. The stream
+/// ends when the server shuts down.
+///
+/// Clients must send `Accept: text/event-stream`, or the stream is cut off by the
+/// request timeout.
+pub async fn logs_stream(
+    State(state): State<Arc<AppState>>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let (recent, receiver) = state.logs.subscribe();
+    let closed = state.logs.closed();
+
+    let live = futures::stream::unfold(receiver, move |mut receiver| {
+        let closed = closed.clone();
+        async move {
+            let event = tokio::select! {
+                _ = closed.cancelled() => return None,
+                line = receiver.recv() => match line {
+                    Ok(line) => Event::default().data(line),
+                    Err(RecvError::Lagged(missed)) => {
+                        Event::default().event("lagged").data(missed.to_string())
+                    }
+                    Err(RecvError::Closed) => return None,
+                },
+            };
+            Some((event, receiver))
+        }
+    });
+    let stream = futures::stream::iter(recent)
+        .map(|line| Event::default().data(line))
+        .chain(live)
+        .map(Ok);
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+/// Re-evaluates whether the application is initialized, i.e. has a user.
+///
+/// The initialized state is cached once a user exists, so users removed outside the
+/// application are not noticed. This endpoint drops the cache and checks the database
+/// again. If no user is left, `/api/auth/init` becomes available again, but then no
+/// administrator can call this endpoint either, so send the server SIGHUP instead.
+///
+/// The response is a JSON object with the field `initialized`, the state found.
+///
+/// # Errors
+///
+/// Returns an error if database operations fail.
+pub async fn initialized_refresh(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<InitializedResp>, AxumError> {
+    let initialized = user::reinitlizated(&state).await?;
+
+    Ok(Json(InitializedResp { initialized }))
+}
+
+/// Returns the percentiles of the time between receiving an agent event and persisting it.
+///
+/// Percentiles are approximated by histogram buckets and cover all events processed
+/// since startup.
+pub async fn stats_latency(State(state): State<Arc<AppState>>) -> Json<LatencyStatsResp> {
+    let latency = &state.eventbus.latency;
+    let percentile = |quantile| {
+        latency
+            .percentile(quantile)
+            .map(|latency| latency.as_secs_f64() * 1000.0)
+    };
+
+    Json(LatencyStatsResp {
+        count: latency.count(),
+        p50_ms: percentile(0.5),
+        p95_ms: percentile(0.95),
+        p99_ms: percentile(0.99),
+    })
+}
+
+/// Returns the number of captchas generated, answered correctly and answered wrong.
+///
+/// This endpoint accepts the following query parameters:
+///
+/// - `range`: How far back captchas count, such as `15m` or `6h` (default: `1h`, at
+///   most `24h`).
+///
+/// Captchas are counted per minute, so the range is rounded up to a whole minute. The
+/// counts are kept in memory and only cover this server since startup.
+///
+/// # Errors
+///
+/// Returns `400 Bad Request` if the range is malformed.
+pub async fn stats_captcha(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<CaptchaStatsReq>,
+) -> Result<Json<CaptchaStatsResp>, AxumError> {
+    let range = params::parse_range(
+        query.range.as_deref(),
+        chrono::Duration::hours(1),
+        chrono::Duration::minutes(CAPTCHA_STATS_MINUTES),
+    )?;
+    let counts = state.captcha_stats.since(chrono::Utc::now() - range);
+
+    Ok(Json(CaptchaStatsResp {
+        range_secs: range.num_seconds(),
+        generated: counts.generated,
+        verified: counts.verified,
+        failed: counts.failed,
+    }))
+}
+
+/// Lists the alerts raised by the alert evaluator, newest first.
+///
+/// This endpoint accepts the following query parameters:
+///
+/// - `resolved`: If `true`, only acknowledged alerts are returned, if `false`, only open
+///   alerts are returned (default: all).
+///
+/// At most 100 alerts are returned.
+pub async fn alerts(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<AlertListReq>,
+) -> Result<Json<Vec<AlertResp>>, AxumError> {
+    let alerts = internal::alerts(&state, query.resolved).await?;
+
+    Ok(Json(alerts.into_iter().map(dto::alert).collect()))
+}
+
+/// Acknowledges the alert with the given `id`, marking it resolved.
+///
+/// Acknowledging an already resolved alert keeps its original `resolved_at`. Once
+/// resolved, the alert rule may raise a new alert of the same kind.
+///
+/// # Errors
+///
+/// Returns `404 Not Found` if the alert does not exist.
+pub async fn alert_ack(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<AlertResp>, AxumError> {
+    let alert = internal::alert_ack(&state, id).await?;
+
+    Ok(Json(dto::alert(alert)))
+}
+
+/// Returns the alert thresholds set for the host with the given `id`.
+///
+/// Thresholds which are not set are `null`, the host is then checked against the
+/// global `--alert-cpu-percent`, `--alert-memory-percent` and `--alert-disk-percent`.
+///
+/// # Errors
+///
+/// Returns `404 Not Found` if the host does not exist.
+pub async fn host_alert_thresholds(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<HostAlertThresholdResp>, AxumError> {
+    let host = internal::host(&state, id).await?;
+    let thresholds = internal::host_alert_thresholds(&state, host.id).await?;
+
+    Ok(Json(dto::host_alert_threshold(thresholds)))
+}
+
+/// Sets the alert thresholds of the host with the given `id`.
+///
+/// This endpoint takes a JSON object with the following fields:
+///
+/// - `cpu_percent`: The CPU usage percentage that raises a `host_cpu` alert.
+/// - `memory_percent`: The memory usage percentage that raises a `host_memory` alert.
+/// - `disk_percent`: The disk usage percentage that raises a `host_disk` alert.
+///
+/// Each threshold is 0 to 100, where 0 disables the alert for the host. A missing or
+/// `null` threshold falls back to the global `--alert-cpu-percent`,
+/// `--alert-memory-percent` or `--alert-disk-percent`. The thresholds replace the ones
+/// set before, so an empty object removes them all.
+///
+/// The alert evaluator checks the latest metrics sample of every host against its
+/// thresholds each `--alert-interval`.
+///
+/// # Errors
+///
+/// Returns `404 Not Found` if the host does not exist, or `400 Bad Request` if a
+/// threshold is above 100.
+pub async fn host_alert_thresholds_set(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    Json(body): Json<HostAlertThresholdReq>,
+) -> Result<Json<HostAlertThresholdResp>, AxumError> {
+    if [body.cpu_percent, body.memory_percent, body.disk_percent]
+        .into_iter()
+        .flatten()
+        .any(|percent| percent > 100)
+    {
+        return Err(StatusError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_threshold",
+            "thresholds must be 0 to 100 percent",
+        )
+        .into());
+    }
+
+    let host = internal::host(&state, id).await?;
+    let thresholds = internal::host_alert_thresholds_set(&state, host.id, &body).await?;
+
+    Ok(Json(dto::host_alert_threshold(thresholds)))
+}
+
+/// Merges a duplicate host into the host with the given `id`.
+///
+/// This endpoint takes a JSON object with the following fields:
+///
+/// - `source`: The ID of the duplicate host to merge.
+///
+/// Information the target never reported is taken over from the source, then the
+/// source is soft-deleted. Everything happens in a single transaction. Agents still
+/// reporting as the source report as the target afterwards.
+///
+/// # Errors
+///
+/// Returns `404 Not Found` if either host does not exist, or `400 Bad Request` if
+/// both IDs are the same.
+pub async fn host_merge(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    Json(body): Json<HostMergeReq>,
+) -> Result<Json<HostResp>, AxumError> {
+    // a given value parses to some uuid or fails
+    let source = params::parse_uuid(Some(&body.source))?.unwrap_or_default();
+    let merged = internal::merge_hosts(&state, id, source).await?;
+
+    Ok(Json(dto::host(merged)))
+}
+
+/// Asks every agent connected over websocket to fetch its configuration again.
+///
+/// The `{"command":"refresh_config"}` message is sent right away and not queued, agents
+/// which are not connected fetch their configuration when they reconnect anyway. The
+/// response tells how many connections were notified.
+pub async fn agents_refresh_config(
+    State(state): State<Arc<AppState>>,
+) -> Json<CommandBroadcastResp> {
+    let message = serde_json::json!({ "command": "refresh_config" }).to_string();
+    let notified = state.connections.broadcast(&message);
+    tracing::info!("asked {} agents to refresh their config", notified);
+
+    Json(CommandBroadcastResp { notified })
+}
+
+/// Lists the agents which recently ran into an error, most recent error first.
+///
+/// The error of a host is the last malformed event it sent, or the last event the
+/// server failed to apply. This endpoint accepts the following query parameters:
+///
+/// - `range`: How far back errors count, such as `30m`, `12h` or `7d` (default: `24h`,
+///   at most `90d`).
+///
+/// Deleted hosts are not listed. At most 100 agents are returned.
+///
+/// # Errors
+///
+/// Returns `400 Bad Request` if the range is malformed.
+pub async fn agent_errors(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<AgentErrorListReq>,
+) -> Result<Json<Vec<AgentErrorResp>>, AxumError> {
+    let range = params::parse_range(
+        query.range.as_deref(),
+        chrono::Duration::hours(24),
+        chrono::Duration::days(90),
+    )?;
+    let hosts = internal::agent_errors(&state, chrono::Utc::now() - range).await?;
+
+    Ok(Json(hosts.into_iter().map(dto::agent_error).collect()))
+}
+
+/// Closes the live websocket connections of the host with the given `id`.
+///
+/// The connections are closed with a policy violation close frame, events the agent
+/// sends afterwards are dropped. The agent is free to reconnect, revoke its token
+/// first to keep it out. The response tells whether a connection was live.
+///
+/// # Errors
+///
+/// Returns `404 Not Found` if the host does not exist.
+pub async fn host_disconnect(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<HostDisconnectResp>, AxumError> {
+    let host = internal::host(&state, id).await?;
+    let connected = state.connections.disconnect(host.id);
+    if connected {
+        tracing::info!("disconnected host with machine id: {}", host.machine_id);
+    }
+
+    Ok(Json(HostDisconnectResp { connected }))
+}
+
+/// Lists the log snippets uploaded by the agent of the host with the given `id`,
+/// newest first.
+///
+/// # Errors
+///
+/// Returns `404 Not Found` if the host does not exist.
+pub async fn host_logs(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Vec<HostLogResp>>, AxumError> {
+    let host = internal::host(&state, id).await?;
+    let logs = internal::host_logs(&state, host.id).await?;
+
+    Ok(Json(logs.into_iter().map(dto::host_log).collect()))
+}
+
+/// Maximum number of labels accepted by `host_label_by_filter`.
+const LABELS_MAX: usize = 32;
+
+/// Sets labels on all hosts matching a filter.
+///
+/// This endpoint accepts the `pending`, `q` and `os_family` query parameters of the
+/// host list, selecting the hosts to label. Without any, all hosts are labeled.
+///
+/// This endpoint takes a JSON object with the following fields:
+///
+/// - `labels`: The labels to set, an object of names to values. Names have 1 to 64
+///   characters, each an ASCII letter, digit, `-`, `_`, `.` or `/`, values at most 256
+///   characters. At most 32 labels are set at once.
+///
+/// Existing labels with the same names are overwritten, all hosts are updated in one
+/// transaction. The response is a JSON object with the following fields:
+///
+/// - `affected`: The number of labeled hosts.
+///
+/// # Errors
+///
+/// Returns `400 Bad Request` if no labels are given, too many or an invalid one.
+pub async fn host_label_by_filter(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<HostListReq>,
+    Json(body): Json<HostLabelReq>,
+) -> Result<Json<HostLabelByFilterResp>, AxumError> {
+    let valid_name = |name: &str| {
+        (1..=64).contains(&name.len())
+            && name
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'/'))
+    };
+    let problem = if body.labels.is_empty() {
+        Some("no labels given".to_owned())
+    } else if body.labels.len() > LABELS_MAX {
+        Some(format!("at most {} labels can be set at once", LABELS_MAX))
+    } else {
+        body.labels.iter().find_map(|(name, value)| {
+            if !valid_name(name) {
+                Some(format!("invalid label name {:?}", name))
+            } else if value.chars().count() > 256 {
+                Some(format!("value of label {:?} is too long", name))
+            } else {
+                None
+            }
+        })
+    };
+    if let Some(problem) = problem {
+        return Err(StatusError::new(StatusCode::BAD_REQUEST, "invalid_labels", problem).into());
+    }
+
+    let affected = internal::host_label_by_filter(&state, &query, &body.labels).await?;
+
+    Ok(Json(HostLabelByFilterResp { affected }))
+}
+
+/// Returns the labels of the host with the given `id`, as an object of names to values.
+///
+/// # Errors
+///
+/// Returns `404 Not Found` if the host does not exist.
+pub async fn host_labels(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<BTreeMap<String, String>>, AxumError> {
+    let host = internal::host(&state, id).await?;
+    let labels = internal::host_labels(&state, host.id).await?;
+
+    Ok(Json(labels))
+}
+
+/// Sets the display name of the host with the given `id`.
+///
+/// This endpoint takes a JSON object with the following fields:
+///
+/// - `display_name`: The friendly name shown instead of the machine ID, `null` or an
+///   empty string removes it. Surrounding whitespace is trimmed.
+///
+/// # Errors
+///
+/// Returns `404 Not Found` if the host does not exist, or `400 Bad Request` if the name
+/// is longer than 64 characters.
+pub async fn host_display_name(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    Json(body): Json<HostDisplayNameReq>,
+) -> Result<Json<HostResp>, AxumError> {
+    let display_name = body
+        .display_name
+        .as_deref()
+        .map(str::trim)
+        .filter(|name| !name.is_empty());
+    if display_name.is_some_and(|name| name.chars().count() > 64) {
+        return Err(StatusError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_display_name",
+            "display name must not be longer than 64 characters",
+        )
+        .into());
+    }
+
+    let host = internal::host_display_name(&state, id, display_name).await?;
+
+    Ok(Json(dto::host(host)))
+}
+
+/// Puts the host with the given `id` in maintenance, or takes it out.
+///
+/// This endpoint takes a JSON object with the following fields:
+///
+/// - `until`: The RFC 3339 timestamp the maintenance ends at, `null` ends it now.
+///
+/// Until then, the host is not counted offline when it stops reporting, neither in
+/// the offline counts nor by the offline alert. It is still counted online while it
+/// reports.
+///
+/// # Errors
+///
+/// Returns `404 Not Found` if the host does not exist, or `400 Bad Request` if the
+/// timestamp is malformed.
+pub async fn host_maintenance(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    Json(body): Json<HostMaintenanceReq>,
+) -> Result<Json<HostResp>, AxumError> {
+    let until = params::parse_time(body.until.as_deref())?;
+    let host = internal::host_maintenance(&state, id, until).await?;
+
+    Ok(Json(dto::host(host)))
+}
+
+/// Queues a command for the host with the given `id`.
+///
+/// This endpoint takes any JSON value as the command. Queued commands are persisted
+/// and delivered to the agent on its next `config` request or websocket connect, so
+/// they survive both a host being offline and a server restart.
+///
+/// # Errors
+///
+/// Returns `404 Not Found` if the host does not exist.
+pub async fn host_command_create(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    Json(command): Json<serde_json::Value>,
+) -> Result<Json<HostCommandResp>, AxumError> {
+    let command = internal::host_command_create(&state, id, &command).await?;
+
+    Ok(Json(dto::host_command(command)))
+}
+
+/// Mints a single-use agent enrollment token.
+///
+/// This endpoint takes an optional JSON object with the following fields:
+///
+/// - `ttl`: The token lifetime in seconds (default: 1 hour, max: 7 days).
+///
+/// The response contains the `token` and its `expired_at` timestamp. An agent passes
+/// the token to its `config` endpoint on first contact to obtain an agent token. Only
+/// the peppered hash of the token is stored, so it cannot be shown again.
+pub async fn enrollment_create(
+    State(state): State<Arc<AppState>>,
+    body: Result<Json<EnrollmentCreateReq>, JsonRejection>,
+) -> Result<Json<EnrollmentCreateResp>, AxumError> {
+    // the body is optional, a request without one uses the defaults
+    let body = match body {
+        Ok(Json(body)) => body,
+        Err(JsonRejection::MissingJsonContentType(_)) => EnrollmentCreateReq::default(),
+        Err(rejection) => {
+            return Err(
+                StatusError::new(rejection.status(), "invalid_body", rejection.body_text()).into(),
+            )
+        }
+    };
+    let (enrollment, token) = internal::enrollment_create(&state, body.ttl).await?;
+
+    Ok(Json(EnrollmentCreateResp {
+        token,
+        expired_at: enrollment.expired_at.to_rfc3339(),
+    }))
+}
+
+/// Creates a regular (non-administrator) user.
+///
+/// This endpoint takes a JSON object with the following fields:
+///
+/// - `email`: The email address of the user, unique among all users.
+/// - `password`: The password of the user.
+/// - `nickname`: The nickname of the user (default: the email address).
+///
+/// # Errors
+///
+/// Returns `400 Bad Request` if the email or password is empty or a field is too long
+/// (see `--max-nickname-length`, `--max-email-length` and `--max-password-length`),
+/// `403 Forbidden` if `--max-users` is reached, or `409 Conflict` if the email is
+/// already taken.
+pub async fn user_create(
+    State(state): State<Arc<AppState>>,
+    Json(body): Json<UserCreateReq>,
+) -> Result<Json<UserResp>, AxumError> {
+    if body.email.trim().is_empty() || body.password.is_empty() {
+        return Err(StatusError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_user",
+            "email and password must not be empty",
+        )
+        .into());
+    }
+    user::check_lengths(
+        &state,
+        body.nickname.as_deref(),
+        Some(body.email.trim()),
+        Some(&body.password),
+    )?;
+
+    user::ensure_capacity(&state).await?;
+    let user = internal::user_create(&state, body).await?;
+
+    Ok(Json(dto::user(user)))
+}
+
+/// Returns the effective configuration.
+///
+/// Each entry contains the argument name, its value (redacted if sensitive) and
+/// the source the value was taken from (`default`, `args` or `env`, or `reload` if it
+/// was changed by a reload on SIGHUP).
+pub async fn config_effective(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<EffectiveConfigResp>, AxumError> {
+    Ok(Json(EffectiveConfigResp {
+        entries: state.config(),
+    }))
+}
+
+/// Sends a synthetic `test` event to the configured webhook.
+///
+/// The event is delivered once, without retries. The response is a JSON object with
+/// the following fields:
+///
+/// - `status`: The HTTP status returned by the receiver, if any.
+/// - `latency_ms`: The time the delivery took in milliseconds.
+/// - `error`: The error message if the request could not be sent or the receiver did
+///   not answer with a success status.
+///
+/// # Errors
+///
+/// Returns `404 Not Found` if no webhook is configured.
+pub async fn webhook_test(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<WebhookTestResp>, AxumError> {
+    let Some(webhook) = &state.webhook else {
+        return Err(StatusError::new(
+            StatusCode::NOT_FOUND,
+            "webhook_not_configured",
+            "no webhook url is configured",
+        )
+        .into());
+    };
+
+    let event = Webhook::event(Uuid::nil(), "", WebhookChange::Test);
+    let started = Instant::now();
+    let result = webhook.deliver(&event).await;
+    let latency_ms = started.elapsed().as_millis() as u64;
+
+    Ok(Json(match result {
+        Ok(status) => WebhookTestResp {
+            status: Some(status.as_u16()),
+            latency_ms,
+            error: (!status.is_success()).then(|| format!("receiver answered {}", status)),
+        },
+        Err(err) => WebhookTestResp {
+            status: None,
+            latency_ms,
+            error: Some(err.to_string()),
+        },
+    }))
+}
+
+/// Returns the schema migration status.
+///
+/// The response is a JSON object with the following fields:
+///
+/// - `applied`: The names of the migrations applied to the database, oldest first.
+/// - `pending`: The names of the migrations known to this build but not applied yet.
+pub async fn schema(State(state): State<Arc<AppState>>) -> Result<Json<SchemaResp>, AxumError> {
+    let db = state.database.as_ref();
+
+    let applied = Migrator::get_applied_migrations(db).await?;
+    let pending = Migrator::get_pending_migrations(db).await?;
+
+    Ok(Json(SchemaResp {
+        applied: applied.iter().map(|m| m.name().to_owned()).collect(),
+        pending: pending.iter().map(|m| m.name().to_owned()).collect(),
+    }))
+}
+
+/// Creates a point-in-time backup of the database.
+///
+/// This endpoint is only supported for the sqlite backend. The backup is written
+/// to a timestamped file under `<data-dir>/backups` using `VACUUM INTO`.
+///
+/// The response is a JSON object with the following fields:
+///
+/// - `path`: The path of the created backup file.
+///
+/// # Errors
+///
+/// Returns `501 Not Implemented` if the database backend is not sqlite.
+pub async fn backup(State(state): State<Arc<AppState>>) -> Result<Json<BackupResp>, AxumError> {
+    let path = internal::backup_sqlite(&state).await?;
+
+    Ok(Json(BackupResp { path }))
+}
+
+mod internal {
+    use crate::api::dashboard;
+    use crate::api::dto;
+    use crate::api::user::hash_password;
+    use crate::prelude::axum::StatusError;
+    use crate::prelude::seaorm::*;
+    use crate::state::AppState;
+    use anyhow::Result;
+    use axum::http::header;
+    use axum::http::HeaderMap;
+    use axum::http::StatusCode;
+    use chrono::DateTime;
+    use chrono::Utc;
+    use futures::channel::mpsc;
+    use futures::SinkExt;
+    use proto::admin::alert::HostAlertThresholdReq;
+    use proto::admin::event::EventListReq;
+    use proto::admin::host::HostByHardwareReq;
+    use proto::admin::host::HostImportReq;
+    use proto::admin::host::HostImportResp;
+    use proto::admin::host::HostImportRow;
+    use proto::admin::host::HostImportStatus;
+    use proto::admin::host::HostListReq;
+    use proto::admin::user::UserCreateReq;
+    use sea_orm::sea_query::OnConflict;
+    use sea_orm::ActiveValue;
+    use sea_orm::Condition;
+    use sea_orm::DatabaseTransaction;
+    use sea_orm::DbBackend;
+    use sea_orm::IntoActiveModel;
+    use sea_orm::QuerySelect;
+    use sea_orm::TransactionTrait;
+    use std::collections::BTreeMap;
+    use std::collections::HashSet;
+    use std::net::IpAddr;
+
+    /// Builds the condition matching the active hosts selected by the host list filters.
+    ///
+    /// Paging parameters are ignored.
+    fn host_filter(query: &HostListReq) -> Condition {
+        let mut condition = Condition::all().add(host::Column::DeletedAt.is_null());
+
+        // search machine id and display name
+        if let Some(q) = query.q.as_deref().map(str::trim).filter(|q| !q.is_empty()) {
+            condition = condition.add(
+                Condition::any()
+                    .add(host::Column::MachineId.contains(q))
+                    .add(host::Column::DisplayName.contains(q)),
+            );
+        }
+
+        // hosts never reported OS information have an empty family
+        if let Some(pending) = query.pending {
+            condition = if pending {
+                condition.add(host::Column::OsFamily.eq(""))
+            } else {
+                condition.add(host::Column::OsFamily.ne(""))
+            };
+        }
+
+        if let Some(family) = query.os_family.as_deref() {
+            condition = condition.add(host::Column::OsFamily.eq(family));
+        }
+
+        condition
+    }
+
+    /// Loads a page of hosts matching the given filters, ordered by id.
+    pub async fn hosts(state: &AppState, query: &HostListReq) -> Result<Vec<host::Model>> {
+        let size = query.size.unwrap_or(20).clamp(1, 100);
+        let hosts = Host::find()
+            .filter(host_filter(query))
+            .order_by_asc(host::Column::Id)
+            .paginate(state.database.as_ref(), size)
+            .fetch_page(query.page.unwrap_or(0))
+            .await?;
+
+        Ok(hosts)
+    }
+
+    /// Sets the `labels` on all hosts matching the host list filters, in one transaction.
+    ///
+    /// Existing labels with the same names are overwritten, other labels are kept.
+    /// Returns the number of labeled hosts.
+    pub async fn host_label_by_filter(
+        state: &AppState,
+        query: &HostListReq,
+        labels: &BTreeMap<String, String>,
+    ) -> Result<u64> {
+        let txn = state.database.begin().await?;
+
+        let ids: Vec<Uuid> = Host::find()
+            .select_only()
+            .column(host::Column::Id)
+            .filter(host_filter(query))
+            .into_tuple()
+            .all(&txn)
+            .await?;
+
+        let now = chrono::Utc::now();
+        for chunk in ids.chunks(100) {
+            let models = chunk.iter().flat_map(|host_id| {
+                labels
+                    .iter()
+                    .map(move |(name, value)| host_label::ActiveModel {
+                        id: Set(Uuid::from_bytes(uuidv7::create_raw())),
+                        host_id: Set(*host_id),
+                        name: Set(name.clone()),
+                        value: Set(value.clone()),
+                        updated_at: Set(now),
+                    })
+            });
+
+            HostLabel::insert_many(models)
+                .on_conflict(
+                    OnConflict::columns([host_label::Column::HostId, host_label::Column::Name])
+                        .update_columns([host_label::Column::Value, host_label::Column::UpdatedAt])
+                        .to_owned(),
+                )
+                .exec(&txn)
+                .await?;
+        }
+
+        txn.commit().await?;
+
+        Ok(ids.len() as u64)
+    }
+
+    /// Loads the labels of the host, by name.
+    pub async fn host_labels(state: &AppState, host_id: Uuid) -> Result<BTreeMap<String, String>> {
+        let labels = HostLabel::find()
+            .filter(host_label::Column::HostId.eq(host_id))
+            .all(state.database.as_ref())
+            .await?;
+
+        Ok(labels
+            .into_iter()
+            .map(|label| (label.name, label.value))
+            .collect())
+    }
+
+    /// Loads the active hosts with an error recorded since `since`, most recent first.
+    pub async fn agent_errors(state: &AppState, since: DateTime<Utc>) -> Result<Vec<host::Model>> {
+        Ok(Host::find()
+            .filter(host::Column::DeletedAt.is_null())
+            .filter(host::Column::LastError.is_not_null())
+            .filter(host::Column::LastErrorAt.gte(since))
+            .order_by_desc(host::Column::LastErrorAt)
+            .limit(100)
+            .all(state.database.as_ref())
+            .await?)
+    }
+
+    /// Loads the alert thresholds of the host, all unset if it has none.
+    pub async fn host_alert_thresholds(
+        state: &AppState,
+        host_id: Uuid,
+    ) -> Result<host_alert_threshold::Model> {
+        let thresholds = HostAlertThreshold::find_by_id(host_id)
+            .one(state.database.as_ref())
+            .await?;
+
+        Ok(thresholds.unwrap_or(host_alert_threshold::Model {
+            host_id,
+            cpu_percent: None,
+            memory_percent: None,
+            disk_percent: None,
+            updated_at: Utc::now(),
+        }))
+    }
+
+    /// Replaces the alert thresholds of the host, removing them if none is set.
+    pub async fn host_alert_thresholds_set(
+        state: &AppState,
+        host_id: Uuid,
+        thresholds: &HostAlertThresholdReq,
+    ) -> Result<host_alert_threshold::Model> {
+        let model = host_alert_threshold::Model {
+            host_id,
+            cpu_percent: thresholds.cpu_percent.map(i16::from),
+            memory_percent: thresholds.memory_percent.map(i16::from),
+            disk_percent: thresholds.disk_percent.map(i16::from),
+            updated_at: Utc::now(),
+        };
+
+        if model.cpu_percent.is_none()
+            && model.memory_percent.is_none()
+            && model.disk_percent.is_none()
+        {
+            HostAlertThreshold::delete_by_id(host_id)
+                .exec(state.database.as_ref())
+                .await?;
+            return Ok(model);
+        }
+
+        HostAlertThreshold::insert(model.clone().into_active_model())
+            .on_conflict(
+                OnConflict::column(host_alert_threshold::Column::HostId)
+                    .update_columns([
+                        host_alert_threshold::Column::CpuPercent,
+                        host_alert_threshold::Column::MemoryPercent,
+                        host_alert_threshold::Column::DiskPercent,
+                        host_alert_threshold::Column::UpdatedAt,
+                    ])
+                    .to_owned(),
+            )
+            .exec(state.database.as_ref())
+            .await?;
+
+        Ok(model)
+    }
+
+    /// Loads the latest alerts, optionally filtered by their resolution.
+    pub async fn alerts(state: &AppState, resolved: Option<bool>) -> Result<Vec<alert::Model>> {
+        let mut select = Alert::find().order_by_desc(alert::Column::CreatedAt);
+        if let Some(resolved) = resolved {
+            select = if resolved {
+                select.filter(alert::Column::ResolvedAt.is_not_null())
+            } else {
+                select.filter(alert::Column::ResolvedAt.is_null())
+            };
+        }
+
+        Ok(select.limit(100).all(state.database.as_ref()).await?)
+    }
+
+    /// Marks the alert with the given `id` resolved, if it is not yet.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StatusError` if the alert does not exist, or an error if database
+    /// operations fail.
+    pub async fn alert_ack(state: &AppState, id: Uuid) -> Result<alert::Model> {
+        let alert = Alert::find_by_id(id)
+            .one(state.database.as_ref())
+            .await?
+            .ok_or_else(|| {
+                StatusError::new(
+                    StatusCode::NOT_FOUND,
+                    "alert_not_found",
+                    "alert does not exist",
+                )
+            })?;
+        if alert.resolved_at.is_some() {
+            return Ok(alert);
+        }
+
+        let alert = Alert::update(alert::ActiveModel {
+            id: Unchanged(alert.id),
+            resolved_at: Set(Some(chrono::Utc::now())),
+            ..Default::default()
+        })
+        .exec(state.database.as_ref())
+        .await?;
+
+        tracing::info!("acknowledged alert {}: {}", alert.kind, alert.id);
+
+        Ok(alert)
+    }
+
+    /// Loads the active hosts matching every hardware hash given in `query`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StatusError` if no hash is given, or an error if database operations
+    /// fail.
+    pub async fn hosts_by_hardware(
+        state: &AppState,
+        query: &HostByHardwareReq,
+    ) -> Result<Vec<host::Model>> {
+        let condition = [
+            (host::Column::HashedCpu, query.hashed_cpu),
+            (host::Column::HashedGpu, query.hashed_gpu),
+            (host::Column::HashedMemory, query.hashed_memory),
+            (host::Column::HashedDisk, query.hashed_disk),
+            (host::Column::HashedNetwork, query.hashed_network),
+        ]
+        .into_iter()
+        .filter_map(|(column, hash)| hash.map(|hash| column.eq(hash)))
+        .fold(Condition::all(), Condition::add);
+        if condition.is_empty() {
+            return Err(StatusError::new(
+                StatusCode::BAD_REQUEST,
+                "missing_hardware_hash",
+                "at least one hardware hash is required",
+            )
+            .into());
+        }
+
+        let hosts = Host::find()
+            .filter(host::Column::DeletedAt.is_null())
+            .filter(condition)
+            .order_by_asc(host::Column::Id)
+            .limit(1000)
+            .all(state.database.as_ref())
+            .await?;
+
+        Ok(hosts)
+    }
+
+    /// Parses an import upload according to its content type.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StatusError` if the content type is unsupported or the upload is
+    /// malformed.
+    pub fn parse_import(headers: &HeaderMap, body: &[u8]) -> Result<Vec<HostImportReq>> {
+        let content_type = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let invalid = |err: &dyn std::fmt::Display| {
+            StatusError::new(StatusCode::BAD_REQUEST, "invalid_import", err.to_string())
+        };
+
+        if content_type.starts_with("text/csv") {
+            let hosts = csv::Reader::from_reader(body)
+                .deserialize()
+                .collect::<Result<Vec<HostImportReq>, _>>()
+                .map_err(|err| invalid(&err))?;
+            Ok(hosts)
+        } else if content_type.starts_with("application/json") {
+            let hosts = serde_json::from_slice(body).map_err(|err| invalid(&err))?;
+            Ok(hosts)
+        } else {
+            Err(StatusError::new(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "unsupported_media_type",
+                "expected a text/csv or application/json body",
+            )
+            .into())
+        }
+    }
+
+    /// Validates a host definition and returns it with the machine IP in canonical form.
+    fn validate_import(mut host: HostImportReq) -> Result<HostImportReq, String> {
+        host.machine_id = host.machine_id.trim().to_owned();
+        if host.machine_id.is_empty() {
+            return Err("machine_id is required".to_owned());
+        }
+
+        if !host.machine_ip.is_empty() {
+            let ip = host
+                .machine_ip
+                .trim()
+                .parse::<IpAddr>()
+                .map_err(|_| format!("invalid machine_ip `{}`", host.machine_ip))?;
+            host.machine_ip = ip.to_canonical().to_string();
+        }
+
+        for (field, value, max) in [
+            ("machine_id", &host.machine_id, 255),
+            ("machine_country", &host.machine_country, 3),
+            ("os_family", &host.os_family, 8),
+            ("os_name", &host.os_name, 64),
+            ("os_version", &host.os_version, 64),
+        ] {
+            if value.chars().count() > max {
+                return Err(format!(
+                    "{} must not be longer than {} characters",
+                    field, max
+                ));
+            }
+        }
+
+        Ok(host)
+    }
+
+    /// Inserts the valid, unknown `hosts` in a single transaction and reports the result
+    /// of every row.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if database operations fail, in which case nothing is imported.
+    pub async fn import_hosts(
+        state: &AppState,
+        hosts: Vec<HostImportReq>,
+    ) -> Result<HostImportResp> {
+        let txn = state.database.begin().await?;
+
+        // machine ids already known, including soft-deleted hosts which would be restored
+        let mut known = Host::find()
+            .select_only()
+            .column(host::Column::MachineId)
+            .filter(host::Column::MachineId.is_in(hosts.iter().map(|host| host.machine_id.trim())))
+            .into_tuple::<String>()
+            .all(&txn)
+            .await?
+            .into_iter()
+            .collect::<HashSet<_>>();
+
+        let mut resp = HostImportResp::default();
+        for (index, host) in hosts.into_iter().enumerate() {
+            let machine_id = host.machine_id.trim().to_owned();
+            let (status, message) = match validate_import(host) {
+                Err(message) => (HostImportStatus::Invalid, Some(message)),
+                Ok(host) if !known.insert(host.machine_id.clone()) => (
+                    HostImportStatus::Conflict,
+                    Some("machine_id already exists".to_owned()),
+                ),
+                Ok(host) => {
+                    Host::insert(host::ActiveModel {
+                        id: Set(Uuid::from_bytes(uuidv7::create_raw())),
+                        machine_id: Set(host.machine_id),
+                        machine_ip: Set(host.machine_ip),
+                        machine_country: Set(host.machine_country),
+                        machine_geo: Set("".to_owned()),
+                        os_family: Set(host.os_family),
+                        os_name: Set(host.os_name),
+                        os_version: Set(host.os_version),
+                        os_arch: Set("".to_owned()),
+                        os_build: Set("".to_owned()),
+                        os_virtualization: Set(false),
+                        hashed_cpu: Set(0),
+                        hashed_gpu: Set(0),
+                        hashed_memory: Set(0),
+                        hashed_disk: Set(0),
+                        hashed_network: Set(0),
+                        deleted_at: Set(None),
+                        agent_token: Set(None),
+                        last_seen: Set(None),
+                        agent_version: Set(None),
+                        os_arch_raw: Set(None),
+                        display_name: Set(None),
+                        os_virtualization_platform: Set(None),
+                        last_error: Set(None),
+                        last_error_at: Set(None),
+                        maintenance_until: Set(None),
+                        merged_into: Set(None),
+                    })
+                    .exec(&txn)
+                    .await?;
+
+                    (HostImportStatus::Imported, None)
+                }
+            };
+
+            match status {
+                HostImportStatus::Imported => resp.imported += 1,
+                HostImportStatus::Conflict => resp.conflicts += 1,
+                HostImportStatus::Invalid => resp.invalid += 1,
+            }
+            resp.rows.push(HostImportRow {
+                row: index + 1,
+                machine_id,
+                status,
+                message,
+            });
+        }
+
+        txn.commit().await?;
+
+        Ok(resp)
+    }
+
+    /// Loads the host with the given `id`, including soft-deleted hosts.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StatusError` if the host does not exist, or an error if database
+    /// operations fail.
+    pub async fn host(state: &AppState, id: Uuid) -> Result<host::Model> {
+        Host::find_by_id(id)
+            .one(state.database.as_ref())
+            .await?
+            .ok_or_else(|| {
+                StatusError::new(
+                    StatusCode::NOT_FOUND,
+                    "host_not_found",
+                    "host does not exist",
+                )
+                .into()
+            })
+    }
+
+    /// Loads the log snippets of the host, newest first.
+    pub async fn host_logs(state: &AppState, host_id: Uuid) -> Result<Vec<host_log::Model>> {
+        let logs = HostLog::find()
+            .filter(host_log::Column::HostId.eq(host_id))
+            .order_by_desc(host_log::Column::Id)
+            .all(state.database.as_ref())
+            .await?;
+
+        Ok(logs)
+    }
+
+    /// Sets or removes the display name of the active host with the given `id`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StatusError` if the host does not exist, or an error if database
+    /// operations fail.
+    pub async fn host_display_name(
+        state: &AppState,
+        id: Uuid,
+        display_name: Option<&str>,
+    ) -> Result<host::Model> {
+        let updated = Host::update_many()
+            .col_expr(host::Column::DisplayName, Expr::value(display_name))
+            .filter(host::Column::Id.eq(id))
+            .filter(host::Column::DeletedAt.is_null())
+            .exec(state.database.as_ref())
+            .await?;
+        if updated.rows_affected != 1 {
+            return Err(StatusError::new(
+                StatusCode::NOT_FOUND,
+                "host_not_found",
+                "host does not exist",
+            )
+            .into());
+        }
+
+        host(state, id).await
+    }
+
+    /// Sets or removes the end of the maintenance of the active host with the given `id`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StatusError` if the host does not exist, or an error if database
+    /// operations fail.
+    pub async fn host_maintenance(
+        state: &AppState,
+        id: Uuid,
+        until: Option<DateTime<Utc>>,
+    ) -> Result<host::Model> {
+        let updated = Host::update_many()
+            .col_expr(host::Column::MaintenanceUntil, Expr::value(until))
+            .filter(host::Column::Id.eq(id))
+            .filter(host::Column::DeletedAt.is_null())
+            .exec(state.database.as_ref())
+            .await?;
+        if updated.rows_affected != 1 {
+            return Err(StatusError::new(
+                StatusCode::NOT_FOUND,
+                "host_not_found",
+                "host does not exist",
+            )
+            .into());
+        }
+
+        host(state, id).await
+    }
+
+    /// Writes the export bundle of the `target` host to `tx`, chunk by chunk.
+    ///
+    /// Events and metrics samples are read in batches, so memory stays flat for hosts
+    /// with a long history.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if serialization or database operations fail, or the receiver
+    /// is gone.
+    pub async fn write_bundle(
+        state: &AppState,
+        target: host::Model,
+        tx: &mut mpsc::Sender<Result<Vec<u8>>>,
+    ) -> Result<()> {
+        const BATCH_SIZE: u64 = 500;
+
+        let id = target.id;
+
+        // host
+        let mut chunk = b"{\"host\":".to_vec();
+        serde_json::to_writer(&mut chunk, &dto::host(target))?;
+        chunk.extend_from_slice(b",\"events\":[");
+        tx.send(Ok(chunk)).await?;
+
+        // events, batch by batch
+        let (mut after, mut first) = (None, true);
+        loop {
+            let mut select = EventLog::find()
+                .filter(event_log::Column::HostId.eq(id))
+                .order_by_asc(event_log::Column::Id)
+                .limit(BATCH_SIZE);
+            if let Some(after) = after {
+                select = select.filter(event_log::Column::Id.gt(after));
+            }
+
+            let events = select.all(state.database.as_ref()).await?;
+            let Some(last) = events.last().map(|event| event.id) else {
+                break;
+            };
+
+            let mut chunk = Vec::new();
+            for event in events {
+                if !std::mem::take(&mut first) {
+                    chunk.push(b',');
+                }
+                serde_json::to_writer(&mut chunk, &dto::event(event))?;
+            }
+            tx.send(Ok(chunk)).await?;
+
+            after = Some(last);
+        }
+        tx.send(Ok(b"],\"metrics\":[".to_vec())).await?;
+
+        // metrics, batch by batch
+        let (mut after, mut first, to) = (None, true, Utc::now());
+        loop {
+            let metrics =
+                dashboard::internal::metrics_after(state, id, DateTime::UNIX_EPOCH, to, after)
+                    .await?;
+            let Some(last) = metrics.last().map(|metric| (metric.recorded_at, metric.id)) else {
+                break;
+            };
+
+            let mut chunk = Vec::new();
+            for metric in metrics {
+                if !std::mem::take(&mut first) {
+                    chunk.push(b',');
+                }
+                serde_json::to_writer(&mut chunk, &dto::metric(metric))?;
+            }
+            tx.send(Ok(chunk)).await?;
+
+            after = Some(last);
+        }
+
+        // commands
+        let commands = HostCommand::find()
+            .filter(host_command::Column::HostId.eq(id))
+            .order_by_asc(host_command::Column::CreatedAt)
+            .all(state.database.as_ref())
+            .await?
+            .into_iter()
+            .map(dto::host_command)
+            .collect::<Vec<_>>();
+
+        let mut chunk = b"],\"commands\":".to_vec();
+        serde_json::to_writer(&mut chunk, &commands)?;
+        tx.send(Ok(chunk)).await?;
+
+        // labels
+        let mut chunk = b",\"labels\":".to_vec();
+        serde_json::to_writer(&mut chunk, &host_labels(state, id).await?)?;
+        tx.send(Ok(chunk)).await?;
+
+        // hardware changes
+        let changes = HardwareChange::find()
+            .filter(hardware_change::Column::HostId.eq(id))
+            .order_by_asc(hardware_change::Column::ChangedAt)
+            .order_by_asc(hardware_change::Column::Id)
+            .all(state.database.as_ref())
+            .await?
+            .into_iter()
+            .map(dto::hardware_change)
+            .collect::<Vec<_>>();
+
+        let mut chunk = b",\"hardware_changes\":".to_vec();
+        serde_json::to_writer(&mut chunk, &changes)?;
+        tx.send(Ok(chunk)).await?;
+
+        // logs
+        let logs = host_logs(state, id)
+            .await?
+            .into_iter()
+            .map(dto::host_log)
+            .collect::<Vec<_>>();
+
+        let mut chunk = b",\"logs\":".to_vec();
+        serde_json::to_writer(&mut chunk, &logs)?;
+        chunk.push(b'}');
+        tx.send(Ok(chunk)).await?;
+
+        Ok(())
+    }
+
+    /// Loads the latest hardware changes, optionally of a single host.
+    pub async fn hardware_changes(
+        state: &AppState,
+        host_id: Option<Uuid>,
+        limit: u64,
+    ) -> Result<Vec<hardware_change::Model>> {
+        let mut select = HardwareChange::find()
+            .order_by_desc(hardware_change::Column::ChangedAt)
+            .order_by_desc(hardware_change::Column::Id);
+        if let Some(host_id) = host_id {
+            select = select.filter(hardware_change::Column::HostId.eq(host_id));
+        }
+
+        Ok(select.limit(limit).all(state.database.as_ref()).await?)
+    }
+
+    /// Loads a page of events matching the given filters, ordered by `received_at`
+    /// descending.
+    pub async fn events(
+        state: &AppState,
+        query: &EventListReq,
+        host_id: Option<Uuid>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Result<Vec<event_log::Model>> {
+        let mut select = EventLog::find()
+            .order_by_desc(event_log::Column::ReceivedAt)
+            .order_by_desc(event_log::Column::Id);
+
+        if let Some(event_type) = &query.event_type {
+            select = select.filter(event_log::Column::EventType.eq(event_type));
+        }
+        if let Some(host_id) = host_id {
+            select = select.filter(event_log::Column::HostId.eq(host_id));
+        }
+        if let Some(from) = from {
+            select = select.filter(event_log::Column::ReceivedAt.gte(from));
+        }
+        if let Some(to) = to {
+            select = select.filter(event_log::Column::ReceivedAt.lte(to));
+        }
+
+        let size = query.size.unwrap_or(20).clamp(1, 100);
+        let events = select
+            .paginate(state.database.as_ref(), size)
+            .fetch_page(query.page.unwrap_or(0))
+            .await?;
+
+        Ok(events)
+    }
+
+    /// Loads the next batch of hosts with an id greater than `after`, ordered by id.
+    ///
+    /// Soft-deleted hosts are excluded. Returns an empty batch once all hosts are read.
+    pub async fn hosts_after(state: &AppState, after: Option<Uuid>) -> Result<Vec<host::Model>> {
+        const BATCH_SIZE: u64 = 500;
+
+        let mut select = Host::find()
+            .filter(host::Column::DeletedAt.is_null())
+            .order_by_asc(host::Column::Id)
+            .limit(BATCH_SIZE);
+        if let Some(after) = after {
+            select = select.filter(host::Column::Id.gt(after));
+        }
+
+        Ok(select.all(state.database.as_ref()).await?)
+    }
+
+    /// Merges the `source` host into the `target` host.
+    ///
+    /// Fields the target never reported (empty strings, zero hashes) are taken over from
+    /// the source, the rows related to the source are moved to the target with
+    /// `move_host_rows`, then the source is soft-deleted and marked as merged into the
+    /// target, so it is never restored. All steps run in one transaction.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StatusError` if the hosts are the same or do not exist, or an error if
+    /// database operations fail.
+    pub async fn merge_hosts(state: &AppState, target: Uuid, source: Uuid) -> Result<host::Model> {
+        if target == source {
+            return Err(StatusError::new(
+                StatusCode::BAD_REQUEST,
+                "invalid_merge",
+                "cannot merge a host into itself",
+            )
+            .into());
+        }
+
+        let txn = state.database.begin().await?;
+
+        // load both hosts, ignoring soft-deleted ones
+        let mut hosts = Host::find()
+            .filter(host::Column::Id.is_in([target, source]))
+            .filter(host::Column::DeletedAt.is_null())
+            .all(&txn)
+            .await?;
+        let position = |id| hosts.iter().position(|host| host.id == id);
+        let (Some(t), Some(s)) = (position(target), position(source)) else {
+            return Err(StatusError::new(
+                StatusCode::NOT_FOUND,
+                "host_not_found",
+                "target or source host does not exist",
+            )
+            .into());
+        };
+        let (target, source) = (hosts[t].clone(), hosts.swap_remove(s));
+
+        // take over information the target never reported
+        let merged = Host::update(host::ActiveModel {
+            id: Unchanged(target.id),
+            machine_ip: merge_str(&target.machine_ip, source.machine_ip),
+            machine_country: merge_str(&target.machine_country, source.machine_country),
+            machine_geo: merge_str(&target.machine_geo, source.machine_geo),
+            os_family: merge_str(&target.os_family, source.os_family.clone()),
+            os_name: merge_str(&target.os_name, source.os_name),
+            os_version: merge_str(&target.os_version, source.os_version),
+            os_arch_raw: if target.os_arch.is_empty() && !source.os_arch.is_empty() {
+                Set(source.os_arch_raw)
+            } else {
+                NotSet
+            },
+            os_arch: merge_str(&target.os_arch, source.os_arch),
+            os_build: merge_str(&target.os_build, source.os_build),
+            os_virtualization: if target.os_family.is_empty() && !source.os_family.is_empty() {
+                Set(source.os_virtualization)
+            } else {
+                NotSet
+            },
+            os_virtualization_platform: if target.os_family.is_empty()
+                && !source.os_family.is_empty()
+            {
+                Set(source.os_virtualization_platform)
+            } else {
+                NotSet
+            },
+            hashed_cpu: merge_hash(target.hashed_cpu, source.hashed_cpu),
+            hashed_gpu: merge_hash(target.hashed_gpu, source.hashed_gpu),
+            hashed_memory: merge_hash(target.hashed_memory, source.hashed_memory),
+            hashed_disk: merge_hash(target.hashed_disk, source.hashed_disk),
+            hashed_network: merge_hash(target.hashed_network, source.hashed_network),
+            display_name: if target.display_name.is_none() && source.display_name.is_some() {
+                Set(source.display_name)
+            } else {
+                NotSet
+            },
+            ..Default::default()
+        })
+        .exec(&txn)
+        .await?;
+
+        move_host_rows(&txn, target.id, source.id).await?;
+
+        // soft-delete source, recording the merge so it is not restored again
+        Host::update(host::ActiveModel {
+            id: Unchanged(source.id),
+            deleted_at: Set(Some(chrono::Utc::now())),
+            merged_into: Set(Some(target.id)),
+            ..Default::default()
+        })
+        .exec(&txn)
+        .await?;
+
+        txn.commit().await?;
+
+        tracing::info!("merged host {} into {}", source.id, merged.id);
+
+        Ok(merged)
+    }
+
+    /// Moves the events, metrics, logs, hardware changes, alerts, labels, commands and
+    /// alert thresholds of the `source` host to the `target` host.
+    ///
+    /// Labels and alert thresholds the target has itself take precedence, the ones of
+    /// the source are deleted then.
+    async fn move_host_rows(
+        txn: &DatabaseTransaction,
+        target: Uuid,
+        source: Uuid,
+    ) -> Result<(), DbErr> {
+        EventLog::update_many()
+            .col_expr(event_log::Column::HostId, Expr::value(target))
+            .filter(event_log::Column::HostId.eq(source))
+            .exec(txn)
+            .await?;
+        Metric::update_many()
+            .col_expr(metric::Column::HostId, Expr::value(target))
+            .filter(metric::Column::HostId.eq(source))
+            .exec(txn)
+            .await?;
+        HostLog::update_many()
+            .col_expr(host_log::Column::HostId, Expr::value(target))
+            .filter(host_log::Column::HostId.eq(source))
+            .exec(txn)
+            .await?;
+        HardwareChange::update_many()
+            .col_expr(hardware_change::Column::HostId, Expr::value(target))
+            .filter(hardware_change::Column::HostId.eq(source))
+            .exec(txn)
+            .await?;
+        Alert::update_many()
+            .col_expr(alert::Column::HostId, Expr::value(Some(target)))
+            .filter(alert::Column::HostId.eq(source))
+            .exec(txn)
+            .await?;
+        HostCommand::update_many()
+            .col_expr(host_command::Column::HostId, Expr::value(target))
+            .filter(host_command::Column::HostId.eq(source))
+            .exec(txn)
+            .await?;
+
+        // labels are unique by name per host
+        let names = HostLabel::find()
+            .select_only()
+            .column(host_label::Column::Name)
+            .filter(host_label::Column::HostId.eq(target))
+            .into_tuple::<String>()
+            .all(txn)
+            .await?;
+        HostLabel::delete_many()
+            .filter(host_label::Column::HostId.eq(source))
+            .filter(host_label::Column::Name.is_in(names))
+            .exec(txn)
+            .await?;
+        HostLabel::update_many()
+            .col_expr(host_label::Column::HostId, Expr::value(target))
+            .filter(host_label::Column::HostId.eq(source))
+            .exec(txn)
+            .await?;
+
+        // thresholds are keyed by host
+        if HostAlertThreshold::find_by_id(target)
+            .one(txn)
+            .await?
+            .is_some()
+        {
+            HostAlertThreshold::delete_by_id(source).exec(txn).await?;
+        } else {
+            HostAlertThreshold::update_many()
+                .col_expr(host_alert_threshold::Column::HostId, Expr::value(target))
+                .filter(host_alert_threshold::Column::HostId.eq(source))
+                .exec(txn)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Takes over the `source` value if the `target` value was never reported.
+    fn merge_str(target: &str, source: String) -> ActiveValue<String> {
+        if target.is_empty() && !source.is_empty() {
+            Set(source)
+        } else {
+            NotSet
+        }
+    }
+
+    /// Takes over the `source` hash if the `target` hash was never reported.
+    fn merge_hash(target: i32, source: i32) -> ActiveValue<i32> {
+        if target == 0 && source != 0 {
+            Set(source)
+        } else {
+            NotSet
+        }
+    }
+
+    /// Persists a new enrollment token valid for `ttl` seconds and returns it with the
+    /// token, of which only the peppered hash is stored.
+    pub async fn enrollment_create(
+        state: &AppState,
+        ttl: Option<u64>,
+    ) -> Result<(enrollment::Model, String)> {
+        let ttl = ttl.unwrap_or(60 * 60).clamp(1, 7 * 24 * 60 * 60);
+
+        let token = crate::token::random();
+        let enrollment = Enrollment::insert(enrollment::ActiveModel {
+            id: Set(Uuid::from_bytes(uuidv7::create_raw())),
+            token: Set(crate::token::hash(&state.pepper, &token)),
+            expired_at: Set(chrono::Utc::now() + chrono::Duration::seconds(ttl as i64)),
+            consumed_at: Set(None),
+            host_id: Set(None),
+        })
+        .exec_with_returning(state.database.as_ref())
+        .await?;
+
+        Ok((enrollment, token))
+    }
+
+    /// Persists a new regular user, the password is stored as Argon2 hash.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StatusError` if the email is already taken, or an error if database
+    /// operations fail.
+    pub async fn user_create(state: &AppState, body: UserCreateReq) -> Result<user::Model> {
+        let email = body.email.trim().to_owned();
+
+        let taken = User::find()
+            .filter(user::Column::Email.eq(&email))
+            .count(state.database.as_ref())
+            .await?
+            > 0;
+        if taken {
+            return Err(StatusError::new(
+                StatusCode::CONFLICT,
+                "email_taken",
+                "email is already taken",
+            )
+            .into());
+        }
+
+        let now = chrono::Utc::now();
+        let user = User::insert(user::ActiveModel {
+            id: Set(Uuid::from_bytes(uuidv7::create_raw())),
+            sa: Set(false),
+            nickname: Set(body.nickname.unwrap_or_else(|| email.clone())),
+            email: Set(email),
+            password: Set(hash_password(&body.password)?),
+            created_at: Set(now),
+            updated_at: Set(now),
+        })
+        .exec_with_returning(state.database.as_ref())
+        .await?;
+
+        Ok(user)
+    }
+
+    /// Queues the `command` for the host with the given `id`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StatusError` if the host does not exist, or an error if database
+    /// operations fail.
+    pub async fn host_command_create(
+        state: &AppState,
+        id: Uuid,
+        command: &serde_json::Value,
+    ) -> Result<host_command::Model> {
+        let exists = Host::find_by_id(id)
+            .filter(host::Column::DeletedAt.is_null())
+            .one(state.database.as_ref())
+            .await?
+            .is_some();
+        if !exists {
+            return Err(StatusError::new(
+                StatusCode::NOT_FOUND,
+                "host_not_found",
+                "host does not exist",
+            )
+            .into());
+        }
+
+        let command = HostCommand::insert(host_command::ActiveModel {
+            id: Set(Uuid::from_bytes(uuidv7::create_raw())),
+            host_id: Set(id),
+            command: Set(command.to_string()),
+            created_at: Set(chrono::Utc::now()),
+            delivered_at: Set(None),
+        })
+        .exec_with_returning(state.database.as_ref())
+        .await?;
+
+        Ok(command)
+    }
+
+    /// Backs up the sqlite database into the data directory.
+    ///
+    /// The backup file is named `wk-<timestamp>.db` and placed under `<data-dir>/backups`,
+    /// which is created if it does not exist. `VACUUM INTO` produces a consistent copy
+    /// without blocking readers.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StatusError` if the backend is not sqlite, or an error if the
+    /// backup fails.
+    pub async fn backup_sqlite(state: &AppState) -> Result<String> {
+        if state.database.get_database_backend() != DbBackend::Sqlite {
+            return Err(StatusError::new(
+                StatusCode::NOT_IMPLEMENTED,
+                "unsupported_backend",
+                "backup is only supported for the sqlite backend",
+            )
+            .into());
+        }
+
+        // prepare backup directory
+        let dir = state.args.data_dir.join("backups");
+        tokio::fs::create_dir_all(&dir).await?;
+
+        // generate timestamped file path
+        let path = dir.join(format!(
+            "wk-{}.db",
+            chrono::Utc::now().format("%Y%m%dT%H%M%S%.3fZ")
+        ));
+        let path = path.to_string_lossy().into_owned();
+
+        // write backup using sqlite VACUUM INTO
+        state
+            .database
+            .execute_unprepared(&format!("VACUUM INTO '{}'", path.replace('\'', "''")))
+            .await?;
+
+        tracing::info!("database backup created: {}", path);
+
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::seaorm::*;
+    use crate::testing;
+    use axum::body::Body;
+    use axum::http::header;
+    use axum::http::Method;
+    use axum::http::Request;
+    use axum::http::StatusCode;
+    use database::migrations::Migrator;
+    use database::migrations::MigratorTrait;
+    use futures::SinkExt;
+    use futures::StreamExt;
+    use proto::admin::host::HostResp;
+    use serde_json::json;
+    use std::time::Duration;
+    use tokio_tungstenite::tungstenite::Message;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn pending_hosts_never_reported_os() {
+        let state = testing::state(&[]).await;
+        let token = testing::admin(&state).await;
+        testing::host(&state, "pending").await;
+        let reported = testing::host(&state, "reported").await;
+        Host::update(host::ActiveModel {
+            id: Unchanged(reported.id),
+            os_family: Set("linux".to_owned()),
+            ..Default::default()
+        })
+        .exec(state.database.as_ref())
+        .await
+        .unwrap();
+
+        let request = testing::request(
+            Method::GET,
+            "/api/admin/hosts?pending=true",
+            Some(&token),
+            None,
+        );
+        let (status, body) = testing::send(&testing::router(&state), request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        let machine_ids = body.as_array().unwrap().iter();
+        let machine_ids = machine_ids
+            .map(|host| &host["machine_id"])
+            .collect::<Vec<_>>();
+        assert_eq!(machine_ids, ["pending"]);
+    }
+
+    #[tokio::test]
+    async fn hosts_are_found_by_display_name() {
+        let state = testing::state(&[]).await;
+        let token = testing::admin(&state).await;
+        let router = testing::router(&state);
+        let host = testing::host(&state, "4c4c4544-0042").await;
+        testing::host(&state, "4c4c4544-0043").await;
+
+        let uri = format!("/api/admin/hosts/{}/display-name", host.id);
+        let name = json!({ "display_name": "  Build Server " });
+        let request = testing::request(Method::PUT, &uri, Some(&token), Some(name));
+        let (status, body) = testing::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        let request = testing::request(Method::GET, "/api/admin/hosts?q=build", Some(&token), None);
+        let (status, body) = testing::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let hosts = body.as_array().unwrap();
+        assert_eq!(hosts.len(), 1, "{}", body);
+        assert_eq!(hosts[0]["machine_id"], "4c4c4544-0042");
+        assert_eq!(hosts[0]["display_name"], "Build Server");
+    }
+
+    #[tokio::test]
+    async fn hosts_are_found_by_hardware_hash() {
+        let state = testing::state(&[]).await;
+        let token = testing::admin(&state).await;
+        for (machine_id, cpu) in [
+            ("m1", "AMD Ryzen 9 7950X"),
+            ("m2", "AMD Ryzen 9 7950X"),
+            ("m3", "Intel Core i5"),
+        ] {
+            let hardware = json!([{ "EvtHardwareEmit": { "cpu": cpu } }]);
+            testing::report(&state, machine_id, None, hardware).await;
+        }
+        let hashed_cpu = testing::host(&state, "m1").await.hashed_cpu;
+
+        let uri = format!("/api/admin/hosts/by-hardware?hashed_cpu={}", hashed_cpu);
+        let request = testing::request(Method::GET, &uri, Some(&token), None);
+        let (status, body) = testing::send(&testing::router(&state), request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        let hosts = serde_json::from_value::<Vec<HostResp>>(body).unwrap();
+        let mut machine_ids = hosts
+            .iter()
+            .map(|host| host.machine_id.as_str())
+            .collect::<Vec<_>>();
+        machine_ids.sort();
+        assert_eq!(machine_ids, ["m1", "m2"]);
+    }
+
+    #[tokio::test]
+    async fn changed_hardware_is_recorded() {
+        let state = testing::state(&[]).await;
+        let token = testing::admin(&state).await;
+        for cpu in ["AMD Ryzen 9 7950X", "AMD Ryzen 9 7950X", "Intel Core i5"] {
+            let hardware = json!([{ "EvtHardwareEmit": { "cpu": cpu, "gpu": "Radeon" } }]);
+            testing::report(&state, "m1", None, hardware).await;
+        }
+        let host = testing::host(&state, "m1").await;
+
+        let uri = format!("/api/admin/hardware-changes?host_id={}", host.id);
+        let request = testing::request(Method::GET, &uri, Some(&token), None);
+        let (status, body) = testing::send(&testing::router(&state), request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        let changes = body.as_array().unwrap();
+        assert_eq!(changes.len(), 1, "{}", body);
+        assert_eq!(changes[0]["column"], "hashed_cpu");
+        // the hash of "AMD Ryzen 9 7950X"
+        assert_eq!(changes[0]["old"], -359430173);
+        assert_eq!(changes[0]["new"], host.hashed_cpu);
+    }
+
+    #[tokio::test]
+    async fn stale_host_in_maintenance_is_not_offline() {
+        let state = testing::state(&[]).await;
+        let token = testing::admin(&state).await;
+        let router = testing::router(&state);
+        let two_days_ago = chrono::Utc::now() - chrono::Duration::days(2);
+        for machine_id in ["online", "stale", "maintained"] {
+            let machine = json!([{ "EvtMachineEmit": { "ip": "192.0.2.1", "country": "US" } }]);
+            testing::report(&state, machine_id, None, machine).await;
+        }
+        let stale = testing::host(&state, "stale").await;
+        let maintained = testing::host(&state, "maintained").await;
+        for id in [stale.id, maintained.id] {
+            testing::seen(&state, id, two_days_ago).await;
+        }
+
+        let uri = format!("/api/admin/hosts/{}/maintenance", maintained.id);
+        let until = (chrono::Utc::now() + chrono::Duration::hours(1)).to_rfc3339();
+        let request = testing::request(
+            Method::PUT,
+            &uri,
+            Some(&token),
+            Some(json!({ "until": until })),
+        );
+        let (status, body) = testing::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["in_maintenance"], true);
+
+        let request =
+            testing::request(Method::GET, "/api/dashboard/geo/health", Some(&token), None);
+        let (_, body) = testing::send(&router, request).await;
+        assert_eq!(
+            body,
+            json!([{ "country": "US", "online": 1, "offline": 1 }])
+        );
+    }
+
+    #[tokio::test]
+    async fn agents_with_errors_are_listed() {
+        let state = testing::state(&[]).await;
+        let token = testing::admin(&state).await;
+        let os = json!({ "EvtOsEmit": { "family": "linux" } });
+        testing::report(&state, "broken", None, json!([os, { "EvtBogus": 1 }])).await;
+        testing::report(&state, "healthy", None, json!([os])).await;
+
+        let uri = "/api/admin/agents/errors";
+        let request = testing::request(Method::GET, uri, Some(&token), None);
+        let (status, body) = testing::send(&testing::router(&state), request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        let agents = body.as_array().unwrap();
+        assert_eq!(agents.len(), 1, "{}", body);
+        assert_eq!(agents[0]["machine_id"], "broken");
+        assert!(!agents[0]["error"].as_str().unwrap().is_empty());
+        assert!(!agents[0]["errored_at"].as_str().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn label_by_filter_affects_matching_hosts_only() {
+        let state = testing::state(&[]).await;
+        let token = testing::admin(&state).await;
+        let router = testing::router(&state);
+        for (machine_id, family) in [("m1", "windows"), ("m2", "linux"), ("m3", "windows")] {
+            let os = json!([{ "EvtOsEmit": { "family": family } }]);
+            testing::report(&state, machine_id, None, os).await;
+        }
+
+        let uri = "/api/admin/hosts/label-by-filter?os_family=windows";
+        let labels = json!({ "labels": { "platform": "windows" } });
+        let request = testing::request(Method::POST, uri, Some(&token), Some(labels));
+        let (status, body) = testing::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body, json!({ "affected": 2 }));
+
+        for (machine_id, expected) in [
+            ("m1", json!({ "platform": "windows" })),
+            ("m2", json!({})),
+            ("m3", json!({ "platform": "windows" })),
+        ] {
+            let host = testing::host(&state, machine_id).await;
+            let uri = format!("/api/admin/hosts/{}/labels", host.id);
+            let request = testing::request(Method::GET, &uri, Some(&token), None);
+            assert_eq!(
+                testing::send(&router, request).await.1,
+                expected,
+                "{}",
+                machine_id
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn export_streams_one_host_per_line() {
+        let state = testing::state(&[]).await;
+        let token = testing::admin(&state).await;
+        for machine_id in ["m1", "m2", "m3"] {
+            testing::host(&state, machine_id).await;
+        }
+
+        let uri = "/api/admin/hosts/export?format=ndjson";
+        let request = testing::request(Method::GET, uri, Some(&token), None);
+        let (status, body) = testing::send(&testing::router(&state), request).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let lines = body.as_str().unwrap().lines();
+        let hosts = lines
+            .map(|line| serde_json::from_str::<HostResp>(line).unwrap())
+            .collect::<Vec<_>>();
+        let machine_ids = hosts.iter().map(|host| host.machine_id.as_str());
+        assert_eq!(machine_ids.collect::<Vec<_>>(), ["m1", "m2", "m3"]);
+    }
+
+    #[tokio::test]
+    async fn bundle_has_each_section() {
+        let state = testing::state(&[]).await;
+        let token = testing::admin(&state).await;
+        let router = testing::router(&state);
+        let host = testing::host(&state, "m1").await;
+
+        for cpu in ["cpu0", "cpu1"] {
+            let events = json!([
+                { "EvtOsEmit": { "family": "linux" } },
+                { "EvtHardwareEmit": { "cpu": cpu } },
+                { "EvtMetricsEmit": [10.0, 1, 2, 3, 4] },
+            ]);
+            testing::report(&state, "m1", None, events).await;
+        }
+        let uri = "/api/admin/hosts/label-by-filter?q=m1";
+        let labels = json!({ "labels": { "rack": "a1" } });
+        let request = testing::request(Method::POST, uri, Some(&token), Some(labels));
+        assert_eq!(testing::send(&router, request).await.0, StatusCode::OK);
+        let uri = format!("/api/admin/hosts/{}/commands", host.id);
+        let command = json!({ "action": "restart" });
+        let request = testing::request(Method::POST, &uri, Some(&token), Some(command));
+        assert_eq!(testing::send(&router, request).await.0, StatusCode::OK);
+        let request = Request::post("/api/agent/m1/logs")
+            .body(Body::from("agent started"))
+            .unwrap();
+        assert_eq!(testing::send(&router, request).await.0, StatusCode::OK);
+
+        let uri = format!("/api/admin/hosts/{}/export", host.id);
+        let request = testing::request(Method::GET, &uri, Some(&token), None);
+        let (status, body) = testing::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK);
+
+        assert_eq!(body["host"]["machine_id"], "m1");
+        for section in ["events", "metrics", "commands", "hardware_changes", "logs"] {
+            let entries = body[section].as_array();
+            assert!(
+                entries.is_some_and(|entries| !entries.is_empty()),
+                "{}",
+                section
+            );
+        }
+        assert_eq!(body["labels"], json!({ "rack": "a1" }));
+    }
+
+    #[tokio::test]
+    async fn events_filter_by_type_and_time() {
+        let state = testing::state(&[]).await;
+        let token = testing::admin(&state).await;
+        let router = testing::router(&state);
+        let os = json!([{ "EvtOsEmit": { "family": "linux" } }]);
+        testing::report(&state, "old", None, os).await;
+        EventLog::update_many()
+            .col_expr(
+                event_log::Column::ReceivedAt,
+                Expr::value(chrono::Utc::now() - chrono::Duration::hours(2)),
+            )
+            .exec(state.database.as_ref())
+            .await
+            .unwrap();
+        let events = json!([
+            { "EvtOsEmit": { "family": "linux" } },
+            { "EvtMachineEmit": { "ip": "192.0.2.1" } },
+        ]);
+        testing::report(&state, "new", None, events).await;
+        let new = testing::host(&state, "new").await;
+
+        let from = chrono::Utc::now() - chrono::Duration::hours(1);
+        let from = from.to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+        for (query, expected) in [
+            ("event_type=EvtOsEmit".to_owned(), 2),
+            (format!("event_type=EvtOsEmit&from={}", from), 1),
+            (format!("from={}", from), 2),
+        ] {
+            let uri = format!("/api/admin/events?{}", query);
+            let request = testing::request(Method::GET, &uri, Some(&token), None);
+            let (status, body) = testing::send(&router, request).await;
+            assert_eq!(status, StatusCode::OK, "{}", body);
+            assert_eq!(body.as_array().unwrap().len(), expected, "{}", query);
+        }
+
+        let uri = format!("/api/admin/events?event_type=EvtOsEmit&from={}", from);
+        let request = testing::request(Method::GET, &uri, Some(&token), None);
+        let (_, body) = testing::send(&router, request).await;
+        assert_eq!(body[0]["host_id"], new.id.to_string());
+        assert_eq!(body[0]["event_type"], "EvtOsEmit");
+        assert_eq!(body[0]["payload"]["family"], "linux");
+    }
+
+    #[tokio::test]
+    async fn effective_config_shows_source_and_redacts() {
+        let state = testing::state(&[
+            "--offline-threshold",
+            "120",
+            "--secret",
+            "secret",
+            "--database",
+            "postgres://wk:password@db/wk",
+        ])
+        .await;
+        let token = testing::admin(&state).await;
+
+        let uri = "/api/admin/config/effective";
+        let request = testing::request(Method::GET, uri, Some(&token), None);
+        let (status, body) = testing::send(&testing::router(&state), request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        let entry = |name: &str| {
+            let entries = body["entries"].as_array().unwrap();
+            entries
+                .iter()
+                .find(|entry| entry["name"] == name)
+                .unwrap()
+                .clone()
+        };
+        assert_eq!(
+            entry("offline_threshold"),
+            json!({ "name": "offline_threshold", "value": "120", "source": "args" })
+        );
+        assert_eq!(entry("listen")["source"], "default");
+        assert_eq!(entry("secret")["value"], "***");
+        assert_eq!(entry("database")["value"], "postgres://wk:***@db/wk");
+    }
+
+    #[tokio::test]
+    async fn merge_moves_rows_and_soft_deletes_source() {
+        let state = testing::state(&[]).await;
+        let token = testing::admin(&state).await;
+        let router = testing::router(&state);
+        let target = testing::host(&state, "target").await;
+        let source = testing::host(&state, "source").await;
+
+        let events = json!([
+            { "EvtOsEmit": { "family": "linux" } },
+            { "EvtMetricsEmit": [10.0, 1, 2, 3, 4] },
+        ]);
+        testing::report(&state, "source", None, events).await;
+        let uri = "/api/admin/hosts/label-by-filter?q=source";
+        let labels = json!({ "labels": { "rack": "a1" } });
+        let request = testing::request(Method::POST, uri, Some(&token), Some(labels));
+        assert_eq!(testing::send(&router, request).await.0, StatusCode::OK);
+
+        let uri = format!("/api/admin/hosts/{}/merge", target.id);
+        let body = json!({ "source": target.id });
+        let request = testing::request(Method::POST, &uri, Some(&token), Some(body));
+        assert_eq!(
+            testing::send(&router, request).await.0,
+            StatusCode::BAD_REQUEST
+        );
+
+        let body = json!({ "source": source.id });
+        let request = testing::request(Method::POST, &uri, Some(&token), Some(body));
+        let (status, body) = testing::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        let db = state.database.as_ref();
+        for (host_id, events, metrics) in [(target.id, 1, 1), (source.id, 0, 0)] {
+            let event_logs = EventLog::find().filter(event_log::Column::HostId.eq(host_id));
+            assert_eq!(event_logs.count(db).await.unwrap(), events);
+            let samples = Metric::find().filter(metric::Column::HostId.eq(host_id));
+            assert_eq!(samples.count(db).await.unwrap(), metrics);
+        }
+
+        let uri = format!("/api/admin/hosts/{}/labels", target.id);
+        let request = testing::request(Method::GET, &uri, Some(&token), None);
+        let (status, body) = testing::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({ "rack": "a1" }));
+
+        let source = Host::find_by_id(source.id).one(db).await.unwrap().unwrap();
+        assert!(source.deleted_at.is_some());
+        assert_eq!(source.merged_into, Some(target.id));
+    }
+
+    #[tokio::test]
+    async fn reports_of_merged_hosts_go_to_the_target() {
+        let state = testing::state(&[]).await;
+        let token = testing::admin(&state).await;
+        let router = testing::router(&state);
+        let target = testing::host(&state, "target").await;
+        let source = testing::host(&state, "source").await;
+
+        let uri = format!("/api/admin/hosts/{}/merge", target.id);
+        let body = json!({ "source": source.id });
+        let request = testing::request(Method::POST, &uri, Some(&token), Some(body));
+        assert_eq!(testing::send(&router, request).await.0, StatusCode::OK);
+
+        let os = json!([{ "EvtOsEmit": { "family": "linux" } }]);
+        let (status, _) = testing::report(&state, "source", None, os).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let db = state.database.as_ref();
+        let hosts = Host::find()
+            .filter(host::Column::MachineId.eq("source"))
+            .all(db)
+            .await
+            .unwrap();
+        assert_eq!(
+            hosts.len(),
+            1,
+            "no host is created for the merged machine id"
+        );
+        assert!(hosts[0].deleted_at.is_some());
+
+        let target = Host::find_by_id(target.id).one(db).await.unwrap().unwrap();
+        assert_eq!(target.os_family, "linux");
+    }
+
+    #[tokio::test]
+    async fn webhook_test_reports_delivery() {
+        let (url, mut events) = testing::webhook_receiver().await;
+        let state = testing::state(&["--webhook-url", &url]).await;
+        let token = testing::admin(&state).await;
+
+        let uri = "/api/admin/webhook/test";
+        let request = testing::request(Method::POST, uri, Some(&token), None);
+        let (status, body) = testing::send(&testing::router(&state), request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], 200);
+        assert!(body["latency_ms"].is_u64());
+        assert!(body.get("error").is_none_or(|error| error.is_null()));
+
+        let event = events.recv().await.unwrap();
+        assert_eq!(event["change"], "test");
+    }
+
+    #[tokio::test]
+    async fn webhook_test_reports_failure() {
+        // nothing listens on the port once the listener is dropped
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        drop(listener);
+        let state = testing::state(&["--webhook-url", &url]).await;
+        let token = testing::admin(&state).await;
+
+        let uri = "/api/admin/webhook/test";
+        let request = testing::request(Method::POST, uri, Some(&token), None);
+        let (status, body) = testing::send(&testing::router(&state), request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body["status"].is_null());
+        assert!(body["error"].is_string());
+    }
+
+    #[tokio::test]
+    async fn latency_percentiles_after_events() {
+        let state = testing::state(&[]).await;
+        let token = testing::admin(&state).await;
+        let router = testing::router(&state);
+        let request =
+            || testing::request(Method::GET, "/api/admin/stats/latency", Some(&token), None);
+
+        let (status, body) = testing::send(&router, request()).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["count"], 0);
+        assert!(body["p50_ms"].is_null());
+
+        let events = json!([
+            { "EvtOsEmit": { "family": "linux" } },
+            { "EvtMetricsEmit": [10.0, 1, 2, 3, 4] },
+        ]);
+        testing::report(&state, "m1", None, events).await;
+
+        let (_, body) = testing::send(&router, request()).await;
+        assert_eq!(body["count"], 2);
+        for percentile in ["p50_ms", "p95_ms", "p99_ms"] {
+            assert!(body[percentile].as_f64().is_some(), "{}", body);
+        }
+
+        let request = testing::request(Method::GET, "/metrics", None, None);
+        let (_, body) = testing::send(&router, request).await;
+        assert!(body
+            .as_str()
+            .unwrap()
+            .contains("wk_event_latency_seconds{quantile=\"0.99\"}"));
+    }
+
+    #[tokio::test]
+    async fn refresh_config_reaches_every_agent() {
+        let state = testing::state(&[]).await;
+        let token = testing::admin(&state).await;
+        let addr = testing::serve(&state).await;
+
+        let mut agents = Vec::new();
+        for machine_id in ["m1", "m2"] {
+            let url = format!("ws://{}/api/agent/{}/report", addr, machine_id);
+            let (mut ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+            // answered once the connection is registered
+            ws.send(Message::Ping("registered".into())).await.unwrap();
+            assert!(ws.next().await.unwrap().unwrap().is_pong());
+            agents.push(ws);
+        }
+
+        let uri = "/api/admin/agents/refresh-config";
+        let request = testing::request(Method::POST, uri, Some(&token), None);
+        let (status, body) = testing::send(&testing::router(&state), request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body, json!({ "notified": 2 }));
+
+        for mut ws in agents {
+            let message = tokio::time::timeout(Duration::from_secs(5), ws.next())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            let command =
+                serde_json::from_slice::<serde_json::Value>(&message.into_data()).unwrap();
+            assert_eq!(command, json!({ "command": "refresh_config" }));
+        }
+    }
+
+    #[tokio::test]
+    async fn new_log_lines_are_streamed() {
+        let state = testing::state(&[]).await;
+        let token = testing::admin(&state).await;
+        let router = testing::router(&state);
+        let stream = |token| {
+            let mut request = testing::request(Method::GET, "/api/admin/logs/stream", token, None);
+            request
+                .headers_mut()
+                .insert(header::ACCEPT, "text/event-stream".parse().unwrap());
+            request
+        };
+
+        assert_eq!(
+            testing::send(&router, stream(None)).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+
+        state.logs.push("buffered line".to_owned());
+        let response = router.oneshot(stream(Some(&token))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let mut body = response.into_body().into_data_stream();
+        state.logs.push("new line".to_owned());
+
+        let mut received = String::new();
+        while !received.contains("data: new line") {
+            let chunk = tokio::time::timeout(Duration::from_secs(5), body.next())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            received.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+        assert!(received.contains("data: buffered line"), "{}", received);
+    }
+
+    #[tokio::test]
+    async fn schema_has_no_pending_migrations() {
+        let state = testing::state(&[]).await;
+        let token = testing::admin(&state).await;
+
+        let request = testing::request(Method::GET, "/api/admin/schema", Some(&token), None);
+        let (status, body) = testing::send(&testing::router(&state), request).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let applied = body["applied"].as_array().unwrap();
+        assert_eq!(applied.len(), Migrator::migrations().len());
+        assert_eq!(body["pending"], json!([]));
+    }
+
+    #[tokio::test]
+    async fn import_reports_duplicates() {
+        let state = testing::state(&[]).await;
+        let token = testing::admin(&state).await;
+        testing::host(&state, "known").await;
+
+        let hosts = json!([
+            { "machine_id": "m1", "os_family": "linux" },
+            { "machine_id": "known" },
+            { "machine_id": "m2", "machine_ip": "192.0.2.1" },
+        ]);
+        let uri = "/api/admin/hosts/import";
+        let request = testing::request(Method::POST, uri, Some(&token), Some(hosts));
+        let (status, body) = testing::send(&testing::router(&state), request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["imported"], 2);
+        assert_eq!(body["conflicts"], 1);
+        assert_eq!(body["invalid"], 0);
+        assert_eq!(body["rows"][1]["status"], "conflict");
+
+        let hosts = Host::find().count(state.database.as_ref()).await.unwrap();
+        assert_eq!(hosts, 3);
+    }
+
+    #[tokio::test]
+    async fn backup_writes_sqlite_file() {
+        // an in-memory database would back up into memory as well
+        let dir = tempfile::tempdir().unwrap();
+        let url = format!("sqlite://{}?mode=rwc", dir.path().join("data.db").display());
+        let state = testing::state_on(&url, &["--data-dir", dir.path().to_str().unwrap()]).await;
+        let token = testing::admin(&state).await;
+
+        let request = testing::request(Method::POST, "/api/admin/backup", Some(&token), None);
+        let (status, body) = testing::send(&testing::router(&state), request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        let path = std::path::Path::new(body["path"].as_str().unwrap());
+        assert!(path.starts_with(dir.path().join("backups")));
+        let content = std::fs::read(path).unwrap();
+        assert!(content.starts_with(b"SQLite format 3\0"));
+    }
+}
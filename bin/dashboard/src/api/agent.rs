@@ -1,30 +1,201 @@
+use crate::api::dto;
+use crate::api::params::MachineId;
+use crate::args::ReportOverflow;
+use crate::args::WsFrameFormat;
+use crate::middlewares::bearer_token;
 use crate::prelude::axum::*;
 use crate::state::AppState;
+use crate::state::ClientGuard;
+use crate::state::PooledEvent;
 use anyhow::anyhow;
+use axum::body::Body;
+use axum::extract::rejection::ExtensionRejection;
+use axum::extract::ws::close_code;
+use axum::extract::ws::rejection::WebSocketUpgradeRejection;
+use axum::extract::ws::CloseFrame;
 use axum::extract::ws::Message;
 use axum::extract::ws::WebSocket;
+use axum::extract::Query;
 use axum::extract::WebSocketUpgrade;
+use axum::http::HeaderMap;
+use axum::http::StatusCode;
 use axum::response::IntoResponse;
+use axum::response::Response;
+use axum::Extension;
 use axum::Json;
+use proto::agent::CapabilitiesResp;
+use proto::agent::ConfigReq;
 use proto::agent::Events;
+use proto::agent::UploadCreateReq;
+use proto::agent::UploadCreateResp;
+use proto::agent::UploadResp;
+use proto::agent::WhoamiResp;
+use proto::agent::EVENT_SCHEMA_VERSIONS;
+use sea_orm::TransactionTrait;
 use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
 use tokio::sync::mpsc;
 
+/// Number of events queued to an eventbus worker before senders have to wait.
+const EVENTBUS_WORKER_QUEUE: usize = 1024;
+
+/// Maximum number of merges followed to find the host an agent reports as.
+const MERGE_HOPS_MAX: usize = 16;
+
 /// Finds the host with the given `machine_id` in the database and returns its
 /// configuration. If the host does not exist, creates a new host with the given
 /// `machine_id` and returns its configuration.
 ///
+/// On first contact, an agent can pass a one-time `enrollment_token` query parameter.
+/// The enrollment token is consumed and a new agent token, returned as `token`, is
+/// bound to the host. Once a host is bound, every agent request must carry the agent
+/// token as `Authorization: Bearer <token>`. The agent token is verified before the
+/// host is created, restored or marked as seen.
+///
+/// An agent should pass its version as `agent_version` query parameter, which is
+/// recorded on the host for rollout tracking.
+///
+/// The request is validated before any change is made, and all changes are committed
+/// in one transaction, so an enrollment token is only consumed along with a response
+/// carrying the agent token.
+///
 /// # Errors
 ///
-/// Returns an error if database operations fail.
+/// Returns `400 Bad Request` if the agent version is longer than 32 characters,
+/// `401 Unauthorized` if the enrollment or agent token is invalid, or an error if
+/// database operations fail.
 pub async fn config(
     State(state): State<Arc<AppState>>,
-    Path(machine_id): Path<String>,
+    machine_id: MachineId,
+    Query(query): Query<ConfigReq>,
+    headers: HeaderMap,
 ) -> Result<Json<proto::agent::Config>, AxumError> {
-    // find or create target host
-    _ = internal::upsert_host_with_machine_id(&state, &machine_id).await?;
+    if let Some(version) = &query.agent_version {
+        internal::check_agent_version(version)?;
+    }
+
+    // authenticate against the host as stored, before it is created, restored or seen
+    let found = internal::find_host_to_upsert(&state, &machine_id).await?;
+    let bound = found
+        .as_ref()
+        .is_some_and(|found| found.agent_token.is_some());
+    let enrollment = match query.enrollment_token {
+        Some(enrollment) if !bound => {
+            internal::check_enrollment(&state, state.database.as_ref(), &enrollment).await?;
+            Some(enrollment)
+        }
+        _ => {
+            if let Some(found) = &found {
+                internal::verify_agent_token(&state, found, bearer_token(&headers))?;
+            }
+            None
+        }
+    };
+
+    // find or create target host, then enroll it if asked to
+    let came_online = internal::came_online(&state, found.as_ref());
+    let txn = state.database.begin().await?;
+    let target = internal::upsert_host(&txn, &machine_id, found).await?;
+    let token = match enrollment {
+        Some(enrollment) => Some(internal::enroll(&state, &txn, &target, &enrollment).await?),
+        None => {
+            let token = bearer_token(&headers);
+            internal::upgrade_agent_token(&state, &txn, &target, token).await?;
+            None
+        }
+    };
+
+    // keep track of the running agent version
+    if let Some(version) = &query.agent_version {
+        internal::record_agent_version(&txn, &target, version).await?;
+    }
+
+    // deliver commands queued while the host was away
+    let pending = internal::pending_commands(&txn, &target).await?;
+    internal::mark_commands_delivered(&txn, pending.iter().map(|c| c.id)).await?;
+    let commands = pending.iter().map(dto::command_value).collect();
+
+    txn.commit().await?;
+
+    if came_online {
+        internal::notify_online(&state, &target);
+    }
+
+    Ok(Json(proto::agent::Config { token, commands }))
+}
+
+/// Checks the agent token of the host with the given `machine_id`, without side effects.
+///
+/// Unlike `config`, the host is neither created, restored nor marked as seen, so an
+/// agent can check its stored token before it starts reporting.
+///
+/// The response is a JSON object with the following fields:
+///
+/// - `id`: The host id.
+/// - `machine_id`: The machine id of the host.
+/// - `display_name`: The display name of the host, if set.
+///
+/// # Errors
+///
+/// Returns `401 Unauthorized` if the host does not exist, was deleted or has no bound
+/// agent token, or if the agent token is missing or invalid.
+pub async fn whoami(
+    State(state): State<Arc<AppState>>,
+    machine_id: MachineId,
+    headers: HeaderMap,
+) -> Result<Json<WhoamiResp>, AxumError> {
+    let invalid = || {
+        StatusError::new(
+            StatusCode::UNAUTHORIZED,
+            "agent_token_invalid",
+            "missing or invalid agent token",
+        )
+    };
+
+    // only a bound token can be valid
+    let target = internal::find_host_with_machine_id(&state, &machine_id)
+        .await?
+        .filter(|target| target.agent_token.is_some())
+        .ok_or_else(invalid)?;
+    internal::verify_agent_token(&state, &target, bearer_token(&headers))?;
+
+    Ok(Json(WhoamiResp {
+        id: target.id.to_string(),
+        machine_id: target.machine_id,
+        display_name: target.display_name,
+    }))
+}
 
-    Ok(Json(proto::agent::Config {}))
+/// Maximum size in bytes of a report request body.
+pub const MAX_REPORT_SIZE: usize = 2 * 1024 * 1024;
+
+/// Describes what the server accepts from agents, so they can adapt before reporting.
+///
+/// The response is a JSON object with the following fields:
+///
+/// - `schema_versions`: The supported event schema versions, the current one last.
+/// - `event_types`: The accepted event types.
+/// - `max_report_size`: The maximum size in bytes of a report request body.
+/// - `max_report_events`: The maximum number of events in a report.
+/// - `max_log_size`: The maximum size in bytes of an uploaded log snippet.
+/// - `max_upload_size`: The maximum size in bytes of a file uploaded through an upload URL.
+/// - `ws_frame_format`: The frame type of messages sent over websocket (`text` or `binary`).
+pub async fn capabilities(State(state): State<Arc<AppState>>) -> Json<CapabilitiesResp> {
+    let ws_frame_format = match state.args.ws_frame_format {
+        WsFrameFormat::Text => "text",
+        WsFrameFormat::Binary => "binary",
+    };
+
+    Json(CapabilitiesResp {
+        schema_versions: EVENT_SCHEMA_VERSIONS.to_vec(),
+        event_types: Events::TYPES.iter().map(|&t| t.to_owned()).collect(),
+        max_report_size: MAX_REPORT_SIZE,
+        max_report_events: state.args.max_events_per_report,
+        max_log_size: state.args.max_agent_log_size,
+        max_upload_size: state.args.max_upload_size,
+        ws_frame_format: ws_frame_format.to_owned(),
+    })
 }
 
 /// Handles a report request for the given `machine_id`.
@@ -32,36 +203,215 @@ pub async fn config(
 /// This function processes incoming JSON data representing a list of events
 /// and sends each event to the eventbus associated with the specified
 /// `machine_id`. The eventbus is created or retrieved via an internal
-/// function. If an event cannot be deserialized, it is skipped.
+/// function. If an event cannot be deserialized, it is skipped and its error is
+/// recorded as the last error of the host.
+///
+/// A report may hold at most `--max-events-per-report` events. Larger reports are
+/// rejected, or with `--report-overflow truncate` cut to the limit with a warning.
 ///
 /// # Errors
 ///
-/// Returns an error if the eventbus cannot be created or if sending an event
-/// to the eventbus fails.
-
+/// Returns `400 Bad Request` if the report holds too many events and is rejected, or
+/// an error if the agent token is invalid, the eventbus cannot be created or if
+/// sending an event to the eventbus fails.
 pub async fn report(
     State(state): State<Arc<AppState>>,
-    Path(machine_id): Path<String>,
-    Json(values): Json<Vec<serde_json::Value>>,
+    machine_id: MachineId,
+    headers: HeaderMap,
+    Json(mut values): Json<Vec<serde_json::Value>>,
 ) -> Result<(), AxumError> {
+    // limit the work a single report can cause
+    let max = state.args.max_events_per_report;
+    if values.len() > max {
+        match state.args.report_overflow {
+            ReportOverflow::Reject => {
+                return Err(StatusError::new(
+                    StatusCode::BAD_REQUEST,
+                    "too_many_events",
+                    format!(
+                        "report has {} events, at most {} accepted",
+                        values.len(),
+                        max
+                    ),
+                )
+                .into());
+            }
+            ReportOverflow::Truncate => {
+                tracing::warn!(
+                    "report of {} has {} events, dropped all after the first {}",
+                    machine_id,
+                    values.len(),
+                    max
+                );
+                values.truncate(max);
+            }
+        }
+    }
+
     // create event pipeline
-    let tx = internal::eventbus_with_machine_id(state, &machine_id).await?;
+    let (target, tx) =
+        internal::eventbus_with_machine_id(state.clone(), &machine_id, bearer_token(&headers))
+            .await?;
 
     // dispatch all events
+    let mut malformed = None;
     for value in values {
         match serde_json::from_value(value) {
             Ok(event) => {
-                tx.send(event).await?;
+                tx.send(Instant::now(), event).await?;
             }
             Err(err) => {
                 tracing::warn!("deserialize event failed: {}", err);
+                malformed = Some(err.to_string());
             }
         }
     }
 
+    // remember the last malformed event of the report
+    if let Some(error) = malformed {
+        internal::record_error(&state, target.id, &error).await;
+    }
+
+    Ok(())
+}
+
+/// Stores a diagnostic log snippet uploaded by the agent with the given `machine_id`.
+///
+/// The request body is the UTF-8 log text. Only the latest `--agent-logs-kept`
+/// snippets are kept per host, older ones are deleted.
+///
+/// # Errors
+///
+/// Returns `413 Payload Too Large` if the snippet exceeds `--max-agent-log-size`, or
+/// an error if the agent token is invalid or database operations fail.
+pub async fn logs(
+    State(state): State<Arc<AppState>>,
+    machine_id: MachineId,
+    headers: HeaderMap,
+    content: String,
+) -> Result<(), AxumError> {
+    if content.len() > state.args.max_agent_log_size {
+        return Err(StatusError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "log_too_large",
+            format!(
+                "log snippet must not be larger than {} bytes",
+                state.args.max_agent_log_size
+            ),
+        )
+        .into());
+    }
+
+    let target =
+        internal::upsert_verified_host(&state, &machine_id, bearer_token(&headers)).await?;
+    internal::store_log(&state, &target, content).await?;
+
     Ok(())
 }
 
+/// Issues a short-lived URL the agent with the given `machine_id` can upload a file to.
+///
+/// This endpoint takes a JSON object with the following fields:
+///
+/// - `purpose`: What the file is for (e.g. `diagnostics`), 1 to 32 characters, each an
+///   ASCII lowercase letter, digit, `-` or `_`.
+///
+/// The URL is relative to the server. It carries the host, the purpose and the expiry,
+/// signed with a key derived from the token key (`--token-pepper` or the generated
+/// `token.key`), so rotating `--secret` does not invalidate it. It stays valid for
+/// `--upload-url-ttl`, so uploading through it needs no long-lived credential.
+///
+/// The response is a JSON object with the following fields:
+///
+/// - `url`: The URL to `PUT` the file to.
+/// - `expired_at`: The RFC 3339 time the URL expires.
+///
+/// # Errors
+///
+/// Returns `400 Bad Request` if the purpose is invalid, or an error if the agent token
+/// is invalid.
+pub async fn upload_create(
+    State(state): State<Arc<AppState>>,
+    machine_id: MachineId,
+    headers: HeaderMap,
+    Json(body): Json<UploadCreateReq>,
+) -> Result<Json<UploadCreateResp>, AxumError> {
+    let valid = (1..=32).contains(&body.purpose.len())
+        && body
+            .purpose
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || matches!(b, b'-' | b'_'));
+    if !valid {
+        return Err(StatusError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_purpose",
+            format!("invalid upload purpose {:?}", body.purpose),
+        )
+        .into());
+    }
+
+    let target =
+        internal::upsert_verified_host(&state, &machine_id, bearer_token(&headers)).await?;
+
+    let expired_at =
+        chrono::Utc::now() + chrono::Duration::seconds(state.args.upload_url_ttl as i64);
+    let token = internal::upload_token(&state, target.id, &body.purpose, expired_at.timestamp());
+
+    Ok(Json(UploadCreateResp {
+        url: format!("/api/agent/uploads/{}", token),
+        expired_at: expired_at.to_rfc3339(),
+    }))
+}
+
+/// Stores a file uploaded through a URL issued by `upload_create`.
+///
+/// The request body is the file content, at most `--max-upload-size` bytes. The file
+/// is stored as `<data-dir>/uploads/<host id>/<purpose>-<timestamp>.bin`.
+///
+/// The response is a JSON object with the following fields:
+///
+/// - `name`: The name of the stored file.
+/// - `size`: The size of the stored file in bytes.
+///
+/// # Errors
+///
+/// Returns `401 Unauthorized` if the URL is forged or expired or its host was deleted,
+/// or `413 Payload Too Large` if the file is too large.
+pub async fn upload(
+    State(state): State<Arc<AppState>>,
+    Path(token): Path<String>,
+    body: Body,
+) -> Result<(StatusCode, Json<UploadResp>), AxumError> {
+    // check the URL before receiving the body
+    let (target, purpose) = internal::upload_token_verify(&state, &token).await?;
+
+    let limit = state.args.max_upload_size;
+    let content = axum::body::to_bytes(body, limit).await.map_err(|err| {
+        match std::error::Error::source(&err) {
+            Some(source) if source.is::<http_body_util::LengthLimitError>() => StatusError::new(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "upload_too_large",
+                format!("upload must not be larger than {} bytes", limit),
+            ),
+            _ => StatusError::new(
+                StatusCode::BAD_REQUEST,
+                "invalid_body",
+                format!("request body cannot be read: {}", err),
+            ),
+        }
+    })?;
+
+    let name = internal::store_upload(&state, &target, &purpose, &content).await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(UploadResp {
+            name,
+            size: content.len(),
+        }),
+    ))
+}
+
 /// Handles a WebSocket connection for the given `machine_id`.
 ///
 /// This function upgrades an HTTP request to a WebSocket connection,
@@ -69,23 +419,133 @@ pub async fn report(
 /// WebSocket messages. Each message received is processed by the `handler`
 /// function. If the handler encounters an error, the connection is
 /// terminated.
+///
+/// Commands queued for the host are sent right after the connection is
+/// established. The connection is registered in `AppState::connections`, so an
+/// administrator can close it at any time.
+///
+/// Malformed events are skipped and recorded as the last error of the host, but after
+/// `--ws-max-bad-frames` consecutive ones the connection is closed with a policy
+/// violation, so a broken agent cannot keep the server busy. A well-formed event
+/// resets the count.
+///
+/// The connection counts against `--max-connections-per-ip` until it is closed.
+///
+/// # Errors
+///
+/// Returns `426 Upgrade Required` if the request does not ask for a websocket, or
+/// `400 Bad Request` if its websocket headers are invalid.
 pub async fn websocket(
     State(state): State<Arc<AppState>>,
-    Path(machine_id): Path<String>,
-    upgrade: WebSocketUpgrade,
-) -> Result<impl IntoResponse, AxumError> {
+    machine_id: MachineId,
+    headers: HeaderMap,
+    client: Result<Extension<Arc<ClientGuard>>, ExtensionRejection>,
+    upgrade: Result<WebSocketUpgrade, WebSocketUpgradeRejection>,
+) -> Result<Response, AxumError> {
+    let upgrade = match upgrade {
+        Ok(upgrade) => upgrade,
+        Err(rejection) => return Ok(internal::upgrade_rejected(&machine_id, rejection)),
+    };
+
+    let lifetime = state
+        .args
+        .ws_max_lifetime
+        .map(|secs| internal::jittered(Duration::from_secs(secs)));
+
     // create event pipeline
-    let tx = internal::eventbus_with_machine_id(state, &machine_id).await?;
-
-    Ok(upgrade.on_upgrade(move |mut ws| async move {
-        // translate websocket message
-        while let Some(Ok(message)) = ws.recv().await {
-            if let Err(_) = handler(message, &mut ws, &tx).await {
-                // something went wrong, disconnect connection
-                break;
+    let (target, tx) =
+        internal::eventbus_with_machine_id(state.clone(), &machine_id, bearer_token(&headers))
+            .await?;
+
+    Ok(upgrade
+        .on_upgrade(move |mut ws| async move {
+            // hold the client slot while the connection is open
+            let _client = client.ok();
+
+            // make the connection reachable for administrative disconnects
+            let (connection, mut messages) = state.connections.register(target.id);
+
+            // deliver commands queued while the host was away
+            if let Err(err) = internal::deliver_commands(&state, &target, &mut ws).await {
+                tracing::warn!("deliver commands failed: {}", err);
+                return;
             }
-        }
-    }))
+
+            let expired = async {
+                match lifetime {
+                    Some(lifetime) => tokio::time::sleep(lifetime).await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::pin!(expired);
+
+            let max_bad_frames = state.args.ws_max_bad_frames;
+            let mut bad_frames = 0;
+            let (frame, drain) = loop {
+                tokio::select! {
+                    // translate websocket message
+                    message = ws.recv() => match message {
+                        Some(Ok(message)) => {
+                            match handler(message, &mut ws, &tx, &mut bad_frames).await {
+                                Ok(Some(error)) => {
+                                    internal::record_error(&state, target.id, &error).await;
+                                }
+                                Ok(None) => {}
+                                // something went wrong, disconnect connection
+                                Err(_) => return,
+                            }
+                            if max_bad_frames > 0 && bad_frames >= max_bad_frames {
+                                tracing::warn!(
+                                    "closing websocket of {} after {} malformed events",
+                                    target.machine_id,
+                                    bad_frames
+                                );
+                                break (CloseFrame {
+                                    code: close_code::POLICY,
+                                    reason: "too many malformed events".into(),
+                                }, false);
+                            }
+                        }
+                        _ => return,
+                    },
+                    // forward messages broadcast to all agents
+                    Some(text) = messages.recv() => {
+                        if ws.send(internal::frame(&state, text)).await.is_err() {
+                            return;
+                        }
+                    }
+                    // lifetime reached, ask the agent to reconnect
+                    _ = &mut expired => break (CloseFrame {
+                        code: close_code::NORMAL,
+                        reason: "max lifetime reached".into(),
+                    }, true),
+                    // server shutting down, release the eventbus so it can drain
+                    _ = state.eventbus.shutdown.cancelled() => break (CloseFrame {
+                        code: close_code::AWAY,
+                        reason: "server shutting down".into(),
+                    }, true),
+                    // disconnected by an administrator, drop the connection right away
+                    _ = connection.disconnected() => break (CloseFrame {
+                        code: close_code::POLICY,
+                        reason: "disconnected by administrator".into(),
+                    }, false),
+                }
+            };
+
+            // ask the agent to disconnect, events sent until it acknowledges are still handled
+            if ws.send(Message::Close(Some(frame))).await.is_ok() && drain {
+                while let Some(Ok(message)) = ws.recv().await {
+                    match handler(message, &mut ws, &tx, &mut bad_frames).await {
+                        Ok(Some(error)) => {
+                            internal::record_error(&state, target.id, &error).await;
+                        }
+                        Ok(None) => {}
+                        Err(_) => break,
+                    }
+                }
+            }
+        })
+        .into_response())
 }
 
 /// Handle an incoming websocket message.
@@ -93,24 +553,32 @@ pub async fn websocket(
 /// This function translates the message into an `Events` and sends it to the
 /// eventbus. If the message is a close message, it returns an error.
 ///
+/// `bad_frames` counts the consecutive messages which could not be deserialized, it
+/// is reset by every event that could. The deserialize error of such a message is
+/// returned, so it can be recorded on the host.
+///
 /// # Errors
 ///
 /// Returns an error if the message is a close message or something went wrong.
 async fn handler(
     message: Message,
     ws: &mut WebSocket,
-    tx: &mpsc::Sender<Events>,
-) -> Result<(), anyhow::Error> {
+    tx: &internal::EventbusSender,
+    bad_frames: &mut u32,
+) -> Result<Option<String>, anyhow::Error> {
     match message {
         Message::Text(text) => {
             tracing::trace!("received text");
 
             match serde_json::from_slice(text.as_bytes()) {
                 Ok(event) => {
-                    tx.send(event).await?;
+                    *bad_frames = 0;
+                    tx.send(Instant::now(), event).await?;
                 }
                 Err(err) => {
+                    *bad_frames += 1;
                     tracing::warn!("deserialize event failed: {}", err);
+                    return Ok(Some(err.to_string()));
                 }
             }
         }
@@ -119,10 +587,13 @@ async fn handler(
 
             match serde_json::from_slice(&data) {
                 Ok(event) => {
-                    tx.send(event).await?;
+                    *bad_frames = 0;
+                    tx.send(Instant::now(), event).await?;
                 }
                 Err(err) => {
+                    *bad_frames += 1;
                     tracing::warn!("deserialize event failed: {}", err);
+                    return Ok(Some(err.to_string()));
                 }
             }
         }
@@ -138,34 +609,240 @@ async fn handler(
         }
         _ => {}
     }
-    Ok(())
+    Ok(None)
+}
+
+/// Starts the worker pool of `--eventbus-workers`, if configured.
+///
+/// Every host is assigned to one worker by its ID, and each worker applies its events
+/// one by one, so the events of a host are applied in the order they were received.
+/// On shutdown, workers apply the events already queued before they stop.
+pub fn spawn_eventbus_workers(state: Arc<AppState>) {
+    let Some(count) = state.args.eventbus_workers.filter(|&count| count > 0) else {
+        return;
+    };
+
+    let mut workers = Vec::with_capacity(count);
+    for _ in 0..count {
+        let (tx, mut rx) = mpsc::channel::<PooledEvent>(EVENTBUS_WORKER_QUEUE);
+        workers.push(tx);
+
+        state.eventbus.tasks.spawn({
+            let state = state.clone();
+
+            async move {
+                loop {
+                    tokio::select! {
+                        biased;
+                        queued = rx.recv() => {
+                            let Some((target, received, event)) = queued else {
+                                break;
+                            };
+                            internal::eventbus_receive(&state, &target, received, event).await;
+                        }
+                        // refuse new events, the queued ones are still received above
+                        _ = state.eventbus.shutdown.cancelled() => rx.close(),
+                    }
+                }
+            }
+        });
+    }
+
+    _ = state.eventbus.workers.set(workers);
+    tracing::info!("started {} eventbus workers", count);
 }
 
 mod internal {
+    use super::MERGE_HOPS_MAX;
+    use crate::api::dto;
+    use crate::args::WsFrameFormat;
+    use crate::prelude::axum::StatusError;
     use crate::prelude::seaorm::*;
     use crate::state::AppState;
+    use crate::state::PooledEvent;
+    use crate::webhook::Webhook;
     use anyhow::Result;
+    use argon2::password_hash::rand_core::OsRng;
+    use argon2::password_hash::rand_core::RngCore;
+    use axum::extract::ws::rejection::WebSocketUpgradeRejection;
+    use axum::extract::ws::Message;
+    use axum::extract::ws::WebSocket;
+    use axum::http::header;
+    use axum::http::StatusCode;
+    use axum::response::IntoResponse;
+    use axum::response::Response;
     use proto::agent::Events;
+    use proto::agent::EvtHardwareEmit;
     use proto::agent::EvtMachineEmit;
+    use proto::agent::EvtMetricsEmit;
     use proto::agent::EvtOsEmit;
+    use proto::webhook::WebhookChange;
+    use sea_orm::ConnectionTrait;
     use sea_orm::IntoActiveValue;
+    use sea_orm::QuerySelect;
+    use sea_orm::TransactionTrait;
+    use std::hash::DefaultHasher;
+    use std::hash::Hash;
+    use std::hash::Hasher;
+    use std::net::IpAddr;
+    use std::sync::atomic::Ordering;
     use std::sync::Arc;
+    use std::time::Duration;
+    use std::time::Instant;
     use tokio::sync::mpsc;
+    use tokio::sync::OwnedSemaphorePermit;
+
+    /// Finds the active host with the given `machine_id` in the database, without side
+    /// effects. Soft-deleted hosts are not returned.
+    pub async fn find_host_with_machine_id(
+        state: &AppState,
+        machine_id: &str,
+    ) -> anyhow::Result<Option<host::Model>> {
+        let target = Host::find()
+            .filter(host::Column::MachineId.eq(machine_id))
+            .filter(host::Column::DeletedAt.is_null())
+            .one(state.database.as_ref())
+            .await?;
+
+        Ok(target)
+    }
+
+    /// Finds the host an agent with the given `machine_id` reports as, without side effects.
+    ///
+    /// This is the active host with the given `machine_id`, or else a soft-deleted one,
+    /// which `upsert_host_with_machine_id` restores. Hosts merged into another host are
+    /// never restored, the agent reports as the host it was merged into instead, and
+    /// needs the token of that host if it is bound. Agent tokens are verified against
+    /// the returned host before it is upserted, so a request with an invalid token
+    /// neither restores a host nor marks it as seen.
+    pub async fn find_host_to_upsert(
+        state: &AppState,
+        machine_id: &str,
+    ) -> anyhow::Result<Option<host::Model>> {
+        if let Some(exists) = find_host_with_machine_id(state, machine_id).await? {
+            return Ok(Some(exists));
+        }
+
+        let deleted = Host::find()
+            .filter(host::Column::MachineId.eq(machine_id))
+            .filter(host::Column::MergedInto.is_null())
+            .one(state.database.as_ref())
+            .await?;
+        if deleted.is_some() {
+            return Ok(deleted);
+        }
+
+        let merged = Host::find()
+            .filter(host::Column::MachineId.eq(machine_id))
+            .one(state.database.as_ref())
+            .await?;
+
+        // follow the merges, hosts are merged into active hosts only, so merges do not
+        // form a cycle, but rows edited by hand might
+        let mut found = merged;
+        for _ in 0..MERGE_HOPS_MAX {
+            let Some(merged_into) = found.as_ref().and_then(|found| found.merged_into) else {
+                return Ok(found);
+            };
+            found = Host::find_by_id(merged_into)
+                .one(state.database.as_ref())
+                .await?;
+        }
+
+        Err(anyhow::anyhow!(
+            "host with machine id {} is merged more than {} times",
+            machine_id,
+            MERGE_HOPS_MAX
+        ))
+    }
+
+    /// Verifies the agent `token` against the host of the given `machine_id`, then
+    /// upserts the host with `upsert_host_with_machine_id`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StatusError` if the host is bound and the token is missing or wrong,
+    /// or an error if database operations fail.
+    pub async fn upsert_verified_host(
+        state: &AppState,
+        machine_id: &str,
+        token: Option<&str>,
+    ) -> Result<host::Model> {
+        let found = find_host_to_upsert(state, machine_id).await?;
+        if let Some(found) = &found {
+            verify_agent_token(state, found, token)?;
+        }
+
+        let target = upsert_host_with_machine_id(state, machine_id, found).await?;
+        upgrade_agent_token(state, state.database.as_ref(), &target, token).await?;
+
+        Ok(target)
+    }
 
     /// Finds the host with the given `machine_id` in the database and returns it. If the host
-    /// does not exist, creates a new host with the given `machine_id` and returns it.
+    /// does not exist, creates a new host with the given `machine_id` and returns it. The
+    /// host must have been looked up with `find_host_to_upsert`, passed as `found`.
+    ///
+    /// A soft-deleted host is restored if no active host with the given `machine_id` exists.
+    /// In any case, the `last_seen` timestamp of the host is refreshed, and the webhook (if
+    /// configured) is notified if the host was offline before.
     pub async fn upsert_host_with_machine_id(
         state: &AppState,
         machine_id: &str,
+        found: Option<host::Model>,
     ) -> anyhow::Result<host::Model> {
-        let exists = Host::find()
-            .filter(host::Column::MachineId.eq(machine_id))
-            .one(state.database.as_ref())
+        let came_online = came_online(state, found.as_ref());
+        let target = upsert_host(state.database.as_ref(), machine_id, found).await?;
+        if came_online {
+            notify_online(state, &target);
+        }
+
+        Ok(target)
+    }
+
+    /// Tells whether the host `found` by `find_host_to_upsert` comes online by being
+    /// upserted. A new or restored host was offline too.
+    pub fn came_online(state: &AppState, found: Option<&host::Model>) -> bool {
+        let online_since = state.online_since();
+        found.is_none_or(|found| {
+            found.deleted_at.is_some() || found.last_seen.is_none_or(|seen| seen < online_since)
+        })
+    }
+
+    /// Notifies the webhook (if configured) about the host coming online.
+    pub fn notify_online(state: &AppState, target: &host::Model) {
+        if let Some(webhook) = &state.webhook {
+            webhook.notify(Webhook::event(
+                target.id,
+                &target.machine_id,
+                WebhookChange::Online,
+            ));
+        }
+    }
+
+    /// Updates the `found` host or inserts a new one like `upsert_host_with_machine_id`,
+    /// without notifying the webhook.
+    pub async fn upsert_host(
+        db: &impl ConnectionTrait,
+        machine_id: &str,
+        found: Option<host::Model>,
+    ) -> anyhow::Result<host::Model> {
+        let now = chrono::Utc::now();
+
+        if let Some(target) = found {
+            let restored = target.deleted_at.is_some();
+            let target = Host::update(host::ActiveModel {
+                id: Unchanged(target.id),
+                deleted_at: Set(None),
+                last_seen: Set(Some(now)),
+                ..Default::default()
+            })
+            .exec(db)
             .await?;
 
-        if let Some(target) = exists {
             tracing::debug!(
-                "found host with machine id: {} -> {}",
+                "{} host with machine id: {} -> {}",
+                if restored { "restored" } else { "found" },
                 machine_id,
                 target.id
             );
@@ -189,8 +866,19 @@ mod internal {
                 hashed_memory: Set(0),
                 hashed_disk: Set(0),
                 hashed_network: Set(0),
+                deleted_at: Set(None),
+                agent_token: Set(None),
+                last_seen: Set(Some(now)),
+                agent_version: Set(None),
+                os_arch_raw: Set(None),
+                display_name: Set(None),
+                os_virtualization_platform: Set(None),
+                last_error: Set(None),
+                last_error_at: Set(None),
+                maintenance_until: Set(None),
+                merged_into: Set(None),
             })
-            .exec_with_returning(state.database.as_ref())
+            .exec_with_returning(db)
             .await?;
 
             tracing::debug!(
@@ -203,114 +891,1687 @@ mod internal {
         }
     }
 
-    /// Finds the host with the given `machine_id` in the database and returns a mpsc eventbus
-    /// sender which will send events to the host. If the host does not exist, creates a new host
-    /// with the given `machine_id` and returns its eventbus sender.
+    /// Checks that the `enrollment` token exists, is not expired and was not consumed
+    /// before, without consuming it.
     ///
-    /// The eventbus sender returned by this function is connected to an eventbus receiver running
-    /// in a separate task. Any events sent to the sender will be received by the receiver and
-    /// processed.
+    /// Enrollment tokens are stored as peppered hashes, so the token is hashed before
+    /// it is looked up.
     ///
     /// # Errors
     ///
-    /// Returns an error if database operations fail.
-    pub async fn eventbus_with_machine_id(
-        state: Arc<AppState>,
-        machine_id: &str,
-    ) -> Result<mpsc::Sender<proto::agent::Events>> {
-        let target = upsert_host_with_machine_id(&state, machine_id).await?;
-
-        // create tokio channel
-        let (tx, mut rx) = mpsc::channel::<proto::agent::Events>(16);
-        tokio::spawn({
-            let state = state.clone();
+    /// Returns a `StatusError` if the enrollment token is invalid, or an error if database
+    /// operations fail.
+    pub async fn check_enrollment(
+        state: &AppState,
+        db: &impl ConnectionTrait,
+        enrollment: &str,
+    ) -> Result<()> {
+        let now = chrono::Utc::now();
 
-            async move {
-                while let Some(event) = rx.recv().await {
-                    // received event from client
-                    tracing::debug!("received event from {}: {:?}", &target.machine_id, &event);
-
-                    // dispatch to handler
-                    if let Err(err) = eventbus_handler(&state, &target, event).await {
-                        tracing::warn!("eventbus handler failed: {}", err);
-                    };
-                }
-            }
-        });
+        // load enrollment token
+        let found = Enrollment::find()
+            .filter(enrollment::Column::Token.is_in(state.token_hashes(enrollment)))
+            .one(db)
+            .await?;
+        let reason = match &found {
+            None => Some("enrollment token does not exist"),
+            Some(found) if found.consumed_at.is_some() => Some("enrollment token already used"),
+            Some(found) if found.expired_at <= now => Some("enrollment token expired"),
+            Some(_) => None,
+        };
+        if let Some(reason) = reason {
+            return Err(
+                StatusError::new(StatusCode::UNAUTHORIZED, "enrollment_invalid", reason).into(),
+            );
+        }
 
-        Ok(tx)
+        Ok(())
     }
 
-    /// Handles an `Events` enum by dispatching it to the appropriate handler.
+    /// Consumes the `enrollment` token and binds a newly generated agent token to the host.
     ///
-    /// This function takes an `event` of type `Events` and matches it to call
-    /// the corresponding event handler function.
+    /// The enrollment token must pass `check_enrollment`. The agent token is returned to
+    /// the caller once and required for every following agent request.
     ///
     /// # Errors
     ///
-    /// Returns an error if the event handling fails, which could be due to
-    /// database operation errors.
-    async fn eventbus_handler(state: &AppState, target: &host::Model, event: Events) -> Result<()> {
-        match event {
-            Events::EvtMachineEmit(machine) => {
-                eventbus_handle_machine_emit(state, target, machine).await?;
-            }
-            Events::EvtOsEmit(os) => {
-                eventbus_handle_os_emit(state, target, os).await?;
-            }
+    /// Returns a `StatusError` if the enrollment token is invalid, or an error if database
+    /// operations fail.
+    pub async fn enroll(
+        state: &AppState,
+        db: &impl ConnectionTrait,
+        target: &host::Model,
+        enrollment: &str,
+    ) -> Result<String> {
+        check_enrollment(state, db, enrollment).await?;
+
+        // consume enrollment token, guarded against concurrent use
+        let now = chrono::Utc::now();
+        let consumed = Enrollment::update_many()
+            .col_expr(enrollment::Column::ConsumedAt, Expr::value(Some(now)))
+            .col_expr(enrollment::Column::HostId, Expr::value(Some(target.id)))
+            .filter(enrollment::Column::Token.is_in(state.token_hashes(enrollment)))
+            .filter(enrollment::Column::ConsumedAt.is_null())
+            .filter(enrollment::Column::ExpiredAt.gt(now))
+            .exec(db)
+            .await?;
+        if consumed.rows_affected != 1 {
+            return Err(StatusError::new(
+                StatusCode::UNAUTHORIZED,
+                "enrollment_invalid",
+                "enrollment token already used",
+            )
+            .into());
         }
-        Ok(())
+
+        // bind agent token to host, only its peppered hash is stored
+        let token = crate::token::random();
+        Host::update(host::ActiveModel {
+            id: Unchanged(target.id),
+            agent_token: Set(Some(crate::token::hash(&state.pepper, &token))),
+            ..Default::default()
+        })
+        .exec(db)
+        .await?;
+
+        tracing::info!("enrolled host with machine id: {}", target.machine_id);
+
+        Ok(token)
     }
 
-    /// Handles a `EvtMachineEmit` event sent to the eventbus.
+    /// Checks that the agent `version` fits the host record.
+    ///
+    /// # Errors
     ///
-    /// This function updates the `machine_*` fields of the host.
+    /// Returns a `StatusError` if the version is longer than 32 characters.
+    pub fn check_agent_version(version: &str) -> Result<(), StatusError> {
+        if version.len() > 32 {
+            return Err(StatusError::new(
+                StatusCode::BAD_REQUEST,
+                "invalid_agent_version",
+                "agent version must not be longer than 32 characters",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Records the agent `version` running on the host, if it changed. The version must
+    /// pass `check_agent_version`.
     ///
     /// # Errors
     ///
     /// Returns an error if database operations fail.
-    async fn eventbus_handle_machine_emit(
-        state: &AppState,
+    pub async fn record_agent_version(
+        db: &impl ConnectionTrait,
         target: &host::Model,
-        machine: EvtMachineEmit,
+        version: &str,
     ) -> Result<()> {
-        Host::update(host::ActiveModel {
-            id: target.id.into_active_value(),
-            machine_ip: machine.ip.into_active_value(),
-            machine_country: machine.country.into_active_value_(),
-            ..Default::default()
-        })
-        .exec(state.database.as_ref())
-        .await?;
+        if target.agent_version.as_deref() != Some(version) {
+            Host::update(host::ActiveModel {
+                id: Unchanged(target.id),
+                agent_version: Set(Some(version.to_owned())),
+                ..Default::default()
+            })
+            .exec(db)
+            .await?;
+        }
 
         Ok(())
     }
 
-    /// Handles an `EvtOsEmit` event sent to the eventbus.
+    /// Verifies the agent `token` presented for the host.
+    ///
+    /// Hosts without a bound agent token are accepted without a token. The host stores
+    /// the peppered hash of its token, so the presented token is hashed before comparing,
+    /// with any of the peppers of `AppState::token_hashes`.
+    ///
+    /// # Errors
     ///
-    /// This function updates the `os_*` fields of the host.
+    /// Returns a `StatusError` if the host is bound and the token is missing or wrong.
+    pub fn verify_agent_token(
+        state: &AppState,
+        target: &host::Model,
+        token: Option<&str>,
+    ) -> Result<(), StatusError> {
+        let presented = token.map(|v| state.token_hashes(v)).unwrap_or_default();
+        match &target.agent_token {
+            Some(expected) if !presented.contains(expected) => Err(StatusError::new(
+                StatusCode::UNAUTHORIZED,
+                "agent_token_invalid",
+                "missing or invalid agent token",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Stores the hash of the agent `token` with the current pepper, if the host stores
+    /// it with a legacy one. The token must have passed `verify_agent_token`.
     ///
     /// # Errors
     ///
     /// Returns an error if database operations fail.
-    async fn eventbus_handle_os_emit(
+    pub async fn upgrade_agent_token(
         state: &AppState,
+        db: &impl ConnectionTrait,
         target: &host::Model,
-        os: EvtOsEmit,
+        token: Option<&str>,
     ) -> Result<()> {
+        let (Some(stored), Some(token)) = (&target.agent_token, token) else {
+            return Ok(());
+        };
+        let hashes = state.token_hashes(token);
+        if hashes[0] == *stored || !hashes.contains(stored) {
+            return Ok(());
+        }
+
         Host::update(host::ActiveModel {
-            id: target.id.into_active_value(),
-            os_family: os.family.into_active_value(),
-            os_name: os.name.into_active_value_(),
-            os_version: os.version.into_active_value_(),
-            os_arch: os.arch.into_active_value_(),
-            os_build: os.build.into_active_value_(),
-            os_virtualization: os.virtualization.into_active_value_(),
+            id: Unchanged(target.id),
+            agent_token: Set(Some(hashes[0].clone())),
             ..Default::default()
         })
-        .exec(state.database.as_ref())
+        .exec(db)
         .await?;
+        tracing::info!("upgraded agent token of host {}", target.id);
 
         Ok(())
     }
+
+    /// Builds the token of an upload URL, `<host id>.<purpose>.<expiry>.<signature>`.
+    ///
+    /// The expiry is a unix timestamp, the signature covers everything before it.
+    pub fn upload_token(state: &AppState, host_id: Uuid, purpose: &str, expires: i64) -> String {
+        let payload = format!("{}.{}.{}", host_id, purpose, expires);
+        let signature = crate::token::hash(&state.upload_key, &payload);
+
+        format!("{}.{}", payload, signature)
+    }
+
+    /// Checks the token of an upload URL and returns the host and purpose it was
+    /// issued for.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StatusError` if the token is malformed, its signature is wrong, it
+    /// expired or its host no longer exists, or an error if database operations fail.
+    pub async fn upload_token_verify(
+        state: &AppState,
+        token: &str,
+    ) -> Result<(host::Model, String)> {
+        let invalid = || {
+            StatusError::new(
+                StatusCode::UNAUTHORIZED,
+                "upload_url_invalid",
+                "upload url is invalid or expired",
+            )
+        };
+
+        let (payload, signature) = token.rsplit_once('.').ok_or_else(invalid)?;
+        if !crate::token::verify(&state.upload_key, payload, signature) {
+            return Err(invalid().into());
+        }
+
+        let mut parts = payload.split('.');
+        let (Some(host_id), Some(purpose), Some(expires), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid().into());
+        };
+        let host_id = Uuid::parse_str(host_id).map_err(|_| invalid())?;
+        let expires = expires.parse::<i64>().map_err(|_| invalid())?;
+        if expires <= chrono::Utc::now().timestamp() {
+            return Err(invalid().into());
+        }
+
+        let target = Host::find_by_id(host_id)
+            .filter(host::Column::DeletedAt.is_null())
+            .one(state.database.as_ref())
+            .await?
+            .ok_or_else(invalid)?;
+
+        Ok((target, purpose.to_owned()))
+    }
+
+    /// Writes an uploaded file below `<data-dir>/uploads/<host id>` and returns its name.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be written.
+    pub async fn store_upload(
+        state: &AppState,
+        target: &host::Model,
+        purpose: &str,
+        content: &[u8],
+    ) -> Result<String> {
+        let dir = state
+            .args
+            .data_dir
+            .join("uploads")
+            .join(target.id.to_string());
+        tokio::fs::create_dir_all(&dir).await?;
+
+        let name = format!(
+            "{}-{}.bin",
+            purpose,
+            chrono::Utc::now().format("%Y%m%dT%H%M%S%.3fZ")
+        );
+        tokio::fs::write(dir.join(&name), content).await?;
+
+        tracing::info!(
+            "stored upload {} of {} ({} bytes)",
+            name,
+            target.machine_id,
+            content.len()
+        );
+
+        Ok(name)
+    }
+
+    /// Stores a log snippet of the host, then deletes all but the latest
+    /// `--agent-logs-kept` snippets of the host.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if database operations fail.
+    pub async fn store_log(state: &AppState, target: &host::Model, content: String) -> Result<()> {
+        let txn = state.database.begin().await?;
+
+        HostLog::insert(host_log::ActiveModel {
+            id: Set(Uuid::from_bytes(uuidv7::create_raw())),
+            host_id: Set(target.id),
+            content: Set(content),
+            created_at: Set(chrono::Utc::now()),
+        })
+        .exec(&txn)
+        .await?;
+
+        // ids are time ordered, everything past the newest ones is outdated;
+        // skipped in memory because sqlite rejects an OFFSET without a LIMIT
+        let outdated: Vec<Uuid> = HostLog::find()
+            .select_only()
+            .column(host_log::Column::Id)
+            .filter(host_log::Column::HostId.eq(target.id))
+            .order_by_desc(host_log::Column::Id)
+            .into_tuple()
+            .all(&txn)
+            .await?
+            .into_iter()
+            .skip(state.args.agent_logs_kept as usize)
+            .collect();
+        if !outdated.is_empty() {
+            HostLog::delete_many()
+                .filter(host_log::Column::Id.is_in(outdated))
+                .exec(&txn)
+                .await?;
+        }
+
+        txn.commit().await?;
+
+        Ok(())
+    }
+
+    /// Loads the commands queued for the host which were not delivered yet, oldest first.
+    pub async fn pending_commands(
+        db: &impl ConnectionTrait,
+        target: &host::Model,
+    ) -> Result<Vec<host_command::Model>> {
+        let commands = HostCommand::find()
+            .filter(host_command::Column::HostId.eq(target.id))
+            .filter(host_command::Column::DeliveredAt.is_null())
+            .order_by_asc(host_command::Column::CreatedAt)
+            .all(db)
+            .await?;
+
+        Ok(commands)
+    }
+
+    /// Marks the commands with the given `ids` as delivered.
+    pub async fn mark_commands_delivered(
+        db: &impl ConnectionTrait,
+        ids: impl IntoIterator<Item = Uuid>,
+    ) -> Result<()> {
+        let ids = ids.into_iter().collect::<Vec<_>>();
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        HostCommand::update_many()
+            .col_expr(
+                host_command::Column::DeliveredAt,
+                Expr::value(chrono::Utc::now()),
+            )
+            .filter(host_command::Column::Id.is_in(ids))
+            .filter(host_command::Column::DeliveredAt.is_null())
+            .exec(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Sends the pending commands of the host over the websocket, one message per command
+    /// (see `frame`). Each command is marked as delivered once it was sent.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if sending a message or database operations fail.
+    pub async fn deliver_commands(
+        state: &AppState,
+        target: &host::Model,
+        ws: &mut WebSocket,
+    ) -> Result<()> {
+        for command in pending_commands(state.database.as_ref(), target).await? {
+            let text = dto::command_value(&command).to_string();
+            ws.send(frame(state, text)).await?;
+            mark_commands_delivered(state.database.as_ref(), [command.id]).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Wraps a JSON message sent to an agent in the frame type set by `--ws-frame-format`.
+    pub fn frame(state: &AppState, text: String) -> Message {
+        match state.args.ws_frame_format {
+            WsFrameFormat::Text => Message::Text(text.into()),
+            WsFrameFormat::Binary => Message::Binary(text.into()),
+        }
+    }
+
+    /// Finds the host with the given `machine_id` in the database and returns it together with
+    /// a mpsc eventbus sender which will send events to the host. If the host does not exist,
+    /// creates a new host with the given `machine_id` and returns its eventbus sender.
+    ///
+    /// The eventbus sender returned by this function is connected to an eventbus receiver running
+    /// in a separate task. Any events sent to the sender will be received by the receiver and
+    /// processed. The receiver task is tracked, so events still buffered on shutdown are
+    /// persisted before the server exits. With `--eventbus-workers`, events are sent to the
+    /// pool worker of the host instead, see `spawn_eventbus_workers`. The sender holds the
+    /// eventbus task permit then, so the same limit applies.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server is shutting down, the agent `token` is invalid, the
+    /// eventbus task limit is reached or database operations fail.
+    pub async fn eventbus_with_machine_id(
+        state: Arc<AppState>,
+        machine_id: &str,
+        token: Option<&str>,
+    ) -> Result<(host::Model, EventbusSender)> {
+        // stop accepting events once the eventbus is draining
+        if state.eventbus.shutdown.is_cancelled() {
+            return Err(StatusError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "shutting_down",
+                "server is shutting down",
+            )
+            .into());
+        }
+
+        // reserve an eventbus task before touching the database, rejecting agents
+        // beyond the cap
+        let Ok(permit) = state.eventbus.permits.clone().try_acquire_owned() else {
+            tracing::warn!(
+                "eventbus task limit reached, rejected machine id: {}",
+                machine_id
+            );
+            return Err(StatusError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "eventbus_full",
+                "too many concurrent agents, retry later",
+            )
+            .into());
+        };
+
+        let target = upsert_verified_host(&state, machine_id, token).await?;
+
+        // hand events to the pool worker of the host, if there is a pool
+        if let Some(workers) = state.eventbus.workers.get() {
+            let mut hasher = DefaultHasher::new();
+            target.id.hash(&mut hasher);
+            let worker = &workers[hasher.finish() as usize % workers.len()];
+
+            let tx = EventbusSender::Pool {
+                target: Arc::new(target.clone()),
+                worker: worker.clone(),
+                _permit: permit,
+            };
+            return Ok((target, tx));
+        }
+
+        // create tokio channel
+        // events are paired with their receipt time to measure the ingestion latency
+        let (tx, mut rx) = mpsc::channel::<(Instant, proto::agent::Events)>(16);
+        state.eventbus.tasks.spawn({
+            let state = state.clone();
+            let target = target.clone();
+
+            async move {
+                let _permit = permit;
+
+                while let Some((received, event)) = rx.recv().await {
+                    eventbus_receive(&state, &target, received, event).await;
+                }
+            }
+        });
+
+        Ok((target, EventbusSender::Task(tx)))
+    }
+
+    /// Sender of the events of a host, see `eventbus_with_machine_id`.
+    pub enum EventbusSender {
+        /// Channel of the receiver task of the host.
+        Task(mpsc::Sender<(Instant, Events)>),
+        /// Queue of the pool worker of the host, holding the eventbus task permit.
+        Pool {
+            target: Arc<host::Model>,
+            worker: mpsc::Sender<PooledEvent>,
+            _permit: OwnedSemaphorePermit,
+        },
+    }
+
+    impl EventbusSender {
+        /// Sends the `event` received at `received`, waiting while the receiver is busy.
+        ///
+        /// # Errors
+        ///
+        /// Returns an error if the receiver stopped, such as on shutdown.
+        pub async fn send(&self, received: Instant, event: Events) -> Result<()> {
+            match self {
+                Self::Task(tx) => tx.send((received, event)).await?,
+                Self::Pool { target, worker, .. } => {
+                    worker.send((target.clone(), received, event)).await?
+                }
+            }
+
+            Ok(())
+        }
+    }
+
+    /// Applies an event received from the host with `eventbus_handler`, recording the
+    /// error on the host if it fails.
+    pub async fn eventbus_receive(
+        state: &AppState,
+        target: &host::Model,
+        received: Instant,
+        event: Events,
+    ) {
+        // received event from client
+        tracing::debug!("received event from {}: {:?}", &target.machine_id, &event);
+
+        // dispatch to handler
+        if let Err(err) = eventbus_handler(state, target, received, event).await {
+            tracing::warn!("eventbus handler failed: {}", err);
+            record_error(state, target.id, &err.to_string()).await;
+        };
+    }
+
+    /// Handles an `Events` enum by dispatching it to the appropriate handler.
+    ///
+    /// This function applies the `event` with `eventbus_apply`. Once the change is
+    /// applied, the host's `last_seen` is refreshed and the webhook (if configured) is
+    /// notified in the background.
+    ///
+    /// With `--dedup-window`, an event identical to the last applied event of the same
+    /// type is skipped if that one was applied within the window. Only `last_seen` is
+    /// refreshed for a skipped event.
+    ///
+    /// The time from `received` until the event is persisted is recorded in the
+    /// eventbus latency histogram.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the event handling fails, which could be due to
+    /// database operation errors.
+    async fn eventbus_handler(
+        state: &AppState,
+        target: &host::Model,
+        received: Instant,
+        event: Events,
+    ) -> Result<()> {
+        let fingerprint = eventbus_fingerprint(state, &event)?;
+        let duplicate = fingerprint
+            .as_ref()
+            .is_some_and(|fingerprint| eventbus_is_duplicate(state, target, fingerprint));
+
+        let change = if duplicate {
+            tracing::debug!("skipped duplicate event from {}", &target.machine_id);
+            None
+        } else {
+            eventbus_apply(state, target, event).await?
+        };
+
+        // remember applied event for deduplication
+        if let (false, Some((kind, payload))) = (duplicate, fingerprint) {
+            let mut applied = state.eventbus.applied.lock().unwrap();
+            applied.insert((target.id, kind), (payload, Instant::now()));
+        }
+
+        // refresh last seen
+        Host::update(host::ActiveModel {
+            id: Unchanged(target.id),
+            last_seen: Set(Some(chrono::Utc::now())),
+            ..Default::default()
+        })
+        .exec(state.database.as_ref())
+        .await?;
+
+        // everything is persisted, record time since receipt
+        state.eventbus.latency.record(received.elapsed());
+
+        // notify webhook about applied change
+        if let (Some(webhook), Some(change)) = (&state.webhook, change) {
+            webhook.notify(Webhook::event(target.id, &target.machine_id, change));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the type and serialized form of `event` if it is subject to deduplication.
+    ///
+    /// Nothing is returned if `--dedup-window` is not set. Metrics samples are never
+    /// deduplicated, identical samples are still valid measurements.
+    fn eventbus_fingerprint(
+        state: &AppState,
+        event: &Events,
+    ) -> Result<Option<(&'static str, String)>> {
+        if state.settings().dedup_window.is_none() {
+            return Ok(None);
+        }
+
+        let kind = match event {
+            Events::EvtMachineEmit(_) => "EvtMachineEmit",
+            Events::EvtOsEmit(_) => "EvtOsEmit",
+            Events::EvtHardwareEmit(_) => "EvtHardwareEmit",
+            Events::EvtMetricsEmit(_) => return Ok(None),
+        };
+
+        Ok(Some((kind, serde_json::to_string(event)?)))
+    }
+
+    /// Checks whether the event with the given `fingerprint` was applied to the host
+    /// within `--dedup-window`.
+    fn eventbus_is_duplicate(
+        state: &AppState,
+        target: &host::Model,
+        (kind, payload): &(&'static str, String),
+    ) -> bool {
+        let window = Duration::from_secs(state.settings().dedup_window.unwrap_or_default());
+        let applied = state.eventbus.applied.lock().unwrap();
+
+        applied
+            .get(&(target.id, *kind))
+            .is_some_and(|(last, at)| last == payload && at.elapsed() < window)
+    }
+
+    /// Applies an event to the host and returns the change to notify the webhook about.
+    ///
+    /// This function records the `event` in the event log, then matches it to call the
+    /// corresponding event handler function.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if database operations fail.
+    async fn eventbus_apply(
+        state: &AppState,
+        target: &host::Model,
+        event: Events,
+    ) -> Result<Option<WebhookChange>> {
+        // keep the event in the fleet-wide event log, metrics samples have their own table
+        if !matches!(event, Events::EvtMetricsEmit(_)) {
+            eventbus_record(state, target, &event).await?;
+        }
+
+        let change = match event {
+            Events::EvtMachineEmit(machine) => {
+                eventbus_handle_machine_emit(state, target, machine).await?;
+                Some(WebhookChange::Machine)
+            }
+            Events::EvtOsEmit(os) => {
+                eventbus_handle_os_emit(state, target, os).await?;
+                Some(WebhookChange::Os)
+            }
+            Events::EvtHardwareEmit(hardware) => {
+                eventbus_handle_hardware_emit(state, target, hardware).await?;
+                Some(WebhookChange::Hardware)
+            }
+            Events::EvtMetricsEmit(metrics) => {
+                eventbus_handle_metrics_emit(state, target, metrics).await?;
+                None
+            }
+        };
+
+        Ok(change)
+    }
+
+    /// Appends an event to the event log.
+    ///
+    /// The event type is the tag the event was sent with (e.g. `EvtOsEmit`), the payload
+    /// is the JSON content of the event.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if database operations fail.
+    async fn eventbus_record(state: &AppState, target: &host::Model, event: &Events) -> Result<()> {
+        let value = serde_json::to_value(event)?;
+        let Some((event_type, payload)) = value.as_object().and_then(|tagged| tagged.iter().next())
+        else {
+            return Ok(());
+        };
+
+        EventLog::insert(event_log::ActiveModel {
+            id: Set(Uuid::from_bytes(uuidv7::create_raw())),
+            host_id: Set(target.id),
+            event_type: Set(event_type.clone()),
+            payload: Set(payload.to_string()),
+            received_at: Set(chrono::Utc::now()),
+        })
+        .exec(state.database.as_ref())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Handles a `EvtMachineEmit` event sent to the eventbus.
+    ///
+    /// This function updates the `machine_*` fields of the host. The IP address is stored
+    /// in its canonical form (compressed IPv6, IPv4-mapped addresses as IPv4). An IP that
+    /// does not parse is logged and not stored.
+    ///
+    /// The server does not resolve countries itself. If the agent reports no country,
+    /// `--default-country` is stored instead, if set.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if database operations fail.
+    async fn eventbus_handle_machine_emit(
+        state: &AppState,
+        target: &host::Model,
+        machine: EvtMachineEmit,
+    ) -> Result<()> {
+        let ip = match machine.ip.trim().parse::<IpAddr>() {
+            Ok(ip) => Some(ip.to_canonical().to_string()),
+            Err(_) => {
+                tracing::warn!(
+                    "skipped invalid ip from {}: {:?}",
+                    target.machine_id,
+                    machine.ip
+                );
+                None
+            }
+        };
+
+        let country = match (machine.country, state.settings().default_country) {
+            (Some(country), _) if !country.trim().is_empty() => Some(country),
+            (_, Some(default)) => Some(default),
+            (country, None) => country,
+        };
+
+        Host::update(host::ActiveModel {
+            id: target.id.into_active_value(),
+            machine_ip: ip.into_active_value_(),
+            machine_country: country.into_active_value_(),
+            ..Default::default()
+        })
+        .exec(state.database.as_ref())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Handles an `EvtOsEmit` event sent to the eventbus.
+    ///
+    /// This function updates the `os_*` fields of the host. The architecture is stored
+    /// normalized by `normalize_arch`, the value sent by the agent is kept in `os_arch_raw`.
+    ///
+    /// A reported virtualization platform (e.g. `kvm`, `vmware`, `docker`) is stored
+    /// lowercased in `os_virtualization_platform` and implies `os_virtualization`.
+    /// Reporting no virtualization clears the platform.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if database operations fail.
+    async fn eventbus_handle_os_emit(
+        state: &AppState,
+        target: &host::Model,
+        os: EvtOsEmit,
+    ) -> Result<()> {
+        let platform = os
+            .virtualization_platform
+            .as_deref()
+            .map(|platform| platform.trim().to_lowercase())
+            .filter(|platform| !platform.is_empty())
+            .map(|platform| platform.chars().take(64).collect::<String>());
+        let (virtualization, platform) = match platform {
+            Some(platform) => (Some(true), Set(Some(platform))),
+            None if os.virtualization == Some(false) => (Some(false), Set(None)),
+            None => (os.virtualization, NotSet),
+        };
+
+        Host::update(host::ActiveModel {
+            id: target.id.into_active_value(),
+            os_family: os.family.into_active_value(),
+            os_name: os.name.into_active_value_(),
+            os_version: os.version.into_active_value_(),
+            os_arch: os.arch.as_deref().map(normalize_arch).into_active_value_(),
+            os_arch_raw: os.arch.map(Some).into_active_value_(),
+            os_build: os.build.into_active_value_(),
+            os_virtualization: virtualization.into_active_value_(),
+            os_virtualization_platform: platform,
+            ..Default::default()
+        })
+        .exec(state.database.as_ref())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Handles an `EvtHardwareEmit` event sent to the eventbus.
+    ///
+    /// This function hashes each reported hardware description with `hash_hardware` and
+    /// updates the `hashed_*` fields of the host. Every hash that replaces a previously
+    /// reported, different hash is recorded in the `hardware_change` table.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if database operations fail.
+    async fn eventbus_handle_hardware_emit(
+        state: &AppState,
+        target: &host::Model,
+        hardware: EvtHardwareEmit,
+    ) -> Result<()> {
+        let hash = |value: Option<String>| value.as_deref().map(hash_hardware);
+        let (cpu, gpu, memory, disk, network) = (
+            hash(hardware.cpu),
+            hash(hardware.gpu),
+            hash(hardware.memory),
+            hash(hardware.disk),
+            hash(hardware.network),
+        );
+
+        let txn = state.database.begin().await?;
+
+        // compare against the stored hashes, `target` may be outdated
+        let Some(current) = Host::find_by_id(target.id).one(&txn).await? else {
+            return Ok(());
+        };
+
+        // a zero hash was never reported, so it cannot change
+        let now = chrono::Utc::now();
+        let changes = [
+            ("hashed_cpu", current.hashed_cpu, cpu),
+            ("hashed_gpu", current.hashed_gpu, gpu),
+            ("hashed_memory", current.hashed_memory, memory),
+            ("hashed_disk", current.hashed_disk, disk),
+            ("hashed_network", current.hashed_network, network),
+        ]
+        .into_iter()
+        .filter_map(|(column, old, new)| match new {
+            Some(new) if old != 0 && old != new => Some(hardware_change::ActiveModel {
+                id: Set(Uuid::from_bytes(uuidv7::create_raw())),
+                host_id: Set(target.id),
+                column: Set(column.to_owned()),
+                old: Set(old),
+                new: Set(new),
+                changed_at: Set(now),
+            }),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+        Host::update(host::ActiveModel {
+            id: target.id.into_active_value(),
+            hashed_cpu: cpu.into_active_value_(),
+            hashed_gpu: gpu.into_active_value_(),
+            hashed_memory: memory.into_active_value_(),
+            hashed_disk: disk.into_active_value_(),
+            hashed_network: network.into_active_value_(),
+            ..Default::default()
+        })
+        .exec(&txn)
+        .await?;
+
+        if !changes.is_empty() {
+            tracing::info!(
+                "hardware of {} changed: {} hashes",
+                &target.machine_id,
+                changes.len()
+            );
+            HardwareChange::insert_many(changes).exec(&txn).await?;
+        }
+
+        txn.commit().await?;
+
+        Ok(())
+    }
+
+    /// Handles an `EvtMetricsEmit` event sent to the eventbus.
+    ///
+    /// This function appends the sample to the `metric` table. Byte counts beyond
+    /// `i64::MAX` cannot be stored and are dropped.
+    ///
+    /// With `--metrics-interval`, a sample arriving within the interval after the last
+    /// stored sample of the host is dropped and counted instead, so the table grows by
+    /// at most one row per host and interval however often the agent reports.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if database operations fail.
+    async fn eventbus_handle_metrics_emit(
+        state: &AppState,
+        target: &host::Model,
+        metrics: EvtMetricsEmit,
+    ) -> Result<()> {
+        if let Some(interval) = state.settings().metrics_interval {
+            let interval = Duration::from_secs(interval);
+            let mut sampled = state.eventbus.sampled.lock().unwrap();
+            let now = Instant::now();
+
+            // reserve the slot before inserting, so concurrent samples cannot both pass
+            if sampled
+                .get(&target.id)
+                .is_some_and(|at| now.duration_since(*at) < interval)
+            {
+                state
+                    .eventbus
+                    .dropped_samples
+                    .fetch_add(1, Ordering::Relaxed);
+                return Ok(());
+            }
+            sampled.insert(target.id, now);
+        }
+
+        let bytes = |value: Option<u64>| value.and_then(|v| i64::try_from(v).ok());
+
+        Metric::insert(metric::ActiveModel {
+            id: Set(Uuid::from_bytes(uuidv7::create_raw())),
+            host_id: Set(target.id),
+            cpu_usage: Set(metrics.cpu_usage.filter(|v| v.is_finite())),
+            memory_used: Set(bytes(metrics.memory_used)),
+            memory_total: Set(bytes(metrics.memory_total)),
+            disk_used: Set(bytes(metrics.disk_used)),
+            disk_total: Set(bytes(metrics.disk_total)),
+            recorded_at: Set(chrono::Utc::now()),
+        })
+        .exec(state.database.as_ref())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Hashes a hardware description into the `i32` stored in the `hashed_*` columns.
+    ///
+    /// The hash is the 32-bit FNV-1a hash of the UTF-8 bytes, with its bits reinterpreted
+    /// as a two's complement `i32` (hashes above `i32::MAX` become negative). This never
+    /// overflows the column and is stable across releases, platforms and database backends,
+    /// so the same hardware always stores the same value.
+    fn hash_hardware(value: &str) -> i32 {
+        const FNV_OFFSET_BASIS: u32 = 0x811c_9dc5;
+        const FNV_PRIME: u32 = 0x0100_0193;
+
+        let hash = value.bytes().fold(FNV_OFFSET_BASIS, |hash, byte| {
+            (hash ^ byte as u32).wrapping_mul(FNV_PRIME)
+        });
+
+        hash as i32
+    }
+
+    /// Normalizes a reported CPU architecture to a canonical name, so aliases are counted
+    /// together. Names follow Rust's `std::env::consts::ARCH`, shortened where they would
+    /// not fit the 8 characters of the `os_arch` column:
+    ///
+    /// - `x86_64`: `x86_64`, `x86-64`, `amd64`, `x64`, `em64t`
+    /// - `x86`: `x86`, `i386`, `i486`, `i586`, `i686`, `386`
+    /// - `aarch64`: `aarch64`, `arm64`, `armv8`, `armv8l`
+    /// - `arm`: `arm`, `armv6l`, `armv7`, `armv7l`, `armhf`, `armel`
+    /// - `ppc64`: `powerpc64`, `ppc64`, `ppc64le`
+    /// - `riscv64`: `riscv64`, `riscv64gc`
+    ///
+    /// Matching is case-insensitive. Other values are stored trimmed, lowercased and cut
+    /// to 8 characters.
+    fn normalize_arch(raw: &str) -> String {
+        let arch = raw.trim().to_ascii_lowercase();
+
+        match arch.as_str() {
+            "x86_64" | "x86-64" | "amd64" | "x64" | "em64t" => "x86_64",
+            "x86" | "i386" | "i486" | "i586" | "i686" | "386" => "x86",
+            "aarch64" | "arm64" | "armv8" | "armv8l" => "aarch64",
+            "arm" | "armv6l" | "armv7" | "armv7l" | "armhf" | "armel" => "arm",
+            "powerpc64" | "ppc64" | "ppc64le" => "ppc64",
+            "riscv64" | "riscv64gc" => "riscv64",
+            _ => return arch.chars().take(8).collect(),
+        }
+        .to_owned()
+    }
+
+    /// Records `error` as the latest error of the host, listed by
+    /// `/api/admin/agents/errors`.
+    ///
+    /// Failures are only logged, a broken agent must not break its own connection.
+    pub async fn record_error(state: &AppState, host_id: Uuid, error: &str) {
+        let recorded = Host::update(host::ActiveModel {
+            id: Unchanged(host_id),
+            last_error: Set(Some(error.to_owned())),
+            last_error_at: Set(Some(chrono::Utc::now())),
+            ..Default::default()
+        })
+        .exec(state.database.as_ref())
+        .await;
+
+        if let Err(err) = recorded {
+            tracing::warn!("record agent error failed: {}", err);
+        }
+    }
+
+    /// Renders a failed websocket upgrade as a client error and logs its reason.
+    ///
+    /// Requests which do not ask for a websocket in their `Connection` and `Upgrade`
+    /// headers get `426 Upgrade Required` with an `Upgrade: websocket` header, other
+    /// malformed upgrade requests get the status of the rejection, usually
+    /// `400 Bad Request`.
+    pub fn upgrade_rejected(machine_id: &str, rejection: WebSocketUpgradeRejection) -> Response {
+        let reason = rejection.body_text();
+        tracing::info!("rejected websocket upgrade of {}: {}", machine_id, reason);
+
+        if rejection.status() == StatusCode::UPGRADE_REQUIRED
+            || matches!(
+                rejection,
+                WebSocketUpgradeRejection::InvalidConnectionHeader(_)
+                    | WebSocketUpgradeRejection::InvalidUpgradeHeader(_)
+            )
+        {
+            return (
+                [(header::UPGRADE, "websocket")],
+                StatusError::new(StatusCode::UPGRADE_REQUIRED, "upgrade_required", reason),
+            )
+                .into_response();
+        }
+
+        StatusError::new(rejection.status(), "invalid_upgrade", reason).into_response()
+    }
+
+    /// Shortens a websocket `lifetime` by a random jitter of up to 10%.
+    ///
+    /// Connections accepted together would otherwise all be recycled at the same time,
+    /// making the whole fleet reconnect at once.
+    pub fn jittered(lifetime: Duration) -> Duration {
+        let ratio = OsRng.next_u32() as f64 / u32::MAX as f64;
+
+        lifetime.mul_f64(1.0 - ratio * 0.1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::seaorm::*;
+    use crate::state::AppState;
+    use crate::testing;
+    use axum::body::Body;
+    use axum::http::header;
+    use axum::http::Method;
+    use axum::http::Request;
+    use axum::http::StatusCode;
+    use futures::SinkExt;
+    use futures::StreamExt;
+    use serde_json::json;
+    use serde_json::Value;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+    use tokio_tungstenite::tungstenite::Message;
+    use tower::ServiceExt;
+
+    /// Mints an enrollment token through the admin API.
+    async fn enrollment(state: &Arc<AppState>) -> String {
+        let token = testing::admin(state).await;
+        let request = testing::request(Method::POST, "/api/admin/enrollments", Some(&token), None);
+        let (status, body) = testing::send(&testing::router(state), request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        body["token"].as_str().unwrap().to_owned()
+    }
+
+    /// Asks for the config of the host with `machine_id`, passing the `enrollment` token.
+    async fn config(
+        state: &Arc<AppState>,
+        machine_id: &str,
+        enrollment: &str,
+    ) -> (StatusCode, Value) {
+        let uri = format!(
+            "/api/agent/{}/config?enrollment_token={}",
+            machine_id, enrollment
+        );
+        let request = testing::request(Method::GET, &uri, None, None);
+        testing::send(&testing::router(state), request).await
+    }
+
+    #[tokio::test]
+    async fn enrollment_binds_agent_token() {
+        let state = testing::state(&[]).await;
+        let enrollment = enrollment(&state).await;
+
+        let (status, body) = config(&state, "m1", &enrollment).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let token = body["token"].as_str().unwrap();
+
+        let router = testing::router(&state);
+        let request = testing::request(Method::GET, "/api/agent/m1/config", Some(token), None);
+        let (status, _) = testing::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // the host is bound now, its token is required
+        let request = testing::request(Method::GET, "/api/agent/m1/config", None, None);
+        let (status, _) = testing::send(&router, request).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn whoami_needs_a_bound_token() {
+        let state = testing::state(&[]).await;
+        let enrollment = enrollment(&state).await;
+        let (status, body) = config(&state, "m1", &enrollment).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let token = body["token"].as_str().unwrap().to_owned();
+        testing::host(&state, "unbound").await;
+
+        let router = testing::router(&state);
+        let whoami = |machine_id: &str, token: Option<&str>| {
+            let uri = format!("/api/agent/{}/whoami", machine_id);
+            testing::request(Method::GET, &uri, token, None)
+        };
+
+        let (status, body) = testing::send(&router, whoami("m1", Some(&token))).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let host = Host::find()
+            .filter(host::Column::MachineId.eq("m1"))
+            .one(state.database.as_ref())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(body["id"], host.id.to_string());
+        assert_eq!(body["machine_id"], "m1");
+
+        for (machine_id, token) in [
+            ("m1", None),
+            ("m1", Some("wrong")),
+            ("unbound", Some(token.as_str())),
+            ("unknown", Some(token.as_str())),
+        ] {
+            let (status, body) = testing::send(&router, whoami(machine_id, token)).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{}: {}", machine_id, body);
+            assert_eq!(body["code"], "agent_token_invalid");
+        }
+
+        // asking did not create the host
+        let unknown = Host::find()
+            .filter(host::Column::MachineId.eq("unknown"))
+            .one(state.database.as_ref())
+            .await
+            .unwrap();
+        assert!(unknown.is_none());
+    }
+
+    #[tokio::test]
+    async fn enrollment_is_single_use() {
+        let state = testing::state(&[]).await;
+        let enrollment = enrollment(&state).await;
+
+        let (status, _) = config(&state, "m1", &enrollment).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, body) = config(&state, "m2", &enrollment).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["code"], "enrollment_invalid");
+        assert_eq!(body["message"], "enrollment token already used");
+    }
+
+    #[tokio::test]
+    async fn enrollment_expires() {
+        let state = testing::state(&[]).await;
+        let enrollment = enrollment(&state).await;
+        Enrollment::update_many()
+            .col_expr(
+                enrollment::Column::ExpiredAt,
+                Expr::value(chrono::Utc::now() - chrono::Duration::seconds(1)),
+            )
+            .exec(state.database.as_ref())
+            .await
+            .unwrap();
+
+        let (status, body) = config(&state, "m1", &enrollment).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["message"], "enrollment token expired");
+
+        let found = Host::find()
+            .filter(host::Column::MachineId.eq("m1"))
+            .one(state.database.as_ref())
+            .await
+            .unwrap();
+        assert!(found.is_none());
+    }
+
+    #[tokio::test]
+    async fn hardware_hash_above_i32_is_stored() {
+        let state = testing::state(&[]).await;
+
+        // FNV-1a hashes this to 3935537123, which exceeds `i32::MAX`
+        let hardware = json!([{ "EvtHardwareEmit": { "cpu": "AMD Ryzen 9 7950X" } }]);
+        let uri = "/api/agent/m1/report";
+        let request = testing::request(Method::POST, uri, None, Some(hardware));
+        let (status, _) = testing::send(&testing::router(&state), request).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // events are applied in the background
+        let stored = async {
+            while testing::host(&state, "m1").await.hashed_cpu != -359430173 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), stored)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn websocket_closed_after_max_lifetime() {
+        let state = testing::state(&["--ws-max-lifetime", "1"]).await;
+        let addr = testing::serve(&state).await;
+
+        let url = format!("ws://{}/api/agent/m1/report", addr);
+        let (mut ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        let message = tokio::time::timeout(Duration::from_secs(5), ws.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+
+        let Message::Close(Some(frame)) = message else {
+            panic!("expected close frame, got {:?}", message);
+        };
+        assert_eq!(frame.code, CloseCode::Normal);
+        assert_eq!(frame.reason, "max lifetime reached");
+    }
+
+    #[tokio::test]
+    async fn websocket_upgrade_is_required_and_checked() {
+        let state = testing::state(&[]).await;
+        let router = testing::router(&state);
+
+        let request = testing::request(Method::GET, "/api/agent/m1/report", None, None);
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UPGRADE_REQUIRED);
+        assert_eq!(response.headers()[header::UPGRADE], "websocket");
+
+        // asks for a websocket, but with an unsupported version
+        let request = Request::get("/api/agent/m1/report")
+            .header(header::CONNECTION, "upgrade")
+            .header(header::UPGRADE, "websocket")
+            .header(header::SEC_WEBSOCKET_VERSION, "8")
+            .header(header::SEC_WEBSOCKET_KEY, "dGhlIHNhbXBsZSBub25jZQ==")
+            .body(Body::empty())
+            .unwrap();
+        let (status, body) = testing::send(&router, request).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+        assert_eq!(body["code"], "invalid_upgrade");
+    }
+
+    #[tokio::test]
+    async fn websocket_closed_after_consecutive_malformed_frames() {
+        let state = testing::state(&["--ws-max-bad-frames", "3"]).await;
+        let addr = testing::serve(&state).await;
+
+        let url = format!("ws://{}/api/agent/m1/report", addr);
+        let (ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        let (mut sink, mut stream) = ws.split();
+        let mut next = async || {
+            tokio::time::timeout(Duration::from_secs(5), stream.next())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap()
+        };
+        let malformed = || Message::Text("not an event".into());
+        let valid = Message::Text(r#"{ "EvtOsEmit": { "family": "linux" } }"#.into());
+
+        // a valid frame in between resets the count
+        for message in [malformed(), malformed(), valid, malformed(), malformed()] {
+            sink.send(message).await.unwrap();
+        }
+        sink.send(Message::Ping("open".into())).await.unwrap();
+        assert!(next().await.is_pong());
+
+        sink.send(malformed()).await.unwrap();
+        let message = next().await;
+        let Message::Close(Some(frame)) = message else {
+            panic!("expected close frame, got {:?}", message);
+        };
+        assert_eq!(frame.code, CloseCode::Policy);
+        assert_eq!(frame.reason, "too many malformed events");
+    }
+
+    #[tokio::test]
+    async fn command_queued_while_offline_is_delivered_once() {
+        let state = testing::state(&[]).await;
+        let token = testing::admin(&state).await;
+        let router = testing::router(&state);
+        let host = testing::host(&state, "m1").await;
+
+        let uri = format!("/api/admin/hosts/{}/commands", host.id);
+        let command = json!({ "action": "restart" });
+        let request = testing::request(Method::POST, &uri, Some(&token), Some(command.clone()));
+        let (status, _) = testing::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let request = || testing::request(Method::GET, "/api/agent/m1/config", None, None);
+        let (status, body) = testing::send(&router, request()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["commands"], json!([command]));
+
+        // delivered once, an empty command list is left out
+        let (_, body) = testing::send(&router, request()).await;
+        assert_eq!(body, json!({}));
+    }
+
+    #[tokio::test]
+    async fn arch_aliases_normalize_the_same() {
+        let state = testing::state(&[]).await;
+
+        for (machine_id, arch) in [("m1", "amd64"), ("m2", "x86_64")] {
+            let os = json!([{ "EvtOsEmit": { "family": "linux", "arch": arch } }]);
+            testing::report(&state, machine_id, None, os).await;
+
+            let host = testing::host(&state, machine_id).await;
+            assert_eq!(host.os_arch, "x86_64");
+            assert_eq!(host.os_arch_raw.as_deref(), Some(arch));
+        }
+    }
+
+    #[tokio::test]
+    async fn eventbus_cap_rejects_further_agents() {
+        let state = testing::state(&["--max-eventbus-tasks", "1"]).await;
+        let addr = testing::serve(&state).await;
+
+        // a connected websocket holds its eventbus task
+        let url = format!("ws://{}/api/agent/m1/report", addr);
+        let (ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+
+        let os = json!([{ "EvtOsEmit": { "family": "linux" } }]);
+        let request = testing::request(Method::POST, "/api/agent/m2/report", None, Some(os));
+        let (status, body) = testing::send(&testing::router(&state), request).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["code"], "eventbus_full");
+        assert_eq!(state.eventbus.permits.available_permits(), 0);
+
+        // the rejected agent did not get a host
+        let rejected = Host::find()
+            .filter(host::Column::MachineId.eq("m2"))
+            .one(state.database.as_ref())
+            .await
+            .unwrap();
+        assert!(rejected.is_none());
+
+        drop(ws);
+    }
+
+    #[tokio::test]
+    async fn report_over_the_cap_is_rejected() {
+        let state = testing::state(&["--max-events-per-report", "2"]).await;
+        let os = |family: &str| json!({ "EvtOsEmit": { "family": family } });
+
+        let events = json!([os("linux"), os("linux"), os("windows")]);
+        let (status, body) = testing::report(&state, "m1", None, events).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+        assert_eq!(body["code"], "too_many_events");
+
+        // nothing of the report was persisted, not even the host
+        let found = Host::find()
+            .filter(host::Column::MachineId.eq("m1"))
+            .one(state.database.as_ref())
+            .await
+            .unwrap();
+        assert!(found.is_none());
+
+        let events = json!([os("linux"), os("windows")]);
+        let (status, body) = testing::report(&state, "m1", None, events).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let host = testing::host(&state, "m1").await;
+        assert_eq!(host.os_family, "windows");
+    }
+
+    #[tokio::test]
+    async fn machine_ip_is_validated() {
+        let state = testing::state(&[]).await;
+
+        for (ip, stored) in [
+            ("192.0.2.1", "192.0.2.1"),
+            ("2001:0db8:0000:0000:0000:0000:0000:0001", "2001:db8::1"),
+            // skipped, the last valid ip is kept
+            ("not an ip", "2001:db8::1"),
+        ] {
+            let machine = json!([{ "EvtMachineEmit": { "ip": ip } }]);
+            testing::report(&state, "m1", None, machine).await;
+
+            let host = testing::host(&state, "m1").await;
+            assert_eq!(host.machine_ip, stored, "{}", ip);
+        }
+    }
+
+    #[tokio::test]
+    async fn token_hash_verifies_with_its_pepper_only() {
+        let state = testing::state(&[]).await;
+        let router = testing::router(&state);
+        let host = testing::host(&state, "m1").await;
+        let token = crate::token::random();
+
+        let other = crate::token::pepper(b"other-token-key");
+        for (pepper, expected) in [
+            (other, StatusCode::UNAUTHORIZED),
+            (state.pepper.clone(), StatusCode::OK),
+        ] {
+            Host::update(host::ActiveModel {
+                id: Unchanged(host.id),
+                agent_token: Set(Some(crate::token::hash(&pepper, &token))),
+                ..Default::default()
+            })
+            .exec(state.database.as_ref())
+            .await
+            .unwrap();
+
+            let request = testing::request(Method::GET, "/api/agent/m1/config", Some(&token), None);
+            let (status, body) = testing::send(&router, request).await;
+            assert_eq!(status, expected, "{}", body);
+        }
+    }
+
+    #[tokio::test]
+    async fn commands_are_sent_in_the_frame_format() {
+        for (format, binary) in [("text", false), ("binary", true)] {
+            let state = testing::state(&["--ws-frame-format", format]).await;
+            let token = testing::admin(&state).await;
+            let host = testing::host(&state, "m1").await;
+            let uri = format!("/api/admin/hosts/{}/commands", host.id);
+            let command = json!({ "action": "restart" });
+            let request = testing::request(Method::POST, &uri, Some(&token), Some(command.clone()));
+            assert_eq!(
+                testing::send(&testing::router(&state), request).await.0,
+                StatusCode::OK
+            );
+
+            let addr = testing::serve(&state).await;
+            let url = format!("ws://{}/api/agent/m1/report", addr);
+            let (mut ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+            let message = tokio::time::timeout(Duration::from_secs(5), ws.next())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+
+            assert_eq!(message.is_binary(), binary, "{:?}", message);
+            let sent = serde_json::from_slice::<Value>(&message.into_data()).unwrap();
+            assert_eq!(sent, command);
+        }
+    }
+
+    #[tokio::test]
+    async fn metrics_encodings_store_the_same_row() {
+        let state = testing::state(&[]).await;
+        let verbose = json!({
+            "cpu_usage": 12.5,
+            "memory_used": 1024,
+            "memory_total": 4096,
+            "disk_used": null,
+            "disk_total": 65536,
+        });
+        let compact = json!([12.5, 1024, 4096, null, 65536]);
+
+        let mut stored = Vec::new();
+        for (machine_id, metrics) in [("m1", verbose), ("m2", compact)] {
+            let events = json!([{ "EvtMetricsEmit": metrics }]);
+            let (status, body) = testing::report(&state, machine_id, None, events).await;
+            assert_eq!(status, StatusCode::OK, "{}", body);
+
+            let host = testing::host(&state, machine_id).await;
+            let row = Metric::find()
+                .filter(metric::Column::HostId.eq(host.id))
+                .one(state.database.as_ref())
+                .await
+                .unwrap()
+                .unwrap();
+            stored.push((
+                row.cpu_usage,
+                row.memory_used,
+                row.memory_total,
+                row.disk_used,
+                row.disk_total,
+            ));
+        }
+
+        assert_eq!(
+            stored[0],
+            (Some(12.5), Some(1024), Some(4096), None, Some(65536))
+        );
+        assert_eq!(stored[0], stored[1]);
+    }
+
+    #[tokio::test]
+    async fn duplicate_within_window_is_not_applied() {
+        let state = testing::state(&["--dedup-window", "60"]).await;
+        let linux = json!([{ "EvtOsEmit": { "family": "linux", "version": "6.1" } }]);
+        let upgraded = json!([{ "EvtOsEmit": { "family": "linux", "version": "6.8" } }]);
+
+        let mut applied = Vec::new();
+        for os in [linux.clone(), linux, upgraded] {
+            testing::report(&state, "m1", None, os).await;
+            let count = EventLog::find()
+                .filter(event_log::Column::EventType.eq("EvtOsEmit"))
+                .count(state.database.as_ref())
+                .await
+                .unwrap();
+            applied.push(count);
+        }
+        assert_eq!(applied, [1, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn pooled_events_of_a_host_are_applied_in_order() {
+        let state = testing::state(&["--eventbus-workers", "2"]).await;
+        super::spawn_eventbus_workers(state.clone());
+        let router = testing::router(&state);
+
+        let versions: Vec<String> = (1..=20).map(|version| version.to_string()).collect();
+        for version in &versions {
+            for machine_id in ["m1", "m2"] {
+                let uri = format!("/api/agent/{}/report", machine_id);
+                let events = json!([{ "EvtOsEmit": { "family": "linux", "version": version } }]);
+                let request = testing::request(Method::POST, &uri, None, Some(events));
+                let (status, body) = testing::send(&router, request).await;
+                assert_eq!(status, StatusCode::OK, "{}", body);
+            }
+        }
+        state.drain(Duration::from_secs(5)).await;
+
+        for machine_id in ["m1", "m2"] {
+            let host = Host::find()
+                .filter(host::Column::MachineId.eq(machine_id))
+                .one(state.database.as_ref())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(host.os_version, "20");
+
+            let applied: Vec<String> = EventLog::find()
+                .filter(event_log::Column::HostId.eq(host.id))
+                .order_by_asc(event_log::Column::ReceivedAt)
+                .all(state.database.as_ref())
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|row| {
+                    let payload: Value = serde_json::from_str(&row.payload).unwrap();
+                    payload["version"].as_str().unwrap().to_owned()
+                })
+                .collect();
+            assert_eq!(applied, versions);
+        }
+    }
+
+    #[tokio::test]
+    async fn admin_disconnects_live_connection() {
+        let state = testing::state(&[]).await;
+        let token = testing::admin(&state).await;
+        let router = testing::router(&state);
+        let host = testing::host(&state, "m1").await;
+        let addr = testing::serve(&state).await;
+
+        let url = format!("ws://{}/api/agent/m1/report", addr);
+        let (ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        let (mut sink, mut stream) = ws.split();
+        let mut next = async || {
+            tokio::time::timeout(Duration::from_secs(5), stream.next())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap()
+        };
+        // answered once the connection is registered
+        sink.send(Message::Ping("registered".into())).await.unwrap();
+        assert!(next().await.is_pong());
+
+        let uri = format!("/api/admin/hosts/{}/disconnect", host.id);
+        let request = || testing::request(Method::POST, &uri, Some(&token), None);
+        let (status, body) = testing::send(&router, request()).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body, json!({ "connected": true }));
+
+        let message = next().await;
+        let Message::Close(Some(frame)) = message else {
+            panic!("expected close frame, got {:?}", message);
+        };
+        assert_eq!(frame.code, CloseCode::Policy);
+
+        let (_, body) = testing::send(&router, request()).await;
+        assert_eq!(body, json!({ "connected": false }));
+    }
+
+    #[tokio::test]
+    async fn signed_upload_url_is_verified() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = testing::state(&["--data-dir", dir.path().to_str().unwrap()]).await;
+        let router = testing::router(&state);
+        let put = |url: &str| {
+            Request::put(url)
+                .body(Body::from("diagnostic bytes"))
+                .unwrap()
+        };
+
+        let purpose = json!({ "purpose": "diagnostics" });
+        let request = testing::request(Method::POST, "/api/agent/m1/uploads", None, Some(purpose));
+        let (status, body) = testing::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let url = body["url"].as_str().unwrap().to_owned();
+
+        let (status, body) = testing::send(&router, put(&url)).await;
+        assert_eq!(status, StatusCode::CREATED, "{}", body);
+        assert_eq!(body["size"], 16);
+        let host = testing::host(&state, "m1").await;
+        let stored = dir.path().join("uploads").join(host.id.to_string());
+        let stored = stored.join(body["name"].as_str().unwrap());
+        assert_eq!(std::fs::read(stored).unwrap(), b"diagnostic bytes");
+
+        // expired, signature tampered with, and signed for another host
+        let other = testing::host(&state, "m2").await;
+        let expires = chrono::Utc::now().timestamp();
+        let expired = super::internal::upload_token(&state, host.id, "diagnostics", expires - 1);
+        let mut tampered = url.clone();
+        let last = if tampered.pop() == Some('0') {
+            '1'
+        } else {
+            '0'
+        };
+        tampered.push(last);
+        let moved = url.replace(&host.id.to_string(), &other.id.to_string());
+        for url in [format!("/api/agent/uploads/{}", expired), tampered, moved] {
+            let (status, body) = testing::send(&router, put(&url)).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{}: {}", url, body);
+            assert_eq!(body["code"], "upload_url_invalid");
+        }
+    }
+
+    #[tokio::test]
+    async fn uploaded_logs_are_kept_and_served() {
+        let state = testing::state(&["--agent-logs-kept", "2", "--max-agent-log-size", "16"]).await;
+        let token = testing::admin(&state).await;
+        let router = testing::router(&state);
+        let upload = |content: &'static str| {
+            Request::post("/api/agent/m1/logs")
+                .body(Body::from(content))
+                .unwrap()
+        };
+
+        for content in ["first", "second", "third"] {
+            let (status, body) = testing::send(&router, upload(content)).await;
+            assert_eq!(status, StatusCode::OK, "{}", body);
+        }
+        let (status, _) = testing::send(&router, upload("longer than 16 bytes")).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        let host = testing::host(&state, "m1").await;
+        let uri = format!("/api/admin/hosts/{}/logs", host.id);
+        let request = testing::request(Method::GET, &uri, Some(&token), None);
+        let (status, body) = testing::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        let logs = body.as_array().unwrap().iter();
+        let contents = logs.map(|log| &log["content"]).collect::<Vec<_>>();
+        assert_eq!(contents, ["third", "second"]);
+    }
+
+    #[tokio::test]
+    async fn capabilities_list_version_and_event_types() {
+        let state = testing::state(&[]).await;
+
+        let request = testing::request(Method::GET, "/api/agent/capabilities", None, None);
+        let (status, body) = testing::send(&testing::router(&state), request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["schema_versions"], json!([1]));
+        assert_eq!(
+            body["event_types"],
+            json!([
+                "EvtMachineEmit",
+                "EvtOsEmit",
+                "EvtHardwareEmit",
+                "EvtMetricsEmit"
+            ])
+        );
+        assert_eq!(body["max_report_size"], super::MAX_REPORT_SIZE);
+    }
+
+    #[tokio::test]
+    async fn samples_within_interval_are_dropped() {
+        let state = testing::state(&["--metrics-interval", "60"]).await;
+        for cpu in [10.0, 20.0, 30.0, 40.0, 50.0] {
+            let metrics = json!([{ "EvtMetricsEmit": [cpu, 1, 2, 3, 4] }]);
+            testing::report(&state, "m1", None, metrics).await;
+        }
+
+        let samples = Metric::find().all(state.database.as_ref()).await.unwrap();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].cpu_usage, Some(10.0));
+        let dropped = state
+            .eventbus
+            .dropped_samples
+            .load(std::sync::atomic::Ordering::Relaxed);
+        assert_eq!(dropped, 4);
+    }
+
+    #[tokio::test]
+    async fn unresolved_country_falls_back_to_default() {
+        let state = testing::state(&["--default-country", "LAN"]).await;
+        for (machine_id, country) in [("m1", json!(null)), ("m2", json!("DE"))] {
+            let machine = json!([{ "EvtMachineEmit": { "ip": "10.0.0.1", "country": country } }]);
+            testing::report(&state, machine_id, None, machine).await;
+        }
+
+        assert_eq!(testing::host(&state, "m1").await.machine_country, "LAN");
+        // a country sent by the agent is kept
+        assert_eq!(testing::host(&state, "m2").await.machine_country, "DE");
+    }
 }
@@ -1,12 +1,30 @@
+use crate::api::dto;
+use crate::api::params::JsonOrForm;
+use crate::api::user;
+use crate::middlewares::AuthorizedToken;
 use crate::prelude::axum::*;
 use crate::state::AppState;
+use axum::extract::ConnectInfo;
 use axum::extract::Query;
 use axum::extract::State;
+use axum::http::header;
+use axum::http::HeaderMap;
+use axum::http::StatusCode;
+use axum::Extension;
 use axum::Json;
+use proto::admin::user::UserResp;
+use proto::auth::authorize::AuthorizeReq;
+use proto::auth::authorize::AuthorizeResp;
 use proto::auth::captcha::CaptchaGenerateReq;
 use proto::auth::captcha::CaptchaGenerateResp;
 use proto::auth::init::InitReq;
+use proto::auth::profile::ProfileUpdateReq;
+use proto::auth::session::RefreshReq;
+use proto::auth::session::SessionResp;
+use sea_orm::prelude::Uuid;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 
 /// Generates a new captcha image.
 ///
@@ -20,119 +38,312 @@ use std::sync::Arc;
 ///
 /// The image is a PNG image with a width and height of 220x120 pixels.
 /// The image contains 4 random characters.
+///
+/// In `--test-mode`, the response also carries the `answer`, so automated UI tests can
+/// solve the captcha. It is never included otherwise.
+///
+/// If the captcha is disabled, an empty stub is returned instead.
+///
+/// # Errors
+///
+/// Returns `500 Internal Server Error` with code `captcha_render_failed` if the image
+/// cannot be rendered, even at the default size.
 pub async fn captcha(
     State(state): State<Arc<AppState>>,
     Query(query): Query<CaptchaGenerateReq>,
 ) -> Result<Json<CaptchaGenerateResp>, AxumError> {
+    // captcha disabled, return stub
+    if state.args.disable_captcha {
+        return Ok(Json(CaptchaGenerateResp {
+            id: String::new(),
+            base64: String::new(),
+            answer: None,
+        }));
+    }
+
     // polyfill width and height
-    let (width, height) = (query.w.unwrap_or(220), query.h.unwrap_or(120));
+    let (width, height) = internal::CAPTCHA_DEFAULT_SIZE;
+    let (width, height) = (query.w.unwrap_or(width), query.h.unwrap_or(height));
 
     // generate captcha
-    let (id, base64) = internal::captcha_generate(&state, width, height).await?;
+    let (id, base64, answer) = internal::captcha_generate(&state, width, height).await?;
 
-    Ok(Json(CaptchaGenerateResp { id, base64 }))
+    Ok(Json(CaptchaGenerateResp {
+        id,
+        base64,
+        answer: state.args.test_mode.then_some(answer),
+    }))
 }
 
 /// Initializes the application.
 ///
-/// This endpoint takes a JSON object with the following fields:
+/// This endpoint takes a JSON object, or an equivalent URL encoded form, with the
+/// following fields:
 ///
 /// - `email`: The email address of the first admin user.
 /// - `password`: The password of the first admin user.
+/// - `nickname`: The nickname of the first admin user (default: `Admin`). A blank
+///   nickname, as sent by an empty form field, counts as missing.
 ///
 /// If the application is not initialized, this endpoint will check the captcha
-/// and create the first admin user.
+/// and create the first admin user. With `--disable-init-after-setup`, it answers
+/// `404 Not Found` once the application is initialized.
 ///
+/// # Errors
+///
+/// Returns `400 Bad Request` if the nickname, email or password is too long (see
+/// `--max-nickname-length`, `--max-email-length` and `--max-password-length`).
 pub async fn init(
     State(state): State<Arc<AppState>>,
-    Json(query): Json<InitReq>,
+    JsonOrForm(query): JsonOrForm<InitReq>,
 ) -> Result<(), AxumError> {
+    let nickname = query
+        .nickname
+        .as_deref()
+        .map(str::trim)
+        .filter(|nickname| !nickname.is_empty());
+    user::check_lengths(&state, nickname, Some(&query.email), Some(&query.password))?;
+
     // verify captcha
     internal::captcha_verify(&state, &query.captcha_id, &query.captcha_answer).await?;
 
     // execute initlizate workflow if not initlizated
-    if !internal::initlizated(&state).await? {
-        internal::initlizate(&state, &query.email, &query.password).await?;
+    if !user::initlizated(&state).await? {
+        user::ensure_capacity(&state).await?;
+        let nickname = nickname.unwrap_or(internal::DEFAULT_NICKNAME);
+        internal::initlizate(&state, nickname, &query.email, &query.password).await?;
+
+        // cache the new state, which also writes the `--initialized-marker`
+        user::initlizated(&state).await?;
     }
 
     Ok(())
 }
 
+/// Logs a user in.
+///
+/// This endpoint takes a JSON object, or an equivalent URL encoded form, with the
+/// `email` and `password` of the user and returns an authorize `token` valid for
+/// `--token-ttl`, with its `expired_at` timestamp.
+///
+/// Besides the access token, a refresh token is returned. It opens a session bound to
+/// the client's user agent and address, which can be listed and revoked through
+/// `/api/auth/sessions`.
+///
+/// After `--login-max-failures` consecutive wrong passwords, the account is locked for
+/// `--login-lockout`. A successful login resets the failure count.
+///
+/// # Errors
+///
+/// Returns `401 Unauthorized` if the credentials are invalid, or `423 Locked` if the
+/// account is locked.
+pub async fn authorize(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    JsonOrForm(body): JsonOrForm<AuthorizeReq>,
+) -> Result<Json<AuthorizeResp>, AxumError> {
+    let invalid = || {
+        StatusError::new(
+            StatusCode::UNAUTHORIZED,
+            "invalid_credentials",
+            "invalid email or password",
+        )
+    };
+
+    let found = internal::user_by_email(&state, body.email.trim())
+        .await?
+        .ok_or_else(invalid)?;
+
+    if let Some(remaining) = state.logins.locked(found.id) {
+        return Err(StatusError::new(
+            StatusCode::LOCKED,
+            "account_locked",
+            format!(
+                "too many failed logins, retry in {} seconds",
+                remaining.as_secs().max(1)
+            ),
+        )
+        .into());
+    }
+
+    if !user::verify_password(&found.password, &body.password) {
+        if state.args.login_max_failures > 0 {
+            let lockout = Duration::from_secs(state.args.login_lockout);
+            if state
+                .logins
+                .failed(found.id, state.args.login_max_failures, lockout)
+            {
+                tracing::warn!("locked account {} after failed logins", found.id);
+            }
+        }
+        return Err(invalid().into());
+    }
+    state.logins.succeeded(found.id);
+
+    let user_agent = headers
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.chars().take(256).collect::<String>());
+    let refresh_token =
+        internal::session_create(&state, found.id, user_agent, addr.ip().to_string()).await?;
+
+    let token = AuthorizedToken::issue(&state, found.id, state.args.token_ttl)?;
+    let expired_at = chrono::Utc::now() + chrono::Duration::seconds(state.args.token_ttl as i64);
+
+    Ok(Json(AuthorizeResp {
+        token,
+        expired_at: expired_at.to_rfc3339(),
+        refresh_token,
+    }))
+}
+
+/// Issues a new access token for an existing session.
+///
+/// This endpoint takes a JSON object with the following fields:
+///
+/// - `refresh_token`: The refresh token returned by `/api/auth/authorize`.
+///
+/// The session's last use is updated. Sessions unused for longer than `--session-ttl`
+/// can no longer be refreshed.
+///
+/// # Errors
+///
+/// Returns `401 Unauthorized` if the refresh token is unknown, revoked or expired.
+pub async fn refresh(
+    State(state): State<Arc<AppState>>,
+    Json(body): Json<RefreshReq>,
+) -> Result<Json<AuthorizeResp>, AxumError> {
+    let session = internal::session_refresh(&state, &body.refresh_token).await?;
+
+    let token = AuthorizedToken::issue(&state, session.user_id, state.args.token_ttl)?;
+    let expired_at = chrono::Utc::now() + chrono::Duration::seconds(state.args.token_ttl as i64);
+
+    Ok(Json(AuthorizeResp {
+        token,
+        expired_at: expired_at.to_rfc3339(),
+        refresh_token: body.refresh_token,
+    }))
+}
+
+/// Lists the sessions of the authenticated user, most recently used first.
+pub async fn sessions(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<AuthorizedToken>,
+) -> Result<Json<Vec<SessionResp>>, AxumError> {
+    let sessions = internal::sessions(&state, token.uid).await?;
+
+    Ok(Json(sessions.into_iter().map(dto::session).collect()))
+}
+
+/// Revokes the session with the given `id` of the authenticated user.
+///
+/// Its refresh token stops working immediately. Access tokens already issued stay
+/// valid until they expire.
+///
+/// # Errors
+///
+/// Returns `404 Not Found` if the session does not exist or belongs to another user.
+pub async fn session_revoke(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<AuthorizedToken>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, AxumError> {
+    internal::session_revoke(&state, token.uid, id).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Updates the profile of the authenticated user.
+///
+/// This endpoint takes a JSON object with the following optional fields:
+///
+/// - `nickname`: The new nickname, at most `--max-nickname-length` characters.
+/// - `email`: The new email address, unique among all users, at most
+///   `--max-email-length` characters.
+///
+/// Omitted fields are kept. Any other field, such as `sa`, is ignored, so the
+/// administrator flag can never be changed through this endpoint.
+///
+/// # Errors
+///
+/// Returns `400 Bad Request` if a field is empty or too long, `404 Not Found` if the
+/// user no longer exists, or `409 Conflict` if the email is already taken.
+pub async fn profile(
+    State(state): State<Arc<AppState>>,
+    Extension(token): Extension<AuthorizedToken>,
+    Json(body): Json<ProfileUpdateReq>,
+) -> Result<Json<UserResp>, AxumError> {
+    let nickname = body.nickname.map(|nickname| nickname.trim().to_owned());
+    let email = body.email.map(|email| email.trim().to_owned());
+    for (field, value) in [("nickname", &nickname), ("email", &email)] {
+        if value.as_ref().is_some_and(|value| value.is_empty()) {
+            return Err(StatusError::new(
+                StatusCode::BAD_REQUEST,
+                "invalid_profile",
+                format!("{} must not be empty", field),
+            )
+            .into());
+        }
+    }
+    user::check_lengths(&state, nickname.as_deref(), email.as_deref(), None)?;
+
+    let user = internal::profile_update(&state, token.uid, nickname, email).await?;
+
+    Ok(Json(dto::user(user)))
+}
+
 mod internal {
+    use crate::api::user::hash_password;
+    use crate::prelude::axum::StatusError;
     use crate::state::AppState;
     use anyhow::anyhow;
     use anyhow::Result;
-    use argon2::password_hash::rand_core::OsRng;
-    use argon2::password_hash::SaltString;
-    use argon2::Argon2;
-    use argon2::PasswordHasher;
+    use axum::http::StatusCode;
     use captcha::filters::Noise;
     use captcha::Captcha;
-    use database::models::captcha as captcha_;
-    use database::models::captcha::Entity as Captcha_;
+    use database::models::session;
+    use database::models::session::Entity as Session;
     use database::models::user;
     use database::models::user::Entity as User;
     use sea_orm::prelude::*;
+    use sea_orm::ActiveValue::Set;
     use sea_orm::IntoActiveModel;
+    use sea_orm::QueryOrder;
     use std::str::FromStr;
-    use std::sync::atomic::AtomicBool;
-    use std::sync::atomic::Ordering;
-
-    /// Atomic boolean to check if the database has been initialized
-    ///
-    /// if this value is true, checks can fast returning
-    const REF_INITLIZATED: AtomicBool = AtomicBool::new(false);
-
-    /// Checks if the database has any users.
-    ///
-    /// If the database has at least one user, this function returns `Ok(true)`.
-    /// Otherwise, it returns `Ok(false)`.
-    ///
-    /// This function is used to check if the database has been initialized.
-    /// If the database has not been initialized, the application will
-    /// redirect to the initialization page.
-    pub async fn initlizated(state: &AppState) -> Result<bool> {
-        if !REF_INITLIZATED.load(Ordering::Relaxed) {
-            // check any user exists
-            let next = User::find().count(state.database.as_ref()).await? > 0;
 
-            // CAS false -> next
-            _ = REF_INITLIZATED.compare_exchange(false, next, Ordering::Relaxed, Ordering::Relaxed);
+    /// Nickname of the first admin user if none is given.
+    pub const DEFAULT_NICKNAME: &str = "Admin";
 
-            Ok(next)
-        } else {
-            Ok(true)
-        }
-    }
+    /// Width and height of captcha images if none are requested, also used as fallback
+    /// if an image cannot be rendered at the requested size.
+    pub const CAPTCHA_DEFAULT_SIZE: (u32, u32) = (220, 120);
 
     /// Initializes the application by creating the first admin user.
     ///
     /// This function will be called when the application is first started.
     /// It will check if the database has been initialized (i.e., if the database
     /// has at least one user). If the database has not been initialized, it will
-    /// create the first admin user with the given email and password.
+    /// create the first admin user with the given nickname, email and password.
     ///
     /// # Errors
     ///
     /// Returns an error if database operations fail or user exists.
-    pub async fn initlizate(state: &AppState, email: &str, password: &str) -> Result<()> {
-        let algo = Argon2::default();
-
-        // generate salt
-        let salt = SaltString::generate(&mut OsRng);
-
+    pub async fn initlizate(
+        state: &AppState,
+        nickname: &str,
+        email: &str,
+        password: &str,
+    ) -> Result<()> {
         // generate password hash
-        let hash = algo
-            .hash_password(password.as_bytes(), &salt)
-            .map_err(|e| anyhow!("generate password hash failed. {}", e))?
-            .to_string();
+        let hash = hash_password(password)?;
 
         // persist user
         User::insert(
             user::Model {
                 id: Uuid::nil(),
                 sa: true,
-                nickname: "Admin".to_owned(),
+                nickname: nickname.to_owned(),
                 email: email.to_owned(),
                 password: hash.to_owned(),
                 created_at: chrono::Utc::now(),
@@ -146,79 +357,618 @@ mod internal {
         Ok(())
     }
 
-    /// Generates a new captcha image and persists it in the database.
+    /// Loads the user with the given `email`.
+    pub async fn user_by_email(state: &AppState, email: &str) -> Result<Option<user::Model>> {
+        let found = User::find()
+            .filter(user::Column::Email.eq(email))
+            .one(state.database.as_ref())
+            .await?;
+
+        Ok(found)
+    }
+
+    /// Updates the nickname and email of the user `uid`, bumping `updated_at`.
+    ///
+    /// `None` keeps the current value.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StatusError` if the user does not exist or the email is taken by
+    /// another user, or an error if database operations fail.
+    pub async fn profile_update(
+        state: &AppState,
+        uid: Uuid,
+        nickname: Option<String>,
+        email: Option<String>,
+    ) -> Result<user::Model> {
+        let found = User::find_by_id(uid)
+            .one(state.database.as_ref())
+            .await?
+            .ok_or_else(|| {
+                StatusError::new(
+                    StatusCode::NOT_FOUND,
+                    "user_not_found",
+                    "user does not exist",
+                )
+            })?;
+
+        if let Some(email) = email.as_ref().filter(|email| **email != found.email) {
+            let taken = User::find()
+                .filter(user::Column::Email.eq(email))
+                .filter(user::Column::Id.ne(uid))
+                .count(state.database.as_ref())
+                .await?
+                > 0;
+            if taken {
+                return Err(StatusError::new(
+                    StatusCode::CONFLICT,
+                    "email_taken",
+                    "email is already taken",
+                )
+                .into());
+            }
+        }
+
+        let mut model = found.into_active_model();
+        if let Some(nickname) = nickname {
+            model.nickname = Set(nickname);
+        }
+        if let Some(email) = email {
+            model.email = Set(email);
+        }
+        model.updated_at = Set(chrono::Utc::now());
+
+        Ok(model.update(state.database.as_ref()).await?)
+    }
+
+    /// Opens a session for the user `uid` and returns its refresh token.
+    ///
+    /// Only the peppered hash of the refresh token is stored.
+    pub async fn session_create(
+        state: &AppState,
+        uid: Uuid,
+        user_agent: Option<String>,
+        ip: String,
+    ) -> Result<String> {
+        let token = crate::token::random();
+        let now = chrono::Utc::now();
+
+        session::ActiveModel {
+            id: Set(Uuid::from_bytes(uuidv7::create_raw())),
+            user_id: Set(uid),
+            refresh_token: Set(crate::token::hash(&state.pepper, &token)),
+            user_agent: Set(user_agent),
+            ip: Set(Some(ip)),
+            created_at: Set(now),
+            last_used_at: Set(now),
+        }
+        .insert(state.database.as_ref())
+        .await?;
+
+        Ok(token)
+    }
+
+    /// Looks up the session of a refresh token and marks it as used.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StatusError` if no session unused for less than `--session-ttl` has
+    /// the token, or an error if database operations fail.
+    pub async fn session_refresh(state: &AppState, token: &str) -> Result<session::Model> {
+        let now = chrono::Utc::now();
+        let since = now - chrono::Duration::seconds(state.args.session_ttl as i64);
+
+        let found = Session::find()
+            .filter(session::Column::RefreshToken.is_in(state.token_hashes(token)))
+            .filter(session::Column::LastUsedAt.gt(since))
+            .one(state.database.as_ref())
+            .await?
+            .ok_or_else(|| {
+                StatusError::new(
+                    StatusCode::UNAUTHORIZED,
+                    "refresh_token_invalid",
+                    "refresh token is invalid or expired",
+                )
+            })?;
+
+        // hashes made with a legacy pepper are replaced
+        let mut model = found.into_active_model();
+        model.refresh_token = Set(crate::token::hash(&state.pepper, token));
+        model.last_used_at = Set(now);
+
+        Ok(model.update(state.database.as_ref()).await?)
+    }
+
+    /// Loads the sessions of the user `uid`, most recently used first.
+    pub async fn sessions(state: &AppState, uid: Uuid) -> Result<Vec<session::Model>> {
+        let sessions = Session::find()
+            .filter(session::Column::UserId.eq(uid))
+            .order_by_desc(session::Column::LastUsedAt)
+            .all(state.database.as_ref())
+            .await?;
+
+        Ok(sessions)
+    }
+
+    /// Deletes the session `id` of the user `uid`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StatusError` if the user has no such session, or an error if
+    /// database operations fail.
+    pub async fn session_revoke(state: &AppState, uid: Uuid, id: Uuid) -> Result<()> {
+        let deleted = Session::delete_many()
+            .filter(session::Column::Id.eq(id))
+            .filter(session::Column::UserId.eq(uid))
+            .exec(state.database.as_ref())
+            .await?;
+
+        if deleted.rows_affected == 0 {
+            return Err(StatusError::new(
+                StatusCode::NOT_FOUND,
+                "session_not_found",
+                "session does not exist",
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// Generates a new captcha image and persists it in the captcha store.
     ///
-    /// This function generates a new captcha image and persists it in the database.
+    /// This function generates a new captcha image and persists its answer in the
+    /// `--captcha-store`.
     /// The image is a PNG image with a width and height of the given parameters.
-    /// The image contains 4 random characters.
+    /// The image contains 4 random characters. The captcha expires after `--captcha-ttl`.
+    ///
+    /// The characters are drawn from `--captcha-charset`, which by default leaves out
+    /// look-alikes such as `1`, `i`, `j` and `l`. Characters the captcha font cannot
+    /// render are ignored. The stored answer consists of exactly the drawn characters.
     ///
-    /// The response is a tuple of two strings. The first element is the ID of the captcha.
-    /// The second element is the base64 encoding of the captcha image.
+    /// The response is a tuple of three strings. The first element is the ID of the captcha.
+    /// The second element is the base64 encoding of the captcha image, the third is the
+    /// answer.
+    ///
+    /// Rendering the image is CPU-bound, so it runs on the blocking thread pool instead
+    /// of stalling the async runtime. Some sizes cannot be rendered, then the captcha is
+    /// rendered once more at `CAPTCHA_DEFAULT_SIZE`.
     pub async fn captcha_generate(
         state: &AppState,
         width: u32,
         height: u32,
-    ) -> Result<(String, String)> {
+    ) -> Result<(String, String, String)> {
         // generate captcha (Captcha is not Send + Sync, so we need generate it in closure)
-        let (answer, base64) = {
-            let mut captcha = Captcha::new();
-            captcha.add_chars(4);
-            captcha.view(width, height);
-            captcha.apply_filter(Noise::new(0.1));
+        let charset = state.args.captcha_charset.clone();
+        let (answer, base64) = tokio::task::spawn_blocking(move || {
+            // restrict answers to the configured characters the font supports
+            let supported = Captcha::new().supported_chars();
+            let mut chars: Vec<char> = charset.chars().filter(|c| supported.contains(c)).collect();
+            chars.sort_unstable();
+            chars.dedup();
+            if chars.is_empty() {
+                return Err(anyhow!("captcha charset has no supported characters"));
+            }
 
-            let answer = captcha.chars_as_string();
-            let base64 = captcha.as_base64();
+            let rendered = render(&chars, width, height).or_else(|| {
+                let (default_width, default_height) = CAPTCHA_DEFAULT_SIZE;
+                tracing::warn!(
+                    "captcha rendering at {}x{} failed, retrying at {}x{}",
+                    width,
+                    height,
+                    default_width,
+                    default_height
+                );
+                render(&chars, default_width, default_height)
+            });
+            let Some(rendered) = rendered else {
+                tracing::error!("captcha rendering failed at the default size");
+                return Err(StatusError::new(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "captcha_render_failed",
+                    "captcha image could not be rendered",
+                )
+                .into());
+            };
 
-            (answer, base64.ok_or(anyhow!("captcha generate failed"))?)
-        };
+            Ok(rendered)
+        })
+        .await??;
 
-        // storage captcha in database
-        let persisted = Captcha_::insert(
-            captcha_::Model {
-                id: Uuid::from_bytes(uuidv7::create_raw()),
-                answer: answer,
-                expired_at: chrono::Utc::now(),
-            }
-            .into_active_model(),
-        )
-        .exec_with_returning(state.database.as_ref())
-        .await?;
+        // storage captcha answer
+        let expired_at =
+            chrono::Utc::now() + chrono::Duration::seconds(state.args.captcha_ttl as i64);
+        let id = state.captchas.insert(answer.clone(), expired_at).await?;
+        state.captcha_stats.generated();
 
         Ok((
-            format!("{}", persisted.id),
+            format!("{}", id),
             format!("data:image/png;base64,{}", base64),
+            answer,
         ))
     }
 
+    /// Renders a captcha of 4 characters drawn from `chars` as a `width` x `height` PNG
+    /// image and returns its answer and the base64 encoding of the image, or `None` if
+    /// it cannot be rendered at that size.
+    ///
+    /// Panics of the captcha library, such as on tiny sizes, count as failures.
+    fn render(chars: &[char], width: u32, height: u32) -> Option<(String, String)> {
+        std::panic::catch_unwind(|| {
+            let mut captcha = Captcha::new();
+            captcha.set_chars(chars);
+            captcha.add_chars(4);
+            captcha.view(width, height);
+            captcha.apply_filter(Noise::new(0.1));
+
+            let answer = captcha.chars_as_string();
+            captcha.as_base64().map(|base64| (answer, base64))
+        })
+        .ok()
+        .flatten()
+    }
+
     /// Verifies the given captcha `id` and `answer`.
     ///
-    /// This function loads the captcha from the database, checks if it is expired,
-    /// deletes it from the database, and compares the answer. If the answer is
+    /// This function takes the captcha out of the captcha store, unless it expired,
+    /// and compares the answer. If the answer is
     /// invalid or the captcha does not exist, an error is returned.
     ///
+    /// If the captcha is disabled, this function always succeeds. Otherwise the answer
+    /// is counted in `AppState::captcha_stats`, a malformed ID as a wrong answer.
+    ///
     /// # Errors
     ///
     /// Returns an error if the captcha is invalid.
     pub async fn captcha_verify(state: &AppState, id: &str, answer: &str) -> Result<()> {
-        // load captcha from database
-        let found = Captcha_::find()
-            .filter(captcha_::Column::Id.eq(Uuid::from_str(id)?))
-            .filter(captcha_::Column::ExpiredAt.lt(chrono::Utc::now()))
-            .one(state.database.as_ref())
-            .await?;
-
-        // delete captcha from database
-        if let Some(found) = found.clone() {
-            Captcha_::delete_by_id(found.id)
-                .exec(state.database.as_ref())
-                .await?;
+        // captcha disabled, skip verification
+        if state.args.disable_captcha {
+            return Ok(());
         }
 
+        // take captcha out of the store, it can only be answered once
+        let id = Uuid::from_str(id).inspect_err(|_| state.captcha_stats.answered(false))?;
+        let expected = state.captchas.take(id).await?;
+
         // compare answer
-        if found.is_none() || found.unwrap().answer != answer {
+        let correct = expected.as_deref() == Some(answer);
+        state.captcha_stats.answered(correct);
+        if !correct {
             return Err(anyhow!("invalid captcha"));
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::internal;
+    use crate::middlewares::AuthorizedToken;
+    use crate::state::AppState;
+    use crate::testing;
+    use axum::http::header;
+    use axum::http::HeaderValue;
+    use axum::http::Method;
+    use axum::http::StatusCode;
+    use database::models::prelude::User;
+    use sea_orm::prelude::Uuid;
+    use sea_orm::EntityTrait;
+    use sea_orm::PaginatorTrait;
+    use serde_json::json;
+    use serde_json::Value;
+    use std::collections::HashMap;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+
+    /// Creates the user `user@example.com` with the password `password` through the
+    /// admin API and returns its id.
+    async fn user(state: &Arc<AppState>) -> Uuid {
+        let admin = testing::admin(state).await;
+        let user = json!({ "email": "user@example.com", "password": "password" });
+        let request = testing::request(Method::POST, "/api/admin/users", Some(&admin), Some(user));
+        let (status, body) = testing::send(&testing::router(state), request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        Uuid::parse_str(body["id"].as_str().unwrap()).unwrap()
+    }
+
+    /// Logs `user@example.com` in with `password`.
+    async fn login(state: &Arc<AppState>, password: &str) -> (StatusCode, Value) {
+        let credentials = json!({ "email": "user@example.com", "password": password });
+        let request =
+            testing::request(Method::POST, "/api/auth/authorize", None, Some(credentials));
+        testing::send(&testing::router(state), request).await
+    }
+
+    #[tokio::test]
+    async fn init_accepts_empty_captcha_when_disabled() {
+        let _initialization = testing::initialization().await;
+        let state = testing::state(&["--disable-captcha"]).await;
+        crate::api::user::reinitlizated(&state).await.unwrap();
+        let router = testing::router(&state);
+
+        let request = testing::request(Method::GET, "/api/auth/captcha", None, None);
+        let (status, body) = testing::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({ "id": "", "base64": "" }));
+
+        let body = json!({
+            "captcha_id": "",
+            "captcha_answer": "",
+            "email": "admin@example.com",
+            "password": "password",
+        });
+        let request = testing::request(Method::POST, "/api/auth/init", None, Some(body));
+        let (status, _) = testing::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let users = User::find().count(state.database.as_ref()).await.unwrap();
+        assert_eq!(users, 1);
+    }
+
+    #[tokio::test]
+    async fn init_nickname_falls_back_to_admin() {
+        let _initialization = testing::initialization().await;
+
+        for (nickname, expected) in [
+            (json!(" Ops "), "Ops"),
+            (json!(null), "Admin"),
+            (json!("  "), "Admin"),
+        ] {
+            let state = testing::state(&["--disable-captcha"]).await;
+            crate::api::user::reinitlizated(&state).await.unwrap();
+
+            let body = json!({
+                "captcha_id": "",
+                "captcha_answer": "",
+                "nickname": nickname,
+                "email": "admin@example.com",
+                "password": "password",
+            });
+            let request = testing::request(Method::POST, "/api/auth/init", None, Some(body));
+            let (status, body) = testing::send(&testing::router(&state), request).await;
+            assert_eq!(status, StatusCode::OK, "{}", body);
+
+            let user = User::find()
+                .one(state.database.as_ref())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(user.nickname, expected, "{}", nickname);
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn captcha_renders_off_the_runtime_thread() {
+        let state = testing::state(&[]).await;
+
+        // only polled if the generation gives the single runtime thread back
+        let polled = Arc::new(AtomicBool::new(false));
+        tokio::spawn({
+            let polled = polled.clone();
+            async move { polled.store(true, Ordering::Relaxed) }
+        });
+
+        let (id, base64, answer) = internal::captcha_generate(&state, 220, 120).await.unwrap();
+        assert!(polled.load(Ordering::Relaxed));
+
+        // a PNG signature, followed by the image
+        assert!(base64.starts_with("data:image/png;base64,iVBORw0KGgo"));
+        assert!(base64.len() > 1000);
+        assert_eq!(answer.chars().count(), 4);
+        assert!(!id.is_empty());
+    }
+
+    #[tokio::test]
+    async fn unrenderable_size_falls_back_to_default() {
+        let state = testing::state(&["--captcha-store", "memory"]).await;
+
+        // an image without width cannot be encoded
+        let request = testing::request(Method::GET, "/api/auth/captcha?w=0&h=1", None, None);
+        let (status, body) = testing::send(&testing::router(&state), request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        // the PNG signature and header up to the width and height take 32 base64
+        // characters, the same as those of an image of the default size
+        let (width, height) = internal::CAPTCHA_DEFAULT_SIZE;
+        let (_, default, _) = internal::captcha_generate(&state, width, height)
+            .await
+            .unwrap();
+        let header = |base64: &str| base64["data:image/png;base64,".len()..][..32].to_owned();
+        assert_eq!(header(body["base64"].as_str().unwrap()), header(&default));
+    }
+
+    #[tokio::test]
+    async fn answers_only_use_the_charset() {
+        let state = testing::state(&["--captcha-charset", "AB7"]).await;
+
+        for _ in 0..10 {
+            let (_, _, answer) = internal::captcha_generate(&state, 220, 120).await.unwrap();
+            assert!(answer.chars().all(|c| "AB7".contains(c)), "{}", answer);
+        }
+    }
+
+    #[tokio::test]
+    async fn stores_take_captchas_once_until_expired() {
+        for store in ["database", "memory"] {
+            let state = testing::state(&["--captcha-store", store]).await;
+
+            let verify = async |id: &str, answer: &str| {
+                internal::captcha_verify(&state, id, answer).await.is_ok()
+            };
+
+            let (id, _, answer) = internal::captcha_generate(&state, 220, 120).await.unwrap();
+            assert!(verify(&id, &answer).await, "{}", store);
+            assert!(!verify(&id, &answer).await, "{}: answered twice", store);
+
+            // a wrong answer uses the captcha up as well
+            let (id, _, answer) = internal::captcha_generate(&state, 220, 120).await.unwrap();
+            assert!(!verify(&id, "wrong").await, "{}", store);
+            assert!(
+                !verify(&id, &answer).await,
+                "{}: answered after wrong",
+                store
+            );
+
+            let expired_at = chrono::Utc::now() - chrono::Duration::seconds(1);
+            let id = state
+                .captchas
+                .insert("ABCD".to_owned(), expired_at)
+                .await
+                .unwrap();
+            assert!(
+                !verify(&id.to_string(), "ABCD").await,
+                "{}: expired captcha answered",
+                store
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn captcha_answers_are_counted() {
+        let state = testing::state(&["--captcha-store", "memory"]).await;
+        let token = testing::admin(&state).await;
+
+        let (width, height) = internal::CAPTCHA_DEFAULT_SIZE;
+        let (id, _, answer) = internal::captcha_generate(&state, width, height)
+            .await
+            .unwrap();
+        assert!(internal::captcha_verify(&state, &id, &answer).await.is_ok());
+        assert!(internal::captcha_verify(&state, &id, "wrong")
+            .await
+            .is_err());
+
+        let uri = "/api/admin/captcha/stats?range=5m";
+        let request = testing::request(Method::GET, uri, Some(&token), None);
+        let (status, body) = testing::send(&testing::router(&state), request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(
+            body,
+            json!({ "range_secs": 300, "generated": 1, "verified": 1, "failed": 1 })
+        );
+    }
+
+    #[tokio::test]
+    async fn answer_is_exposed_in_test_mode_only() {
+        for (args, exposed) in [(&[][..], false), (&["--test-mode"], true)] {
+            let state = testing::state(args).await;
+
+            let request = testing::request(Method::GET, "/api/auth/captcha", None, None);
+            let (status, body) = testing::send(&testing::router(&state), request).await;
+            assert_eq!(status, StatusCode::OK, "{}", body);
+            assert_eq!(body["answer"].is_string(), exposed, "{}", body);
+        }
+    }
+
+    #[tokio::test]
+    async fn profile_changes_nickname_but_never_sa() {
+        let state = testing::state(&[]).await;
+        let router = testing::router(&state);
+        let id = user(&state).await;
+        let created = User::find_by_id(id)
+            .one(state.database.as_ref())
+            .await
+            .unwrap()
+            .unwrap();
+        let token = AuthorizedToken::issue(&state, id, 3600).unwrap();
+
+        let profile = json!({ "nickname": " Renamed ", "sa": true });
+        let request = testing::request(
+            Method::PUT,
+            "/api/auth/profile",
+            Some(&token),
+            Some(profile),
+        );
+        let (status, body) = testing::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        let updated = User::find_by_id(id)
+            .one(state.database.as_ref())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(updated.nickname, "Renamed");
+        assert_eq!(updated.email, "user@example.com");
+        assert!(!updated.sa);
+        assert!(updated.updated_at > created.updated_at);
+    }
+
+    #[tokio::test]
+    async fn failed_logins_lock_until_lockout_ends() {
+        let state = testing::state(&["--login-max-failures", "3", "--login-lockout", "1"]).await;
+        user(&state).await;
+
+        for _ in 0..3 {
+            assert_eq!(login(&state, "wrong").await.0, StatusCode::UNAUTHORIZED);
+        }
+        // locked, even with the right password
+        let (status, body) = login(&state, "password").await;
+        assert_eq!(status, StatusCode::LOCKED);
+        assert_eq!(body["code"], "account_locked");
+
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        let (status, body) = login(&state, "password").await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+    }
+
+    #[tokio::test]
+    async fn successful_login_resets_failures() {
+        let state = testing::state(&["--login-max-failures", "2"]).await;
+        user(&state).await;
+
+        for password in ["wrong", "password", "wrong"] {
+            login(&state, password).await;
+        }
+        assert_eq!(login(&state, "password").await.0, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn revoked_session_cannot_refresh() {
+        let state = testing::state(&[]).await;
+        let router = testing::router(&state);
+        user(&state).await;
+        let mut logins = HashMap::new();
+        for device in ["laptop", "phone"] {
+            let credentials = json!({ "email": "user@example.com", "password": "password" });
+            let mut request =
+                testing::request(Method::POST, "/api/auth/authorize", None, Some(credentials));
+            request
+                .headers_mut()
+                .insert(header::USER_AGENT, HeaderValue::from_static(device));
+            let (status, body) = testing::send(&router, request).await;
+            assert_eq!(status, StatusCode::OK, "{}", body);
+            logins.insert(device, body);
+        }
+        let token = logins["laptop"]["token"].as_str().unwrap();
+
+        let request = testing::request(Method::GET, "/api/auth/sessions", Some(token), None);
+        let (status, body) = testing::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let sessions = body.as_array().unwrap();
+        assert_eq!(sessions.len(), 2, "{}", body);
+        assert!(sessions.iter().all(|session| session["ip"] == "127.0.0.1"));
+        let phone = sessions
+            .iter()
+            .find(|session| session["user_agent"] == "phone")
+            .unwrap();
+
+        let uri = format!("/api/auth/sessions/{}", phone["id"].as_str().unwrap());
+        let request = testing::request(Method::DELETE, &uri, Some(token), None);
+        assert_eq!(
+            testing::send(&router, request).await.0,
+            StatusCode::NO_CONTENT
+        );
+
+        for (device, expected) in [
+            ("phone", StatusCode::UNAUTHORIZED),
+            ("laptop", StatusCode::OK),
+        ] {
+            let refresh = json!({ "refresh_token": logins[device]["refresh_token"] });
+            let request = testing::request(Method::POST, "/api/auth/refresh", None, Some(refresh));
+            let (status, body) = testing::send(&router, request).await;
+            assert_eq!(status, expected, "{}: {}", device, body);
+        }
+    }
+}
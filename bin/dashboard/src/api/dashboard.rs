@@ -0,0 +1,1741 @@
+use crate::api::dto;
+use crate::api::params;
+use crate::prelude::axum::*;
+use crate::state::AppState;
+use axum::body::Body;
+use axum::extract::Query;
+use axum::http::header;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Json;
+use proto::admin::host::HostResp;
+use proto::dashboard::agent::AgentVersionResp;
+use proto::dashboard::fleet::FleetSnapshotReq;
+use proto::dashboard::fleet::FleetSnapshotResp;
+use proto::dashboard::geo::GeoHealthResp;
+use proto::dashboard::host::HostCompareReq;
+use proto::dashboard::host::HostCompareResp;
+use proto::dashboard::host::HostFieldDiff;
+use proto::dashboard::host::StaleHostReq;
+use proto::dashboard::host::StaleHostResp;
+use proto::dashboard::metric::MetricAggregateReq;
+use proto::dashboard::metric::MetricAggregateResp;
+use proto::dashboard::metric::MetricExportReq;
+use proto::dashboard::metric::MissingMetricsReq;
+use proto::dashboard::metric::MissingMetricsResp;
+use proto::dashboard::os::OsVersionReq;
+use proto::dashboard::os::OsVersionResp;
+use proto::dashboard::os::OutdatedBuildResp;
+use proto::dashboard::prefix::IdPrefixReq;
+use proto::dashboard::prefix::IdPrefixResp;
+use proto::dashboard::uptime::UptimeReq;
+use proto::dashboard::uptime::UptimeResp;
+use proto::dashboard::virtualization::VirtualizationResp;
+use sea_orm::prelude::Uuid;
+use std::sync::Arc;
+
+/// Returns the recorded fleet online-count series.
+///
+/// This endpoint accepts the following query parameters:
+///
+/// - `from`: The RFC 3339 start of the range (default: 24 hours before `to`).
+/// - `to`: The RFC 3339 end of the range (default: now).
+///
+/// The snapshots are ordered by `recorded_at` ascending, at most 1000 are returned.
+///
+/// # Errors
+///
+/// Returns `400 Bad Request` if the range cannot be parsed.
+pub async fn fleet_snapshots(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<FleetSnapshotReq>,
+) -> Result<Json<Vec<FleetSnapshotResp>>, AxumError> {
+    let to = params::parse_time(query.to.as_deref())?.unwrap_or_else(chrono::Utc::now);
+    let from = params::parse_time(query.from.as_deref())?
+        .unwrap_or_else(|| to - chrono::Duration::hours(24));
+
+    let snapshots = internal::fleet_snapshots(&state, from, to).await?;
+
+    Ok(Json(
+        snapshots
+            .into_iter()
+            .map(|snapshot| FleetSnapshotResp {
+                online: snapshot.online,
+                total: snapshot.total,
+                recorded_at: snapshot.recorded_at.to_rfc3339(),
+            })
+            .collect(),
+    ))
+}
+
+/// Counts hosts grouped by the agent version they run.
+///
+/// Soft-deleted hosts are excluded. Hosts that never reported a version are counted
+/// with a `null` `agent_version`. The counts are ordered by version.
+pub async fn agent_versions(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<AgentVersionResp>>, AxumError> {
+    let versions = internal::agent_versions(&state).await?;
+
+    Ok(Json(
+        versions
+            .into_iter()
+            .map(|(agent_version, count)| AgentVersionResp {
+                agent_version,
+                count,
+            })
+            .collect(),
+    ))
+}
+
+/// Lists online hosts which did not send metrics recently.
+///
+/// This endpoint accepts the following query parameters:
+///
+/// - `max_age`: Seconds after which the latest metrics sample is considered missing
+///   (default: 600).
+/// - `limit`: The number of hosts to return (default: 100, max: 1000).
+///
+/// A host is listed if it is online (see `--offline-threshold`) but its latest metrics
+/// sample is older than `max_age` or it never sent one, which usually points to a
+/// misconfigured agent. Soft-deleted hosts are excluded. Hosts are ordered by machine
+/// ID.
+pub async fn hosts_missing_metrics(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<MissingMetricsReq>,
+) -> Result<Json<Vec<MissingMetricsResp>>, AxumError> {
+    let max_age = query.max_age.unwrap_or(600).min(u32::MAX.into());
+    let since = chrono::Utc::now() - chrono::Duration::seconds(max_age as i64);
+    let limit = query.limit.unwrap_or(100).clamp(1, 1000);
+    let hosts = internal::hosts_missing_metrics(&state, since, limit).await?;
+
+    Ok(Json(
+        hosts
+            .into_iter()
+            .map(|(host, last_metrics_at)| MissingMetricsResp {
+                id: host.id.to_string(),
+                machine_id: host.machine_id,
+                last_seen: host.last_seen.map(|v| v.to_rfc3339()),
+                last_metrics_at: last_metrics_at.map(|v| v.to_rfc3339()),
+            })
+            .collect(),
+    ))
+}
+
+/// Counts the online and offline hosts per country.
+///
+/// A host is online if it was seen within the offline threshold. Hosts without a known
+/// country are counted under an empty `country`. Soft-deleted hosts and offline hosts
+/// in maintenance are excluded. The counts are ordered by country.
+pub async fn geo_health(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<GeoHealthResp>>, AxumError> {
+    let countries = internal::geo_health(&state).await?;
+
+    Ok(Json(
+        countries
+            .into_iter()
+            .map(|(country, online, total)| GeoHealthResp {
+                country,
+                online,
+                offline: total - online,
+            })
+            .collect(),
+    ))
+}
+
+/// Maximum number of ids accepted by `hosts_batch`.
+const HOSTS_BATCH_MAX: usize = 100;
+
+/// Fetches the hosts with the given ids in one request.
+///
+/// The request body is a JSON array of host ids. Ids of missing or soft-deleted hosts
+/// are omitted from the response, which is ordered like the request.
+///
+/// # Errors
+///
+/// Returns `400 Bad Request` if more than 100 ids are given.
+pub async fn hosts_batch(
+    State(state): State<Arc<AppState>>,
+    Json(ids): Json<Vec<Uuid>>,
+) -> Result<Json<Vec<HostResp>>, AxumError> {
+    if ids.len() > HOSTS_BATCH_MAX {
+        return Err(StatusError::new(
+            StatusCode::BAD_REQUEST,
+            "batch_too_large",
+            format!("at most {} ids can be fetched at once", HOSTS_BATCH_MAX),
+        )
+        .into());
+    }
+
+    let mut hosts = internal::hosts_by_ids(&state, &ids).await?;
+
+    Ok(Json(
+        ids.iter()
+            .filter_map(|id| hosts.remove(id))
+            .map(dto::host)
+            .collect(),
+    ))
+}
+
+/// Host fields compared by `hosts_compare`, as named in `HostResp`.
+const HOST_COMPARED_FIELDS: &[&str] = &[
+    "machine_ip",
+    "machine_country",
+    "machine_geo",
+    "os_family",
+    "os_name",
+    "os_version",
+    "os_arch",
+    "os_arch_raw",
+    "os_build",
+    "os_virtualization",
+    "os_virtualization_platform",
+    "hashed_cpu",
+    "hashed_gpu",
+    "hashed_memory",
+    "hashed_disk",
+    "hashed_network",
+    "agent_version",
+];
+
+/// Compares two hosts field by field.
+///
+/// This endpoint accepts the following query parameters:
+///
+/// - `a`: The id of the first host (required).
+/// - `b`: The id of the second host (required).
+///
+/// The response holds both hosts as `a` and `b`, and in `differences` every compared
+/// field whose values differ, with the value of each host. The network, OS, hardware
+/// hash and agent version fields are compared, identity and activity fields such as
+/// `machine_id` or `last_seen` are not.
+///
+/// # Errors
+///
+/// Returns `400 Bad Request` if an id is missing or malformed, or `404 Not Found` if a
+/// host does not exist or was deleted.
+pub async fn hosts_compare(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<HostCompareReq>,
+) -> Result<Json<HostCompareResp>, AxumError> {
+    let required = |name: &str| {
+        StatusError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_uuid",
+            format!("`{}` is required", name),
+        )
+    };
+    let a = params::parse_uuid(query.a.as_deref())?.ok_or_else(|| required("a"))?;
+    let b = params::parse_uuid(query.b.as_deref())?.ok_or_else(|| required("b"))?;
+
+    let hosts = internal::hosts_by_ids(&state, &[a, b]).await?;
+    let take = |id: Uuid| {
+        // both sides may be the same host
+        hosts.get(&id).cloned().map(dto::host).ok_or_else(|| {
+            StatusError::new(
+                StatusCode::NOT_FOUND,
+                "host_not_found",
+                format!("host {} not found", id),
+            )
+        })
+    };
+    let (a, b) = (take(a)?, take(b)?);
+
+    let (values_a, values_b) = (serde_json::to_value(&a)?, serde_json::to_value(&b)?);
+    let differences = HOST_COMPARED_FIELDS
+        .iter()
+        .filter(|&&field| values_a[field] != values_b[field])
+        .map(|&field| HostFieldDiff {
+            field: field.to_owned(),
+            a: values_a[field].clone(),
+            b: values_b[field].clone(),
+        })
+        .collect();
+
+    Ok(Json(HostCompareResp { a, b, differences }))
+}
+
+/// Lists the hosts which did not report for the longest time, oldest first.
+///
+/// This endpoint accepts the following query parameters:
+///
+/// - `limit`: The number of hosts to return (default: 20, max: 100).
+///
+/// `stale_seconds` is the time since `last_seen`, computed when the request is served.
+/// Hosts that were never seen and soft-deleted hosts are excluded.
+pub async fn stale_hosts(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<StaleHostReq>,
+) -> Result<Json<Vec<StaleHostResp>>, AxumError> {
+    let limit = query.limit.unwrap_or(20).clamp(1, 100);
+    let hosts = internal::stale_hosts(&state, limit).await?;
+
+    let now = chrono::Utc::now();
+    Ok(Json(
+        hosts
+            .into_iter()
+            .filter_map(|host| {
+                let last_seen = host.last_seen?;
+                Some(StaleHostResp {
+                    id: host.id.to_string(),
+                    machine_id: host.machine_id,
+                    display_name: host.display_name,
+                    last_seen: last_seen.to_rfc3339(),
+                    stale_seconds: (now - last_seen).num_seconds().max(0),
+                })
+            })
+            .collect(),
+    ))
+}
+
+/// Counts the hosts of an OS family grouped by their OS version.
+///
+/// This endpoint accepts the following query parameters:
+///
+/// - `family`: The OS family to break down, as stored in `os_family` (required).
+///
+/// Soft-deleted hosts are excluded. Hosts that never reported a version are counted
+/// with an empty `os_version`. The counts are ordered by version.
+///
+/// # Errors
+///
+/// Returns `400 Bad Request` if `family` is missing or no active host has that family.
+pub async fn os_versions(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<OsVersionReq>,
+) -> Result<Json<Vec<OsVersionResp>>, AxumError> {
+    let family = query.family.unwrap_or_default();
+    if !internal::os_families(&state).await?.contains(&family) {
+        return Err(StatusError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_family",
+            format!("unknown os family: {:?}", family),
+        )
+        .into());
+    }
+
+    let versions = internal::os_versions(&state, &family).await?;
+
+    Ok(Json(
+        versions
+            .into_iter()
+            .map(|(os_version, count)| OsVersionResp { os_version, count })
+            .collect(),
+    ))
+}
+
+/// Lists the hosts whose OS build is older than their `--os-build-baseline`.
+///
+/// A host is checked against the baseline of its `os_name`, or else of its
+/// `os_family`, both matched exactly. Builds are compared part by part, splitting at
+/// every character which is not an ASCII letter or digit: numeric parts compare as
+/// numbers, other parts as text, and missing parts count as `0`. So `10.0.19045` is
+/// older than `10.0.22631`, and `10.0` equals `10.0.0`.
+///
+/// Soft-deleted hosts and hosts that never reported a build are excluded. The hosts
+/// are ordered by machine ID. Without baselines, nothing is outdated.
+pub async fn os_builds_outdated(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<OutdatedBuildResp>>, AxumError> {
+    let baselines = &state.args.os_build_baseline;
+    if baselines.is_empty() {
+        return Ok(Json(Vec::new()));
+    }
+
+    let oses = baselines.iter().map(|baseline| baseline.os.clone());
+    let hosts = internal::hosts_with_os(&state, oses).await?;
+
+    let outdated = hosts
+        .into_iter()
+        .filter_map(|host| {
+            let baseline = baselines
+                .iter()
+                .find(|baseline| baseline.os == host.os_name)
+                .or_else(|| {
+                    baselines
+                        .iter()
+                        .find(|baseline| baseline.os == host.os_family)
+                })?;
+            internal::build_older(&host.os_build, &baseline.build).then(|| OutdatedBuildResp {
+                id: host.id.to_string(),
+                machine_id: host.machine_id,
+                os_family: host.os_family,
+                os_name: host.os_name,
+                os_build: host.os_build,
+                baseline: baseline.build.clone(),
+            })
+        })
+        .collect();
+
+    Ok(Json(outdated))
+}
+
+/// Maximum number of buckets returned by `metrics_aggregate`.
+const METRICS_AGGREGATE_BUCKETS_MAX: i64 = 1000;
+
+/// Returns the fleet-wide CPU and memory usage over a range, in time buckets.
+///
+/// This endpoint accepts the following query parameters:
+///
+/// - `range`: The range ending now, such as `12h` or `7d` (default: `24h`, max: `90d`).
+/// - `bucket`: The bucket width, such as `5m` or `1h` (default: `1h`, or the range if
+///   shorter), at most the range. A range may have at most 1000 buckets.
+///
+/// Buckets are aligned to multiples of their width since the unix epoch, the first one
+/// may start before the range. Within a bucket, the samples of each host are averaged
+/// first, so hosts reporting more often do not weigh more. Per bucket, `cpu_usage` is
+/// the average over the hosts, `memory_used` and `memory_total` are the sums, and
+/// `hosts` is the number of hosts with a sample. Buckets without samples are omitted.
+///
+/// # Errors
+///
+/// Returns `400 Bad Request` if the range or the bucket is malformed, or if the range
+/// has too many buckets.
+pub async fn metrics_aggregate(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<MetricAggregateReq>,
+) -> Result<Json<Vec<MetricAggregateResp>>, AxumError> {
+    let range = params::parse_range(
+        query.range.as_deref(),
+        chrono::Duration::hours(24),
+        chrono::Duration::days(90),
+    )?;
+    let bucket = chrono::Duration::hours(1).min(range);
+    let bucket =
+        params::parse_range(query.bucket.as_deref(), bucket, range).map_err(|err| StatusError {
+            code: "invalid_bucket",
+            ..err
+        })?;
+    if range.num_seconds() / bucket.num_seconds() > METRICS_AGGREGATE_BUCKETS_MAX {
+        return Err(StatusError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_bucket",
+            format!(
+                "bucket too small, a range may have at most {} buckets",
+                METRICS_AGGREGATE_BUCKETS_MAX
+            ),
+        )
+        .into());
+    }
+
+    let to = chrono::Utc::now();
+    let from = to - range;
+    let buckets = internal::metrics_aggregate(&state, from, to, bucket.num_seconds()).await?;
+
+    Ok(Json(
+        buckets
+            .into_iter()
+            .map(|bucket| MetricAggregateResp {
+                bucket_start: chrono::DateTime::from_timestamp(bucket.start, 0)
+                    .unwrap_or_default()
+                    .to_rfc3339(),
+                hosts: bucket.hosts,
+                cpu_usage: bucket.cpu_usage,
+                memory_used: bucket.memory_used.map(|v| v.round() as i64),
+                memory_total: bucket.memory_total.map(|v| v.round() as i64),
+            })
+            .collect(),
+    ))
+}
+
+/// Maximum number of buckets the contacts of a host are grouped into by `host_uptime`.
+const UPTIME_BUCKETS_MAX: i64 = 10000;
+
+/// Estimates the share of time a host was online over a range.
+///
+/// This endpoint accepts the following query parameters:
+///
+/// - `range`: The range ending now, such as `12h` or `7d` (default: `7d`, max: `90d`).
+///
+/// The server keeps no explicit online history, so the ratio is estimated from the
+/// times the host was heard from: its logged events, its stored metrics samples and its
+/// `last_seen`. Like the online status, every contact counts the host as online for
+/// `--offline-threshold` afterwards, and `uptime` is the covered share of the range,
+/// between 0 and 1. Gaps longer than the threshold count as downtime, including the
+/// time before the first contact. The estimate is coarser with `--metrics-interval` or
+/// `--dedup-window` set, as fewer contacts are recorded, and with ranges of more than
+/// 10000 times the threshold, where gaps shorter than a 10000th of the range are not
+/// noticed.
+///
+/// # Errors
+///
+/// Returns `400 Bad Request` if the range is malformed, or `404 Not Found` if the host
+/// does not exist.
+pub async fn host_uptime(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<UptimeReq>,
+) -> Result<Json<UptimeResp>, AxumError> {
+    let range = params::parse_range(
+        query.range.as_deref(),
+        chrono::Duration::days(7),
+        chrono::Duration::days(90),
+    )?;
+    let to = chrono::Utc::now();
+    let from = to - range;
+    let threshold = chrono::Duration::seconds(state.settings().offline_threshold as i64);
+
+    let host = internal::hosts_by_ids(&state, &[id])
+        .await?
+        .remove(&id)
+        .ok_or_else(|| {
+            StatusError::new(
+                StatusCode::NOT_FOUND,
+                "host_not_found",
+                "host does not exist",
+            )
+        })?;
+
+    // contacts of a bucket no longer than the threshold cover the time from the first
+    // of them until the threshold after the last, so their times need not be loaded
+    let bucket = threshold
+        .num_seconds()
+        .max(range.num_seconds() / UPTIME_BUCKETS_MAX);
+    let mut heartbeats =
+        internal::heartbeats(&state, host.id, from - threshold, to, bucket).await?;
+    heartbeats.extend(
+        host.last_seen
+            .filter(|at| *at >= from - threshold)
+            .map(|at| internal::Heartbeats {
+                first: at,
+                last: at,
+                count: 1,
+            }),
+    );
+    heartbeats.sort_unstable_by_key(|heartbeats| heartbeats.first);
+
+    // sum the parts of the range covered by the threshold after each contact
+    let mut covered = chrono::Duration::zero();
+    let mut until = from;
+    for heartbeats in &heartbeats {
+        let start = heartbeats.first.max(until);
+        let end = (heartbeats.last + threshold).min(to);
+        if end > start {
+            covered += end - start;
+            until = end;
+        }
+    }
+
+    Ok(Json(UptimeResp {
+        id: host.id.to_string(),
+        from: from.to_rfc3339(),
+        to: to.to_rfc3339(),
+        uptime: covered.num_milliseconds() as f64 / range.num_milliseconds() as f64,
+        heartbeats: heartbeats
+            .iter()
+            .map(|heartbeats| heartbeats.count as u64)
+            .sum(),
+    }))
+}
+
+/// Header of the CSV metrics export, in the field order of `MetricResp`.
+const METRICS_EXPORT_COLUMNS: &[&str] = &[
+    "recorded_at",
+    "cpu_usage",
+    "memory_used",
+    "memory_total",
+    "disk_used",
+    "disk_total",
+];
+
+/// Exports the metrics samples of the host with the given `id`, oldest first.
+///
+/// This endpoint accepts the following query parameters:
+///
+/// - `from`: The RFC 3339 start of the range (default: 24 hours before `to`).
+/// - `to`: The RFC 3339 end of the range (default: now).
+/// - `format`: The export format, `csv` or `json` (default: `csv`).
+///
+/// CSV exports have a header row, missing values are left empty. JSON exports are an
+/// array of samples. Samples are read in batches and streamed, so memory stays flat
+/// regardless of the range, and offered as a file download.
+///
+/// # Errors
+///
+/// Returns `400 Bad Request` if the range or the format is invalid, or `404 Not Found`
+/// if the host does not exist.
+pub async fn host_metrics_export(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<MetricExportReq>,
+) -> Result<impl IntoResponse, AxumError> {
+    let csv = match query.format.as_deref().unwrap_or("csv") {
+        "csv" => true,
+        "json" => false,
+        _ => {
+            return Err(StatusError::new(
+                StatusCode::BAD_REQUEST,
+                "unsupported_format",
+                "format must be `csv` or `json`",
+            )
+            .into())
+        }
+    };
+    let to = params::parse_time(query.to.as_deref())?.unwrap_or_else(chrono::Utc::now);
+    let from = params::parse_time(query.from.as_deref())?
+        .unwrap_or_else(|| to - chrono::Duration::hours(24));
+    if from > to {
+        return Err(StatusError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_range",
+            "`from` must not be after `to`",
+        )
+        .into());
+    }
+
+    if !internal::hosts_by_ids(&state, &[id])
+        .await?
+        .contains_key(&id)
+    {
+        return Err(StatusError::new(
+            StatusCode::NOT_FOUND,
+            "host_not_found",
+            "host does not exist",
+        )
+        .into());
+    }
+
+    // scan samples batch by batch, resuming after the last exported one
+    let stream = futures::stream::try_unfold((None, false), move |(after, done)| {
+        let state = state.clone();
+        async move {
+            if done {
+                return anyhow::Ok(None);
+            }
+
+            let first = after.is_none();
+            let metrics = internal::metrics_after(&state, id, from, to, after).await?;
+            let next = metrics.last().map(|metric| (metric.recorded_at, metric.id));
+
+            let mut chunk = Vec::new();
+            if csv {
+                let mut writer = csv::WriterBuilder::new()
+                    .has_headers(false)
+                    .from_writer(&mut chunk);
+                if first {
+                    writer.write_record(METRICS_EXPORT_COLUMNS)?;
+                }
+                for metric in metrics {
+                    writer.serialize(dto::metric(metric))?;
+                }
+                writer.flush()?;
+            } else {
+                for (i, metric) in metrics.into_iter().enumerate() {
+                    chunk.push(if first && i == 0 { b'[' } else { b',' });
+                    serde_json::to_writer(&mut chunk, &dto::metric(metric))?;
+                }
+                match (first, next) {
+                    (true, None) => chunk.extend_from_slice(b"[]"),
+                    (false, None) => chunk.push(b']'),
+                    _ => {}
+                }
+            }
+
+            Ok(Some((chunk, (next, next.is_none()))))
+        }
+    });
+
+    let (content_type, extension) = if csv {
+        ("text/csv", "csv")
+    } else {
+        ("application/json", "json")
+    };
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_owned()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"host-{}-metrics.{}\"", id, extension),
+            ),
+        ],
+        Body::from_stream(stream),
+    ))
+}
+
+/// Counts hosts grouped by virtualization platform.
+///
+/// Soft-deleted hosts are excluded. Bare-metal hosts are counted with `virtualization`
+/// false, virtualized hosts whose agent does not report a platform with a `null`
+/// `platform`. The counts are ordered by platform.
+pub async fn virtualization(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<VirtualizationResp>>, AxumError> {
+    let platforms = internal::virtualization(&state).await?;
+
+    Ok(Json(
+        platforms
+            .into_iter()
+            .map(|(virtualization, platform, count)| VirtualizationResp {
+                virtualization,
+                platform,
+                count,
+            })
+            .collect(),
+    ))
+}
+
+/// Counts hosts grouped by the leading characters of their machine id.
+///
+/// This endpoint accepts the following query parameters:
+///
+/// - `len`: The number of leading characters to group by, between 1 and 64 (required).
+///
+/// Soft-deleted hosts are excluded. Machine ids shorter than `len` are grouped as a
+/// whole. The counts are ordered by prefix.
+///
+/// # Errors
+///
+/// Returns `400 Bad Request` if `len` is missing or out of range.
+pub async fn id_prefixes(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<IdPrefixReq>,
+) -> Result<Json<Vec<IdPrefixResp>>, AxumError> {
+    let len = match query.len {
+        Some(len @ 1..=64) => len,
+        _ => {
+            return Err(StatusError::new(
+                StatusCode::BAD_REQUEST,
+                "invalid_len",
+                "len must be between 1 and 64",
+            )
+            .into())
+        }
+    };
+
+    let prefixes = internal::id_prefixes(&state, len).await?;
+
+    Ok(Json(
+        prefixes
+            .into_iter()
+            .map(|(prefix, count)| IdPrefixResp { prefix, count })
+            .collect(),
+    ))
+}
+
+pub(super) mod internal {
+    use crate::prelude::seaorm::*;
+    use crate::state::AppState;
+    use anyhow::Result;
+    use chrono::DateTime;
+    use chrono::Utc;
+    use sea_orm::sea_query::Alias;
+    use sea_orm::sea_query::Func;
+    use sea_orm::sea_query::Order;
+    use sea_orm::sea_query::Query;
+    use sea_orm::sea_query::SimpleExpr;
+    use sea_orm::Condition;
+    use sea_orm::ConnectionTrait;
+    use sea_orm::DbBackend;
+    use sea_orm::JoinType;
+    use sea_orm::QuerySelect;
+    use sea_orm::RelationDef;
+    use std::collections::HashMap;
+
+    /// Loads the fleet snapshots recorded within `from..=to`.
+    pub async fn fleet_snapshots(
+        state: &AppState,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<fleet_snapshot::Model>> {
+        let snapshots = FleetSnapshot::find()
+            .filter(fleet_snapshot::Column::RecordedAt.between(from, to))
+            .order_by_asc(fleet_snapshot::Column::RecordedAt)
+            .limit(1000)
+            .all(state.database.as_ref())
+            .await?;
+
+        Ok(snapshots)
+    }
+
+    /// Counts the active hosts per agent version.
+    pub async fn agent_versions(state: &AppState) -> Result<Vec<(Option<String>, i64)>> {
+        let versions = Host::find()
+            .select_only()
+            .column(host::Column::AgentVersion)
+            .column_as(host::Column::Id.count(), "count")
+            .filter(host::Column::DeletedAt.is_null())
+            .group_by(host::Column::AgentVersion)
+            .order_by_asc(host::Column::AgentVersion)
+            .into_tuple()
+            .all(state.database.as_ref())
+            .await?;
+
+        Ok(versions)
+    }
+
+    /// Counts the active hosts per virtualization flag and platform.
+    pub async fn virtualization(state: &AppState) -> Result<Vec<(bool, Option<String>, i64)>> {
+        let platforms = Host::find()
+            .select_only()
+            .column(host::Column::OsVirtualization)
+            .column(host::Column::OsVirtualizationPlatform)
+            .column_as(host::Column::Id.count(), "count")
+            .filter(host::Column::DeletedAt.is_null())
+            .group_by(host::Column::OsVirtualization)
+            .group_by(host::Column::OsVirtualizationPlatform)
+            .order_by_asc(host::Column::OsVirtualization)
+            .order_by_asc(host::Column::OsVirtualizationPlatform)
+            .into_tuple()
+            .all(state.database.as_ref())
+            .await?;
+
+        Ok(platforms)
+    }
+
+    /// Counts the active hosts per first `len` characters of their machine id.
+    pub async fn id_prefixes(state: &AppState, len: u32) -> Result<Vec<(String, i64)>> {
+        let prefix = SimpleExpr::from(
+            Func::cust(Alias::new("SUBSTR"))
+                .arg(Expr::col(host::Column::MachineId))
+                .arg(1)
+                .arg(len),
+        );
+
+        let prefixes = Host::find()
+            .select_only()
+            .column_as(prefix.clone(), "prefix")
+            .column_as(host::Column::Id.count(), "count")
+            .filter(host::Column::DeletedAt.is_null())
+            .group_by(prefix.clone())
+            .order_by_asc(prefix)
+            .into_tuple()
+            .all(state.database.as_ref())
+            .await?;
+
+        Ok(prefixes)
+    }
+
+    /// Builds the SQL expression of the unix time at which the bucket of `bucket`
+    /// seconds containing the timestamp `column` starts, which every backend computes
+    /// differently.
+    fn bucket_start(backend: DbBackend, column: &str, bucket: i64) -> String {
+        match backend {
+            DbBackend::Sqlite => format!(
+                "CAST(strftime('%s', {1}) AS INTEGER) / {0} * {0}",
+                bucket, column
+            ),
+            DbBackend::Postgres => format!(
+                "CAST(FLOOR(EXTRACT(EPOCH FROM {1}) / {0}) AS BIGINT) * {0}",
+                bucket, column
+            ),
+            DbBackend::MySql => format!(
+                "CAST(FLOOR(UNIX_TIMESTAMP({1}) / {0}) AS SIGNED) * {0}",
+                bucket, column
+            ),
+        }
+    }
+
+    /// Fleet-wide metrics of one time bucket, see `metrics_aggregate`.
+    pub struct MetricsBucket {
+        pub start: i64,
+        pub hosts: i64,
+        pub cpu_usage: Option<f64>,
+        pub memory_used: Option<f64>,
+        pub memory_total: Option<f64>,
+    }
+
+    /// Aggregates the metrics samples recorded within `from..=to` into buckets of
+    /// `bucket` seconds, ordered by time.
+    ///
+    /// The samples are averaged per host and bucket in a subquery, which the outer
+    /// query aggregates per bucket. Averages of integer columns are cast to floating
+    /// point, as some backends return them as decimals.
+    pub async fn metrics_aggregate(
+        state: &AppState,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        bucket: i64,
+    ) -> Result<Vec<MetricsBucket>> {
+        let backend = state.database.get_database_backend();
+        let start = bucket_start(backend, "recorded_at", bucket);
+        let float = match backend {
+            DbBackend::Sqlite => "REAL",
+            DbBackend::Postgres => "DOUBLE PRECISION",
+            DbBackend::MySql => "DOUBLE",
+        };
+        let avg =
+            |column: metric::Column| Func::cast_as(Func::avg(Expr::col(column)), Alias::new(float));
+
+        let per_host = Query::select()
+            .column(metric::Column::HostId)
+            .expr_as(Expr::cust(&start), Alias::new("start"))
+            .expr_as(
+                Func::avg(Expr::col(metric::Column::CpuUsage)),
+                Alias::new("cpu_usage"),
+            )
+            .expr_as(avg(metric::Column::MemoryUsed), Alias::new("memory_used"))
+            .expr_as(avg(metric::Column::MemoryTotal), Alias::new("memory_total"))
+            .from(Metric)
+            .and_where(metric::Column::RecordedAt.between(from, to))
+            .group_by_col(metric::Column::HostId)
+            .add_group_by([Expr::cust(&start)])
+            .to_owned();
+
+        let buckets = Query::select()
+            .column(Alias::new("start"))
+            .expr_as(
+                Func::count(Expr::col(Alias::new("host_id"))),
+                Alias::new("hosts"),
+            )
+            .expr_as(
+                Func::avg(Expr::col(Alias::new("cpu_usage"))),
+                Alias::new("cpu_usage"),
+            )
+            .expr_as(
+                Func::sum(Expr::col(Alias::new("memory_used"))),
+                Alias::new("memory_used"),
+            )
+            .expr_as(
+                Func::sum(Expr::col(Alias::new("memory_total"))),
+                Alias::new("memory_total"),
+            )
+            .from_subquery(per_host, Alias::new("per_host"))
+            .group_by_col(Alias::new("start"))
+            .order_by(Alias::new("start"), Order::Asc)
+            .to_owned();
+
+        let rows = state.database.query_all(backend.build(&buckets)).await?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(MetricsBucket {
+                    start: row.try_get("", "start")?,
+                    hosts: row.try_get("", "hosts")?,
+                    cpu_usage: row.try_get("", "cpu_usage")?,
+                    memory_used: row.try_get("", "memory_used")?,
+                    memory_total: row.try_get("", "memory_total")?,
+                })
+            })
+            .collect()
+    }
+
+    /// Loads up to `limit` online hosts whose latest metrics sample was recorded before
+    /// `since`, together with the time of that sample (if any).
+    ///
+    /// The hosts are selected by one grouped query joining their metrics, the latest
+    /// sample is compared in `HAVING`.
+    pub async fn hosts_missing_metrics(
+        state: &AppState,
+        since: DateTime<Utc>,
+        limit: u64,
+    ) -> Result<Vec<(host::Model, Option<DateTime<Utc>>)>> {
+        let latest = || Expr::expr(metric::Column::RecordedAt.max());
+        let metrics: RelationDef = Metric::belongs_to(Host)
+            .from(metric::Column::HostId)
+            .to(host::Column::Id)
+            .into();
+
+        let missing: HashMap<Uuid, Option<DateTime<Utc>>> = Host::find()
+            .select_only()
+            .column(host::Column::Id)
+            .column_as(latest(), "last_metrics_at")
+            .join_rev(JoinType::LeftJoin, metrics)
+            .filter(host::Column::DeletedAt.is_null())
+            .filter(host::Column::LastSeen.gte(state.online_since()))
+            .group_by(host::Column::Id)
+            .group_by(host::Column::MachineId)
+            .having(
+                Condition::any()
+                    .add(latest().is_null())
+                    .add(latest().lt(since)),
+            )
+            .order_by_asc(host::Column::MachineId)
+            .limit(limit)
+            .into_tuple::<(Uuid, Option<DateTime<Utc>>)>()
+            .all(state.database.as_ref())
+            .await?
+            .into_iter()
+            .collect();
+
+        let hosts = Host::find()
+            .filter(host::Column::Id.is_in(missing.keys().copied()))
+            .order_by_asc(host::Column::MachineId)
+            .all(state.database.as_ref())
+            .await?;
+
+        Ok(hosts
+            .into_iter()
+            .map(|host| {
+                let last_metrics_at = missing.get(&host.id).copied().flatten();
+                (host, last_metrics_at)
+            })
+            .collect())
+    }
+
+    /// Counts the active hosts per country, as `(country, online, total)`.
+    ///
+    /// Both counts come from one grouped query, online hosts are counted conditionally.
+    pub async fn geo_health(state: &AppState) -> Result<Vec<(String, i64, i64)>> {
+        let online = Expr::case(host::Column::LastSeen.gte(state.online_since()), 1);
+
+        let countries = Host::find()
+            .select_only()
+            .column(host::Column::MachineCountry)
+            .column_as(SimpleExpr::from(Func::count(online)), "online")
+            .column_as(host::Column::Id.count(), "total")
+            .filter(host::Column::DeletedAt.is_null())
+            .filter(state.counted_hosts())
+            .group_by(host::Column::MachineCountry)
+            .order_by_asc(host::Column::MachineCountry)
+            .into_tuple()
+            .all(state.database.as_ref())
+            .await?;
+
+        Ok(countries)
+    }
+
+    /// Loads the active hosts with a reported build whose OS name or family is one of
+    /// `oses`, ordered by machine ID.
+    pub async fn hosts_with_os(
+        state: &AppState,
+        oses: impl IntoIterator<Item = String> + Clone,
+    ) -> Result<Vec<host::Model>> {
+        let hosts = Host::find()
+            .filter(host::Column::DeletedAt.is_null())
+            .filter(host::Column::OsBuild.ne(""))
+            .filter(
+                Condition::any()
+                    .add(host::Column::OsName.is_in(oses.clone()))
+                    .add(host::Column::OsFamily.is_in(oses)),
+            )
+            .order_by_asc(host::Column::MachineId)
+            .all(state.database.as_ref())
+            .await?;
+
+        Ok(hosts)
+    }
+
+    /// Returns whether the OS `build` is older than `baseline`, see `os_builds_outdated`.
+    pub fn build_older(build: &str, baseline: &str) -> bool {
+        fn parts(build: &str) -> Vec<&str> {
+            build
+                .split(|c: char| !c.is_ascii_alphanumeric())
+                .filter(|part| !part.is_empty())
+                .collect()
+        }
+        let (build, baseline) = (parts(build), parts(baseline));
+
+        for i in 0..build.len().max(baseline.len()) {
+            let a = build.get(i).copied().unwrap_or("0");
+            let b = baseline.get(i).copied().unwrap_or("0");
+            let order = match (a.parse::<u64>(), b.parse::<u64>()) {
+                (Ok(a), Ok(b)) => a.cmp(&b),
+                _ => a.cmp(b),
+            };
+            if order.is_ne() {
+                return order.is_lt();
+            }
+        }
+
+        false
+    }
+
+    /// Loads the active hosts with the given ids, by id.
+    pub async fn hosts_by_ids(
+        state: &AppState,
+        ids: &[Uuid],
+    ) -> Result<HashMap<Uuid, host::Model>> {
+        let hosts = Host::find()
+            .filter(host::Column::Id.is_in(ids.iter().copied()))
+            .filter(host::Column::DeletedAt.is_null())
+            .all(state.database.as_ref())
+            .await?;
+
+        Ok(hosts.into_iter().map(|host| (host.id, host)).collect())
+    }
+
+    /// Loads the next batch of metrics samples of the host recorded within `from..=to`,
+    /// oldest first, resuming after the `(recorded_at, id)` of the last loaded sample.
+    pub async fn metrics_after(
+        state: &AppState,
+        host_id: Uuid,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        after: Option<(DateTime<Utc>, Uuid)>,
+    ) -> Result<Vec<metric::Model>> {
+        const BATCH_SIZE: u64 = 500;
+
+        let mut select = Metric::find()
+            .filter(metric::Column::HostId.eq(host_id))
+            .filter(metric::Column::RecordedAt.between(from, to))
+            .order_by_asc(metric::Column::RecordedAt)
+            .order_by_asc(metric::Column::Id)
+            .limit(BATCH_SIZE);
+        if let Some((recorded_at, id)) = after {
+            select = select.filter(
+                Condition::any()
+                    .add(metric::Column::RecordedAt.gt(recorded_at))
+                    .add(
+                        Condition::all()
+                            .add(metric::Column::RecordedAt.eq(recorded_at))
+                            .add(metric::Column::Id.gt(id)),
+                    ),
+            );
+        }
+
+        Ok(select.all(state.database.as_ref()).await?)
+    }
+
+    /// The contacts with a host within one time bucket, see `heartbeats`.
+    pub struct Heartbeats {
+        pub first: DateTime<Utc>,
+        pub last: DateTime<Utc>,
+        pub count: i64,
+    }
+
+    /// Loads the times the host was heard from within `from..=to`, from its event log
+    /// and its metrics samples, unordered.
+    ///
+    /// The contacts are grouped into buckets of `bucket` seconds in the query, of which
+    /// only the first and the last contact are loaded, so the rows read are bounded by
+    /// the number of buckets rather than the number of contacts.
+    pub async fn heartbeats(
+        state: &AppState,
+        host_id: Uuid,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        bucket: i64,
+    ) -> Result<Vec<Heartbeats>> {
+        let backend = state.database.get_database_backend();
+
+        let start = bucket_start(backend, "received_at", bucket);
+        let mut heartbeats: Vec<(DateTime<Utc>, DateTime<Utc>, i64)> = EventLog::find()
+            .select_only()
+            .column_as(event_log::Column::ReceivedAt.min(), "first")
+            .column_as(event_log::Column::ReceivedAt.max(), "last")
+            .column_as(event_log::Column::Id.count(), "count")
+            .filter(event_log::Column::HostId.eq(host_id))
+            .filter(event_log::Column::ReceivedAt.between(from, to))
+            .group_by(Expr::cust(start))
+            .into_tuple()
+            .all(state.database.as_ref())
+            .await?;
+
+        let start = bucket_start(backend, "recorded_at", bucket);
+        let samples: Vec<(DateTime<Utc>, DateTime<Utc>, i64)> = Metric::find()
+            .select_only()
+            .column_as(metric::Column::RecordedAt.min(), "first")
+            .column_as(metric::Column::RecordedAt.max(), "last")
+            .column_as(metric::Column::Id.count(), "count")
+            .filter(metric::Column::HostId.eq(host_id))
+            .filter(metric::Column::RecordedAt.between(from, to))
+            .group_by(Expr::cust(start))
+            .into_tuple()
+            .all(state.database.as_ref())
+            .await?;
+        heartbeats.extend(samples);
+
+        Ok(heartbeats
+            .into_iter()
+            .map(|(first, last, count)| Heartbeats { first, last, count })
+            .collect())
+    }
+
+    /// Loads up to `limit` active hosts that were seen, least recently seen first.
+    pub async fn stale_hosts(state: &AppState, limit: u64) -> Result<Vec<host::Model>> {
+        let hosts = Host::find()
+            .filter(host::Column::DeletedAt.is_null())
+            .filter(host::Column::LastSeen.is_not_null())
+            .order_by_asc(host::Column::LastSeen)
+            .limit(limit)
+            .all(state.database.as_ref())
+            .await?;
+
+        Ok(hosts)
+    }
+
+    /// Loads the distinct OS families of the active hosts.
+    pub async fn os_families(state: &AppState) -> Result<Vec<String>> {
+        let families = Host::find()
+            .select_only()
+            .column(host::Column::OsFamily)
+            .distinct()
+            .filter(host::Column::DeletedAt.is_null())
+            .into_tuple()
+            .all(state.database.as_ref())
+            .await?;
+
+        Ok(families)
+    }
+
+    /// Counts the active hosts of the OS `family` per OS version.
+    pub async fn os_versions(state: &AppState, family: &str) -> Result<Vec<(String, i64)>> {
+        let versions = Host::find()
+            .select_only()
+            .column(host::Column::OsVersion)
+            .column_as(host::Column::Id.count(), "count")
+            .filter(host::Column::DeletedAt.is_null())
+            .filter(host::Column::OsFamily.eq(family))
+            .group_by(host::Column::OsVersion)
+            .order_by_asc(host::Column::OsVersion)
+            .into_tuple()
+            .all(state.database.as_ref())
+            .await?;
+
+        Ok(versions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::seaorm::*;
+    use crate::testing;
+    use axum::http::Method;
+    use axum::http::StatusCode;
+    use chrono::SecondsFormat;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn agent_versions_are_counted() {
+        let state = testing::state(&[]).await;
+        let token = testing::admin(&state).await;
+        let router = testing::router(&state);
+        for (machine_id, version) in [("m1", "1.0"), ("m2", "1.0"), ("m3", "2.0"), ("m4", "2.0")] {
+            let uri = format!("/api/agent/{}/config?agent_version={}", machine_id, version);
+            let request = testing::request(Method::GET, &uri, None, None);
+            assert_eq!(testing::send(&router, request).await.0, StatusCode::OK);
+        }
+        testing::host(&state, "m5").await;
+
+        // deleted hosts are not counted
+        let deleted = testing::host(&state, "m4").await;
+        Host::update(host::ActiveModel {
+            id: Unchanged(deleted.id),
+            deleted_at: Set(Some(chrono::Utc::now())),
+            ..Default::default()
+        })
+        .exec(state.database.as_ref())
+        .await
+        .unwrap();
+
+        let uri = "/api/dashboard/agent-versions";
+        let request = testing::request(Method::GET, uri, Some(&token), None);
+        let (status, body) = testing::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            json!([
+                { "agent_version": null, "count": 1 },
+                { "agent_version": "1.0", "count": 2 },
+                { "agent_version": "2.0", "count": 1 },
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn online_host_without_metrics_is_flagged() {
+        let state = testing::state(&[]).await;
+        let token = testing::admin(&state).await;
+        testing::host(&state, "broken").await;
+        let metrics = json!([{ "EvtMetricsEmit": [10.0, 1, 2, 3, 4] }]);
+        testing::report(&state, "healthy", None, metrics).await;
+        let offline = testing::host(&state, "offline").await;
+        testing::seen(
+            &state,
+            offline.id,
+            chrono::Utc::now() - chrono::Duration::days(2),
+        )
+        .await;
+
+        let uri = "/api/dashboard/hosts/missing-metrics";
+        let request = testing::request(Method::GET, uri, Some(&token), None);
+        let (status, body) = testing::send(&testing::router(&state), request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        let hosts = body.as_array().unwrap();
+        assert_eq!(hosts.len(), 1, "{}", body);
+        assert_eq!(hosts[0]["machine_id"], "broken");
+        assert_eq!(hosts[0]["last_metrics_at"], json!(null));
+    }
+
+    #[tokio::test]
+    async fn os_versions_are_counted_per_family() {
+        let state = testing::state(&[]).await;
+        let token = testing::admin(&state).await;
+        for (machine_id, family, version) in [
+            ("m1", "linux", "6.1"),
+            ("m2", "linux", "6.1"),
+            ("m3", "linux", "6.8"),
+            ("m4", "windows", "11"),
+        ] {
+            let os = json!([{ "EvtOsEmit": { "family": family, "version": version } }]);
+            testing::report(&state, machine_id, None, os).await;
+        }
+
+        let router = testing::router(&state);
+        let request = |uri| testing::request(Method::GET, uri, Some(&token), None);
+        let (status, body) =
+            testing::send(&router, request("/api/dashboard/os-versions?family=linux")).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(
+            body,
+            json!([
+                { "os_version": "6.1", "count": 2 },
+                { "os_version": "6.8", "count": 1 },
+            ])
+        );
+
+        let (status, _) =
+            testing::send(&router, request("/api/dashboard/os-versions?family=bsd")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn stale_hosts_are_oldest_first() {
+        let state = testing::state(&[]).await;
+        let token = testing::admin(&state).await;
+        let now = chrono::Utc::now();
+        for (machine_id, hours) in [("m1", 1), ("m2", 48), ("m3", 5)] {
+            let host = testing::host(&state, machine_id).await;
+            testing::seen(&state, host.id, now - chrono::Duration::hours(hours)).await;
+        }
+
+        let uri = "/api/dashboard/stale-hosts?limit=2";
+        let request = testing::request(Method::GET, uri, Some(&token), None);
+        let (status, body) = testing::send(&testing::router(&state), request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        let hosts = body.as_array().unwrap();
+        let machine_ids = hosts
+            .iter()
+            .map(|host| &host["machine_id"])
+            .collect::<Vec<_>>();
+        assert_eq!(machine_ids, ["m2", "m3"]);
+        let stale_seconds = hosts[0]["stale_seconds"].as_i64().unwrap();
+        assert!((48 * 3600..48 * 3600 + 60).contains(&stale_seconds));
+    }
+
+    #[tokio::test]
+    async fn geo_health_splits_online_and_offline() {
+        let state = testing::state(&[]).await;
+        let token = testing::admin(&state).await;
+        let two_days_ago = chrono::Utc::now() - chrono::Duration::days(2);
+        for (machine_id, country, online) in [
+            ("m1", "US", true),
+            ("m2", "US", false),
+            ("m3", "US", true),
+            ("m4", "DE", false),
+        ] {
+            let machine = json!([{ "EvtMachineEmit": { "ip": "192.0.2.1", "country": country } }]);
+            testing::report(&state, machine_id, None, machine).await;
+            if !online {
+                let host = testing::host(&state, machine_id).await;
+                testing::seen(&state, host.id, two_days_ago).await;
+            }
+        }
+
+        let request =
+            testing::request(Method::GET, "/api/dashboard/geo/health", Some(&token), None);
+        let (status, body) = testing::send(&testing::router(&state), request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(
+            body,
+            json!([
+                { "country": "DE", "online": 0, "offline": 1 },
+                { "country": "US", "online": 2, "offline": 1 },
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn batch_omits_missing_hosts() {
+        let state = testing::state(&[]).await;
+        let token = testing::admin(&state).await;
+        let m1 = testing::host(&state, "m1").await;
+        testing::host(&state, "m2").await;
+        let m3 = testing::host(&state, "m3").await;
+
+        let ids = json!([m1.id, Uuid::nil(), m3.id]);
+        let request = testing::request(
+            Method::POST,
+            "/api/dashboard/hosts/batch",
+            Some(&token),
+            Some(ids),
+        );
+        let (status, body) = testing::send(&testing::router(&state), request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        let hosts = body.as_array().unwrap().iter();
+        let mut machine_ids = hosts
+            .map(|host| host["machine_id"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        machine_ids.sort();
+        assert_eq!(machine_ids, ["m1", "m3"]);
+    }
+
+    #[tokio::test]
+    async fn virtualization_platforms_are_counted() {
+        let state = testing::state(&[]).await;
+        let token = testing::admin(&state).await;
+        for (machine_id, os) in [
+            (
+                "m1",
+                json!({ "family": "linux", "virtualization_platform": "kvm" }),
+            ),
+            (
+                "m2",
+                json!({ "family": "linux", "virtualization_platform": "kvm" }),
+            ),
+            ("m3", json!({ "family": "linux", "virtualization": false })),
+        ] {
+            testing::report(&state, machine_id, None, json!([{ "EvtOsEmit": os }])).await;
+        }
+
+        let host = testing::host(&state, "m1").await;
+        assert_eq!(host.os_virtualization_platform.as_deref(), Some("kvm"));
+        assert!(host.os_virtualization);
+
+        let request = testing::request(
+            Method::GET,
+            "/api/dashboard/virtualization",
+            Some(&token),
+            None,
+        );
+        let (status, body) = testing::send(&testing::router(&state), request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(
+            body,
+            json!([
+                { "virtualization": false, "platform": null, "count": 1 },
+                { "virtualization": true, "platform": "kvm", "count": 2 },
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn uptime_counts_gaps_beyond_threshold_as_down() {
+        let state = testing::state(&["--offline-threshold", "3600"]).await;
+        let token = testing::admin(&state).await;
+        let host = testing::host(&state, "m1").await;
+
+        // heard from every half hour over the last 12 hours only
+        let now = chrono::Utc::now();
+        for half_hours in 1..=24 {
+            Metric::insert(metric::ActiveModel {
+                id: Set(Uuid::from_bytes(uuidv7::create_raw())),
+                host_id: Set(host.id),
+                cpu_usage: Set(Some(10.0)),
+                memory_used: Set(None),
+                memory_total: Set(None),
+                disk_used: Set(None),
+                disk_total: Set(None),
+                recorded_at: Set(now - chrono::Duration::minutes(30 * half_hours)),
+            })
+            .exec(state.database.as_ref())
+            .await
+            .unwrap();
+        }
+
+        let uri = format!("/api/dashboard/hosts/{}/uptime?range=1d", host.id);
+        let request = testing::request(Method::GET, &uri, Some(&token), None);
+        let (status, body) = testing::send(&testing::router(&state), request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        let uptime = body["uptime"].as_f64().unwrap();
+        assert!((0.45..=0.55).contains(&uptime), "{}", body);
+        assert!(body["heartbeats"].as_u64().unwrap() >= 24, "{}", body);
+    }
+
+    #[tokio::test]
+    async fn uptime_counts_every_contact_of_a_bucket() {
+        let state = testing::state(&["--offline-threshold", "600"]).await;
+        let token = testing::admin(&state).await;
+        let host = testing::host(&state, "m1").await;
+
+        // 30 samples within the last 10 minutes, grouped into two buckets at most
+        let now = chrono::Utc::now();
+        for seconds in (0..600).step_by(20) {
+            Metric::insert(metric::ActiveModel {
+                id: Set(Uuid::from_bytes(uuidv7::create_raw())),
+                host_id: Set(host.id),
+                cpu_usage: Set(Some(10.0)),
+                memory_used: Set(None),
+                memory_total: Set(None),
+                disk_used: Set(None),
+                disk_total: Set(None),
+                recorded_at: Set(now - chrono::Duration::seconds(600 - seconds)),
+            })
+            .exec(state.database.as_ref())
+            .await
+            .unwrap();
+        }
+
+        let uri = format!("/api/dashboard/hosts/{}/uptime?range=1h", host.id);
+        let request = testing::request(Method::GET, &uri, Some(&token), None);
+        let (status, body) = testing::send(&testing::router(&state), request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        // online from the first sample on
+        let uptime = body["uptime"].as_f64().unwrap();
+        assert!((0.16..=0.17).contains(&uptime), "{}", body);
+        assert!(body["heartbeats"].as_u64().unwrap() >= 30, "{}", body);
+    }
+
+    #[tokio::test]
+    async fn machine_ids_are_counted_by_prefix() {
+        let state = testing::state(&[]).await;
+        let token = testing::admin(&state).await;
+        for machine_id in ["abc-1", "abc-2", "abd-1", "b", "abc-3"] {
+            testing::host(&state, machine_id).await;
+        }
+
+        // deleted hosts are not counted
+        let deleted = testing::host(&state, "abc-3").await;
+        Host::update(host::ActiveModel {
+            id: Unchanged(deleted.id),
+            deleted_at: Set(Some(chrono::Utc::now())),
+            ..Default::default()
+        })
+        .exec(state.database.as_ref())
+        .await
+        .unwrap();
+
+        let router = testing::router(&state);
+        let request = |uri| testing::request(Method::GET, uri, Some(&token), None);
+        let (status, body) =
+            testing::send(&router, request("/api/dashboard/id-prefixes?len=3")).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(
+            body,
+            json!([
+                { "prefix": "abc", "count": 2 },
+                { "prefix": "abd", "count": 1 },
+                { "prefix": "b", "count": 1 },
+            ])
+        );
+
+        let (_, body) = testing::send(&router, request("/api/dashboard/id-prefixes?len=1")).await;
+        assert_eq!(
+            body,
+            json!([{ "prefix": "a", "count": 3 }, { "prefix": "b", "count": 1 }])
+        );
+
+        let (status, _) = testing::send(&router, request("/api/dashboard/id-prefixes?len=0")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn compare_reports_os_family_difference() {
+        let state = testing::state(&[]).await;
+        let token = testing::admin(&state).await;
+        for (machine_id, family) in [("m1", "linux"), ("m2", "windows")] {
+            let os = json!([{ "EvtOsEmit": { "family": family, "arch": "x86_64" } }]);
+            testing::report(&state, machine_id, None, os).await;
+        }
+        let a = testing::host(&state, "m1").await;
+        let b = testing::host(&state, "m2").await;
+
+        let uri = format!("/api/dashboard/hosts/compare?a={}&b={}", a.id, b.id);
+        let request = testing::request(Method::GET, &uri, Some(&token), None);
+        let (status, body) = testing::send(&testing::router(&state), request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["a"]["machine_id"], "m1");
+        assert_eq!(body["b"]["machine_id"], "m2");
+
+        let differences = body["differences"].as_array().unwrap();
+        let family = differences
+            .iter()
+            .find(|diff| diff["field"] == "os_family")
+            .unwrap();
+        assert_eq!(family["a"], "linux");
+        assert_eq!(family["b"], "windows");
+        // the same arch is no difference
+        assert!(differences.iter().all(|diff| diff["field"] != "os_arch"));
+    }
+
+    #[tokio::test]
+    async fn metrics_are_aggregated_per_bucket() {
+        let state = testing::state(&[]).await;
+        let token = testing::admin(&state).await;
+        let a = testing::host(&state, "m1").await;
+        let b = testing::host(&state, "m2").await;
+
+        // two hours ago and one hour ago, aligned to the hour
+        let hour = chrono::Utc::now().timestamp() / 3600 * 3600 - 2 * 3600;
+        let at = |seconds: i64| chrono::DateTime::from_timestamp(hour + seconds, 0).unwrap();
+        for (host_id, recorded_at, cpu, used, total) in [
+            (a.id, at(600), 10.0, 100, 1000),
+            (a.id, at(1200), 30.0, 300, 1000),
+            (b.id, at(600), 50.0, 400, 2000),
+            (a.id, at(3600 + 600), 60.0, 500, 1000),
+        ] {
+            Metric::insert(metric::ActiveModel {
+                id: Set(Uuid::from_bytes(uuidv7::create_raw())),
+                host_id: Set(host_id),
+                cpu_usage: Set(Some(cpu)),
+                memory_used: Set(Some(used)),
+                memory_total: Set(Some(total)),
+                disk_used: Set(None),
+                disk_total: Set(None),
+                recorded_at: Set(recorded_at),
+            })
+            .exec(state.database.as_ref())
+            .await
+            .unwrap();
+        }
+
+        let uri = "/api/dashboard/metrics/aggregate?range=24h&bucket=1h";
+        let request = testing::request(Method::GET, uri, Some(&token), None);
+        let (status, body) = testing::send(&testing::router(&state), request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        // samples are averaged per host first, then averaged or summed over the hosts
+        assert_eq!(
+            body,
+            json!([
+                {
+                    "bucket_start": at(0).to_rfc3339(),
+                    "hosts": 2,
+                    "cpu_usage": 35.0,
+                    "memory_used": 600,
+                    "memory_total": 3000,
+                },
+                {
+                    "bucket_start": at(3600).to_rfc3339(),
+                    "hosts": 1,
+                    "cpu_usage": 60.0,
+                    "memory_used": 500,
+                    "memory_total": 1000,
+                },
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn short_ranges_default_to_buckets_of_the_range() {
+        let state = testing::state(&[]).await;
+        let token = testing::admin(&state).await;
+        let host = testing::host(&state, "m1").await;
+
+        // in the second half of an hour, where half-hour and hour buckets start apart
+        let now = chrono::Utc::now().timestamp_millis();
+        let into_hour = now.rem_euclid(3_600_000);
+        let recorded_at = if into_hour >= 1_800_000 {
+            now
+        } else {
+            now - into_hour - 1
+        };
+        let recorded_at = chrono::DateTime::from_timestamp_millis(recorded_at).unwrap();
+        Metric::insert(metric::ActiveModel {
+            id: Set(Uuid::from_bytes(uuidv7::create_raw())),
+            host_id: Set(host.id),
+            cpu_usage: Set(Some(10.0)),
+            memory_used: Set(None),
+            memory_total: Set(None),
+            disk_used: Set(None),
+            disk_total: Set(None),
+            recorded_at: Set(recorded_at),
+        })
+        .exec(state.database.as_ref())
+        .await
+        .unwrap();
+
+        let uri = "/api/dashboard/metrics/aggregate?range=30m";
+        let request = testing::request(Method::GET, uri, Some(&token), None);
+        let (status, body) = testing::send(&testing::router(&state), request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        let start = recorded_at.timestamp() / 1800 * 1800;
+        let start = chrono::DateTime::from_timestamp(start, 0).unwrap();
+        assert_eq!(
+            body,
+            json!([{
+                "bucket_start": start.to_rfc3339(),
+                "hosts": 1,
+                "cpu_usage": 10.0,
+                "memory_used": null,
+                "memory_total": null,
+            }])
+        );
+    }
+
+    #[tokio::test]
+    async fn metrics_export_has_header_and_rows_of_range() {
+        let state = testing::state(&[]).await;
+        let token = testing::admin(&state).await;
+        let host = testing::host(&state, "m1").await;
+        let now = chrono::Utc::now();
+        for hours in [1, 2, 3, 5] {
+            Metric::insert(metric::ActiveModel {
+                id: Set(Uuid::from_bytes(uuidv7::create_raw())),
+                host_id: Set(host.id),
+                cpu_usage: Set(Some(hours as f64)),
+                memory_used: Set(Some(1)),
+                memory_total: Set(Some(2)),
+                disk_used: Set(None),
+                disk_total: Set(None),
+                recorded_at: Set(now - chrono::Duration::hours(hours)),
+            })
+            .exec(state.database.as_ref())
+            .await
+            .unwrap();
+        }
+
+        // the sample of five hours ago is out of range
+        let from = (now - chrono::Duration::hours(4)).to_rfc3339_opts(SecondsFormat::Secs, true);
+        let router = testing::router(&state);
+        let export = |format: &str| {
+            let uri = format!(
+                "/api/dashboard/hosts/{}/metrics/export?from={}&format={}",
+                host.id, from, format
+            );
+            testing::request(Method::GET, &uri, Some(&token), None)
+        };
+
+        let (status, body) = testing::send(&router, export("csv")).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let lines = body.as_str().unwrap().lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[0],
+            "recorded_at,cpu_usage,memory_used,memory_total,disk_used,disk_total"
+        );
+        assert_eq!(lines.len(), 4, "{:?}", lines);
+        // oldest first, missing values left empty
+        assert!(lines[1].ends_with(",3.0,1,2,,"), "{}", lines[1]);
+
+        let (status, body) = testing::send(&router, export("json")).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let samples = body.as_array().unwrap();
+        assert_eq!(samples.len(), 3, "{}", body);
+        assert_eq!(samples[0]["cpu_usage"], 3.0);
+    }
+
+    #[tokio::test]
+    async fn builds_below_the_baseline_are_outdated() {
+        let state = testing::state(&[
+            "--os-build-baseline",
+            "linux=10.2",
+            "--os-build-baseline",
+            "Windows 11=10.0.22631",
+        ])
+        .await;
+        let token = testing::admin(&state).await;
+        for (machine_id, family, name, build) in [
+            ("m1", "linux", "Debian", "9.5"),
+            ("m2", "linux", "Debian", "10.2"),
+            ("m3", "linux", "Debian", "10.10"),
+            ("m4", "windows", "Windows 11", "10.0.19045"),
+            ("m5", "windows", "Windows 11", "10.0.22631.1"),
+            ("m6", "bsd", "FreeBSD", "1"),
+        ] {
+            let os = json!([{ "EvtOsEmit": { "family": family, "name": name, "build": build } }]);
+            testing::report(&state, machine_id, None, os).await;
+        }
+
+        let uri = "/api/dashboard/os-builds/outdated";
+        let request = testing::request(Method::GET, uri, Some(&token), None);
+        let (status, body) = testing::send(&testing::router(&state), request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        // 9 is older than 10, and 10 newer than 2, as numbers
+        let outdated = body.as_array().unwrap();
+        let machine_ids = outdated
+            .iter()
+            .map(|host| &host["machine_id"])
+            .collect::<Vec<_>>();
+        assert_eq!(machine_ids, ["m1", "m4"], "{}", body);
+        assert_eq!(outdated[0]["baseline"], "10.2");
+        assert_eq!(outdated[1]["baseline"], "10.0.22631");
+    }
+}
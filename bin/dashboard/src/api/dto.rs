@@ -0,0 +1,180 @@
+use database::models::alert;
+use database::models::event_log;
+use database::models::hardware_change;
+use database::models::host;
+use database::models::host_alert_threshold;
+use database::models::host_command;
+use database::models::host_log;
+use database::models::metric;
+use database::models::session;
+use database::models::user;
+use proto::admin::agent::AgentErrorResp;
+use proto::admin::alert::AlertResp;
+use proto::admin::alert::HostAlertThresholdResp;
+use proto::admin::command::HostCommandResp;
+use proto::admin::event::EventResp;
+use proto::admin::hardware::HardwareChangeResp;
+use proto::admin::host::HostResp;
+use proto::admin::log::HostLogResp;
+use proto::admin::user::UserResp;
+use proto::auth::session::SessionResp;
+use proto::dashboard::metric::MetricResp;
+
+/// Converts a host model into its response representation.
+pub fn host(model: host::Model) -> HostResp {
+    HostResp {
+        id: model.id.to_string(),
+        machine_id: model.machine_id,
+        display_name: model.display_name,
+        machine_ip: model.machine_ip,
+        machine_country: model.machine_country,
+        machine_geo: model.machine_geo,
+        os_family: model.os_family,
+        os_name: model.os_name,
+        os_version: model.os_version,
+        os_arch: model.os_arch,
+        os_arch_raw: model.os_arch_raw,
+        os_build: model.os_build,
+        os_virtualization: model.os_virtualization,
+        os_virtualization_platform: model.os_virtualization_platform,
+        hashed_cpu: model.hashed_cpu,
+        hashed_gpu: model.hashed_gpu,
+        hashed_memory: model.hashed_memory,
+        hashed_disk: model.hashed_disk,
+        hashed_network: model.hashed_network,
+        last_seen: model.last_seen.map(|time| time.to_rfc3339()),
+        agent_version: model.agent_version,
+        in_maintenance: model
+            .maintenance_until
+            .is_some_and(|until| until > chrono::Utc::now()),
+        maintenance_until: model.maintenance_until.map(|time| time.to_rfc3339()),
+    }
+}
+
+/// Converts a host with a recorded error into its agent error representation.
+///
+/// Hosts without an error get an empty message.
+pub fn agent_error(model: host::Model) -> AgentErrorResp {
+    AgentErrorResp {
+        host_id: model.id.to_string(),
+        machine_id: model.machine_id,
+        display_name: model.display_name,
+        error: model.last_error.unwrap_or_default(),
+        errored_at: model
+            .last_error_at
+            .map(|time| time.to_rfc3339())
+            .unwrap_or_default(),
+    }
+}
+
+/// Converts a metrics sample into its response representation.
+pub fn metric(model: metric::Model) -> MetricResp {
+    MetricResp {
+        recorded_at: model.recorded_at.to_rfc3339(),
+        cpu_usage: model.cpu_usage,
+        memory_used: model.memory_used,
+        memory_total: model.memory_total,
+        disk_used: model.disk_used,
+        disk_total: model.disk_total,
+    }
+}
+
+/// Converts an alert into its response representation.
+pub fn alert(model: alert::Model) -> AlertResp {
+    AlertResp {
+        id: model.id.to_string(),
+        kind: model.kind,
+        host_id: model.host_id.map(|id| id.to_string()),
+        message: model.message,
+        created_at: model.created_at.to_rfc3339(),
+        resolved_at: model.resolved_at.map(|time| time.to_rfc3339()),
+    }
+}
+
+/// Converts the alert thresholds of a host into their response representation.
+pub fn host_alert_threshold(model: host_alert_threshold::Model) -> HostAlertThresholdResp {
+    let percent = |value: Option<i16>| value.and_then(|value| u8::try_from(value).ok());
+
+    HostAlertThresholdResp {
+        host_id: model.host_id.to_string(),
+        cpu_percent: percent(model.cpu_percent),
+        memory_percent: percent(model.memory_percent),
+        disk_percent: percent(model.disk_percent),
+    }
+}
+
+/// Converts a queued host command into its response representation.
+pub fn host_command(model: host_command::Model) -> HostCommandResp {
+    HostCommandResp {
+        command: command_value(&model),
+        id: model.id.to_string(),
+        host_id: model.host_id.to_string(),
+        created_at: model.created_at.to_rfc3339(),
+        delivered_at: model.delivered_at.map(|time| time.to_rfc3339()),
+    }
+}
+
+/// Returns the JSON value of a queued command.
+///
+/// Commands are validated when queued, so an unparsable command is passed on as a
+/// JSON string instead of being dropped.
+pub fn command_value(model: &host_command::Model) -> serde_json::Value {
+    serde_json::from_str(&model.command)
+        .unwrap_or_else(|_| serde_json::Value::String(model.command.clone()))
+}
+
+/// Converts an event log entry into its response representation.
+pub fn event(model: event_log::Model) -> EventResp {
+    EventResp {
+        payload: serde_json::from_str(&model.payload).unwrap_or_default(),
+        id: model.id.to_string(),
+        host_id: model.host_id.to_string(),
+        event_type: model.event_type,
+        received_at: model.received_at.to_rfc3339(),
+    }
+}
+
+/// Converts an agent log snippet into its response representation.
+pub fn host_log(model: host_log::Model) -> HostLogResp {
+    HostLogResp {
+        id: model.id.to_string(),
+        host_id: model.host_id.to_string(),
+        content: model.content,
+        created_at: model.created_at.to_rfc3339(),
+    }
+}
+
+/// Converts a user model into its response representation, leaving out the password hash.
+pub fn user(model: user::Model) -> UserResp {
+    UserResp {
+        id: model.id.to_string(),
+        sa: model.sa,
+        nickname: model.nickname,
+        email: model.email,
+        created_at: model.created_at.to_rfc3339(),
+        updated_at: model.updated_at.to_rfc3339(),
+    }
+}
+
+/// Converts a login session into its response representation.
+pub fn session(model: session::Model) -> SessionResp {
+    SessionResp {
+        id: model.id.to_string(),
+        user_agent: model.user_agent,
+        ip: model.ip,
+        created_at: model.created_at.to_rfc3339(),
+        last_used_at: model.last_used_at.to_rfc3339(),
+    }
+}
+
+/// Converts a hardware change into its response representation.
+pub fn hardware_change(model: hardware_change::Model) -> HardwareChangeResp {
+    HardwareChangeResp {
+        id: model.id.to_string(),
+        host_id: model.host_id.to_string(),
+        column: model.column,
+        old: model.old,
+        new: model.new,
+        changed_at: model.changed_at.to_rfc3339(),
+    }
+}
@@ -0,0 +1,21 @@
+use crate::prelude::axum::*;
+use crate::state::AppState;
+use axum::http::StatusCode;
+use std::sync::Arc;
+
+/// Reports whether the server is able to serve requests.
+///
+/// # Errors
+///
+/// Returns `503 Service Unavailable` if the database cannot be reached.
+pub async fn healthz(State(state): State<Arc<AppState>>) -> Result<&'static str, StatusError> {
+    state.database.ping().await.map_err(|err| {
+        StatusError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "database_unavailable",
+            err.to_string(),
+        )
+    })?;
+
+    Ok("ok")
+}
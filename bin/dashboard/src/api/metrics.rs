@@ -0,0 +1,255 @@
+use crate::prelude::axum::*;
+use crate::prelude::seaorm::metric;
+use crate::state::AppState;
+use axum::http::header;
+use axum::response::IntoResponse;
+use std::fmt::Write;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+/// Content type of the Prometheus text exposition format.
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Quantiles exported for latency summaries.
+const QUANTILES: [f64; 3] = [0.5, 0.95, 0.99];
+
+/// Exports the server metrics in the Prometheus text exposition format.
+///
+/// The following metrics are exported:
+///
+/// - `wk_event_latency_seconds`: Summary of the time between receiving an agent event
+///   and persisting it. Quantiles are omitted until an event was processed.
+/// - `wk_metrics_samples_dropped_total`: Counter of metrics samples dropped by
+///   `--metrics-interval`.
+pub async fn metrics(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let latency = &state.eventbus.latency;
+
+    let mut body = String::new();
+    let _ = writeln!(
+        body,
+        "# HELP wk_event_latency_seconds Time between receiving an agent event and persisting it."
+    );
+    let _ = writeln!(body, "# TYPE wk_event_latency_seconds summary");
+    for quantile in QUANTILES {
+        if let Some(value) = latency.percentile(quantile) {
+            let _ = writeln!(
+                body,
+                "wk_event_latency_seconds{{quantile=\"{}\"}} {}",
+                quantile,
+                value.as_secs_f64()
+            );
+        }
+    }
+    let _ = writeln!(
+        body,
+        "wk_event_latency_seconds_sum {}",
+        latency.sum().as_secs_f64()
+    );
+    let _ = writeln!(body, "wk_event_latency_seconds_count {}", latency.count());
+
+    let _ = writeln!(
+        body,
+        "# HELP wk_metrics_samples_dropped_total Metrics samples dropped by the per-host interval."
+    );
+    let _ = writeln!(body, "# TYPE wk_metrics_samples_dropped_total counter");
+    let _ = writeln!(
+        body,
+        "wk_metrics_samples_dropped_total {}",
+        state.eventbus.dropped_samples.load(Ordering::Relaxed)
+    );
+
+    ([(header::CONTENT_TYPE, CONTENT_TYPE)], body)
+}
+
+/// Exports the latest metrics sample of every host as labeled gauges, in the Prometheus
+/// text exposition format.
+///
+/// Every series is labeled with `host_id` and `os_family`. The following metrics are
+/// exported, a value the agent did not report is omitted:
+///
+/// - `wk_host_cpu_usage`: The CPU usage in percent.
+/// - `wk_host_memory_used_bytes` / `wk_host_memory_total_bytes`: The memory usage.
+/// - `wk_host_disk_used_bytes` / `wk_host_disk_total_bytes`: The disk usage.
+/// - `wk_host_metrics_omitted`: Unlabeled gauge of the hosts left out by the cap.
+///
+/// Soft-deleted hosts and hosts without a sample are excluded. To bound the number of
+/// series, at most `--metrics-max-hosts` hosts are exported, ordered by id.
+///
+/// # Errors
+///
+/// Returns an error if database operations fail.
+pub async fn host_metrics(
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, AxumError> {
+    let (samples, omitted) = internal::latest_samples(&state, state.args.metrics_max_hosts).await?;
+
+    type Value = fn(&metric::Model) -> Option<f64>;
+    let gauges: [(&str, &str, Value); 5] = [
+        ("wk_host_cpu_usage", "CPU usage in percent.", |m| {
+            m.cpu_usage
+        }),
+        ("wk_host_memory_used_bytes", "Used memory in bytes.", |m| {
+            m.memory_used.map(|v| v as f64)
+        }),
+        (
+            "wk_host_memory_total_bytes",
+            "Total memory in bytes.",
+            |m| m.memory_total.map(|v| v as f64),
+        ),
+        (
+            "wk_host_disk_used_bytes",
+            "Used disk space in bytes.",
+            |m| m.disk_used.map(|v| v as f64),
+        ),
+        (
+            "wk_host_disk_total_bytes",
+            "Total disk space in bytes.",
+            |m| m.disk_total.map(|v| v as f64),
+        ),
+    ];
+
+    let mut body = String::new();
+    for (name, help, value) in gauges {
+        let _ = writeln!(body, "# HELP {} {}", name, help);
+        let _ = writeln!(body, "# TYPE {} gauge", name);
+        for (host, sample) in &samples {
+            if let Some(value) = value(sample) {
+                let _ = writeln!(
+                    body,
+                    "{}{{host_id=\"{}\",os_family=\"{}\"}} {}",
+                    name,
+                    host.id,
+                    escape_label(&host.os_family),
+                    value
+                );
+            }
+        }
+    }
+
+    let _ = writeln!(
+        body,
+        "# HELP wk_host_metrics_omitted Hosts left out of the per-host series by the cap."
+    );
+    let _ = writeln!(body, "# TYPE wk_host_metrics_omitted gauge");
+    let _ = writeln!(body, "wk_host_metrics_omitted {}", omitted);
+
+    Ok(([(header::CONTENT_TYPE, CONTENT_TYPE)], body))
+}
+
+/// Escapes a label value for the Prometheus text exposition format.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+mod internal {
+    use crate::prelude::seaorm::*;
+    use crate::state::AppState;
+    use anyhow::Result;
+    use chrono::DateTime;
+    use chrono::Utc;
+    use sea_orm::QuerySelect;
+    use std::collections::HashMap;
+
+    /// Loads the latest metrics sample of up to `limit` active hosts, ordered by host id,
+    /// and the number of further hosts with a sample that were left out.
+    pub async fn latest_samples(
+        state: &AppState,
+        limit: u64,
+    ) -> Result<(Vec<(host::Model, metric::Model)>, u64)> {
+        let hosts = Host::find()
+            .filter(host::Column::DeletedAt.is_null())
+            .order_by_asc(host::Column::Id)
+            .all(state.database.as_ref())
+            .await?;
+
+        let latest: HashMap<Uuid, DateTime<Utc>> = Metric::find()
+            .select_only()
+            .column(metric::Column::HostId)
+            .column_as(metric::Column::RecordedAt.max(), "recorded_at")
+            .group_by(metric::Column::HostId)
+            .into_tuple::<(Uuid, DateTime<Utc>)>()
+            .all(state.database.as_ref())
+            .await?
+            .into_iter()
+            .collect();
+
+        let (hosts, omitted): (Vec<_>, Vec<_>) = hosts
+            .into_iter()
+            .filter(|host| latest.contains_key(&host.id))
+            .enumerate()
+            .partition(|(index, _)| (*index as u64) < limit);
+
+        let mut samples: HashMap<Uuid, metric::Model> = Metric::find()
+            .filter(metric::Column::HostId.is_in(hosts.iter().map(|(_, host)| host.id)))
+            .filter(
+                metric::Column::RecordedAt.is_in(hosts.iter().map(|(_, host)| latest[&host.id])),
+            )
+            .all(state.database.as_ref())
+            .await?
+            .into_iter()
+            .filter(|sample| latest.get(&sample.host_id) == Some(&sample.recorded_at))
+            .map(|sample| (sample.host_id, sample))
+            .collect();
+
+        Ok((
+            hosts
+                .into_iter()
+                .filter_map(|(_, host)| {
+                    let sample = samples.remove(&host.id)?;
+                    Some((host, sample))
+                })
+                .collect(),
+            omitted.len() as u64,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing;
+    use axum::http::Method;
+    use axum::http::StatusCode;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn host_gauges_are_labeled_and_capped() {
+        let state = testing::state(&["--metrics-max-hosts", "2"]).await;
+        for (machine_id, family) in [("m1", "linux"), ("m2", "windows"), ("m3", "linux")] {
+            let events = json!([
+                { "EvtOsEmit": { "family": family } },
+                { "EvtMetricsEmit": [12.5, 1, 2, 3, 4] },
+            ]);
+            testing::report(&state, machine_id, None, events).await;
+        }
+
+        let request = testing::request(Method::GET, "/metrics/hosts", None, None);
+        let (status, body) = testing::send(&testing::router(&state), request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let body = body.as_str().unwrap();
+
+        let lines = body.lines().collect::<Vec<_>>();
+        let cpu = lines
+            .iter()
+            .filter(|line| line.starts_with("wk_host_cpu_usage{"))
+            .count();
+        assert_eq!(cpu, 2, "{}", body);
+        assert!(lines.contains(&"wk_host_metrics_omitted 1"), "{}", body);
+
+        // hosts are exported by id, the last one is left out
+        let mut hosts = Vec::new();
+        for machine_id in ["m1", "m2", "m3"] {
+            hosts.push(testing::host(&state, machine_id).await);
+        }
+        hosts.sort_by_key(|host| host.id);
+        for (index, host) in hosts.iter().enumerate() {
+            let gauge = format!(
+                "wk_host_cpu_usage{{host_id=\"{}\",os_family=\"{}\"}} 12.5",
+                host.id, host.os_family
+            );
+            assert_eq!(lines.contains(&gauge.as_str()), index < 2, "{}", body);
+        }
+    }
+}
@@ -1,2 +1,11 @@
+pub mod admin;
 pub mod agent;
 pub mod auth;
+pub mod dashboard;
+pub mod health;
+pub mod metrics;
+pub mod server;
+pub mod user;
+
+mod dto;
+mod params;
@@ -0,0 +1,287 @@
+use crate::middlewares::is_form;
+use crate::prelude::axum::StatusError;
+use axum::extract::FromRequest;
+use axum::extract::FromRequestParts;
+use axum::extract::Path;
+use axum::extract::Request;
+use axum::http::header;
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use axum::Form;
+use axum::Json;
+use chrono::DateTime;
+use chrono::Utc;
+use sea_orm::prelude::Uuid;
+use serde::de::DeserializeOwned;
+use std::fmt;
+use std::ops::Deref;
+
+/// Parses an optional RFC 3339 timestamp query parameter.
+///
+/// # Errors
+///
+/// Returns a `StatusError` if the timestamp is malformed.
+pub fn parse_time(value: Option<&str>) -> Result<Option<DateTime<Utc>>, StatusError> {
+    value
+        .map(|value| {
+            DateTime::parse_from_rfc3339(value)
+                .map(|time| time.with_timezone(&Utc))
+                .map_err(|err| {
+                    StatusError::new(
+                        StatusCode::BAD_REQUEST,
+                        "invalid_time",
+                        format!("invalid timestamp `{}`: {}", value, err),
+                    )
+                })
+        })
+        .transpose()
+}
+
+/// Parses an optional time range query parameter, such as `30m`, `12h` or `7d`.
+///
+/// The range is a positive integer followed by a unit, one of `s`, `m`, `h` or `d`.
+/// `default` is returned if the parameter is missing.
+///
+/// # Errors
+///
+/// Returns a `StatusError` if the range is malformed, zero or longer than `max`.
+pub fn parse_range(
+    value: Option<&str>,
+    default: chrono::Duration,
+    max: chrono::Duration,
+) -> Result<chrono::Duration, StatusError> {
+    let Some(value) = value else {
+        return Ok(default);
+    };
+
+    let invalid = |reason: &str| {
+        StatusError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_range",
+            format!("invalid range `{}`: {}", value, reason),
+        )
+    };
+
+    // the unit is the last character, which need not be a single byte
+    let split = value.char_indices().last().map_or(0, |(index, _)| index);
+    let (amount, unit) = value.split_at(split);
+    let amount = amount
+        .parse::<i64>()
+        .ok()
+        .filter(|amount| *amount > 0)
+        .ok_or_else(|| invalid("expected a positive number followed by a unit"))?;
+    let range = match unit {
+        "s" => chrono::Duration::try_seconds(amount),
+        "m" => chrono::Duration::try_minutes(amount),
+        "h" => chrono::Duration::try_hours(amount),
+        "d" => chrono::Duration::try_days(amount),
+        _ => return Err(invalid("unit must be one of `s`, `m`, `h` or `d`")),
+    }
+    .filter(|range| *range <= max)
+    .ok_or_else(|| invalid("range is too long"))?;
+
+    Ok(range)
+}
+
+/// Parses an optional UUID query parameter.
+///
+/// # Errors
+///
+/// Returns a `StatusError` if the UUID is malformed.
+pub fn parse_uuid(value: Option<&str>) -> Result<Option<Uuid>, StatusError> {
+    value
+        .map(|value| {
+            Uuid::parse_str(value).map_err(|err| {
+                StatusError::new(
+                    StatusCode::BAD_REQUEST,
+                    "invalid_uuid",
+                    format!("invalid uuid `{}`: {}", value, err),
+                )
+            })
+        })
+        .transpose()
+}
+
+/// Machine ID extracted from the `{machine_id}` path segment of agent routes.
+///
+/// A valid machine ID has 1 to 255 characters, each an ASCII letter, digit, `-`, `_`,
+/// `.` or `:`. Invalid IDs are rejected with `400 Bad Request` before the handler runs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MachineId(String);
+
+impl MachineId {
+    /// Validates a machine ID.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `StatusError` if the ID is empty, too long or contains other characters.
+    pub fn parse(value: &str) -> Result<Self, StatusError> {
+        let valid = (1..=255).contains(&value.len())
+            && value
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b':'));
+        if !valid {
+            return Err(StatusError::new(
+                StatusCode::BAD_REQUEST,
+                "invalid_machine_id",
+                format!("invalid machine id `{}`", value),
+            ));
+        }
+
+        Ok(Self(value.to_owned()))
+    }
+}
+
+impl Deref for MachineId {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for MachineId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl<S> FromRequestParts<S> for MachineId
+where
+    S: Send + Sync,
+{
+    type Rejection = StatusError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Path(value) = Path::<String>::from_request_parts(parts, state)
+            .await
+            .map_err(|err| {
+                StatusError::new(
+                    StatusCode::BAD_REQUEST,
+                    "invalid_machine_id",
+                    err.body_text(),
+                )
+            })?;
+
+        Self::parse(&value)
+    }
+}
+
+/// Request body deserialized from either JSON or a URL encoded form.
+///
+/// The encoding is chosen by the `Content-Type`: form encoded bodies are read like
+/// `Form`, everything else like `Json`, so HTML forms and minimal clients can post the
+/// same request as JSON clients. Rejections are those of the chosen extractor.
+pub struct JsonOrForm<T>(pub T);
+
+impl<S, T> FromRequest<S> for JsonOrForm<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let form = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(is_form);
+
+        if form {
+            let Form(value) = Form::<T>::from_request(req, state)
+                .await
+                .map_err(IntoResponse::into_response)?;
+            Ok(Self(value))
+        } else {
+            let Json(value) = Json::<T>::from_request(req, state)
+                .await
+                .map_err(IntoResponse::into_response)?;
+            Ok(Self(value))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing;
+    use axum::body::Body;
+    use axum::http::header;
+    use axum::http::Method;
+    use axum::http::Request;
+    use axum::http::StatusCode;
+
+    #[test]
+    fn range_needs_a_number_and_a_known_unit() {
+        let day = chrono::Duration::days(1);
+        let parse = |value| super::parse_range(Some(value), day, day);
+
+        assert_eq!(parse("30m").unwrap(), chrono::Duration::minutes(30));
+        assert_eq!(parse("1d").unwrap(), day);
+        for invalid in ["5é", "é", "5x", "", "h", "0h", "25h"] {
+            let err = parse(invalid).unwrap_err();
+            assert_eq!(err.status, StatusCode::BAD_REQUEST, "{}", invalid);
+            assert_eq!(err.code, "invalid_range", "{}", invalid);
+        }
+    }
+
+    #[tokio::test]
+    async fn machine_id_is_validated_by_the_extractor() {
+        let state = testing::state(&[]).await;
+        let router = testing::router(&state);
+        let config = |machine_id: &str| {
+            let uri = format!("/api/agent/{}/config", machine_id);
+            testing::request(Method::GET, &uri, None, None)
+        };
+
+        for valid in ["m1", "4c4c4544-0042.host_a:1", &"a".repeat(255)] {
+            let (status, body) = testing::send(&router, config(valid)).await;
+            assert_eq!(status, StatusCode::OK, "{}: {}", valid, body);
+        }
+        for invalid in ["bad%20id", "m%241", "%C3%A9t%C3%A9", &"a".repeat(256)] {
+            let (status, body) = testing::send(&router, config(invalid)).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}: {}", invalid, body);
+            assert_eq!(body["code"], "invalid_machine_id");
+        }
+    }
+
+    #[tokio::test]
+    async fn auth_accepts_form_bodies() {
+        let _initialization = testing::initialization().await;
+        let state = testing::state(&["--disable-captcha"]).await;
+        crate::api::user::reinitlizated(&state).await.unwrap();
+        let router = testing::router(&state);
+        let post = |uri: &str, content_type: &str, body: &'static str| {
+            Request::post(uri)
+                .header(header::CONTENT_TYPE, content_type)
+                .body(Body::from(body))
+                .unwrap()
+        };
+        let form = "application/x-www-form-urlencoded";
+
+        let init = "captcha_id=&captcha_answer=&email=admin%40example.com&password=secret+1";
+        let (status, body) = testing::send(&router, post("/api/auth/init", form, init)).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        let credentials = "email=admin%40example.com&password=secret+1";
+        let request = post("/api/auth/authorize", form, credentials);
+        let (status, body) = testing::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert!(body["token"].is_string(), "{}", body);
+
+        for uri in ["/api/auth/init", "/api/auth/authorize"] {
+            let request = post(uri, "text/plain", credentials);
+            let (status, body) = testing::send(&router, request).await;
+            assert_eq!(
+                status,
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "{}: {}",
+                uri,
+                body
+            );
+            assert_eq!(body["code"], "unsupported_media_type");
+        }
+    }
+}
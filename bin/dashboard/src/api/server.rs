@@ -0,0 +1,96 @@
+use crate::prelude::axum::*;
+use crate::state::AppState;
+use axum::Json;
+use proto::server::ServerCapabilitiesResp;
+use std::sync::Arc;
+
+/// Describes the optional features of the server, so a frontend can adapt its UI to
+/// the version and configuration it talks to.
+///
+/// The response is a JSON object with the following fields:
+///
+/// - `version`: The server version.
+/// - `tls`: Whether the server terminates TLS itself, always `false` as it is meant to
+///   run behind a reverse proxy.
+/// - `geoip`: Whether the server assigns a country to hosts whose agent reports none,
+///   which it does with `--default-country` set.
+/// - `default_country`: The country of hosts which report none, see `--default-country`.
+/// - `webhooks`: Whether host changes are sent to `--webhook-url`.
+/// - `metrics`: Whether the Prometheus endpoints are served on this listener, rather
+///   than on `--admin-listen`.
+/// - `admin_api`: Whether the admin API is served on this listener.
+/// - `captcha`: Whether initialization requires a captcha.
+/// - `read_only`: Whether the server runs with `--read-only`.
+///
+/// No secret or address is exposed, so the endpoint requires no authorization.
+pub async fn capabilities(State(state): State<Arc<AppState>>) -> Json<ServerCapabilitiesResp> {
+    let args = &state.args;
+    let separate = args.admin_listen.is_some();
+    let default_country = state.settings().default_country;
+
+    Json(ServerCapabilitiesResp {
+        version: env!("CARGO_PKG_VERSION").to_owned(),
+        tls: false,
+        geoip: default_country.is_some(),
+        default_country,
+        webhooks: args.webhook_url.is_some(),
+        metrics: !separate,
+        admin_api: !(separate && args.admin_listen_api),
+        captcha: !args.disable_captcha,
+        read_only: args.read_only,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing;
+    use axum::http::Method;
+    use axum::http::StatusCode;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn capabilities_follow_the_config_and_expose_no_secret() {
+        let state = testing::state(&[
+            "--secret",
+            "capabilities-secret",
+            "--webhook-url",
+            "http://hooks.example.com/capabilities-token",
+            "--default-country",
+            "DE",
+            "--disable-captcha",
+        ])
+        .await;
+
+        let request = testing::request(Method::GET, "/api/capabilities", None, None);
+        let (status, body) = testing::send(&testing::router(&state), request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(
+            body,
+            json!({
+                "version": env!("CARGO_PKG_VERSION"),
+                "tls": false,
+                "geoip": true,
+                "default_country": "DE",
+                "webhooks": true,
+                "metrics": true,
+                "admin_api": true,
+                "captcha": false,
+                "read_only": false,
+            })
+        );
+
+        let raw = body.to_string();
+        assert!(!raw.contains("capabilities-secret"), "{}", raw);
+        assert!(!raw.contains("capabilities-token"), "{}", raw);
+    }
+
+    #[tokio::test]
+    async fn geoip_follows_the_default_country() {
+        let state = testing::state(&[]).await;
+        let request = testing::request(Method::GET, "/api/capabilities", None, None);
+        let (status, body) = testing::send(&testing::router(&state), request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["geoip"], false);
+        assert_eq!(body["default_country"], json!(null));
+    }
+}
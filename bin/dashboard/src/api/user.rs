@@ -0,0 +1,353 @@
+use crate::prelude::axum::StatusError;
+use crate::state::AppState;
+use anyhow::anyhow;
+use anyhow::Result;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::SaltString;
+use argon2::Argon2;
+use argon2::PasswordHash;
+use argon2::PasswordHasher;
+use argon2::PasswordVerifier;
+use axum::http::StatusCode;
+use database::models::prelude::User;
+use sea_orm::EntityTrait;
+use sea_orm::PaginatorTrait;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+/// Atomic boolean to check if the database has been initialized
+///
+/// if this value is true, checks can fast returning
+static REF_INITLIZATED: AtomicBool = AtomicBool::new(false);
+
+/// Checks if the database has any users.
+///
+/// If the database has at least one user, this function returns `Ok(true)`.
+/// Otherwise, it returns `Ok(false)`.
+///
+/// This function is used to check if the database has been initialized.
+/// If the database has not been initialized, the application will
+/// redirect to the initialization page.
+///
+/// With `--initialized-marker`, an existing marker file counts as initialized without
+/// asking the database, and the marker is written once users are found.
+pub async fn initlizated(state: &AppState) -> Result<bool> {
+    if !REF_INITLIZATED.load(Ordering::Relaxed) {
+        let marker = marker_path(state);
+        if let Some(marker) = &marker {
+            if tokio::fs::try_exists(marker).await.unwrap_or(false) {
+                REF_INITLIZATED.store(true, Ordering::Relaxed);
+                return Ok(true);
+            }
+        }
+
+        // check any user exists
+        let next = User::find().count(state.database.as_ref()).await? > 0;
+
+        // persist for the next start, a missing marker only costs another count
+        if let (true, Some(marker)) = (next, &marker) {
+            if let Err(err) = tokio::fs::write(marker, b"").await {
+                tracing::warn!(
+                    "write initialized marker {} failed: {}",
+                    marker.display(),
+                    err
+                );
+            }
+        }
+
+        // CAS false -> next
+        _ = REF_INITLIZATED.compare_exchange(false, next, Ordering::Relaxed, Ordering::Relaxed);
+
+        Ok(next)
+    } else {
+        Ok(true)
+    }
+}
+
+/// Drops the cached initialized state and checks the database again.
+///
+/// Once initialized, the state is otherwise never checked again, so users removed
+/// outside the application, e.g. directly in the database, are only noticed this way.
+/// The marker of `--initialized-marker` is removed first, so it cannot hide them.
+pub async fn reinitlizated(state: &AppState) -> Result<bool> {
+    REF_INITLIZATED.store(false, Ordering::Relaxed);
+
+    if let Some(marker) = marker_path(state) {
+        match tokio::fs::remove_file(&marker).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+    }
+
+    initlizated(state).await
+}
+
+/// Path of the initialized marker, if `--initialized-marker` is set.
+fn marker_path(state: &AppState) -> Option<PathBuf> {
+    state
+        .args
+        .initialized_marker
+        .then(|| state.args.data_dir.join("initialized"))
+}
+
+/// Hashes a user `password` with Argon2 and a random salt, in PHC string format.
+///
+/// # Errors
+///
+/// Returns an error if the hash cannot be generated.
+pub fn hash_password(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| anyhow!("generate password hash failed. {}", e))
+}
+
+/// Ensures another user may be created under `--max-users`.
+///
+/// # Errors
+///
+/// Returns `403 Forbidden` with code `user_limit_reached` if the number of users has
+/// reached the cap, or an error if database operations fail.
+pub async fn ensure_capacity(state: &AppState) -> Result<()> {
+    let Some(max) = state.args.max_users.filter(|max| *max > 0) else {
+        return Ok(());
+    };
+
+    if User::find().count(state.database.as_ref()).await? >= max {
+        return Err(StatusError::new(
+            StatusCode::FORBIDDEN,
+            "user_limit_reached",
+            format!("user limit of {} reached", max),
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Checks user fields against `--max-nickname-length`, `--max-email-length` and
+/// `--max-password-length`, before they reach the database or the password hasher.
+///
+/// Lengths are counted in characters, `None` fields are not checked.
+///
+/// # Errors
+///
+/// Returns `400 Bad Request` with code `nickname_too_long`, `email_too_long` or
+/// `password_too_long` for the first field that is too long.
+pub fn check_lengths(
+    state: &AppState,
+    nickname: Option<&str>,
+    email: Option<&str>,
+    password: Option<&str>,
+) -> Result<(), StatusError> {
+    let fields = [
+        (
+            "nickname",
+            "nickname_too_long",
+            nickname,
+            state.args.max_nickname_length,
+        ),
+        (
+            "email",
+            "email_too_long",
+            email,
+            state.args.max_email_length,
+        ),
+        (
+            "password",
+            "password_too_long",
+            password,
+            state.args.max_password_length,
+        ),
+    ];
+    for (field, code, value, max) in fields {
+        if value.is_some_and(|value| value.chars().count() > max) {
+            return Err(StatusError::new(
+                StatusCode::BAD_REQUEST,
+                code,
+                format!("{} must not be longer than {} characters", field, max),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks a user `password` against its stored Argon2 `hash`.
+///
+/// A malformed hash never matches.
+pub fn verify_password(hash: &str, password: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|hash| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing;
+    use axum::http::Method;
+    use axum::http::StatusCode;
+    use database::models::prelude::User;
+    use sea_orm::EntityTrait;
+    use sea_orm::PaginatorTrait;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn users_beyond_the_cap_are_refused() {
+        let state = testing::state(&["--max-users", "2"]).await;
+        let token = testing::admin(&state).await;
+        let router = testing::router(&state);
+        let create = |email: &str| {
+            let user = json!({ "email": email, "password": "password" });
+            testing::request(Method::POST, "/api/admin/users", Some(&token), Some(user))
+        };
+
+        let (status, body) = testing::send(&router, create("second@example.com")).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        let (status, body) = testing::send(&router, create("third@example.com")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["code"], "user_limit_reached");
+    }
+
+    #[tokio::test]
+    async fn overlong_fields_are_refused() {
+        let state = testing::state(&[
+            "--max-nickname-length",
+            "8",
+            "--max-email-length",
+            "20",
+            "--max-password-length",
+            "10",
+        ])
+        .await;
+        let token = testing::admin(&state).await;
+        let router = testing::router(&state);
+        let long_nickname = "n".repeat(9);
+        let long_email = format!("{}@example.com", "e".repeat(9));
+        let long_password = "p".repeat(11);
+
+        let fields = [
+            ("nickname", long_nickname.as_str(), "nickname_too_long"),
+            ("email", long_email.as_str(), "email_too_long"),
+            ("password", long_password.as_str(), "password_too_long"),
+        ];
+        for (field, value, code) in fields {
+            let mut user = json!({
+                "captcha_id": "",
+                "captcha_answer": "",
+                "nickname": "Nick",
+                "email": "a@example.com",
+                "password": "password",
+            });
+            user[field] = json!(value);
+
+            let mut requests = vec![testing::request(
+                Method::POST,
+                "/api/admin/users",
+                Some(&token),
+                Some(user.clone()),
+            )];
+            // the nickname is not part of the init request
+            if field != "nickname" {
+                requests.push(testing::request(
+                    Method::POST,
+                    "/api/auth/init",
+                    None,
+                    Some(user.clone()),
+                ));
+            }
+            // the password is not part of the profile
+            if field != "password" {
+                let profile = json!({ field: value });
+                requests.push(testing::request(
+                    Method::PUT,
+                    "/api/auth/profile",
+                    Some(&token),
+                    Some(profile),
+                ));
+            }
+
+            for request in requests {
+                let uri = request.uri().clone();
+                let (status, body) = testing::send(&router, request).await;
+                assert_eq!(
+                    status,
+                    StatusCode::BAD_REQUEST,
+                    "{} {}: {}",
+                    uri,
+                    field,
+                    body
+                );
+                assert_eq!(body["code"], code, "{} {}", uri, field);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn init_is_available_again_after_reinitialization() {
+        let _initialization = testing::initialization().await;
+        let state = testing::state(&["--disable-captcha"]).await;
+        super::reinitlizated(&state).await.unwrap();
+        let router = testing::router(&state);
+        let init = |email: &str| {
+            let body = json!({
+                "captcha_id": "",
+                "captcha_answer": "",
+                "email": email,
+                "password": "password",
+            });
+            testing::request(Method::POST, "/api/auth/init", None, Some(body))
+        };
+        let users = async || User::find().count(state.database.as_ref()).await.unwrap();
+
+        let (status, body) = testing::send(&router, init("first@example.com")).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(users().await, 1);
+        assert!(super::initlizated(&state).await.unwrap());
+
+        // removed behind the back of the application, the cached state still holds
+        User::delete_many()
+            .exec(state.database.as_ref())
+            .await
+            .unwrap();
+        let (status, _) = testing::send(&router, init("second@example.com")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(users().await, 0);
+
+        assert!(!super::reinitlizated(&state).await.unwrap());
+        let (status, body) = testing::send(&router, init("third@example.com")).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(users().await, 1);
+
+        let token = testing::admin(&state).await;
+        let uri = "/api/admin/initialized/refresh";
+        let request = testing::request(Method::POST, uri, Some(&token), None);
+        let (status, body) = testing::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body, json!({ "initialized": true }));
+    }
+
+    #[tokio::test]
+    async fn marker_counts_as_initialized() {
+        let _initialization = testing::initialization().await;
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = dir.path().to_str().unwrap();
+        let state = testing::state(&["--initialized-marker", "--data-dir", data_dir]).await;
+
+        // no users and no marker yet
+        assert!(!super::reinitlizated(&state).await.unwrap());
+
+        std::fs::write(dir.path().join("initialized"), b"").unwrap();
+        assert!(super::initlizated(&state).await.unwrap());
+        assert_eq!(
+            User::find().count(state.database.as_ref()).await.unwrap(),
+            0
+        );
+    }
+}
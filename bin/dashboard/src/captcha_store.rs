@@ -0,0 +1,130 @@
+use crate::prelude::seaorm::*;
+use anyhow::Result;
+use chrono::DateTime;
+use chrono::Utc;
+use dashmap::DashMap;
+use futures::future::BoxFuture;
+use sea_orm::DatabaseConnection;
+use sea_orm::IntoActiveModel;
+use std::sync::Arc;
+
+/// Storage of the answers of generated captchas, selected by `--captcha-store`.
+///
+/// A captcha can be taken once, and only until it expires. Expired captchas are
+/// removed by `sweep`.
+pub trait CaptchaStore: Send + Sync {
+    /// Stores the answer of a new captcha expiring at `expired_at` and returns its id.
+    fn insert(&self, answer: String, expired_at: DateTime<Utc>) -> BoxFuture<'_, Result<Uuid>>;
+
+    /// Removes the captcha and returns its answer, unless it does not exist or expired.
+    fn take(&self, id: Uuid) -> BoxFuture<'_, Result<Option<String>>>;
+
+    /// Removes the captchas expiring before `before` and returns their number.
+    fn sweep(&self, before: DateTime<Utc>) -> BoxFuture<'_, Result<u64>>;
+}
+
+/// Keeps captchas in the `captcha` table, shared by every server on the database.
+pub struct DatabaseCaptchaStore {
+    database: Arc<DatabaseConnection>,
+}
+
+impl DatabaseCaptchaStore {
+    pub fn new(database: Arc<DatabaseConnection>) -> Self {
+        Self { database }
+    }
+}
+
+impl CaptchaStore for DatabaseCaptchaStore {
+    fn insert(&self, answer: String, expired_at: DateTime<Utc>) -> BoxFuture<'_, Result<Uuid>> {
+        Box::pin(async move {
+            let id = Uuid::from_bytes(uuidv7::create_raw());
+            Captcha::insert(
+                captcha::Model {
+                    id,
+                    answer,
+                    expired_at,
+                }
+                .into_active_model(),
+            )
+            .exec(self.database.as_ref())
+            .await?;
+
+            Ok(id)
+        })
+    }
+
+    fn take(&self, id: Uuid) -> BoxFuture<'_, Result<Option<String>>> {
+        Box::pin(async move {
+            let found = Captcha::find()
+                .filter(captcha::Column::Id.eq(id))
+                .filter(captcha::Column::ExpiredAt.gt(Utc::now()))
+                .one(self.database.as_ref())
+                .await?;
+
+            let Some(found) = found else {
+                return Ok(None);
+            };
+
+            // only the request which deletes the captcha gets its answer, concurrent
+            // ones may have found it too
+            let deleted = Captcha::delete_many()
+                .filter(captcha::Column::Id.eq(id))
+                .filter(captcha::Column::ExpiredAt.gt(Utc::now()))
+                .exec(self.database.as_ref())
+                .await?;
+
+            Ok((deleted.rows_affected == 1).then_some(found.answer))
+        })
+    }
+
+    fn sweep(&self, before: DateTime<Utc>) -> BoxFuture<'_, Result<u64>> {
+        Box::pin(async move {
+            let swept = Captcha::delete_many()
+                .filter(captcha::Column::ExpiredAt.lt(before))
+                .exec(self.database.as_ref())
+                .await?;
+
+            Ok(swept.rows_affected)
+        })
+    }
+}
+
+/// Keeps captchas in memory, which saves the database writes but only works with a
+/// single server. Captchas are lost on restart.
+///
+/// The map is sharded, so concurrent captchas do not contend on a single lock. Expired
+/// captchas are refused by `take` and removed by the sweep.
+#[derive(Default)]
+pub struct MemoryCaptchaStore {
+    captchas: DashMap<Uuid, (String, DateTime<Utc>)>,
+}
+
+impl CaptchaStore for MemoryCaptchaStore {
+    fn insert(&self, answer: String, expired_at: DateTime<Utc>) -> BoxFuture<'_, Result<Uuid>> {
+        let id = Uuid::from_bytes(uuidv7::create_raw());
+        self.captchas.insert(id, (answer, expired_at));
+
+        Box::pin(std::future::ready(Ok(id)))
+    }
+
+    fn take(&self, id: Uuid) -> BoxFuture<'_, Result<Option<String>>> {
+        let answer = self
+            .captchas
+            .remove(&id)
+            .filter(|(_, (_, expired_at))| *expired_at > Utc::now())
+            .map(|(_, (answer, _))| answer);
+
+        Box::pin(std::future::ready(Ok(answer)))
+    }
+
+    fn sweep(&self, before: DateTime<Utc>) -> BoxFuture<'_, Result<u64>> {
+        let mut swept = 0;
+        self.captchas.retain(|_, (_, expired_at)| {
+            let keep = *expired_at >= before;
+            swept += u64::from(!keep);
+            keep
+        });
+
+        Box::pin(std::future::ready(Ok(swept)))
+    }
+}
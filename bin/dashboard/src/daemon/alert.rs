@@ -0,0 +1,306 @@
+use crate::prelude::seaorm::*;
+use crate::state::AppState;
+use anyhow::Result;
+use sea_orm::ConnectionTrait;
+use sea_orm::DbBackend;
+use sea_orm::Statement;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Evaluates the fleet-wide alert rules and raises an alert for each rule crossing its
+/// threshold:
+///
+/// - `hosts_offline`: The share of offline hosts reached `--alert-offline-percent`.
+///   Offline hosts in maintenance are not counted.
+/// - `database_size`: The database grew beyond `--alert-database-size` megabytes.
+///
+/// Besides, the latest metrics sample each host sent since the previous evaluation is
+/// checked against the host's thresholds, see `host_metrics`.
+///
+/// A rule raises no new alert while an earlier alert of the same kind is unresolved,
+/// for host rules of the same kind and host.
+///
+/// # Errors
+///
+/// Returns an error if database operations fail.
+pub async fn run(state: Arc<AppState>) -> Result<()> {
+    if state.args.alert_offline_percent > 0 {
+        let hosts = Host::find()
+            .filter(host::Column::DeletedAt.is_null())
+            .filter(state.counted_hosts());
+        let total = hosts.clone().count(state.database.as_ref()).await?;
+        let online = hosts
+            .filter(host::Column::LastSeen.gte(state.online_since()))
+            .count(state.database.as_ref())
+            .await?;
+        let offline = total - online;
+
+        if total > 0 && offline * 100 >= total * state.args.alert_offline_percent as u64 {
+            let message = format!("{} of {} hosts are offline", offline, total);
+            raise(&state, "hosts_offline", None, message).await?;
+        }
+    }
+
+    if let Some(limit) = state.args.alert_database_size {
+        let size = database_size(&state).await?;
+        if size > limit.saturating_mul(1024 * 1024) {
+            let message = format!("database size is {} MiB", size / 1024 / 1024);
+            raise(&state, "database_size", None, message).await?;
+        }
+    }
+
+    host_metrics(&state).await?;
+
+    Ok(())
+}
+
+/// Checks the latest metrics sample of each host sent within the last
+/// `--alert-interval` and raises an alert for each threshold it reached:
+///
+/// - `host_cpu`: The CPU usage reached the CPU threshold.
+/// - `host_memory`: The share of used memory reached the memory threshold.
+/// - `host_disk`: The share of used disk space reached the disk threshold.
+///
+/// The thresholds set for the host take precedence over `--alert-cpu-percent`,
+/// `--alert-memory-percent` and `--alert-disk-percent`. A threshold of 0 disables
+/// the rule.
+async fn host_metrics(state: &AppState) -> Result<()> {
+    let thresholds: HashMap<Uuid, host_alert_threshold::Model> = HostAlertThreshold::find()
+        .all(state.database.as_ref())
+        .await?
+        .into_iter()
+        .map(|thresholds| (thresholds.host_id, thresholds))
+        .collect();
+    let args = &state.args;
+    if thresholds.is_empty()
+        && args.alert_cpu_percent == 0
+        && args.alert_memory_percent == 0
+        && args.alert_disk_percent == 0
+    {
+        return Ok(());
+    }
+
+    // keep the latest sample of each host
+    let since = chrono::Utc::now() - chrono::Duration::seconds(args.alert_interval as i64);
+    let mut samples: HashMap<Uuid, metric::Model> = HashMap::new();
+    for sample in Metric::find()
+        .filter(metric::Column::RecordedAt.gte(since))
+        .order_by_asc(metric::Column::RecordedAt)
+        .all(state.database.as_ref())
+        .await?
+    {
+        samples.insert(sample.host_id, sample);
+    }
+
+    let share = |used: Option<i64>, total: Option<i64>| match (used, total) {
+        (Some(used), Some(total)) if total > 0 => Some(used as f64 * 100.0 / total as f64),
+        _ => None,
+    };
+
+    for (host_id, sample) in samples {
+        let set = thresholds.get(&host_id);
+        let threshold = |own: Option<i16>, global: u8| own.unwrap_or(global as i16);
+        let rules = [
+            (
+                "host_cpu",
+                "cpu usage",
+                sample.cpu_usage,
+                threshold(set.and_then(|t| t.cpu_percent), args.alert_cpu_percent),
+            ),
+            (
+                "host_memory",
+                "memory usage",
+                share(sample.memory_used, sample.memory_total),
+                threshold(
+                    set.and_then(|t| t.memory_percent),
+                    args.alert_memory_percent,
+                ),
+            ),
+            (
+                "host_disk",
+                "disk usage",
+                share(sample.disk_used, sample.disk_total),
+                threshold(set.and_then(|t| t.disk_percent), args.alert_disk_percent),
+            ),
+        ];
+
+        for (kind, name, value, threshold) in rules {
+            let Some(value) = value.filter(|value| threshold > 0 && *value >= threshold as f64)
+            else {
+                continue;
+            };
+
+            let message = format!("{} is {:.1}%, threshold is {}%", name, value, threshold);
+            raise(state, kind, Some(host_id), message).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Inserts an alert of the given `kind` for the host, or fleet-wide without a
+/// `host_id`, unless one is still unresolved.
+async fn raise(state: &AppState, kind: &str, host_id: Option<Uuid>, message: String) -> Result<()> {
+    let unresolved = Alert::find()
+        .filter(alert::Column::Kind.eq(kind))
+        .filter(match host_id {
+            Some(host_id) => alert::Column::HostId.eq(host_id),
+            None => alert::Column::HostId.is_null(),
+        })
+        .filter(alert::Column::ResolvedAt.is_null())
+        .count(state.database.as_ref())
+        .await?;
+    if unresolved > 0 {
+        return Ok(());
+    }
+
+    Alert::insert(alert::ActiveModel {
+        id: Set(Uuid::from_bytes(uuidv7::create_raw())),
+        kind: Set(kind.to_owned()),
+        host_id: Set(host_id),
+        message: Set(message.clone()),
+        created_at: Set(chrono::Utc::now()),
+        resolved_at: Set(None),
+    })
+    .exec(state.database.as_ref())
+    .await?;
+
+    tracing::warn!("raised alert {}: {}", kind, message);
+
+    Ok(())
+}
+
+/// Queries the size of the database in bytes.
+async fn database_size(state: &AppState) -> Result<u64> {
+    let backend = state.database.get_database_backend();
+    let sql = match backend {
+        DbBackend::Sqlite => {
+            "SELECT page_count * page_size AS size FROM pragma_page_count(), pragma_page_size()"
+        }
+        DbBackend::Postgres => "SELECT pg_database_size(current_database()) AS size",
+        DbBackend::MySql => {
+            "SELECT CAST(COALESCE(SUM(data_length + index_length), 0) AS SIGNED) AS size \
+             FROM information_schema.tables WHERE table_schema = DATABASE()"
+        }
+    };
+
+    let size = match state
+        .database
+        .query_one(Statement::from_string(backend, sql))
+        .await?
+    {
+        Some(row) => row.try_get::<i64>("", "size")?,
+        None => 0,
+    };
+
+    Ok(size.max(0) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing;
+    use axum::http::Method;
+    use axum::http::StatusCode;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn crossing_threshold_raises_alert_until_acked() {
+        let state = testing::state(&["--alert-offline-percent", "50"]).await;
+        let token = testing::admin(&state).await;
+        let router = testing::router(&state);
+        testing::host(&state, "online").await;
+        let offline = testing::host(&state, "offline").await;
+        testing::seen(
+            &state,
+            offline.id,
+            chrono::Utc::now() - chrono::Duration::days(2),
+        )
+        .await;
+
+        // raised once while unresolved
+        super::run(state.clone()).await.unwrap();
+        super::run(state.clone()).await.unwrap();
+
+        let request = || testing::request(Method::GET, "/api/admin/alerts", Some(&token), None);
+        let (status, body) = testing::send(&router, request()).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let alerts = body.as_array().unwrap();
+        assert_eq!(alerts.len(), 1, "{}", body);
+        assert_eq!(alerts[0]["kind"], "hosts_offline");
+        assert_eq!(alerts[0]["message"], "1 of 2 hosts are offline");
+        assert!(alerts[0]["resolved_at"].is_null());
+
+        let uri = format!(
+            "/api/admin/alerts/{}/ack",
+            alerts[0]["id"].as_str().unwrap()
+        );
+        let ack = testing::request(Method::POST, &uri, Some(&token), None);
+        let (status, body) = testing::send(&router, ack).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        let (_, body) = testing::send(&router, request()).await;
+        assert!(body[0]["resolved_at"].is_string(), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn stale_host_in_maintenance_is_not_counted() {
+        let state = testing::state(&["--alert-offline-percent", "50"]).await;
+        let token = testing::admin(&state).await;
+        let router = testing::router(&state);
+        testing::host(&state, "online").await;
+        let two_days_ago = chrono::Utc::now() - chrono::Duration::days(2);
+        let stale = testing::host(&state, "stale").await;
+        let maintained = testing::host(&state, "maintained").await;
+        for id in [stale.id, maintained.id] {
+            testing::seen(&state, id, two_days_ago).await;
+        }
+
+        let uri = format!("/api/admin/hosts/{}/maintenance", maintained.id);
+        let until = (chrono::Utc::now() + chrono::Duration::hours(1)).to_rfc3339();
+        let request = testing::request(
+            Method::PUT,
+            &uri,
+            Some(&token),
+            Some(json!({ "until": until })),
+        );
+        let (status, body) = testing::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        super::run(state.clone()).await.unwrap();
+
+        let request = testing::request(Method::GET, "/api/admin/alerts", Some(&token), None);
+        let (status, body) = testing::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body[0]["message"], "1 of 2 hosts are offline", "{}", body);
+    }
+
+    #[tokio::test]
+    async fn host_threshold_below_the_default_raises_alert() {
+        let state = testing::state(&["--alert-cpu-percent", "90"]).await;
+        let token = testing::admin(&state).await;
+        let router = testing::router(&state);
+        let metrics = json!([{ "EvtMetricsEmit": [60.0, 1, 2, 3, 4] }]);
+        for machine_id in ["tight", "default"] {
+            testing::report(&state, machine_id, None, metrics.clone()).await;
+        }
+        let tight = testing::host(&state, "tight").await;
+
+        let uri = format!("/api/admin/hosts/{}/alert-thresholds", tight.id);
+        let thresholds = json!({ "cpu_percent": 50 });
+        let request = testing::request(Method::PUT, &uri, Some(&token), Some(thresholds));
+        let (status, body) = testing::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        super::run(state.clone()).await.unwrap();
+
+        // 60% is under the default of 90%, only the host with its own threshold alerts
+        let request = testing::request(Method::GET, "/api/admin/alerts", Some(&token), None);
+        let (status, body) = testing::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let alerts = body.as_array().unwrap();
+        assert_eq!(alerts.len(), 1, "{}", body);
+        assert_eq!(alerts[0]["kind"], "host_cpu");
+        assert_eq!(alerts[0]["host_id"], tight.id.to_string());
+        assert_eq!(alerts[0]["message"], "cpu usage is 60.0%, threshold is 50%");
+    }
+}
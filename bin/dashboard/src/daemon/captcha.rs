@@ -0,0 +1,66 @@
+use crate::state::AppState;
+use anyhow::Result;
+use std::sync::Arc;
+
+/// Deletes captchas generated longer than `--captcha-sweep-age` ago.
+///
+/// Captchas only store their expiry, so the generation time is derived from it with
+/// the current `--captcha-ttl`. A sweep age below the TTL removes captchas which
+/// would still verify, so abandoned ones are purged early.
+///
+/// # Errors
+///
+/// Returns an error if the captcha store fails.
+pub async fn run(state: Arc<AppState>) -> Result<()> {
+    let ttl = chrono::Duration::seconds(state.args.captcha_ttl as i64);
+    let sweep_age = chrono::Duration::seconds(state.args.captcha_sweep_age as i64);
+    let generated_before = chrono::Utc::now() - sweep_age;
+
+    let swept = state.captchas.sweep(generated_before + ttl).await?;
+
+    if swept > 0 {
+        tracing::debug!("swept {} captchas", swept);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::seaorm::*;
+    use crate::testing;
+    use sea_orm::IntoActiveModel;
+
+    #[tokio::test]
+    async fn sweeps_captchas_past_sweep_age_within_ttl() {
+        let state = testing::state(&["--captcha-ttl", "3600", "--captcha-sweep-age", "60"]).await;
+        let db = state.database.as_ref();
+        let ttl = chrono::Duration::hours(1);
+        let now = chrono::Utc::now();
+
+        // generated two minutes ago, so valid for almost another hour
+        let abandoned_at = now - chrono::Duration::minutes(2) + ttl;
+        let abandoned = Uuid::from_bytes(uuidv7::create_raw());
+        let fresh = Uuid::from_bytes(uuidv7::create_raw());
+        for (id, expired_at) in [(abandoned, abandoned_at), (fresh, now + ttl)] {
+            let model = captcha::Model {
+                id,
+                answer: "AB12".to_owned(),
+                expired_at,
+            };
+            Captcha::insert(model.into_active_model())
+                .exec(db)
+                .await
+                .unwrap();
+        }
+
+        super::run(state.clone()).await.unwrap();
+
+        assert!(Captcha::find_by_id(abandoned)
+            .one(db)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(Captcha::find_by_id(fresh).one(db).await.unwrap().is_some());
+    }
+}
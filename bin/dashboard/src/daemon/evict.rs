@@ -0,0 +1,117 @@
+use crate::prelude::seaorm::*;
+use crate::state::AppState;
+use anyhow::Result;
+use sea_orm::TransactionTrait;
+use std::sync::Arc;
+
+/// Soft-deletes hosts which were not seen for longer than the eviction threshold.
+///
+/// Hosts that were never seen are left alone, and so are hosts in maintenance, like
+/// they are not counted as offline, see `AppState::counted_hosts`. Every evicted host
+/// gets a `HostEvicted`
+/// entry in the event log, with its `last_seen` timestamp as payload.
+///
+/// # Errors
+///
+/// Returns an error if database operations fail.
+pub async fn run(state: Arc<AppState>, threshold: u64) -> Result<()> {
+    let now = chrono::Utc::now();
+    let before = now - chrono::Duration::seconds(threshold as i64);
+
+    let txn = state.database.begin().await?;
+
+    let stale = Host::find()
+        .filter(host::Column::DeletedAt.is_null())
+        .filter(host::Column::LastSeen.lt(before))
+        .filter(state.counted_hosts())
+        .all(&txn)
+        .await?;
+
+    for target in &stale {
+        Host::update(host::ActiveModel {
+            id: Unchanged(target.id),
+            deleted_at: Set(Some(now)),
+            ..Default::default()
+        })
+        .exec(&txn)
+        .await?;
+
+        let payload = serde_json::json!({
+            "last_seen": target.last_seen.map(|time| time.to_rfc3339()),
+        });
+        EventLog::insert(event_log::ActiveModel {
+            id: Set(Uuid::from_bytes(uuidv7::create_raw())),
+            host_id: Set(target.id),
+            event_type: Set("HostEvicted".to_owned()),
+            payload: Set(payload.to_string()),
+            received_at: Set(now),
+        })
+        .exec(&txn)
+        .await?;
+
+        tracing::info!("evicted host with machine id: {}", target.machine_id);
+    }
+
+    txn.commit().await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::seaorm::*;
+    use crate::testing;
+
+    #[tokio::test]
+    async fn evicts_stale_hosts_only() {
+        let state = testing::state(&[]).await;
+        let stale = testing::host(&state, "stale").await;
+        let recent = testing::host(&state, "recent").await;
+        let two_hours_ago = chrono::Utc::now() - chrono::Duration::hours(2);
+        testing::seen(&state, stale.id, two_hours_ago).await;
+
+        super::run(state.clone(), 3600).await.unwrap();
+
+        let db = state.database.as_ref();
+        let stale = Host::find_by_id(stale.id).one(db).await.unwrap().unwrap();
+        assert!(stale.deleted_at.is_some());
+        let recent = Host::find_by_id(recent.id).one(db).await.unwrap().unwrap();
+        assert!(recent.deleted_at.is_none());
+
+        let audit = EventLog::find()
+            .filter(event_log::Column::EventType.eq("HostEvicted"))
+            .all(db)
+            .await
+            .unwrap();
+        assert_eq!(audit.len(), 1);
+        assert_eq!(audit[0].host_id, stale.id);
+    }
+
+    #[tokio::test]
+    async fn keeps_stale_hosts_in_maintenance() {
+        let state = testing::state(&[]).await;
+        let maintained = testing::host(&state, "maintained").await;
+        let two_hours_ago = chrono::Utc::now() - chrono::Duration::hours(2);
+        testing::seen(&state, maintained.id, two_hours_ago).await;
+
+        let db = state.database.as_ref();
+        let until = chrono::Utc::now() + chrono::Duration::hours(1);
+        Host::update(host::ActiveModel {
+            id: Unchanged(maintained.id),
+            maintenance_until: Set(Some(until)),
+            ..Default::default()
+        })
+        .exec(db)
+        .await
+        .unwrap();
+
+        super::run(state.clone(), 3600).await.unwrap();
+
+        let maintained = Host::find_by_id(maintained.id)
+            .one(db)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(maintained.deleted_at.is_none());
+    }
+}
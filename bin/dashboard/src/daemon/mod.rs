@@ -0,0 +1,97 @@
+use crate::state::AppState;
+use anyhow::Result;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::select;
+use tokio::sync::broadcast;
+use tokio::task::JoinSet;
+use tokio::time::MissedTickBehavior;
+
+mod alert;
+mod captcha;
+mod evict;
+mod snapshot;
+
+/// Spawns the daemon tasks.
+///
+/// Each task runs periodically until the shutdown signal is received. The returned
+/// `JoinSet` can be used to wait for all tasks to stop. Tasks that write to the
+/// database are not spawned in read-only mode.
+///
+/// The eviction task is opt-in and only spawned if `--evict-after` is given. The
+/// captcha sweep runs every `--captcha-sweep-age`, but at least once a minute.
+pub fn spawn(state: Arc<AppState>, shutdown: &broadcast::Receiver<()>) -> JoinSet<()> {
+    let mut tasks = JoinSet::new();
+
+    if !state.args.read_only && state.args.snapshot_interval > 0 {
+        let state = state.clone();
+        tasks.spawn(every(
+            "snapshot",
+            Duration::from_secs(state.args.snapshot_interval),
+            shutdown.resubscribe(),
+            move || snapshot::run(state.clone()),
+        ));
+    }
+
+    if !state.args.read_only && state.args.alert_interval > 0 {
+        let state = state.clone();
+        tasks.spawn(every(
+            "alert",
+            Duration::from_secs(state.args.alert_interval),
+            shutdown.resubscribe(),
+            move || alert::run(state.clone()),
+        ));
+    }
+
+    if !state.args.read_only && !state.args.disable_captcha && state.args.captcha_sweep_age > 0 {
+        let state = state.clone();
+        tasks.spawn(every(
+            "captcha",
+            Duration::from_secs(state.args.captcha_sweep_age.clamp(1, 60)),
+            shutdown.resubscribe(),
+            move || captcha::run(state.clone()),
+        ));
+    }
+
+    if let Some(threshold) = state.args.evict_after.filter(|_| !state.args.read_only) {
+        let state = state.clone();
+        tasks.spawn(every(
+            "evict",
+            Duration::from_secs(state.args.evict_interval.max(1)),
+            shutdown.resubscribe(),
+            move || evict::run(state.clone(), threshold),
+        ));
+    }
+
+    tasks
+}
+
+/// Runs `task` every `period` until the shutdown signal is received.
+///
+/// The first run happens immediately. Errors are logged and do not stop the loop.
+async fn every<F, Fut>(
+    name: &'static str,
+    period: Duration,
+    mut shutdown: broadcast::Receiver<()>,
+    mut task: F,
+) where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let mut interval = tokio::time::interval(period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        select! {
+            _ = shutdown.recv() => break,
+            _ = interval.tick() => {
+                if let Err(err) = task().await {
+                    tracing::warn!("daemon task {} failed: {}", name, err);
+                }
+            }
+        }
+    }
+
+    tracing::debug!("daemon task {} stopped", name);
+}
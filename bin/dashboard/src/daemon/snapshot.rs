@@ -0,0 +1,67 @@
+use crate::prelude::seaorm::*;
+use crate::state::AppState;
+use anyhow::Result;
+use std::sync::Arc;
+
+/// Records the current online and total host counts into the `fleet_snapshot` table.
+///
+/// A host is online if it was seen within the offline threshold. Soft-deleted hosts
+/// and offline hosts in maintenance are not counted.
+///
+/// # Errors
+///
+/// Returns an error if database operations fail.
+pub async fn run(state: Arc<AppState>) -> Result<()> {
+    let hosts = Host::find()
+        .filter(host::Column::DeletedAt.is_null())
+        .filter(state.counted_hosts());
+
+    let total = hosts.clone().count(state.database.as_ref()).await?;
+    let online = hosts
+        .filter(host::Column::LastSeen.gte(state.online_since()))
+        .count(state.database.as_ref())
+        .await?;
+
+    FleetSnapshot::insert(fleet_snapshot::ActiveModel {
+        id: Set(Uuid::from_bytes(uuidv7::create_raw())),
+        online: Set(online as i32),
+        total: Set(total as i32),
+        recorded_at: Set(chrono::Utc::now()),
+    })
+    .exec(state.database.as_ref())
+    .await?;
+
+    tracing::debug!("recorded fleet snapshot: {}/{} online", online, total);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing;
+    use axum::http::Method;
+    use axum::http::StatusCode;
+
+    #[tokio::test]
+    async fn snapshots_are_queryable() {
+        let state = testing::state(&[]).await;
+        testing::host(&state, "m1").await;
+        testing::host(&state, "m2").await;
+
+        super::run(state.clone()).await.unwrap();
+        super::run(state.clone()).await.unwrap();
+
+        let token = testing::admin(&state).await;
+        let uri = "/api/dashboard/fleet/snapshots";
+        let request = testing::request(Method::GET, uri, Some(&token), None);
+        let (status, body) = testing::send(&testing::router(&state), request).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let snapshots = body.as_array().unwrap();
+        assert_eq!(snapshots.len(), 2);
+        for snapshot in snapshots {
+            assert_eq!(snapshot["online"], 2);
+            assert_eq!(snapshot["total"], 2);
+        }
+    }
+}
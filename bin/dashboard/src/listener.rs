@@ -0,0 +1,89 @@
+use axum::serve::Listener;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
+use tokio::time::Instant;
+
+/// TCP listener which accepts at most `rate` connections per second on average.
+///
+/// Up to `burst` connections are accepted right away, further ones wait in the kernel
+/// backlog until their turn. This smooths reconnect storms, e.g. all agents
+/// reconnecting after a restart, instead of accepting them all at once. Without a rate,
+/// connections are accepted as they come.
+pub struct RateLimitedListener {
+    inner: TcpListener,
+    interval: Option<Duration>,
+    burst: u32,
+    next: Instant,
+}
+
+impl RateLimitedListener {
+    pub fn new(inner: TcpListener, rate: Option<u32>, burst: u32) -> Self {
+        Self {
+            inner,
+            interval: rate.map(|rate| Duration::from_secs(1) / rate.max(1)),
+            burst: burst.max(1),
+            next: Instant::now(),
+        }
+    }
+}
+
+impl Listener for RateLimitedListener {
+    type Io = TcpStream;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        let Some(interval) = self.interval else {
+            return Listener::accept(&mut self.inner).await;
+        };
+
+        // `next` is when the connection would be due at the steady rate, the burst
+        // allows running ahead of it by `burst - 1` connections
+        let earliest = self
+            .next
+            .checked_sub(interval * (self.burst - 1))
+            .unwrap_or(self.next);
+        tokio::time::sleep_until(earliest).await;
+
+        let accepted = Listener::accept(&mut self.inner).await;
+        self.next = self.next.max(Instant::now()) + interval;
+
+        accepted
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        self.inner.local_addr()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[tokio::test]
+    async fn connections_are_accepted_at_the_rate() {
+        let state = testing::state(&["--accept-rate", "20", "--accept-burst", "2"]).await;
+        let inner = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = inner.local_addr().unwrap();
+        let mut listener =
+            RateLimitedListener::new(inner, state.args.accept_rate, state.args.accept_burst);
+
+        // all connect at once and wait in the backlog
+        let mut clients = Vec::new();
+        for _ in 0..8 {
+            clients.push(TcpStream::connect(addr).await.unwrap());
+        }
+
+        let started = std::time::Instant::now();
+        for _ in 0..8 {
+            listener.accept().await;
+        }
+        // two right away, then one every 50 ms
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(280), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(600), "{:?}", elapsed);
+    }
+}
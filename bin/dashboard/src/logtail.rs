@@ -0,0 +1,101 @@
+use std::collections::VecDeque;
+use std::io;
+use std::sync::Arc;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Number of new lines a subscriber can fall behind before it misses lines.
+const CHANNEL_CAPACITY: usize = 256;
+
+/// Keeps the most recent server log lines and broadcasts new ones to subscribers.
+pub struct LogTail {
+    capacity: usize,
+    lines: Mutex<VecDeque<String>>,
+    sender: broadcast::Sender<String>,
+    closed: CancellationToken,
+}
+
+impl LogTail {
+    /// Creates a log tail keeping the last `capacity` lines.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            lines: Mutex::new(VecDeque::with_capacity(capacity)),
+            sender: broadcast::channel(CHANNEL_CAPACITY).0,
+            closed: CancellationToken::new(),
+        }
+    }
+
+    /// Appends a line, dropping the oldest one if the buffer is full.
+    pub fn push(&self, line: String) {
+        let mut lines = self.lines.lock().unwrap();
+        if self.capacity > 0 {
+            if lines.len() == self.capacity {
+                lines.pop_front();
+            }
+            lines.push_back(line.clone());
+        }
+
+        // sent under the lock, so subscribers neither miss nor repeat a line
+        _ = self.sender.send(line);
+    }
+
+    /// Returns the buffered lines, oldest first, and a receiver for the lines after them.
+    pub fn subscribe(&self) -> (Vec<String>, broadcast::Receiver<String>) {
+        let lines = self.lines.lock().unwrap();
+
+        (lines.iter().cloned().collect(), self.sender.subscribe())
+    }
+
+    /// Returns a token cancelled once the server shuts down, ending all streams.
+    pub fn closed(&self) -> CancellationToken {
+        self.closed.clone()
+    }
+
+    /// Ends all streams, so they do not hold the graceful shutdown.
+    pub fn close(&self) {
+        self.closed.cancel();
+    }
+}
+
+/// Writer for a `tracing_subscriber::fmt` layer which appends to a `LogTail`.
+#[derive(Clone)]
+pub struct LogTailWriter(pub Arc<LogTail>);
+
+impl<'a> MakeWriter<'a> for LogTailWriter {
+    type Writer = LineWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        LineWriter {
+            tail: self.0.clone(),
+            buf: Vec::new(),
+        }
+    }
+}
+
+/// Collects one formatted event and pushes its lines to the `LogTail` when dropped.
+pub struct LineWriter {
+    tail: Arc<LogTail>,
+    buf: Vec<u8>,
+}
+
+impl io::Write for LineWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for LineWriter {
+    fn drop(&mut self) {
+        for line in String::from_utf8_lossy(&self.buf).lines() {
+            self.tail.push(line.to_owned());
+        }
+    }
+}
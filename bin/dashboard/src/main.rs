@@ -1,73 +1,181 @@
 use crate::args::Args;
+use crate::args::RELOADABLE_ARGS;
 use anyhow::{Ok, Result};
 use axum::serve;
-use clap::Parser;
+use axum::serve::ListenerExt;
+use clap::error::ErrorKind;
+use clap::ArgMatches;
+use clap::CommandFactory;
+use clap::FromArgMatches;
 use database::migrations::Migrator;
 use database::migrations::MigratorTrait;
+use listener::RateLimitedListener;
+use logtail::LogTail;
+use logtail::LogTailWriter;
+use sea_orm::sqlx::sqlite::SqliteJournalMode;
+use sea_orm::sqlx::sqlite::SqliteSynchronous;
 use sea_orm::ConnectOptions;
+use sea_orm::ConnectionTrait;
 use sea_orm::Database;
 use sea_orm::DatabaseConnection;
+use sea_orm::DbBackend;
 use state::AppState;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::TcpListener;
 use tokio::select;
 use tokio::signal;
 use tokio::sync::broadcast;
 use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::reload;
 use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::Registry;
 
 mod api;
 mod args;
+mod captcha_store;
+mod daemon;
+mod latency;
+mod listener;
+mod logtail;
 mod middlewares;
 mod prelude;
 mod route;
 mod state;
+#[cfg(test)]
+mod testing;
+mod token;
+mod webhook;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // configure logging
+    // parse command line arguments
+    let matches = Args::matches().unwrap_or_else(|err| err.exit());
+    let mut args = Args::from_arg_matches(&matches)?;
+    args.read_secret_files().unwrap_or_else(|err| err.exit());
+    if let Err(problems) = args.validate() {
+        Args::command()
+            .error(ErrorKind::ArgumentConflict, problems.join("\n"))
+            .exit();
+    }
+    let config = Args::effective(&matches);
+
+    // configure logging, lines are also kept for admins tailing the log
+    let logs = Arc::new(LogTail::new(args.log_tail_lines));
+    let (filter, filter_handle) = reload::Layer::new(make_log_filter(&args));
     tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer().without_time())
         .with(
-            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
-                format!(
-                    "{}=debug,tower_http=debug,axum=trace",
-                    env!("CARGO_CRATE_NAME")
-                )
-                .into()
-            }),
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(LogTailWriter(logs.clone())),
         )
-        .with(tracing_subscriber::fmt::layer().without_time())
         .init();
+    if args.disable_captcha {
+        tracing::warn!("captcha is disabled, only use this on trusted networks");
+    }
+    if args.test_mode {
+        tracing::warn!("test mode is enabled, captcha answers are exposed to clients");
+    }
+    if args.secret.is_none() {
+        tracing::warn!("no secret configured, authorize tokens are invalidated on restart");
+    }
+    if args.previous_secret.is_some() {
+        tracing::info!(
+            "previous secret configured, drop it once its authorize tokens expired (--token-ttl)"
+        );
+    }
 
-    // parse command line arguments
-    let args = Args::parse();
+    check_data_dir(&args)?;
 
     // create shutdown signal receiver
     let mut shutdown = make_shutdown_signal();
 
     // create a TCP listener and a database connection
-    let listener = make_listener(&args).await?;
+    let listener = make_listener(&args.listen).await?;
+    let admin_listener = match &args.admin_listen {
+        Some(addr) => Some(make_listener(addr).await?),
+        None => None,
+    };
     let database = make_database(&args).await?;
+    let token_key = make_token_key(&args)?;
 
     // create app state
-    let state = Arc::new(AppState::new(args, database));
+    let state = Arc::new(AppState::new(args, config, database, logs, &token_key));
+    crate::api::agent::spawn_eventbus_workers(state.clone());
+
+    // log boot summary
+    log_startup(&state, &listener).await?;
 
     // create a router
     let router = crate::route::make(state.clone());
+    let admin_router = crate::route::make_admin_listener(state.clone());
+
+    // spawn daemon tasks
+    let daemons = crate::daemon::spawn(state.clone(), &shutdown);
 
-    // start server
-    serve(listener, router)
+    // apply hot-reloadable settings on SIGHUP
+    spawn_reloader(state.clone(), filter_handle, shutdown.resubscribe());
+
+    // end log streams on shutdown, they would hold the graceful shutdown otherwise
+    tokio::spawn({
+        let state = state.clone();
+        let mut shutdown = shutdown.resubscribe();
+        async move {
+            _ = shutdown.recv().await;
+            state.logs.close();
+        }
+    });
+
+    // smooth connection storms, the no-op tap keeps `SocketAddr` usable as connect info
+    let accept_rate = state.args.accept_rate;
+    let accept_burst = state.args.accept_burst;
+    let listener = RateLimitedListener::new(listener, accept_rate, accept_burst).tap_io(|_| ());
+    let admin_listener = admin_listener.map(|listener| {
+        RateLimitedListener::new(listener, accept_rate, accept_burst).tap_io(|_| ())
+    });
+
+    // start servers, both stop on the same shutdown signal
+    let admin = {
+        let mut shutdown = shutdown.resubscribe();
+        async move {
+            if let Some(listener) = admin_listener {
+                serve(
+                    listener,
+                    admin_router.into_make_service_with_connect_info::<SocketAddr>(),
+                )
+                .with_graceful_shutdown(async move { shutdown.recv().await.unwrap() })
+                .await?;
+            }
+            Ok(())
+        }
+    };
+    let main = async move {
+        serve(
+            listener,
+            router.into_make_service_with_connect_info::<SocketAddr>(),
+        )
         .with_graceful_shutdown({
             async move {
-                // TODO: spawn daemon task
-
                 // wait for shutdown signal
                 shutdown.recv().await.unwrap()
             }
         })
         .await?;
+        Ok(())
+    };
+    tokio::try_join!(main, admin)?;
+
+    // wait daemon tasks stop
+    daemons.join_all().await;
 
-    // TODO: wait daemon task stop
+    // wait buffered agent events persisted
+    state
+        .drain(Duration::from_secs(state.args.shutdown_drain_timeout))
+        .await;
 
     // wait state persisted
     state.close().await?;
@@ -75,16 +183,95 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-/// Create a TCP listener bound to the address given in Args.
+/// Checks that the data directory is not world-writable.
+///
+/// Anyone able to write the directory could replace the persisted database or secret
+/// files. A too permissive directory is logged as warning, or rejected with
+/// `--strict-permissions`. A missing directory is left to be created later, and the
+/// check is skipped on non-unix platforms.
+///
+/// # Errors
+///
+/// Returns an error if the directory is world-writable and `--strict-permissions` is
+/// set, or if its metadata cannot be read.
+fn check_data_dir(args: &Args) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let metadata = match std::fs::metadata(&args.data_dir) {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            metadata => metadata?,
+        };
+
+        let mode = metadata.permissions().mode();
+        if mode & 0o002 != 0 {
+            let message = format!(
+                "data directory {} is world-writable (mode {:o})",
+                args.data_dir.display(),
+                mode & 0o777
+            );
+            if args.strict_permissions {
+                anyhow::bail!(message);
+            }
+            tracing::warn!("{}, restrict it with `chmod o-w`", message);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = args;
+
+    Ok(())
+}
+
+/// Returns the key of the stored token hashes and upload URL signatures.
+///
+/// This is `--token-pepper` if set. Otherwise a key is generated on the first start and
+/// kept as `token.key` in the data directory, readable by the owner only, so the hashes
+/// stay valid across restarts and `--secret` rotations.
+fn make_token_key(args: &Args) -> Result<Vec<u8>> {
+    if let Some(pepper) = &args.token_pepper {
+        return Ok(pepper.clone().into_bytes());
+    }
+
+    let path = args.data_dir.join("token.key");
+    match std::fs::read_to_string(&path) {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        content => {
+            let key = content?.trim().to_owned();
+            if key.is_empty() {
+                anyhow::bail!("token key {} is empty", path.display());
+            }
+            return Ok(key.into_bytes());
+        }
+    }
+
+    // never overwrite a key another process just created
+    let key = crate::token::random();
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        options.mode(0o600);
+    }
+    std::fs::create_dir_all(&args.data_dir)?;
+    std::io::Write::write_all(&mut options.open(&path)?, key.as_bytes())?;
+    tracing::info!("generated token key {}", path.display());
+
+    Ok(key.into_bytes())
+}
+
+/// Create a TCP listener bound to the given address.
 ///
-/// Takes Args as input and attempts to bind a TCP listener to the address given in
-/// Args.listen. The listener is then returned.
+/// Attempts to bind a TCP listener to `addr` (`--listen` or `--admin-listen`). The
+/// listener is then returned.
 ///
 /// # Errors
 ///
 /// Returns an error if the TCP listener cannot be bound to the given address.
-async fn make_listener(args: &Args) -> Result<TcpListener> {
-    let listener = TcpListener::bind(&args.listen).await?;
+async fn make_listener(addr: &str) -> Result<TcpListener> {
+    let listener = TcpListener::bind(addr).await?;
     tracing::info!("listening on {}", listener.local_addr()?);
 
     Ok(listener)
@@ -94,7 +281,12 @@ async fn make_listener(args: &Args) -> Result<TcpListener> {
 ///
 /// This function takes `Args` as input and attempts to parse the database connection string.
 /// The connection string is then used to open a database connection. The migrator is called
-/// to apply any pending migrations, and the connection is then returned.
+/// to apply any pending migrations (skipped in read-only mode), and the connection is then
+/// returned.
+///
+/// Sqlite connections are set up with `journal_mode=WAL` (unless read-only), so dashboard
+/// reads are not blocked while agents report, `synchronous=NORMAL` and the configured
+/// `busy_timeout`. Other backends are left untouched.
 ///
 /// # Errors
 ///
@@ -102,18 +294,225 @@ async fn make_listener(args: &Args) -> Result<TcpListener> {
 /// migration fails, an error is returned.
 async fn make_database(args: &Args) -> Result<DatabaseConnection> {
     // parse connection string
-    let opt = ConnectOptions::new(&args.database);
+    let mut opt = ConnectOptions::new(&args.database);
+
+    // applied to every pooled sqlite connection, ignored by other backends
+    let wal = !args.read_only;
+    let busy_timeout = Duration::from_millis(args.sqlite_busy_timeout);
+    opt.map_sqlx_sqlite_opts(move |opts| {
+        let opts = opts
+            .synchronous(SqliteSynchronous::Normal)
+            .busy_timeout(busy_timeout);
+        if wal {
+            opts.journal_mode(SqliteJournalMode::Wal)
+        } else {
+            opts
+        }
+    });
 
     // open database connection
     let conn = Database::connect(opt).await?;
 
     // run migrations and return connection
     Ok({
-        Migrator::up(&conn, None).await?;
+        if args.no_auto_migrate {
+            check_schema(&conn).await?;
+        } else if !args.read_only {
+            Migrator::up(&conn, None).await?;
+        }
         conn
     })
 }
 
+/// Checks that all migrations known to this build are applied, without applying any.
+///
+/// # Errors
+///
+/// Returns an error if migrations are pending, or if the database has migrations
+/// applied that this build does not know, i.e. its schema is ahead.
+async fn check_schema(conn: &DatabaseConnection) -> Result<()> {
+    let pending = Migrator::get_pending_migrations(conn)
+        .await
+        .map_err(|err| anyhow::anyhow!("cannot verify the database schema: {}", err))?;
+
+    if !pending.is_empty() {
+        let names = pending.iter().map(|m| m.name()).collect::<Vec<_>>();
+        anyhow::bail!(
+            "database schema is not up to date, {} migrations pending ({}); \
+             apply them or start without --no-auto-migrate",
+            names.len(),
+            names.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+/// The effective setup, logged once at startup by `log_startup`.
+#[derive(Debug, PartialEq)]
+struct StartupSummary {
+    listen: SocketAddr,
+    admin_listen: Option<String>,
+    database: DbBackend,
+    /// The number of applied migrations, `None` if they cannot be read.
+    migrations: Option<usize>,
+    read_only: bool,
+    captcha: bool,
+    test_mode: bool,
+    webhook: bool,
+    /// Whether `--secret` is missing and a random key was generated.
+    random_secret: bool,
+}
+
+impl StartupSummary {
+    async fn new(state: &AppState, listen: SocketAddr) -> Self {
+        // a read-only database may not be migrated at all
+        let migrations = Migrator::get_applied_migrations(state.database.as_ref())
+            .await
+            .map(|applied| applied.len())
+            .ok();
+
+        Self {
+            listen,
+            admin_listen: state.args.admin_listen.clone(),
+            database: state.database.get_database_backend(),
+            migrations,
+            read_only: state.args.read_only,
+            captcha: !state.args.disable_captcha,
+            test_mode: state.args.test_mode,
+            webhook: state.webhook.is_some(),
+            random_secret: state.args.secret.is_none(),
+        }
+    }
+}
+
+/// Logs the `StartupSummary` as a single structured event.
+///
+/// # Errors
+///
+/// Returns an error if the local address of the listener cannot be read.
+async fn log_startup(state: &AppState, listener: &TcpListener) -> Result<()> {
+    let summary = StartupSummary::new(state, listener.local_addr()?).await;
+
+    tracing::info!(
+        listen = %summary.listen,
+        admin_listen = ?summary.admin_listen,
+        database = ?summary.database,
+        migrations = ?summary.migrations,
+        read_only = summary.read_only,
+        captcha = summary.captcha,
+        test_mode = summary.test_mode,
+        webhook = summary.webhook,
+        random_secret = summary.random_secret,
+        "startup complete"
+    );
+
+    Ok(())
+}
+
+/// Creates the log filter from `--log-filter`, or else from `RUST_LOG`.
+fn make_log_filter(args: &Args) -> EnvFilter {
+    if let Some(directives) = &args.log_filter {
+        return EnvFilter::new(directives);
+    }
+
+    EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+        format!(
+            "{}=debug,tower_http=debug,axum=trace",
+            env!("CARGO_CRATE_NAME")
+        )
+        .into()
+    })
+}
+
+/// Spawns a task that reloads the settings whenever a SIGHUP signal is received.
+///
+/// The arguments are parsed again, including a changed `--args-file`, and the ones
+/// listed in `RELOADABLE_ARGS` are applied without restarting the listeners:
+///
+/// - `--log-filter`
+/// - `--offline-threshold`
+/// - `--default-country`
+/// - `--metrics-interval`
+/// - `--dedup-window`
+/// - `--secret` and `--previous-secret`, or their files, to rotate the signature key
+///
+/// Changes to other arguments are logged and only take effect after a restart. If the
+/// new arguments are invalid, the current settings are kept. Whether the application
+/// is initialized is also checked again, so `/api/auth/init` becomes available after
+/// all users were removed from the database directly. The task stops on
+/// shutdown. SIGHUP does not exist on non-unix platforms, where nothing is spawned.
+fn spawn_reloader(
+    state: Arc<AppState>,
+    filter: reload::Handle<EnvFilter, Registry>,
+    mut shutdown: broadcast::Receiver<()>,
+) {
+    #[cfg(unix)]
+    tokio::spawn(async move {
+        let mut hangup = signal::unix::signal(signal::unix::SignalKind::hangup())
+            .expect("failed to install SIGHUP signal handler");
+
+        loop {
+            select! {
+                _ = hangup.recv() => {}
+                _ = shutdown.recv() => break,
+            }
+
+            let reloaded = Args::matches()
+                .map_err(Into::into)
+                .and_then(|matches| reload_settings(&state, &filter, &matches));
+            match reloaded {
+                Err(err) => tracing::warn!("settings not reloaded: {}", err),
+                _ => tracing::info!("settings reloaded"),
+            }
+            if let Err(err) = crate::api::user::reinitlizated(&state).await {
+                tracing::warn!("initialized state not re-evaluated: {}", err);
+            }
+        }
+    });
+    #[cfg(not(unix))]
+    let _ = (state, filter, shutdown);
+}
+
+/// Applies the hot-reloadable arguments of the parsed `matches`.
+///
+/// # Errors
+///
+/// Returns an error if the arguments are invalid, in which case nothing is applied.
+#[cfg_attr(not(unix), allow(dead_code))]
+fn reload_settings(
+    state: &AppState,
+    filter: &reload::Handle<EnvFilter, Registry>,
+    matches: &ArgMatches,
+) -> Result<()> {
+    let mut args = Args::from_arg_matches(matches)?;
+    args.read_secret_files()?;
+    args.validate()
+        .map_err(|problems| anyhow::anyhow!(problems.join("; ")))?;
+    let config = Args::effective(matches);
+
+    let current = state.config();
+    for entry in &config {
+        if RELOADABLE_ARGS.contains(&entry.name.as_str()) {
+            continue;
+        }
+        if current
+            .iter()
+            .any(|slot| slot.name == entry.name && slot.value != entry.value)
+        {
+            tracing::warn!(
+                "--{} changed, restart to apply it",
+                entry.name.replace('_', "-")
+            );
+        }
+    }
+
+    filter.reload(make_log_filter(&args))?;
+    state.reload(&args, config);
+
+    Ok(())
+}
+
 /// Creates a broadcast channel that can be used to signal shutdown to other tasks.
 ///
 /// The returned receiver can be used to receive a shutdown signal. When the signal is
@@ -158,3 +557,202 @@ fn make_shutdown_signal() -> broadcast::Receiver<()> {
     // return broadcast receiver
     shutdown_rx
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middlewares::AuthorizedToken;
+    use axum::http::Method;
+    use axum::http::StatusCode;
+    use clap::Parser;
+    use proto::admin::config::ConfigSource;
+    use sea_orm::prelude::Uuid;
+    use sea_orm::DbBackend;
+    use sea_orm::Statement;
+
+    #[tokio::test]
+    async fn startup_summary_has_each_field() {
+        let state = testing::state(&["--disable-captcha", "--read-only"]).await;
+        let listen = SocketAddr::from(([127, 0, 0, 1], 8080));
+
+        let summary = StartupSummary::new(&state, listen).await;
+        assert_eq!(
+            summary,
+            StartupSummary {
+                listen,
+                admin_listen: None,
+                database: DbBackend::Sqlite,
+                migrations: Some(Migrator::migrations().len()),
+                read_only: true,
+                captcha: false,
+                test_mode: false,
+                webhook: false,
+                random_secret: true,
+            }
+        );
+    }
+
+    /// Parses the command line `args` like `Args::matches` does.
+    fn matches(args: &[&str]) -> ArgMatches {
+        Args::command()
+            .try_get_matches_from(std::iter::once("dashboard").chain(args.iter().copied()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn reload_applies_reloadable_args_only() {
+        let state = testing::state(&["--offline-threshold", "60", "--token-ttl", "600"]).await;
+        let (_filter, handle) = reload::Layer::<_, Registry>::new(EnvFilter::new("info"));
+        let token_ttl = |state: &AppState| {
+            let config = state.config();
+            let entry = config.iter().find(|entry| entry.name == "token_ttl");
+            entry.unwrap().value.clone()
+        };
+
+        let changed = matches(&["--offline-threshold", "120", "--token-ttl", "60"]);
+        reload_settings(&state, &handle, &changed).unwrap();
+        assert_eq!(state.settings().offline_threshold, 120);
+        assert_eq!(token_ttl(&state).as_deref(), Some("600"));
+        assert_eq!(state.args.token_ttl, 600);
+
+        // nothing is applied from invalid arguments
+        let invalid = matches(&["--offline-threshold", "180", "--token-ttl", "0"]);
+        assert!(reload_settings(&state, &handle, &invalid).is_err());
+        assert_eq!(state.settings().offline_threshold, 120);
+    }
+
+    #[tokio::test]
+    async fn reloaded_values_show_the_reload_source() {
+        let state = testing::state(&["--offline-threshold", "60", "--dedup-window", "5"]).await;
+        let (_filter, handle) = reload::Layer::<_, Registry>::new(EnvFilter::new("info"));
+        let entry = |name: &str| {
+            let config = state.config();
+            config.into_iter().find(|entry| entry.name == name).unwrap()
+        };
+
+        let changed = matches(&["--offline-threshold", "120", "--dedup-window", "5"]);
+        reload_settings(&state, &handle, &changed).unwrap();
+        let offline_threshold = entry("offline_threshold");
+        assert_eq!(offline_threshold.value.as_deref(), Some("120"));
+        assert_eq!(offline_threshold.source, ConfigSource::Reload);
+        assert_eq!(entry("dedup_window").source, ConfigSource::Args);
+    }
+
+    /// Returns the status of an authorized request with `token` to the router of `state`.
+    async fn authorized(state: &Arc<AppState>, token: &str) -> StatusCode {
+        let uri = "/api/dashboard/agent-versions";
+        let request = testing::request(Method::GET, uri, Some(token), None);
+        testing::send(&testing::router(state), request).await.0
+    }
+
+    #[tokio::test]
+    async fn reload_reads_the_secret_files() {
+        let state = testing::state(&["--secret", "startup"]).await;
+        let before = testing::state(&["--secret", "old"]).await;
+        let after = testing::state(&["--secret", "new"]).await;
+        let (_filter, handle) = reload::Layer::<_, Registry>::new(EnvFilter::new("info"));
+        let dir = tempfile::tempdir().unwrap();
+        let secret_file = dir.path().join("secret");
+        std::fs::write(&secret_file, "new\n").unwrap();
+
+        let startup = AuthorizedToken::issue(&state, Uuid::nil(), 3600).unwrap();
+        let previous = AuthorizedToken::issue(&before, Uuid::nil(), 3600).unwrap();
+        let new = AuthorizedToken::issue(&after, Uuid::nil(), 3600).unwrap();
+        assert_eq!(authorized(&state, &startup).await, StatusCode::OK);
+        assert_eq!(authorized(&state, &new).await, StatusCode::UNAUTHORIZED);
+
+        let rotating = matches(&[
+            "--secret-file",
+            secret_file.to_str().unwrap(),
+            "--previous-secret",
+            "old",
+        ]);
+        reload_settings(&state, &handle, &rotating).unwrap();
+        assert_eq!(authorized(&state, &startup).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(authorized(&state, &new).await, StatusCode::OK);
+        assert_eq!(authorized(&state, &previous).await, StatusCode::OK);
+
+        // tokens issued after the reload are signed with the key of the file
+        let issued = AuthorizedToken::issue(&state, Uuid::nil(), 3600).unwrap();
+        assert_eq!(authorized(&after, &issued).await, StatusCode::OK);
+    }
+
+    /// Reads the value of the sqlite pragma `name`.
+    async fn pragma<T: sea_orm::TryGetable>(conn: &DatabaseConnection, name: &str) -> T {
+        let sql = format!("SELECT * FROM pragma_{}", name);
+        let row = conn
+            .query_one(Statement::from_string(DbBackend::Sqlite, sql))
+            .await
+            .unwrap()
+            .unwrap();
+
+        row.try_get_by_index(0).unwrap()
+    }
+
+    #[tokio::test]
+    async fn sqlite_is_set_up_with_wal() {
+        // an in-memory database has no journal to switch
+        let dir = tempfile::tempdir().unwrap();
+        let url = format!("sqlite://{}?mode=rwc", dir.path().join("wk.db").display());
+        let args = Args::parse_from([
+            "dashboard",
+            "--database",
+            &url,
+            "--sqlite-busy-timeout",
+            "1234",
+        ]);
+        let conn = make_database(&args).await.unwrap();
+
+        assert_eq!(pragma::<String>(&conn, "journal_mode").await, "wal");
+        assert_eq!(pragma::<i64>(&conn, "busy_timeout").await, 1234);
+        // NORMAL
+        assert_eq!(pragma::<i64>(&conn, "synchronous").await, 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn world_writable_data_dir_is_refused_when_strict() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = dir.path().to_str().unwrap();
+        let check = |strict: bool| {
+            let mut args = vec!["dashboard", "--data-dir", data_dir];
+            if strict {
+                args.push("--strict-permissions");
+            }
+            check_data_dir(&Args::parse_from(args))
+        };
+
+        std::fs::set_permissions(dir.path(), std::fs::Permissions::from_mode(0o700)).unwrap();
+        assert!(check(true).is_ok());
+
+        std::fs::set_permissions(dir.path(), std::fs::Permissions::from_mode(0o777)).unwrap();
+        // only warned about without --strict-permissions
+        assert!(check(false).is_ok());
+        let err = check(true).unwrap_err();
+        assert!(
+            err.to_string().contains("is world-writable (mode 777)"),
+            "{}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn unmigrated_database_is_refused_without_auto_migrate() {
+        let args = Args::parse_from([
+            "dashboard",
+            "--database",
+            "sqlite::memory:",
+            "--no-auto-migrate",
+        ]);
+
+        let err = make_database(&args).await.unwrap_err().to_string();
+        assert!(
+            err.starts_with("database schema is not up to date"),
+            "{}",
+            err
+        );
+        assert!(err.contains("start without --no-auto-migrate"), "{}", err);
+    }
+}
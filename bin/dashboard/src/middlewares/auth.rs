@@ -1,9 +1,14 @@
+use crate::prelude::axum::AxumError;
+use crate::prelude::axum::StatusError;
 use crate::state::AppState;
 use axum::extract::Request;
 use axum::extract::State;
+use axum::http::HeaderMap;
 use axum::http::StatusCode;
-use jsonwebtoken::Validation;
+use database::models::prelude::User;
+use jsonwebtoken::errors::ErrorKind;
 use sea_orm::prelude::Uuid;
+use sea_orm::EntityTrait;
 use serde::Deserialize;
 use serde::Serialize;
 use std::sync::Arc;
@@ -18,6 +23,31 @@ pub struct AuthorizedToken {
     pub uid: Uuid,
     pub nbf: usize,
     pub exp: usize,
+    pub iss: String,
+    pub aud: String,
+}
+
+impl AuthorizedToken {
+    /// Mints a signed token for the user `uid`, valid for `ttl` seconds from now.
+    ///
+    /// The `iss` and `aud` claims are taken from `--jwt-issuer` and `--jwt-audience`.
+    pub fn issue(
+        state: &AppState,
+        uid: Uuid,
+        ttl: u64,
+    ) -> Result<String, jsonwebtoken::errors::Error> {
+        let now = jsonwebtoken::get_current_timestamp();
+        let claims = Self {
+            uid,
+            nbf: now as usize,
+            exp: (now + ttl) as usize,
+            iss: state.args.jwt_issuer.clone(),
+            aud: state.args.jwt_audience.clone(),
+        };
+
+        let jwt = state.jwt();
+        jsonwebtoken::encode(&jwt.header, &claims, &jwt.encoding)
+    }
 }
 
 /// Extracts the authorized token from the request and stores it in the request's extensions.
@@ -29,13 +59,13 @@ pub struct AuthorizedToken {
 ///
 /// # Errors
 ///
-/// Returns `StatusCode::UNAUTHORIZED` if the token does not exist or cannot be resolved.
+/// Returns `401 Unauthorized` if the token does not exist or cannot be resolved, see
+/// `resolve_token` for the error codes.
 ///
-#[allow(dead_code)]
 pub async fn authorized_token<B>(
     State(state): State<Arc<AppState>>,
     mut req: Request<B>,
-) -> Result<Request<B>, StatusCode> {
+) -> Result<Request<B>, StatusError> {
     let token = resolve_token(&state, &req)?;
     req.extensions_mut().insert(token.clone());
     req.extensions_mut().insert(Some(token));
@@ -50,7 +80,7 @@ pub async fn authorized_token<B>(
 pub async fn authorized_token_opt<B>(
     State(state): State<Arc<AppState>>,
     mut req: Request<B>,
-) -> Result<Request<B>, StatusCode> {
+) -> Result<Request<B>, StatusError> {
     if let Ok(token) = resolve_token(&state, &req) {
         req.extensions_mut().insert(Some(token));
     }
@@ -58,37 +88,177 @@ pub async fn authorized_token_opt<B>(
     Ok(req)
 }
 
+/// Extracts the authorized token from the request and verifies that it belongs to an
+/// administrator (`sa`) user.
+///
+/// On success, the token is stored in the request's extensions the same way as
+/// `authorized_token` does.
+///
+/// # Errors
+///
+/// Returns `401 Unauthorized` if the token does not exist or cannot be resolved, see
+/// `resolve_token` for the error codes.
+///
+/// Returns `403 Forbidden` if the user does not exist or is not an administrator.
+pub async fn authorized_admin<B>(
+    State(state): State<Arc<AppState>>,
+    mut req: Request<B>,
+) -> Result<Request<B>, AxumError> {
+    let token = resolve_token(&state, &req)?;
+
+    // load user and check administrator flag
+    let user = User::find_by_id(token.uid)
+        .one(state.database.as_ref())
+        .await?;
+    if !user.is_some_and(|user| user.sa) {
+        return Err(StatusError::new(
+            StatusCode::FORBIDDEN,
+            "forbidden",
+            "administrator privileges required",
+        )
+        .into());
+    }
+
+    req.extensions_mut().insert(token.clone());
+    req.extensions_mut().insert(Some(token));
+
+    Ok(req)
+}
+
 /// Resolves the authorized token from the request.
 ///
 /// This function extracts the token from the `Authorization` header and decodes it using the JWT
-/// configuration in the app state. If the token does not exist or cannot be resolved, it returns
-/// `StatusCode::UNAUTHORIZED`.
+/// configuration in the app state. Tokens signed with `--previous-secret` are accepted too.
 ///
 /// # Errors
 ///
-/// Returns `StatusCode::UNAUTHORIZED` if the token does not exist or cannot be resolved.
-///
-/// Returns `StatusCode::INTERNAL_SERVER_ERROR` if the app state is not present in the request's
-/// extensions.
-fn resolve_token<B>(state: &AppState, req: &Request<B>) -> Result<AuthorizedToken, StatusCode> {
+/// Returns `401 Unauthorized` with code `token_expired` if the token is expired, so clients
+/// can refresh it, or with code `token_invalid` if the token does not exist or cannot be
+/// resolved otherwise, including tokens whose `iss` or `aud` claim is missing or does not
+/// match this deployment.
+fn resolve_token<B>(state: &AppState, req: &Request<B>) -> Result<AuthorizedToken, StatusError> {
     // get token from request
-    let token = req
-        .headers()
+    let token = bearer_token(req.headers()).ok_or_else(|| {
+        StatusError::new(
+            StatusCode::UNAUTHORIZED,
+            "token_invalid",
+            "missing bearer token",
+        )
+    })?;
+
+    // decode token using jwt, falling back to the key before a rotation
+    let jwt = state.jwt();
+    let decode = |key| jsonwebtoken::decode::<AuthorizedToken>(token, key, &jwt.validation);
+    let decoded = decode(&jwt.decoding)
+        .or_else(|err| match (err.kind(), &jwt.previous) {
+            (ErrorKind::InvalidSignature, Some(previous)) => decode(previous),
+            _ => Err(err),
+        })
+        .map(|v| v.claims)
+        .map_err(|err| match err.kind() {
+            ErrorKind::ExpiredSignature => {
+                StatusError::new(StatusCode::UNAUTHORIZED, "token_expired", "token expired")
+            }
+            _ => StatusError::new(StatusCode::UNAUTHORIZED, "token_invalid", err.to_string()),
+        })?;
+
+    Ok(decoded)
+}
+
+/// Extracts the bearer token from the `Authorization` header, if present.
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
         .get(AUTHORIZATION_HEADER)
         .and_then(|header| header.to_str().ok())
         .and_then(|value| value.strip_prefix(AUTHORIZATION_PREFIX))
         .map(|token| token.trim_start())
-        .ok_or(StatusCode::UNAUTHORIZED)?
-        .to_owned();
-
-    // decode token using jwt
-    let decoded = jsonwebtoken::decode::<AuthorizedToken>(
-        &token,
-        &state.jwt.decoding,
-        &Validation::default(),
-    )
-    .map(|v| v.claims)
-    .map_err(|_| StatusCode::UNAUTHORIZED)?;
+}
 
-    Ok(decoded)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use axum::http::Method;
+
+    /// Resolves `token` as bearer token of a request.
+    fn resolve(state: &AppState, token: &str) -> Result<AuthorizedToken, StatusError> {
+        resolve_token(
+            state,
+            &testing::request(Method::GET, "/", Some(token), None),
+        )
+    }
+
+    #[tokio::test]
+    async fn expired_token_is_told_apart() {
+        let state = testing::state(&[]).await;
+
+        // beyond the default leeway of a minute
+        let now = jsonwebtoken::get_current_timestamp() as usize;
+        let claims = AuthorizedToken {
+            uid: Uuid::nil(),
+            nbf: now - 3600,
+            exp: now - 120,
+            iss: state.args.jwt_issuer.clone(),
+            aud: state.args.jwt_audience.clone(),
+        };
+        let jwt = state.jwt();
+        let token = jsonwebtoken::encode(&jwt.header, &claims, &jwt.encoding).unwrap();
+
+        let err = resolve(&state, &token).unwrap_err();
+        assert_eq!(err.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(err.code, "token_expired");
+    }
+
+    #[tokio::test]
+    async fn tampered_token_is_invalid() {
+        let state = testing::state(&[]).await;
+        let token = AuthorizedToken::issue(&state, Uuid::nil(), 3600).unwrap();
+        assert!(resolve(&state, &token).is_ok());
+
+        // swap the user id of the payload, keeping the signature
+        let mut parts = token.split('.').map(str::to_owned).collect::<Vec<_>>();
+        let other = AuthorizedToken::issue(&state, Uuid::max(), 3600).unwrap();
+        parts[1] = other.split('.').nth(1).unwrap().to_owned();
+        let tampered = parts.join(".");
+
+        let err = resolve(&state, &tampered).unwrap_err();
+        assert_eq!(err.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(err.code, "token_invalid");
+    }
+
+    #[tokio::test]
+    async fn rotation_accepts_previous_key_and_signs_with_new() {
+        let before = testing::state(&["--secret", "old"]).await;
+        let rotating = testing::state(&["--secret", "new", "--previous-secret", "old"]).await;
+        let after = testing::state(&["--secret", "new"]).await;
+
+        let old = AuthorizedToken::issue(&before, Uuid::nil(), 3600).unwrap();
+        assert!(resolve(&rotating, &old).is_ok());
+        assert!(resolve(&after, &old).is_err());
+
+        let new = AuthorizedToken::issue(&rotating, Uuid::nil(), 3600).unwrap();
+        assert!(resolve(&after, &new).is_ok());
+        assert!(resolve(&before, &new).is_err());
+    }
+
+    #[tokio::test]
+    async fn wrong_audience_is_rejected() {
+        let state = testing::state(&["--jwt-audience", "dashboard"]).await;
+
+        // signed with the same secret, but meant for another service
+        let now = jsonwebtoken::get_current_timestamp() as usize;
+        let claims = AuthorizedToken {
+            uid: Uuid::nil(),
+            nbf: now,
+            exp: now + 3600,
+            iss: state.args.jwt_issuer.clone(),
+            aud: "billing".to_owned(),
+        };
+        let jwt = state.jwt();
+        let token = jsonwebtoken::encode(&jwt.header, &claims, &jwt.encoding).unwrap();
+
+        let err = resolve(&state, &token).unwrap_err();
+        assert_eq!(err.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(err.code, "token_invalid");
+    }
 }
@@ -0,0 +1,102 @@
+use crate::prelude::axum::StatusError;
+use crate::state::AppState;
+use axum::extract::ConnectInfo;
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// Caps the concurrent agent requests and websocket connections of a client IP at
+/// `--max-connections-per-ip`.
+///
+/// The slot is held until the response is sent. It is also passed on as an
+/// `Arc<ClientGuard>` request extension, so a websocket keeps it for as long as the
+/// connection stays open. Without a cap, requests are passed through.
+///
+/// # Errors
+///
+/// Returns `429 Too Many Requests` if the client IP has reached the cap.
+pub async fn client_limit(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let Some(max) = state.args.max_connections_per_ip.filter(|max| *max > 0) else {
+        return next.run(req).await;
+    };
+
+    let Some(guard) = state.clients.acquire(addr.ip(), max) else {
+        tracing::warn!(
+            "rejected connection of {}, cap of {} reached",
+            addr.ip(),
+            max
+        );
+        return StatusError::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            "too_many_connections",
+            format!("at most {} concurrent connections per client", max),
+        )
+        .into_response();
+    };
+
+    req.extensions_mut().insert(Arc::new(guard));
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing;
+    use axum::http::Method;
+    use axum::http::StatusCode;
+    use std::net::IpAddr;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn connections_beyond_the_cap_are_refused() {
+        let state = testing::state(&["--max-connections-per-ip", "1"]).await;
+        let addr = testing::serve(&state).await;
+        let router = testing::router(&state);
+        let config = || testing::request(Method::GET, "/api/agent/m1/config", None, None);
+
+        // an open websocket holds the only slot of the client
+        let url = format!("ws://{}/api/agent/m1/report", addr);
+        let (mut ws, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        let (status, body) = testing::send(&router, config()).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(body["code"], "too_many_connections");
+
+        // released once the server noticed the close
+        ws.close(None).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while testing::send(&router, config()).await.0 != StatusCode::OK {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn slot_is_freed_when_the_guard_drops() {
+        let state = testing::state(&[]).await;
+        let ip = IpAddr::from(testing::CLIENT_ADDR.0);
+
+        let guard = state.clients.acquire(ip, 2).unwrap();
+        let second = state.clients.acquire(ip, 2).unwrap();
+        assert!(state.clients.acquire(ip, 2).is_none());
+        // other clients have slots of their own
+        assert!(state
+            .clients
+            .acquire(IpAddr::from([127, 0, 0, 2]), 2)
+            .is_some());
+
+        drop(guard);
+        assert!(state.clients.acquire(ip, 2).is_some());
+        drop(second);
+    }
+}
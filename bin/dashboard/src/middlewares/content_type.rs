@@ -0,0 +1,100 @@
+use crate::prelude::axum::StatusError;
+use axum::extract::Request;
+use axum::http::header;
+use axum::http::StatusCode;
+
+/// Requires a JSON `Content-Type` for requests that carry a body.
+///
+/// Requests without a body and without a `Content-Type` header are passed through, so
+/// plain `GET`s and body-less `POST`s keep working. Everything else must declare
+/// `application/json` (or a `+json` suffixed type).
+///
+/// # Errors
+///
+/// Returns `415 Unsupported Media Type` if the content type is missing or not JSON.
+pub async fn json_content_type<B>(req: Request<B>) -> Result<Request<B>, StatusError> {
+    check_content_type(
+        req,
+        is_json,
+        "expected request with `Content-Type: application/json`",
+    )
+}
+
+/// Like `json_content_type`, but also accepts `application/x-www-form-urlencoded`, for
+/// endpoints that HTML forms post to.
+///
+/// # Errors
+///
+/// Returns `415 Unsupported Media Type` if the content type is missing or neither JSON
+/// nor form encoded.
+pub async fn json_or_form_content_type<B>(req: Request<B>) -> Result<Request<B>, StatusError> {
+    check_content_type(
+        req,
+        |value| is_json(value) || is_form(value),
+        "expected request with `Content-Type: application/json` \
+         or `application/x-www-form-urlencoded`",
+    )
+}
+
+/// Passes requests without a body and content type, or with a content type accepted by
+/// `accepted`.
+fn check_content_type<B>(
+    req: Request<B>,
+    accepted: impl Fn(&str) -> bool,
+    message: &'static str,
+) -> Result<Request<B>, StatusError> {
+    let headers = req.headers();
+
+    // check request has body
+    let has_body = headers.contains_key(header::TRANSFER_ENCODING)
+        || headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.trim() != "0");
+
+    match headers.get(header::CONTENT_TYPE) {
+        None if !has_body => Ok(req),
+        Some(value) if value.to_str().is_ok_and(accepted) => Ok(req),
+        _ => Err(StatusError::new(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "unsupported_media_type",
+            message,
+        )),
+    }
+}
+
+/// Checks whether the given `Content-Type` value is a JSON media type.
+fn is_json(value: &str) -> bool {
+    let mime = value.split(';').next().unwrap_or_default().trim();
+
+    mime.eq_ignore_ascii_case("application/json") || mime.to_ascii_lowercase().ends_with("+json")
+}
+
+/// Checks whether the given `Content-Type` value is the URL encoded form media type.
+pub fn is_form(value: &str) -> bool {
+    let mime = value.split(';').next().unwrap_or_default().trim();
+
+    mime.eq_ignore_ascii_case("application/x-www-form-urlencoded")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing;
+    use axum::body::Body;
+    use axum::http::header;
+    use axum::http::Request;
+    use axum::http::StatusCode;
+
+    #[tokio::test]
+    async fn wrong_content_type_is_unsupported() {
+        let state = testing::state(&[]).await;
+
+        let request = Request::post("/api/auth/authorize")
+            .header(header::CONTENT_TYPE, "text/plain")
+            .body(Body::from("{}"))
+            .unwrap();
+        let (status, body) = testing::send(&testing::router(&state), request).await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(body["code"], "unsupported_media_type");
+    }
+}
@@ -1,3 +1,15 @@
 mod auth;
+mod client_limit;
+mod content_type;
+mod read_only;
+mod request_id;
+mod setup;
+mod timeout;
 
 pub use self::auth::*;
+pub use self::client_limit::*;
+pub use self::content_type::*;
+pub use self::read_only::*;
+pub use self::request_id::*;
+pub use self::setup::*;
+pub use self::timeout::*;
@@ -0,0 +1,116 @@
+use crate::prelude::axum::StatusError;
+use crate::state::AppState;
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::Method;
+use axum::http::StatusCode;
+use std::sync::Arc;
+
+/// Routes passed through by `read_only_guard` despite their method, so users can still
+/// log in and keep their session to read.
+const READ_ONLY_EXEMPT: &[&str] = &["/api/auth/authorize", "/api/auth/refresh"];
+
+/// Rejects mutating requests when the server runs in read-only mode.
+///
+/// Requests with a safe method (`GET`, `HEAD`, `OPTIONS`) are passed through, as are
+/// logins and session refreshes.
+///
+/// # Errors
+///
+/// Returns `503 Service Unavailable` for any other method in read-only mode.
+pub async fn read_only_guard<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+) -> Result<Request<B>, StatusError> {
+    match *req.method() {
+        Method::GET | Method::HEAD | Method::OPTIONS => Ok(req),
+        _ if READ_ONLY_EXEMPT.contains(&req.uri().path()) => Ok(req),
+        _ => read_only_reject(State(state), req).await,
+    }
+}
+
+/// Rejects every request when the server runs in read-only mode.
+///
+/// Used for routes that write to the database regardless of the request method,
+/// e.g. agent ingestion and captcha generation.
+///
+/// # Errors
+///
+/// Returns `503 Service Unavailable` in read-only mode.
+pub async fn read_only_reject<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+) -> Result<Request<B>, StatusError> {
+    if state.args.read_only {
+        return Err(StatusError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "read_only",
+            "server is running in read-only mode",
+        ));
+    }
+
+    Ok(req)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing;
+    use axum::http::Method;
+    use axum::http::StatusCode;
+    use database::models::prelude::User;
+    use database::models::user;
+    use sea_orm::prelude::Uuid;
+    use sea_orm::EntityTrait;
+    use sea_orm::IntoActiveModel;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn rejects_report_and_serves_host_list() {
+        let state = testing::state(&["--read-only"]).await;
+        let token = testing::admin(&state).await;
+        let router = testing::router(&state);
+
+        let os = json!([{ "EvtOsEmit": { "family": "linux" } }]);
+        let request = testing::request(Method::POST, "/api/agent/m1/report", None, Some(os));
+        let (status, body) = testing::send(&router, request).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["code"], "read_only");
+
+        let request = testing::request(Method::GET, "/api/admin/hosts", Some(&token), None);
+        let (status, body) = testing::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!([]));
+    }
+
+    #[tokio::test]
+    async fn login_and_refresh_are_served() {
+        let state = testing::state(&["--read-only"]).await;
+        let router = testing::router(&state);
+        User::insert(
+            user::Model {
+                id: Uuid::from_bytes(uuidv7::create_raw()),
+                sa: false,
+                nickname: "User".to_owned(),
+                email: "user@example.com".to_owned(),
+                password: crate::api::user::hash_password("password").unwrap(),
+                created_at: chrono::Utc::now(),
+                updated_at: chrono::Utc::now(),
+            }
+            .into_active_model(),
+        )
+        .exec(state.database.as_ref())
+        .await
+        .unwrap();
+
+        let credentials = json!({ "email": "user@example.com", "password": "password" });
+        let request =
+            testing::request(Method::POST, "/api/auth/authorize", None, Some(credentials));
+        let (status, body) = testing::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        let refresh = json!({ "refresh_token": body["refresh_token"] });
+        let request = testing::request(Method::POST, "/api/auth/refresh", None, Some(refresh));
+        let (status, body) = testing::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+    }
+}
@@ -0,0 +1,79 @@
+use crate::state::AppState;
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::HeaderValue;
+use axum::middleware::Next;
+use axum::response::Response;
+use sea_orm::prelude::Uuid;
+use std::sync::Arc;
+
+/// Longest request id accepted from a client.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Id correlating a request with its log lines and response.
+#[derive(Clone, Debug)]
+pub struct RequestId(pub String);
+
+/// Assigns every request an id, carried in the `--request-id-header` header.
+///
+/// An id sent by the client (or a proxy in front of the server) is kept if it is at
+/// most 128 visible ASCII characters, otherwise a new UUID is generated. The id is
+/// set on the request, available as a `RequestId` extension, and echoed in the
+/// response.
+pub async fn request_id(
+    State(state): State<Arc<AppState>>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let name = &state.args.request_id_header;
+
+    let value = req
+        .headers()
+        .get(name)
+        .filter(|value| {
+            value.len() <= MAX_REQUEST_ID_LEN
+                && !value.is_empty()
+                && value.as_bytes().iter().all(u8::is_ascii_graphic)
+        })
+        .cloned()
+        .unwrap_or_else(|| {
+            let id = Uuid::from_bytes(uuidv7::create_raw()).to_string();
+            HeaderValue::from_str(&id).expect("UUIDs are valid header values")
+        });
+    let id = value.to_str().unwrap_or_default().to_owned();
+
+    req.headers_mut().insert(name.clone(), value.clone());
+    req.extensions_mut().insert(RequestId(id));
+
+    let mut resp = next.run(req).await;
+    resp.headers_mut().insert(name.clone(), value);
+
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing;
+    use axum::http::Method;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn custom_header_is_honored_and_echoed() {
+        let state = testing::state(&["--request-id-header", "X-Correlation-Id"]).await;
+        let router = testing::router(&state);
+
+        let mut request = testing::request(Method::GET, "/api/capabilities", None, None);
+        request
+            .headers_mut()
+            .insert("x-correlation-id", "abc-123".parse().unwrap());
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.headers()["x-correlation-id"], "abc-123");
+        assert!(!response.headers().contains_key("x-request-id"));
+
+        // generated if the client sends none
+        let request = testing::request(Method::GET, "/api/capabilities", None, None);
+        let response = router.oneshot(request).await.unwrap();
+        let generated = response.headers()["x-correlation-id"].to_str().unwrap();
+        assert!(sea_orm::prelude::Uuid::parse_str(generated).is_ok());
+    }
+}
@@ -0,0 +1,75 @@
+use crate::api::user;
+use crate::state::AppState;
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::StatusCode;
+use std::sync::Arc;
+
+/// Hides the initialization route once the application is initialized, with
+/// `--disable-init-after-setup`.
+///
+/// The response is the same bare `404 Not Found` as for unknown routes, so the route
+/// cannot be told apart from one that does not exist, whatever the method or body.
+///
+/// # Errors
+///
+/// Returns `404 Not Found` if the setting is enabled and a user exists, or
+/// `500 Internal Server Error` if the initialized state cannot be checked.
+pub async fn init_guard<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+) -> Result<Request<B>, StatusCode> {
+    if !state.args.disable_init_after_setup {
+        return Ok(req);
+    }
+
+    match user::initlizated(&state).await {
+        Ok(false) => Ok(req),
+        Ok(true) => Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            tracing::warn!("check initialized failed: {}", err);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing;
+    use axum::body::Body;
+    use axum::http::Method;
+    use axum::http::Request;
+    use axum::http::StatusCode;
+    use serde_json::json;
+    use serde_json::Value;
+
+    #[tokio::test]
+    async fn init_is_hidden_after_setup() {
+        let _initialization = testing::initialization().await;
+        let state = testing::state(&["--disable-captcha", "--disable-init-after-setup"]).await;
+        crate::api::user::reinitlizated(&state).await.unwrap();
+        let router = testing::router(&state);
+        let init = || {
+            let body = json!({
+                "captcha_id": "",
+                "captcha_answer": "",
+                "email": "admin@example.com",
+                "password": "password",
+            });
+            testing::request(Method::POST, "/api/auth/init", None, Some(body))
+        };
+
+        let (status, body) = testing::send(&router, init()).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        // bare, whatever the method or body
+        let malformed = Request::put("/api/auth/init")
+            .body(Body::from("garbage"))
+            .unwrap();
+        for request in [init(), malformed] {
+            let (status, body) = testing::send(&router, request).await;
+            assert_eq!(status, StatusCode::NOT_FOUND);
+            assert_eq!(body, Value::Null);
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use crate::prelude::axum::StatusError;
+use crate::state::AppState;
+use axum::body::Body;
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::header;
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Cuts off requests which take longer than the configured request timeout.
+///
+/// The timeout covers producing the response head, so streamed bodies are not cut off
+/// once started. WebSocket upgrades and server-sent event streams are passed through.
+/// A request timeout of `0` disables the timeout.
+///
+/// # Errors
+///
+/// Returns `504 Gateway Timeout` if the timeout is exceeded.
+pub async fn request_timeout(
+    State(state): State<Arc<AppState>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let timeout = state.args.request_timeout;
+    if timeout == 0 || is_long_lived(&req) {
+        return next.run(req).await;
+    }
+
+    match tokio::time::timeout(Duration::from_secs(timeout), next.run(req)).await {
+        Ok(resp) => resp,
+        Err(_) => StatusError::new(
+            StatusCode::GATEWAY_TIMEOUT,
+            "request_timeout",
+            format!("request not completed within {} seconds", timeout),
+        )
+        .into_response(),
+    }
+}
+
+/// Cuts off agent requests whose body is not fully received within `--body-timeout`,
+/// so clients cannot hold connections by trickling a body.
+///
+/// The body is buffered before the request is passed on, up to the largest body an
+/// agent route accepts. WebSocket upgrades are passed through. A body timeout of `0`
+/// disables the timeout.
+///
+/// # Errors
+///
+/// Returns `408 Request Timeout` if the timeout is exceeded, `413 Payload Too Large` if
+/// the body exceeds the size limit, or `400 Bad Request` if the body cannot be read.
+pub async fn body_timeout(
+    State(state): State<Arc<AppState>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let timeout = state.args.body_timeout;
+    if timeout == 0 || is_long_lived(&req) {
+        return next.run(req).await;
+    }
+
+    let limit = crate::api::agent::MAX_REPORT_SIZE.max(state.args.max_agent_log_size);
+    let (parts, body) = req.into_parts();
+    let buffered = axum::body::to_bytes(body, limit);
+
+    match tokio::time::timeout(Duration::from_secs(timeout), buffered).await {
+        Ok(Ok(bytes)) => {
+            next.run(Request::from_parts(parts, Body::from(bytes)))
+                .await
+        }
+        Ok(Err(err))
+            if std::error::Error::source(&err)
+                .is_some_and(|source| source.is::<http_body_util::LengthLimitError>()) =>
+        {
+            StatusError::new(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "body_too_large",
+                format!("request body must not be larger than {} bytes", limit),
+            )
+            .into_response()
+        }
+        Ok(Err(err)) => StatusError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_body",
+            format!("request body cannot be read: {}", err),
+        )
+        .into_response(),
+        Err(_) => StatusError::new(
+            StatusCode::REQUEST_TIMEOUT,
+            "body_timeout",
+            format!("request body not received within {} seconds", timeout),
+        )
+        .into_response(),
+    }
+}
+
+/// Checks if the request opens a websocket or an event stream.
+fn is_long_lived(req: &Request) -> bool {
+    let header_contains = |name, value: &str| {
+        req.headers()
+            .get(name)
+            .and_then(|header| header.to_str().ok())
+            .is_some_and(|header| header.to_ascii_lowercase().contains(value))
+    };
+
+    header_contains(header::UPGRADE, "websocket")
+        || header_contains(header::ACCEPT, "text/event-stream")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use axum::body::Body;
+    use axum::middleware::from_fn_with_state;
+    use axum::routing;
+    use axum::Router;
+    use futures::StreamExt;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn slow_handler_is_cut_off() {
+        let state = testing::state(&["--request-timeout", "1"]).await;
+        let router = Router::new()
+            .route(
+                "/slow",
+                routing::get(|| tokio::time::sleep(Duration::from_secs(5))),
+            )
+            .layer(from_fn_with_state(state, request_timeout));
+        // paused only now, the database pool needs the real clock to connect
+        tokio::time::pause();
+
+        let request = Request::builder().uri("/slow").body(Body::empty()).unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn trickled_body_is_cut_off() {
+        let state = testing::state(&["--body-timeout", "1"]).await;
+        let router = Router::new()
+            .route("/report", routing::post(|body: String| async move { body }))
+            .layer(from_fn_with_state(state, body_timeout));
+        tokio::time::pause();
+
+        // the first chunk arrives, the rest never does
+        let chunk = futures::stream::iter([Ok::<_, std::io::Error>("[")]);
+        let body = Body::from_stream(chunk.chain(futures::stream::pending()));
+        let request = Request::post("/report").body(body).unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+    }
+}
@@ -1,19 +1,28 @@
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
+use axum::Json;
+use proto::error::ErrorResp;
+use std::fmt;
 
 pub use axum::extract::Path;
 pub use axum::extract::State;
 
 /// Wrapper for `anyhow::Error` that implements `IntoResponse`.
+///
+/// If the wrapped error is a `StatusError`, it is rendered as a structured response,
+/// otherwise a generic `500 Internal Server Error` is returned.
 pub struct AxumError(anyhow::Error);
 
 impl IntoResponse for AxumError {
     fn into_response(self) -> axum::response::Response {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Internal Server Error: {}", self.0),
-        )
-            .into_response()
+        match self.0.downcast::<StatusError>() {
+            Ok(err) => err.into_response(),
+            Err(err) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Internal Server Error: {}", err),
+            )
+                .into_response(),
+        }
     }
 }
 
@@ -25,3 +34,44 @@ where
         Self(value.into())
     }
 }
+
+/// Error with a status code and a machine readable code.
+///
+/// Rendered as a JSON `ErrorResp` body, so clients can react on `code`.
+#[derive(Debug)]
+pub struct StatusError {
+    pub status: StatusCode,
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl StatusError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for StatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for StatusError {}
+
+impl IntoResponse for StatusError {
+    fn into_response(self) -> axum::response::Response {
+        (
+            self.status,
+            Json(ErrorResp {
+                code: self.code.to_owned(),
+                message: self.message,
+            }),
+        )
+            .into_response()
+    }
+}
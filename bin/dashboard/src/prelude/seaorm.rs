@@ -4,6 +4,7 @@ pub use sea_orm::prelude::*;
 pub use sea_orm::ActiveValue::*;
 pub use sea_orm::EntityTrait;
 pub use sea_orm::QueryFilter;
+pub use sea_orm::QueryOrder;
 
 pub trait IntoActiveValueExt<V>
 where
@@ -1,58 +1,432 @@
 use crate::api;
+use crate::middlewares::authorized_admin;
+use crate::middlewares::authorized_token;
 use crate::middlewares::authorized_token_opt;
+use crate::middlewares::body_timeout;
+use crate::middlewares::client_limit;
+use crate::middlewares::init_guard;
+use crate::middlewares::json_content_type;
+use crate::middlewares::json_or_form_content_type;
+use crate::middlewares::read_only_guard;
+use crate::middlewares::read_only_reject;
+use crate::middlewares::request_id;
+use crate::middlewares::request_timeout;
+use crate::middlewares::RequestId;
 use crate::state::AppState;
+use axum::body::Body;
+use axum::extract::DefaultBodyLimit;
+use axum::extract::Request;
+use axum::middleware::from_fn_with_state;
+use axum::middleware::map_request;
 use axum::middleware::map_request_with_state;
+use axum::response::Response;
 use axum::routing;
 use axum::Router;
+use std::convert::Infallible;
 use std::sync::Arc;
+use std::time::Duration;
+use tower::limit::ConcurrencyLimitLayer;
+use tower_http::trace::DefaultOnRequest;
+use tower_http::trace::DefaultOnResponse;
+use tower_http::trace::OnRequest;
+use tower_http::trace::OnResponse;
 use tower_http::trace::TraceLayer;
+use tracing::Span;
 
+/// Builds the router served on `--listen`.
+///
+/// With `--admin-listen`, the health and metrics endpoints are served on the admin
+/// listener only, as is the admin API with `--admin-listen-api`.
 pub fn make(state: Arc<AppState>) -> Router {
-    Router::new()
+    let separate = state.args.admin_listen.is_some();
+
+    let mut router = Router::new()
+        .route("/api/capabilities", routing::get(api::server::capabilities))
         .nest("/api/auth", make_auth(state.clone()))
         .nest("/api/agent", make_agent(state.clone()))
-        .nest("/api/admin", make_admin(state.clone()))
-        .nest("/api/dashboard", make_dashboard(state.clone()))
-        .with_state(state)
-        .layer(TraceLayer::new_for_http())
+        .nest("/api/dashboard", make_dashboard(state.clone()));
+    if !(separate && state.args.admin_listen_api) {
+        router = router.nest("/api/admin", make_admin(state.clone()));
+    }
+    if !separate {
+        router = router.merge(make_ops());
+    }
+
+    finish(router, state)
+}
+
+/// Builds the router served on `--admin-listen`.
+pub fn make_admin_listener(state: Arc<AppState>) -> Router {
+    let mut router = make_ops();
+    if state.args.admin_listen_api {
+        router = router.nest("/api/admin", make_admin(state.clone()));
+    }
+
+    finish(router, state)
+}
+
+/// Applies the layers shared by all listeners.
+fn finish(router: Router<Arc<AppState>>, state: Arc<AppState>) -> Router {
+    // requests to quiet paths get no span, which also silences their request events,
+    // others are tagged with their request id
+    let quiet = state.args.quiet_paths.clone();
+    let trace = TraceLayer::new_for_http()
+        .make_span_with(move |request: &Request<Body>| {
+            let path = request.uri().path();
+            let quiet = quiet.iter().any(|prefix| {
+                path.strip_prefix(prefix.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            });
+            if quiet {
+                Span::none()
+            } else {
+                let request_id = request
+                    .extensions()
+                    .get::<RequestId>()
+                    .map(|id| id.0.as_str())
+                    .unwrap_or_default();
+                tracing::debug_span!(
+                    "request",
+                    method = %request.method(),
+                    uri = %request.uri(),
+                    version = ?request.version(),
+                    request_id,
+                )
+            }
+        })
+        .on_request(|request: &Request<Body>, span: &Span| {
+            if !span.is_none() {
+                DefaultOnRequest::new().on_request(request, span)
+            }
+        })
+        .on_response(|response: &Response, latency: Duration, span: &Span| {
+            if !span.is_none() {
+                DefaultOnResponse::new().on_response(response, latency, span)
+            }
+        });
+
+    router
+        .layer(map_request_with_state(state.clone(), read_only_guard))
+        .layer(from_fn_with_state(state.clone(), request_timeout))
+        .with_state(state.clone())
+        .layer(trace)
+        .layer(from_fn_with_state(state, request_id))
+}
+
+fn make_ops() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/healthz", routing::get(api::health::healthz))
+        .route("/metrics", routing::get(api::metrics::metrics))
+        .route("/metrics/hosts", routing::get(api::metrics::host_metrics))
 }
 
 fn make_auth(state: Arc<AppState>) -> Router<Arc<AppState>> {
     Router::new()
-        .route("/init", routing::post(api::auth::init))
-        .route("/captcha", routing::get(api::auth::captcha))
+        .route(
+            "/captcha",
+            routing::get(api::auth::captcha)
+                .route_layer(map_request_with_state(state.clone(), read_only_reject)),
+        )
+        .route(
+            "/profile",
+            routing::put(api::auth::profile)
+                .route_layer(map_request_with_state(state.clone(), authorized_token)),
+        )
+        .route(
+            "/sessions",
+            routing::get(api::auth::sessions)
+                .route_layer(map_request_with_state(state.clone(), authorized_token)),
+        )
+        .route(
+            "/sessions/{id}",
+            routing::delete(api::auth::session_revoke)
+                .route_layer(map_request_with_state(state.clone(), authorized_token)),
+        )
+        .route("/refresh", routing::post(api::auth::refresh))
+        .route_layer(map_request(json_content_type))
+        // also posted by HTML forms
         .route("/authorize", routing::get(|| async { "" }))
-        .route("/authorize", routing::post(|| async { "" }))
+        .route("/authorize", routing::post(api::auth::authorize))
+        .route_layer(map_request(json_or_form_content_type))
+        // hidden before anything else is checked, once disabled after setup
+        .route(
+            "/init",
+            routing::post(api::auth::init)
+                .route_layer(map_request(json_or_form_content_type))
+                .layer(map_request_with_state(state.clone(), init_guard)),
+        )
         .layer(map_request_with_state(state.clone(), authorized_token_opt))
 }
 
-fn make_agent(_: Arc<AppState>) -> Router<Arc<AppState>> {
+fn make_agent(state: Arc<AppState>) -> Router<Arc<AppState>> {
     Router::new()
         .route("/{machine_id}/config", routing::get(api::agent::config))
-        .route("/{machine_id}/report", routing::post(api::agent::report))
+        .route(
+            "/{machine_id}/report",
+            routing::post(api::agent::report)
+                .layer::<_, Infallible>(DefaultBodyLimit::max(api::agent::MAX_REPORT_SIZE))
+                .layer(ConcurrencyLimitLayer::new(
+                    state.args.max_concurrent_reports,
+                )),
+        )
         .route("/{machine_id}/report", routing::get(api::agent::websocket))
+        .route("/{machine_id}/logs", routing::post(api::agent::logs))
+        .route(
+            "/{machine_id}/uploads",
+            routing::post(api::agent::upload_create),
+        )
+        .route_layer(from_fn_with_state(state.clone(), body_timeout))
+        // reads its larger body itself, after checking the upload url
+        .route("/uploads/{token}", routing::put(api::agent::upload))
+        .route_layer(map_request_with_state(state.clone(), read_only_reject))
+        // read only, so also served in read-only mode
+        .route("/capabilities", routing::get(api::agent::capabilities))
+        .route("/{machine_id}/whoami", routing::get(api::agent::whoami))
+        .route_layer(from_fn_with_state(state.clone(), client_limit))
 }
 
-fn make_admin(_: Arc<AppState>) -> Router<Arc<AppState>> {
+fn make_admin(state: Arc<AppState>) -> Router<Arc<AppState>> {
     Router::new()
+        .route("/agents/errors", routing::get(api::admin::agent_errors))
+        .route(
+            "/agents/refresh-config",
+            routing::post(api::admin::agents_refresh_config),
+        )
+        .route("/alerts", routing::get(api::admin::alerts))
+        .route("/alerts/{id}/ack", routing::post(api::admin::alert_ack))
+        .route("/backup", routing::post(api::admin::backup))
+        .route("/captcha/stats", routing::get(api::admin::stats_captcha))
         .route("/config", routing::get(|| async { "" }))
         .route("/config", routing::post(|| async { "" }))
-        .route("/hosts", routing::get(|| async { "" }))
+        .route(
+            "/config/effective",
+            routing::get(api::admin::config_effective),
+        )
+        .route("/enrollments", routing::post(api::admin::enrollment_create))
+        .route("/events", routing::get(api::admin::events))
+        .route(
+            "/hardware-changes",
+            routing::get(api::admin::hardware_changes),
+        )
+        .route("/hosts", routing::get(api::admin::hosts))
         .route("/hosts", routing::post(|| async { "" }))
+        .route(
+            "/hosts/by-hardware",
+            routing::get(api::admin::hosts_by_hardware),
+        )
+        .route("/hosts/export", routing::get(api::admin::host_export))
+        .route(
+            "/hosts/label-by-filter",
+            routing::post(api::admin::host_label_by_filter),
+        )
         .route("/hosts/{id}", routing::get(|| async { "" }))
         .route("/hosts/{id}", routing::put(|| async { "" }))
         .route("/hosts/{id}", routing::delete(|| async { "" }))
+        .route(
+            "/hosts/{id}/export",
+            routing::get(api::admin::host_export_bundle),
+        )
+        .route(
+            "/hosts/{id}/disconnect",
+            routing::post(api::admin::host_disconnect),
+        )
+        .route(
+            "/hosts/{id}/alert-thresholds",
+            routing::get(api::admin::host_alert_thresholds)
+                .put(api::admin::host_alert_thresholds_set),
+        )
+        .route("/hosts/{id}/labels", routing::get(api::admin::host_labels))
+        .route("/hosts/{id}/logs", routing::get(api::admin::host_logs))
+        .route(
+            "/hosts/{id}/display-name",
+            routing::put(api::admin::host_display_name),
+        )
+        .route(
+            "/hosts/{id}/commands",
+            routing::post(api::admin::host_command_create),
+        )
+        .route(
+            "/hosts/{id}/maintenance",
+            routing::put(api::admin::host_maintenance),
+        )
+        .route("/hosts/{id}/merge", routing::post(api::admin::host_merge))
+        .route(
+            "/initialized/refresh",
+            routing::post(api::admin::initialized_refresh),
+        )
+        .route("/logs/stream", routing::get(api::admin::logs_stream))
+        .route("/schema", routing::get(api::admin::schema))
+        .route("/stats/latency", routing::get(api::admin::stats_latency))
         .route("/users", routing::get(|| async { "" }))
-        .route("/users", routing::post(|| async { "" }))
+        .route("/users", routing::post(api::admin::user_create))
         .route("/users/{id}", routing::get(|| async { "" }))
         .route("/users/{id}", routing::put(|| async { "" }))
         .route("/users/{id}", routing::delete(|| async { "" }))
+        .route("/webhook/test", routing::post(api::admin::webhook_test))
+        .route_layer(map_request(json_content_type))
+        // accepts CSV uploads too, so registered after the JSON content type check
+        .route("/hosts/import", routing::post(api::admin::host_import))
+        .route_layer(map_request_with_state(state.clone(), authorized_admin))
 }
 
-fn make_dashboard(_: Arc<AppState>) -> Router<Arc<AppState>> {
+fn make_dashboard(state: Arc<AppState>) -> Router<Arc<AppState>> {
     Router::new()
+        .route(
+            "/agent-versions",
+            routing::get(api::dashboard::agent_versions),
+        )
         .route("/config", routing::get(|| async { "" }))
         .route("/summary", routing::get(|| async { "" }))
+        .route("/geo/health", routing::get(api::dashboard::geo_health))
+        .route(
+            "/fleet/snapshots",
+            routing::get(api::dashboard::fleet_snapshots),
+        )
         .route("/hosts", routing::get(|| async { "" }))
+        .route("/hosts/batch", routing::post(api::dashboard::hosts_batch))
+        .route(
+            "/hosts/compare",
+            routing::get(api::dashboard::hosts_compare),
+        )
+        .route(
+            "/hosts/missing-metrics",
+            routing::get(api::dashboard::hosts_missing_metrics),
+        )
         .route("/hosts/{id}", routing::get(|| async { "" }))
+        .route(
+            "/hosts/{id}/metrics/export",
+            routing::get(api::dashboard::host_metrics_export),
+        )
+        .route(
+            "/hosts/{id}/uptime",
+            routing::get(api::dashboard::host_uptime),
+        )
+        .route("/id-prefixes", routing::get(api::dashboard::id_prefixes))
+        .route(
+            "/metrics/aggregate",
+            routing::get(api::dashboard::metrics_aggregate),
+        )
+        .route(
+            "/os-builds/outdated",
+            routing::get(api::dashboard::os_builds_outdated),
+        )
+        .route("/os-versions", routing::get(api::dashboard::os_versions))
+        .route("/stale-hosts", routing::get(api::dashboard::stale_hosts))
+        .route(
+            "/virtualization",
+            routing::get(api::dashboard::virtualization),
+        )
+        .route_layer(map_request_with_state(state.clone(), authorized_token))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing;
+    use axum::http::Method;
+    use axum::http::StatusCode;
+    use database::models::prelude::*;
+    use sea_orm::EntityTrait;
+    use sea_orm::PaginatorTrait;
+    use serde_json::json;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    #[tokio::test]
+    async fn reports_over_the_limit_are_queued() {
+        let state = testing::state(&["--max-concurrent-reports", "2"]).await;
+        let router = testing::router(&state);
+
+        let reports = (0..8).map(|n| {
+            let uri = format!("/api/agent/m{}/report", n);
+            let os = json!([{ "EvtOsEmit": { "family": "linux" } }]);
+            testing::send(
+                &router,
+                testing::request(Method::POST, &uri, None, Some(os)),
+            )
+        });
+        for (status, body) in futures::future::join_all(reports).await {
+            assert_eq!(status, StatusCode::OK, "{}", body);
+        }
+
+        let hosts = Host::find().count(state.database.as_ref()).await.unwrap();
+        assert_eq!(hosts, 8);
+    }
+
+    /// Collects what a subscriber writes.
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn quiet_paths_are_not_logged() {
+        let state = testing::state(&["--quiet-paths", "/api/agent/m1"]).await;
+        let router = testing::router(&state);
+        let captured = Captured::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_ansi(false)
+            .with_writer({
+                let captured = captured.clone();
+                move || captured.clone()
+            })
+            .finish();
+        let _subscriber = tracing::subscriber::set_default(subscriber);
+
+        let logged = async |uri: &str| {
+            let request = testing::request(Method::GET, uri, None, None);
+            assert_eq!(testing::send(&router, request).await.0, StatusCode::OK);
+            let output = captured.0.lock().unwrap();
+            String::from_utf8_lossy(&output).matches(uri).count()
+        };
+        assert_eq!(logged("/api/agent/m1/config").await, 0);
+        assert!(logged("/api/agent/m2/config").await > 0);
+    }
+
+    #[tokio::test]
+    async fn metrics_move_to_the_admin_listener() {
+        let state = testing::state(&["--admin-listen", "127.0.0.1:0"]).await;
+        let request = || testing::request(Method::GET, "/metrics", None, None);
+
+        let (status, _) = testing::send(&testing::router(&state), request()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let admin = super::make_admin_listener(state.clone());
+        let (status, body) = testing::send(&admin, request()).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert!(body.as_str().unwrap().contains("wk_event_latency_seconds"));
+    }
+
+    #[tokio::test]
+    async fn dashboard_requires_a_token() {
+        let state = testing::state(&[]).await;
+        let router = testing::router(&state);
+        let host = testing::host(&state, "m1").await;
+        let token = testing::admin(&state).await;
+
+        for uri in [
+            "/api/dashboard/agent-versions".to_owned(),
+            "/api/dashboard/metrics/aggregate".to_owned(),
+            format!("/api/dashboard/hosts/compare?a={}&b={}", host.id, host.id),
+            format!("/api/dashboard/hosts/{}/uptime", host.id),
+            format!("/api/dashboard/hosts/{}/metrics/export", host.id),
+        ] {
+            let request = testing::request(Method::GET, &uri, None, None);
+            let (status, _) = testing::send(&router, request).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{}", uri);
+
+            let request = testing::request(Method::GET, &uri, Some(&token), None);
+            let (status, body) = testing::send(&router, request).await;
+            assert_eq!(status, StatusCode::OK, "{}: {}", uri, body);
+        }
+    }
 }
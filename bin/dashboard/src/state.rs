@@ -1,43 +1,579 @@
 use crate::args::Args;
+use crate::args::CaptchaStoreKind;
+use crate::args::RELOADABLE_ARGS;
+use crate::captcha_store::CaptchaStore;
+use crate::captcha_store::DatabaseCaptchaStore;
+use crate::captcha_store::MemoryCaptchaStore;
+use crate::latency::LatencyHistogram;
+use crate::logtail::LogTail;
+use crate::prelude::seaorm::*;
+use crate::webhook::Webhook;
 use anyhow::Ok;
 use anyhow::Result;
 use jsonwebtoken::DecodingKey;
 use jsonwebtoken::EncodingKey;
 use jsonwebtoken::Header;
+use jsonwebtoken::Validation;
+use proto::admin::config::ConfigEntry;
+use proto::admin::config::ConfigSource;
+use sea_orm::prelude::Uuid;
+use sea_orm::Condition;
 use sea_orm::DatabaseConnection;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::sync::RwLock;
+use std::time::Duration;
+use std::time::Instant;
+use tokio::sync::mpsc;
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
 
 #[derive(Clone)]
 pub struct AppState {
-    pub jwt: AppStateJwtSecret,
+    pub args: Args,
+    config: Arc<RwLock<Vec<ConfigEntry>>>,
+    settings: Arc<RwLock<Settings>>,
+    jwt: Arc<RwLock<AppStateJwtSecret>>,
+    /// Pepper of the stored token hashes, derived from the token key (`--token-pepper`
+    /// or the generated `token.key`).
+    pub pepper: Vec<u8>,
+    /// Peppers of token hashes stored before the token key was introduced, derived from
+    /// `--secret` and `--previous-secret`, so hashes made before the token key are not
+    /// lost when the secret is rotated. These hashes are still accepted, see
+    /// `AppState::token_hashes`.
+    pub legacy_peppers: Vec<Vec<u8>>,
+    pub upload_key: Vec<u8>,
     pub database: Arc<DatabaseConnection>,
+    pub captchas: Arc<dyn CaptchaStore>,
+    pub webhook: Option<Webhook>,
+    pub eventbus: AppStateEventbus,
+    pub connections: AppStateConnections,
+    pub clients: AppStateClients,
+    pub logins: AppStateLogins,
+    pub captcha_stats: AppStateCaptchaStats,
+    pub logs: Arc<LogTail>,
 }
 
+/// The hot-reloadable arguments, see `RELOADABLE_ARGS`, except `--log-filter` which is
+/// applied to the log subscriber directly.
+///
+/// These are read through `AppState::settings` rather than `AppState::args`, which
+/// keeps the values the server was started with.
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub offline_threshold: u64,
+    pub default_country: Option<String>,
+    pub metrics_interval: Option<u64>,
+    pub dedup_window: Option<u64>,
+}
+
+impl From<&Args> for Settings {
+    fn from(args: &Args) -> Self {
+        Self {
+            offline_threshold: args.offline_threshold,
+            default_country: args.default_country.clone(),
+            metrics_interval: args.metrics_interval,
+            dedup_window: args.dedup_window,
+        }
+    }
+}
+
+/// Keys of the authorize tokens.
+///
+/// Tokens are signed with `encoding`, the key of `--secret`. During a key rotation,
+/// `previous` holds the key of `--previous-secret`, so tokens signed before the rotation
+/// stay valid until they expire.
+///
+/// Only authorize tokens are affected by a rotation. Agent, enrollment and refresh
+/// token hashes and upload URLs are keyed by the token key, see `AppState::pepper`.
+///
+/// The keys are replaced on SIGHUP, see `AppState::reload`, so a rotation does not
+/// need a restart.
 #[derive(Clone)]
 #[allow(dead_code)]
 pub struct AppStateJwtSecret {
     pub header: jsonwebtoken::Header,
     pub encoding: EncodingKey,
     pub decoding: DecodingKey,
+    pub previous: Option<DecodingKey>,
+    pub validation: Validation,
 }
 
-impl AppState {
-    pub fn new(args: Args, database: DatabaseConnection) -> Self {
-        let jwt = {
-            let secret: Vec<u8> = args
-                .secret
-                .map_or_else(|| vec![0u8], |v| v.as_bytes().to_vec());
-
-            AppStateJwtSecret {
-                header: Header::new(jsonwebtoken::Algorithm::HS512),
-                encoding: EncodingKey::from_secret(&secret),
-                decoding: DecodingKey::from_secret(&secret),
+impl AppStateJwtSecret {
+    /// Creates the keys of `secret` and `--previous-secret` of `args`.
+    fn new(args: &Args, secret: &[u8]) -> Self {
+        let header = Header::new(jsonwebtoken::Algorithm::HS512);
+
+        // tokens must be scoped to this deployment, not only signed with its secret
+        let mut validation = Validation::new(header.alg);
+        validation.validate_nbf = true;
+        validation.set_issuer(&[&args.jwt_issuer]);
+        validation.set_audience(&[&args.jwt_audience]);
+        validation.set_required_spec_claims(&["exp", "nbf", "iss", "aud"]);
+
+        Self {
+            header,
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret),
+            previous: args
+                .previous_secret
+                .as_deref()
+                .map(|previous| DecodingKey::from_secret(previous.as_bytes())),
+            validation,
+        }
+    }
+}
+
+/// Tracks the eventbus receiver tasks, so buffered events can be drained on shutdown.
+///
+/// Every receiver task holds one of the `permits`, which caps the number of concurrent
+/// tasks. `applied` keeps the last applied event per host and event type, with the time
+/// it was applied, to skip duplicates within `--dedup-window`. `latency` collects the
+/// time from receiving an event until it is persisted. `sampled` keeps the time the
+/// last metrics sample of each host was stored, to throttle samples to one per
+/// `--metrics-interval`, and `dropped_samples` counts the samples dropped by it.
+///
+/// With `--eventbus-workers`, `workers` holds the queues of the worker pool which
+/// applies the events of all hosts instead of a receiver task per host. The workers
+/// are tracked by `tasks` as well, like the delivery worker of `--webhook-url`.
+#[derive(Clone)]
+pub struct AppStateEventbus {
+    pub tasks: TaskTracker,
+    pub workers: Arc<OnceLock<Vec<mpsc::Sender<PooledEvent>>>>,
+    pub shutdown: CancellationToken,
+    pub permits: Arc<Semaphore>,
+    pub applied: Arc<Mutex<AppliedEvents>>,
+    pub latency: Arc<LatencyHistogram>,
+    pub sampled: Arc<Mutex<HashMap<Uuid, Instant>>>,
+    pub dropped_samples: Arc<AtomicU64>,
+}
+
+/// Event queued to an eventbus worker, with its host and receipt time.
+pub type PooledEvent = (Arc<host::Model>, Instant, proto::agent::Events);
+
+/// Serialized last applied event and its apply time, by host id and event type.
+pub type AppliedEvents = HashMap<(Uuid, &'static str), (String, Instant)>;
+
+/// Registry of the live agent websocket connections, by host id.
+///
+/// Each connection holds a `ConnectionGuard`, which unregisters it when dropped and
+/// resolves `disconnected` once an administrator disconnects the host. Messages passed
+/// to `broadcast` are received on the channel returned by `register`.
+#[derive(Clone, Default)]
+pub struct AppStateConnections {
+    next: Arc<AtomicU64>,
+    live: Arc<Mutex<HashMap<Uuid, HashMap<u64, LiveConnection>>>>,
+}
+
+struct LiveConnection {
+    token: CancellationToken,
+    messages: mpsc::UnboundedSender<String>,
+}
+
+impl AppStateConnections {
+    /// Registers a new connection of the host, returning the receiver of the messages
+    /// broadcast to it.
+    pub fn register(&self, host_id: Uuid) -> (ConnectionGuard, mpsc::UnboundedReceiver<String>) {
+        let id = self.next.fetch_add(1, Ordering::Relaxed);
+        let token = CancellationToken::new();
+        let (messages, rx) = mpsc::unbounded_channel();
+        let mut live = self.live.lock().unwrap();
+        live.entry(host_id).or_default().insert(
+            id,
+            LiveConnection {
+                token: token.clone(),
+                messages,
+            },
+        );
+
+        let guard = ConnectionGuard {
+            connections: self.clone(),
+            host_id,
+            id,
+            token,
+        };
+
+        (guard, rx)
+    }
+
+    /// Sends the JSON `message` to every live connection. Returns the number of
+    /// connections it was sent to.
+    pub fn broadcast(&self, message: &str) -> usize {
+        let live = self.live.lock().unwrap();
+        live.values()
+            .flat_map(HashMap::values)
+            .filter(|connection| connection.messages.send(message.to_owned()).is_ok())
+            .count()
+    }
+
+    /// Signals every connection of the host to close. Returns whether one was live.
+    pub fn disconnect(&self, host_id: Uuid) -> bool {
+        let mut live = self.live.lock().unwrap();
+        let Some(connections) = live.remove(&host_id) else {
+            return false;
+        };
+
+        connections
+            .values()
+            .for_each(|connection| connection.token.cancel());
+        !connections.is_empty()
+    }
+}
+
+/// Open agent requests and websockets by client IP, to cap them per IP.
+///
+/// Each one holds a `ClientGuard`, which releases its slot when dropped.
+#[derive(Clone, Default)]
+pub struct AppStateClients {
+    open: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl AppStateClients {
+    /// Takes a slot of the client `ip`, unless it already holds `max`.
+    pub fn acquire(&self, ip: IpAddr, max: usize) -> Option<ClientGuard> {
+        let mut open = self.open.lock().unwrap();
+        let count = open.entry(ip).or_default();
+        if *count >= max {
+            return None;
+        }
+        *count += 1;
+
+        Some(ClientGuard {
+            clients: self.clone(),
+            ip,
+        })
+    }
+}
+
+/// A slot taken from `AppStateClients`, released when dropped.
+pub struct ClientGuard {
+    clients: AppStateClients,
+    ip: IpAddr,
+}
+
+impl Drop for ClientGuard {
+    fn drop(&mut self) {
+        let mut open = self.clients.open.lock().unwrap();
+        if let Some(count) = open.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                open.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// Consecutive failed logins by user id, to lock accounts under brute force.
+///
+/// A lock expires on its own, which also resets the failure count.
+#[derive(Clone, Default)]
+pub struct AppStateLogins {
+    failures: Arc<Mutex<HashMap<Uuid, LoginFailures>>>,
+}
+
+#[derive(Default)]
+struct LoginFailures {
+    count: u32,
+    locked_until: Option<Instant>,
+}
+
+impl AppStateLogins {
+    /// Returns the remaining lock time of the user, if it is locked.
+    pub fn locked(&self, uid: Uuid) -> Option<Duration> {
+        let mut failures = self.failures.lock().unwrap();
+        let locked_until = failures.get(&uid)?.locked_until?;
+
+        let remaining = locked_until.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            failures.remove(&uid);
+            return None;
+        }
+
+        Some(remaining)
+    }
+
+    /// Counts a failed login of the user, locking it for `lockout` once `max`
+    /// consecutive failures are reached. Returns whether the user got locked.
+    pub fn failed(&self, uid: Uuid, max: u32, lockout: Duration) -> bool {
+        let mut failures = self.failures.lock().unwrap();
+        let entry = failures.entry(uid).or_default();
+        entry.count += 1;
+        if entry.count < max {
+            return false;
+        }
+
+        entry.count = 0;
+        entry.locked_until = Some(Instant::now() + lockout);
+        true
+    }
+
+    /// Resets the failed logins of the user.
+    pub fn succeeded(&self, uid: Uuid) {
+        self.failures.lock().unwrap().remove(&uid);
+    }
+}
+
+/// Number of minutes `AppStateCaptchaStats` keeps counts for.
+pub const CAPTCHA_STATS_MINUTES: i64 = 24 * 60;
+
+/// Captchas generated and answered by this server, counted per minute over the last
+/// `CAPTCHA_STATS_MINUTES`, to monitor abuse.
+///
+/// Counts are kept in memory, so they start over on restart and do not include the
+/// captchas of other servers sharing the database.
+#[derive(Clone, Default)]
+pub struct AppStateCaptchaStats {
+    minutes: Arc<Mutex<VecDeque<(i64, CaptchaCounts)>>>,
+}
+
+/// Numbers of captchas generated, answered correctly and answered wrong.
+///
+/// An answer counts as wrong if the captcha does not exist or expired.
+#[derive(Clone, Copy, Default)]
+pub struct CaptchaCounts {
+    pub generated: u64,
+    pub verified: u64,
+    pub failed: u64,
+}
+
+impl AppStateCaptchaStats {
+    /// Counts a generated captcha.
+    pub fn generated(&self) {
+        self.count(|counts| counts.generated += 1);
+    }
+
+    /// Counts an answered captcha, correctly or not.
+    pub fn answered(&self, correct: bool) {
+        self.count(|counts| {
+            if correct {
+                counts.verified += 1;
+            } else {
+                counts.failed += 1;
+            }
+        });
+    }
+
+    /// Sums the counts of the minutes since `since`, which starts at a whole minute.
+    pub fn since(&self, since: chrono::DateTime<chrono::Utc>) -> CaptchaCounts {
+        let since = since.timestamp().div_euclid(60);
+        let minutes = self.minutes.lock().unwrap();
+
+        minutes.iter().filter(|(minute, _)| *minute >= since).fold(
+            CaptchaCounts::default(),
+            |sum, (_, counts)| CaptchaCounts {
+                generated: sum.generated + counts.generated,
+                verified: sum.verified + counts.verified,
+                failed: sum.failed + counts.failed,
+            },
+        )
+    }
+
+    fn count(&self, f: impl FnOnce(&mut CaptchaCounts)) {
+        let now = chrono::Utc::now().timestamp().div_euclid(60);
+        let mut minutes = self.minutes.lock().unwrap();
+        while minutes
+            .front()
+            .is_some_and(|(minute, _)| *minute <= now - CAPTCHA_STATS_MINUTES)
+        {
+            minutes.pop_front();
+        }
+
+        match minutes.back_mut() {
+            Some((minute, counts)) if *minute == now => f(counts),
+            _ => {
+                let mut counts = CaptchaCounts::default();
+                f(&mut counts);
+                minutes.push_back((now, counts));
+            }
+        }
+    }
+}
+
+/// A registered agent websocket connection, see `AppStateConnections`.
+pub struct ConnectionGuard {
+    connections: AppStateConnections,
+    host_id: Uuid,
+    id: u64,
+    token: CancellationToken,
+}
+
+impl ConnectionGuard {
+    /// Completes once the host was disconnected by an administrator.
+    pub async fn disconnected(&self) {
+        self.token.cancelled().await
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut live = self.connections.live.lock().unwrap();
+        if let Some(connections) = live.get_mut(&self.host_id) {
+            connections.remove(&self.id);
+            if connections.is_empty() {
+                live.remove(&self.host_id);
             }
+        }
+    }
+}
+
+impl AppState {
+    pub fn new(
+        args: Args,
+        config: Vec<ConfigEntry>,
+        database: DatabaseConnection,
+        logs: Arc<LogTail>,
+        token_key: &[u8],
+    ) -> Self {
+        // without a configured secret, authorize tokens only stay valid until the next
+        // restart, stored token hashes are keyed by the token key and not affected
+        let secret: Vec<u8> = args
+            .secret
+            .clone()
+            .unwrap_or_else(crate::token::random)
+            .into_bytes();
+
+        let jwt = AppStateJwtSecret::new(&args, &secret);
+
+        let pepper = crate::token::pepper(token_key);
+        let legacy_peppers = args
+            .secret
+            .iter()
+            .chain(&args.previous_secret)
+            .map(|secret| crate::token::pepper(secret.as_bytes()))
+            .collect();
+        let upload_key = crate::token::upload_key(token_key);
+
+        let eventbus = AppStateEventbus {
+            tasks: TaskTracker::new(),
+            workers: Default::default(),
+            shutdown: CancellationToken::new(),
+            permits: Arc::new(Semaphore::new(args.max_eventbus_tasks)),
+            applied: Default::default(),
+            latency: Default::default(),
+            sampled: Default::default(),
+            dropped_samples: Default::default(),
+        };
+
+        let webhook = args
+            .webhook_url
+            .clone()
+            .map(|url| Webhook::new(url, &eventbus.tasks, eventbus.shutdown.clone()));
+
+        let database = Arc::new(database);
+        let captchas: Arc<dyn CaptchaStore> = match args.captcha_store {
+            CaptchaStoreKind::Database => Arc::new(DatabaseCaptchaStore::new(database.clone())),
+            CaptchaStoreKind::Memory => Arc::new(MemoryCaptchaStore::default()),
         };
 
         Self {
-            jwt: jwt,
-            database: Arc::new(database),
+            settings: Arc::new(RwLock::new(Settings::from(&args))),
+            args,
+            config: Arc::new(RwLock::new(config)),
+            jwt: Arc::new(RwLock::new(jwt)),
+            pepper,
+            legacy_peppers,
+            upload_key,
+            database,
+            captchas,
+            webhook,
+            eventbus,
+            connections: Default::default(),
+            clients: Default::default(),
+            logins: Default::default(),
+            captcha_stats: Default::default(),
+            logs,
+        }
+    }
+
+    /// Returns the hashes a stored `token` may have, the one made with `pepper` first,
+    /// followed by the ones made with `legacy_peppers`.
+    pub fn token_hashes(&self, token: &str) -> Vec<String> {
+        std::iter::once(&self.pepper)
+            .chain(&self.legacy_peppers)
+            .map(|pepper| crate::token::hash(pepper, token))
+            .collect()
+    }
+
+    /// Returns the current hot-reloadable settings.
+    pub fn settings(&self) -> Settings {
+        self.settings.read().unwrap().clone()
+    }
+
+    /// Returns the current keys of the authorize tokens.
+    pub fn jwt(&self) -> AppStateJwtSecret {
+        self.jwt.read().unwrap().clone()
+    }
+
+    /// Returns the effective configuration, including reloaded values.
+    pub fn config(&self) -> Vec<ConfigEntry> {
+        self.config.read().unwrap().clone()
+    }
+
+    /// Applies the hot-reloadable settings of `args`, resolved as `config`.
+    ///
+    /// Entries of other arguments keep their startup value in the effective
+    /// configuration, as their new values are not applied. Entries whose value changed
+    /// get the source `Reload`.
+    pub fn reload(&self, args: &Args, config: Vec<ConfigEntry>) {
+        *self.settings.write().unwrap() = Settings::from(args);
+
+        // without a configured secret, the random key of the startup is kept
+        if let Some(secret) = &args.secret {
+            *self.jwt.write().unwrap() = AppStateJwtSecret::new(args, secret.as_bytes());
+        }
+
+        let mut current = self.config.write().unwrap();
+        for entry in config {
+            if !RELOADABLE_ARGS.contains(&entry.name.as_str()) {
+                continue;
+            }
+            if let Some(slot) = current.iter_mut().find(|slot| slot.name == entry.name) {
+                if slot.value != entry.value {
+                    *slot = ConfigEntry {
+                        source: ConfigSource::Reload,
+                        ..entry
+                    };
+                }
+            }
+        }
+    }
+
+    /// Returns the earliest `last_seen` timestamp of an online host.
+    pub fn online_since(&self) -> chrono::DateTime<chrono::Utc> {
+        let threshold = self.settings().offline_threshold;
+        chrono::Utc::now() - chrono::Duration::seconds(threshold as i64)
+    }
+
+    /// Returns the condition of the hosts counted as online or offline, which leaves
+    /// out the offline hosts in maintenance.
+    pub fn counted_hosts(&self) -> Condition {
+        Condition::any()
+            .add(host::Column::MaintenanceUntil.is_null())
+            .add(host::Column::MaintenanceUntil.lte(chrono::Utc::now()))
+            .add(host::Column::LastSeen.gte(self.online_since()))
+    }
+
+    /// Stops accepting agent events and waits up to `timeout` for the eventbus receivers
+    /// to persist the events still buffered in their channels.
+    pub async fn drain(&self, timeout: Duration) {
+        self.eventbus.shutdown.cancel();
+        self.eventbus.tasks.close();
+
+        if tokio::time::timeout(timeout, self.eventbus.tasks.wait())
+            .await
+            .is_err()
+        {
+            tracing::warn!(
+                "eventbus drain timed out, {} receivers still running",
+                self.eventbus.tasks.len()
+            );
         }
     }
 
@@ -46,3 +582,49 @@ impl AppState {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::testing;
+    use axum::http::Method;
+    use axum::http::StatusCode;
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn drain_flushes_buffered_events() {
+        let state = testing::state(&[]).await;
+        let router = testing::router(&state);
+
+        let events = json!([
+            { "EvtMachineEmit": { "ip": "192.0.2.1" } },
+            { "EvtOsEmit": { "family": "linux" } },
+        ]);
+        let request = testing::request(Method::POST, "/api/agent/m1/report", None, Some(events));
+        assert_eq!(testing::send(&router, request).await.0, StatusCode::OK);
+        state.drain(Duration::from_secs(5)).await;
+
+        let host = testing::host(&state, "m1").await;
+        assert_eq!(host.machine_ip, "192.0.2.1");
+        assert_eq!(host.os_family, "linux");
+
+        // no further events are accepted
+        let events = json!([{ "EvtOsEmit": { "family": "windows" } }]);
+        let request = testing::request(Method::POST, "/api/agent/m1/report", None, Some(events));
+        let (status, body) = testing::send(&router, request).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["code"], "shutting_down");
+    }
+
+    #[tokio::test]
+    async fn secret_is_random_when_not_configured() {
+        let state = testing::state(&[]).await;
+        let other = testing::state(&[]).await;
+        let token = testing::admin(&state).await;
+
+        let request = testing::request(Method::GET, "/api/admin/hosts", Some(&token), None);
+        let (status, body) = testing::send(&testing::router(&other), request).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["code"], "token_invalid");
+    }
+}
@@ -0,0 +1,238 @@
+//! Fixtures of the unit tests.
+//!
+//! Every test gets its own `AppState` on a private in-memory sqlite database, and talks
+//! to it through the router like a client would.
+
+use crate::args::Args;
+use crate::logtail::LogTail;
+use crate::middlewares::AuthorizedToken;
+use crate::state::AppState;
+use axum::body::Body;
+use axum::extract::connect_info::MockConnectInfo;
+use axum::http::header;
+use axum::http::Method;
+use axum::http::Request;
+use axum::http::StatusCode;
+use axum::routing;
+use axum::Json;
+use axum::Router;
+use clap::CommandFactory;
+use clap::FromArgMatches;
+use database::migrations::Migrator;
+use database::migrations::MigratorTrait;
+use database::models::host;
+use database::models::prelude::*;
+use database::models::user;
+use sea_orm::prelude::Uuid;
+use sea_orm::ActiveValue::Set;
+use sea_orm::ActiveValue::Unchanged;
+use sea_orm::ColumnTrait;
+use sea_orm::ConnectOptions;
+use sea_orm::Database;
+use sea_orm::EntityTrait;
+use sea_orm::IntoActiveModel;
+use sea_orm::QueryFilter;
+use serde_json::Value;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tower::ServiceExt;
+
+/// Key of the stored token hashes and upload URLs of test states.
+pub const TOKEN_KEY: &[u8] = b"test-token-key";
+
+/// Address of the client sending the test requests.
+pub const CLIENT_ADDR: ([u8; 4], u16) = ([127, 0, 0, 1], 40000);
+
+/// Creates an `AppState` with the command line `args` on a fresh, migrated in-memory
+/// sqlite database.
+pub async fn state(args: &[&str]) -> Arc<AppState> {
+    state_on("sqlite::memory:", args).await
+}
+
+/// Creates an `AppState` with the command line `args` on the migrated sqlite database
+/// at `url`.
+///
+/// The pool holds a single connection, as every connection to an in-memory database
+/// would open a database of its own.
+pub async fn state_on(url: &str, args: &[&str]) -> Arc<AppState> {
+    let matches = Args::command()
+        .try_get_matches_from(std::iter::once("dashboard").chain(args.iter().copied()))
+        .unwrap();
+    let parsed = Args::from_arg_matches(&matches).unwrap();
+
+    let mut opt = ConnectOptions::new(url);
+    opt.max_connections(1)
+        .min_connections(1)
+        .sqlx_logging(false);
+    let database = Database::connect(opt).await.unwrap();
+    Migrator::up(&database, None).await.unwrap();
+
+    Arc::new(AppState::new(
+        parsed,
+        Args::effective(&matches),
+        database,
+        Arc::new(LogTail::new(100)),
+        TOKEN_KEY,
+    ))
+}
+
+/// Builds the router of `--listen`, with requests coming from `CLIENT_ADDR`.
+pub fn router(state: &Arc<AppState>) -> Router {
+    crate::route::make(state.clone()).layer(MockConnectInfo(SocketAddr::from(CLIENT_ADDR)))
+}
+
+/// Serves the router of `--listen` on a local port and returns its address, for
+/// clients which need a real connection, such as websockets.
+pub async fn serve(state: &Arc<AppState>) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let router = crate::route::make(state.clone());
+    tokio::spawn(async move {
+        axum::serve(
+            listener,
+            router.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+    });
+
+    addr
+}
+
+/// Builds a request with an optional bearer `token` and JSON `body`.
+pub fn request(
+    method: Method,
+    uri: &str,
+    token: Option<&str>,
+    body: Option<Value>,
+) -> Request<Body> {
+    let mut builder = Request::builder().method(method).uri(uri);
+    if let Some(token) = token {
+        builder = builder.header(header::AUTHORIZATION, format!("Bearer {}", token));
+    }
+    match body {
+        Some(body) => builder
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap(),
+        None => builder.body(Body::empty()).unwrap(),
+    }
+}
+
+/// Sends `request` to `router` and returns the status and the body as JSON, or as JSON
+/// string if it is not JSON, `Value::Null` if empty.
+pub async fn send(router: &Router, request: Request<Body>) -> (StatusCode, Value) {
+    let response = router.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+
+    let body = if body.is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_slice(&body)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&body).into_owned()))
+    };
+    (status, body)
+}
+
+/// Creates a super admin user and returns an authorize token for it.
+pub async fn admin(state: &AppState) -> String {
+    let id = Uuid::from_bytes(uuidv7::create_raw());
+    User::insert(
+        user::Model {
+            id,
+            sa: true,
+            nickname: "Admin".to_owned(),
+            email: format!("{}@example.com", id),
+            password: String::new(),
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+        }
+        .into_active_model(),
+    )
+    .exec(state.database.as_ref())
+    .await
+    .unwrap();
+
+    AuthorizedToken::issue(state, id, 3600).unwrap()
+}
+
+/// Creates the host with the given `machine_id` the way an agent does, by asking for
+/// its config, and returns it.
+pub async fn host(state: &Arc<AppState>, machine_id: &str) -> host::Model {
+    let uri = format!("/api/agent/{}/config", machine_id);
+    let (status, body) = send(&router(state), request(Method::GET, &uri, None, None)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    Host::find()
+        .filter(host::Column::MachineId.eq(machine_id))
+        .one(state.database.as_ref())
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+/// Sets the time the host with the given `id` was last seen.
+pub async fn seen(state: &AppState, id: Uuid, last_seen: chrono::DateTime<chrono::Utc>) {
+    Host::update(host::ActiveModel {
+        id: Unchanged(id),
+        last_seen: Set(Some(last_seen)),
+        ..Default::default()
+    })
+    .exec(state.database.as_ref())
+    .await
+    .unwrap();
+}
+
+/// Sends the `events` as report of the host with `machine_id` and waits until they
+/// are applied.
+pub async fn report(
+    state: &Arc<AppState>,
+    machine_id: &str,
+    token: Option<&str>,
+    events: Value,
+) -> (StatusCode, Value) {
+    let uri = format!("/api/agent/{}/report", machine_id);
+    let response = send(
+        &router(state),
+        request(Method::POST, &uri, token, Some(events)),
+    )
+    .await;
+
+    // the receiver task ends once the report is applied
+    state.eventbus.tasks.close();
+    state.eventbus.tasks.wait().await;
+    state.eventbus.tasks.reopen();
+
+    response
+}
+
+/// Serves a webhook receiver on a local port and returns its URL, along with the
+/// events it receives.
+pub async fn webhook_receiver() -> (String, mpsc::UnboundedReceiver<Value>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let router = Router::new().route(
+        "/",
+        routing::post(move |Json(event): Json<Value>| {
+            let tx = tx.clone();
+            async move { _ = tx.send(event) }
+        }),
+    );
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, router).await });
+
+    (url, rx)
+}
+
+/// Serializes the tests which depend on the cached initialized state, see
+/// `api::user::initlizated`, as it is shared by the whole process.
+pub async fn initialization() -> tokio::sync::MutexGuard<'static, ()> {
+    static LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    LOCK.lock().await
+}
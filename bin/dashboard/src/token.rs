@@ -0,0 +1,66 @@
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::rand_core::RngCore;
+use hmac::Hmac;
+use hmac::Mac;
+use sha2::Sha256;
+
+/// Context mixed into the token key to derive the agent token pepper.
+const PEPPER_CONTEXT: &[u8] = b"wk agent token pepper";
+
+/// Context mixed into the token key to derive the upload URL signing key.
+const UPLOAD_CONTEXT: &[u8] = b"wk upload url";
+
+/// Generates a random opaque token.
+///
+/// The token is 32 random bytes encoded as 64 lowercase hex characters.
+pub fn random() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Derives the pepper used by `hash` from the token `key`.
+///
+/// The pepper is `HMAC-SHA256(key, PEPPER_CONTEXT)`, so it changes whenever the key
+/// changes and is never stored in the database. Stored hashes only stay valid as long
+/// as the key does, which is why it is separate from the rotating `--secret`.
+pub fn pepper(key: &[u8]) -> Vec<u8> {
+    keyed(key, PEPPER_CONTEXT)
+}
+
+/// Derives the key used to sign upload URLs from the token `key`.
+pub fn upload_key(key: &[u8]) -> Vec<u8> {
+    keyed(key, UPLOAD_CONTEXT)
+}
+
+/// Checks in constant time that `signature` is `hash(key, message)`.
+pub fn verify(key: &[u8], message: &str, signature: &str) -> bool {
+    let expected = hash(key, message);
+
+    expected.len() == signature.len()
+        && expected
+            .bytes()
+            .zip(signature.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Hashes an opaque `token` for storage.
+///
+/// The hash is `HMAC-SHA256(pepper, token)` encoded as 64 lowercase hex characters.
+/// A leaked hash is useless without the pepper, and tokens are long random values,
+/// so a fast keyed hash is sufficient.
+pub fn hash(pepper: &[u8], token: &str) -> String {
+    keyed(pepper, token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Computes `HMAC-SHA256(key, message)`.
+fn keyed(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
@@ -0,0 +1,167 @@
+use anyhow::Result;
+use proto::webhook::WebhookChange;
+use proto::webhook::WebhookEvent;
+use reqwest::Client;
+use reqwest::StatusCode;
+use reqwest::Url;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+/// Number of events waiting for delivery before further events are dropped.
+const QUEUE_CAPACITY: usize = 1024;
+
+/// Maximum delivery attempts for a single event.
+const DELIVERY_ATTEMPTS: u32 = 5;
+
+/// Initial delay between delivery attempts, doubled after each failure.
+const DELIVERY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Timeout for a single delivery request.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Outbound webhook notifying another system about host changes.
+///
+/// Events are delivered one after another by a single worker, so a slow or
+/// unavailable receiver neither piles up tasks nor blocks ingestion.
+#[derive(Clone)]
+pub struct Webhook {
+    url: Url,
+    client: Client,
+    queue: mpsc::Sender<WebhookEvent>,
+}
+
+impl Webhook {
+    /// Creates the webhook and spawns its delivery worker on `tasks`.
+    ///
+    /// Once `shutdown` is cancelled, the worker delivers the queued events without
+    /// retrying failed ones and stops, so the tasks can be drained.
+    pub fn new(url: Url, tasks: &TaskTracker, shutdown: CancellationToken) -> Self {
+        let (queue, rx) = mpsc::channel(QUEUE_CAPACITY);
+        let webhook = Self {
+            url,
+            client: Client::new(),
+            queue,
+        };
+        tasks.spawn(webhook.clone().run(rx, shutdown));
+
+        webhook
+    }
+
+    /// Creates a webhook event for the given host and change type.
+    pub fn event(host_id: impl ToString, machine_id: &str, change: WebhookChange) -> WebhookEvent {
+        WebhookEvent {
+            host_id: host_id.to_string(),
+            machine_id: machine_id.to_owned(),
+            change,
+            occurred_at: chrono::Utc::now().to_rfc3339(),
+        }
+    }
+
+    /// Queues the event for delivery by the worker.
+    ///
+    /// The event is dropped with a warning if the queue is full or the worker stopped,
+    /// so ingestion is never blocked by a slow or unavailable receiver.
+    pub fn notify(&self, event: WebhookEvent) {
+        if let Err(err) = self.queue.try_send(event) {
+            tracing::warn!("webhook event dropped: {}", err);
+        }
+    }
+
+    /// Delivers the queued events until the queue is drained after `shutdown`.
+    async fn run(self, mut rx: mpsc::Receiver<WebhookEvent>, shutdown: CancellationToken) {
+        loop {
+            let event = tokio::select! {
+                biased;
+                event = rx.recv() => event,
+                // refuse new events, the queued ones are still received above
+                _ = shutdown.cancelled(), if !rx.is_closed() => {
+                    rx.close();
+                    continue;
+                }
+            };
+            let Some(event) = event else {
+                break;
+            };
+
+            self.deliver_with_retries(&event, &shutdown).await;
+        }
+    }
+
+    /// Delivers the event, retrying failed deliveries with exponential backoff until
+    /// `shutdown`.
+    async fn deliver_with_retries(&self, event: &WebhookEvent, shutdown: &CancellationToken) {
+        let mut backoff = DELIVERY_BACKOFF;
+
+        for attempt in 1..=DELIVERY_ATTEMPTS {
+            match self.deliver(event).await {
+                Ok(status) if status.is_success() => return,
+                Ok(status) => {
+                    tracing::warn!("webhook delivery attempt {} failed: {}", attempt, status)
+                }
+                Err(err) => {
+                    tracing::warn!("webhook delivery attempt {} failed: {}", attempt, err)
+                }
+            }
+
+            if attempt < DELIVERY_ATTEMPTS {
+                tokio::select! {
+                    _ = tokio::time::sleep(backoff) => backoff *= 2,
+                    _ = shutdown.cancelled() => break,
+                }
+            }
+        }
+
+        tracing::error!("webhook delivery of {:?} dropped", event.change);
+    }
+
+    /// Delivers the event once and returns the response status.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request could not be sent.
+    pub async fn deliver(&self, event: &WebhookEvent) -> Result<StatusCode> {
+        let resp = self
+            .client
+            .post(self.url.clone())
+            .timeout(DELIVERY_TIMEOUT)
+            .json(event)
+            .send()
+            .await?;
+
+        Ok(resp.status())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing;
+    use axum::http::Method;
+    use axum::http::StatusCode;
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn host_update_is_posted() {
+        let (url, mut events) = testing::webhook_receiver().await;
+        let state = testing::state(&["--webhook-url", &url]).await;
+
+        let os = json!([{ "EvtOsEmit": { "family": "linux" } }]);
+        let request = testing::request(Method::POST, "/api/agent/m1/report", None, Some(os));
+        let (status, _) = testing::send(&testing::router(&state), request).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // the host also came online
+        loop {
+            let event = tokio::time::timeout(Duration::from_secs(5), events.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(event["machine_id"], "m1");
+            if event["change"] == "os" {
+                break;
+            }
+        }
+    }
+}
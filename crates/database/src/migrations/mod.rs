@@ -1,12 +1,52 @@
 pub use sea_orm_migration::prelude::*;
 
 mod v00000000_000001_create_table;
+mod v00000000_000002_host_soft_delete;
+mod v00000000_000003_create_enrollment;
+mod v00000000_000004_fleet_snapshot;
+mod v00000000_000005_host_command;
+mod v00000000_000006_host_agent_version;
+mod v00000000_000007_host_os_arch_raw;
+mod v00000000_000008_create_event_log;
+mod v00000000_000009_create_metric;
+mod v00000000_000010_host_display_name;
+mod v00000000_000011_create_alert;
+mod v00000000_000012_create_host_log;
+mod v00000000_000013_create_hardware_change;
+mod v00000000_000014_create_session;
+mod v00000000_000015_host_virtualization_platform;
+mod v00000000_000016_create_host_label;
+mod v00000000_000017_host_last_error;
+mod v00000000_000018_create_host_alert_threshold;
+mod v00000000_000019_host_maintenance;
+mod v00000000_000020_host_merged_into;
 
 pub struct Migrator;
 
 #[async_trait::async_trait]
 impl MigratorTrait for Migrator {
     fn migrations() -> Vec<Box<dyn MigrationTrait>> {
-        vec![Box::new(v00000000_000001_create_table::Migration)]
+        vec![
+            Box::new(v00000000_000001_create_table::Migration),
+            Box::new(v00000000_000002_host_soft_delete::Migration),
+            Box::new(v00000000_000003_create_enrollment::Migration),
+            Box::new(v00000000_000004_fleet_snapshot::Migration),
+            Box::new(v00000000_000005_host_command::Migration),
+            Box::new(v00000000_000006_host_agent_version::Migration),
+            Box::new(v00000000_000007_host_os_arch_raw::Migration),
+            Box::new(v00000000_000008_create_event_log::Migration),
+            Box::new(v00000000_000009_create_metric::Migration),
+            Box::new(v00000000_000010_host_display_name::Migration),
+            Box::new(v00000000_000011_create_alert::Migration),
+            Box::new(v00000000_000012_create_host_log::Migration),
+            Box::new(v00000000_000013_create_hardware_change::Migration),
+            Box::new(v00000000_000014_create_session::Migration),
+            Box::new(v00000000_000015_host_virtualization_platform::Migration),
+            Box::new(v00000000_000016_create_host_label::Migration),
+            Box::new(v00000000_000017_host_last_error::Migration),
+            Box::new(v00000000_000018_create_host_alert_threshold::Migration),
+            Box::new(v00000000_000019_host_maintenance::Migration),
+            Box::new(v00000000_000020_host_merged_into::Migration),
+        ]
     }
 }
@@ -0,0 +1,64 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum Host {
+    Table,
+    AgentToken,
+}
+
+#[derive(DeriveIden)]
+enum Enrollment {
+    Table,
+    Id,
+    Token,
+    ExpiredAt,
+    ConsumedAt,
+    HostId,
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Enrollment::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(Enrollment::Id))
+                    .col(string_uniq(Enrollment::Token).string_len(64))
+                    .col(timestamp(Enrollment::ExpiredAt))
+                    .col(timestamp_null(Enrollment::ConsumedAt))
+                    .col(uuid_null(Enrollment::HostId))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Host::Table)
+                    .add_column(string_len_null(Host::AgentToken, 64))
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Host::Table)
+                    .drop_column(Host::AgentToken)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_table(Table::drop().table(Enrollment::Table).to_owned())
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,72 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum Host {
+    Table,
+    LastSeen,
+}
+
+#[derive(DeriveIden)]
+enum FleetSnapshot {
+    Table,
+    Id,
+    Online,
+    Total,
+    RecordedAt,
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Host::Table)
+                    .add_column(timestamp_null(Host::LastSeen))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(FleetSnapshot::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(FleetSnapshot::Id))
+                    .col(integer(FleetSnapshot::Online))
+                    .col(integer(FleetSnapshot::Total))
+                    .col(timestamp(FleetSnapshot::RecordedAt))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_fleet_snapshot_recorded_at")
+                    .table(FleetSnapshot::Table)
+                    .col(FleetSnapshot::RecordedAt)
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(FleetSnapshot::Table).to_owned())
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Host::Table)
+                    .drop_column(Host::LastSeen)
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+}
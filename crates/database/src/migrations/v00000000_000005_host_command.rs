@@ -0,0 +1,51 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum HostCommand {
+    Table,
+    Id,
+    HostId,
+    Command,
+    CreatedAt,
+    DeliveredAt,
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(HostCommand::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(HostCommand::Id))
+                    .col(uuid(HostCommand::HostId))
+                    .col(text(HostCommand::Command))
+                    .col(timestamp(HostCommand::CreatedAt))
+                    .col(timestamp_null(HostCommand::DeliveredAt))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_host_command_host_id")
+                    .table(HostCommand::Table)
+                    .col(HostCommand::HostId)
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(HostCommand::Table).to_owned())
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,62 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum EventLog {
+    Table,
+    Id,
+    HostId,
+    EventType,
+    Payload,
+    ReceivedAt,
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(EventLog::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(EventLog::Id))
+                    .col(uuid(EventLog::HostId))
+                    .col(string_len(EventLog::EventType, 64))
+                    .col(text(EventLog::Payload))
+                    .col(timestamp(EventLog::ReceivedAt))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_event_log_received_at")
+                    .table(EventLog::Table)
+                    .col(EventLog::ReceivedAt)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_event_log_host_id_received_at")
+                    .table(EventLog::Table)
+                    .col(EventLog::HostId)
+                    .col(EventLog::ReceivedAt)
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(EventLog::Table).to_owned())
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,58 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum Metric {
+    Table,
+    Id,
+    HostId,
+    CpuUsage,
+    MemoryUsed,
+    MemoryTotal,
+    DiskUsed,
+    DiskTotal,
+    RecordedAt,
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Metric::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(Metric::Id))
+                    .col(uuid(Metric::HostId))
+                    .col(double_null(Metric::CpuUsage))
+                    .col(big_integer_null(Metric::MemoryUsed))
+                    .col(big_integer_null(Metric::MemoryTotal))
+                    .col(big_integer_null(Metric::DiskUsed))
+                    .col(big_integer_null(Metric::DiskTotal))
+                    .col(timestamp(Metric::RecordedAt))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_metric_host_id_recorded_at")
+                    .table(Metric::Table)
+                    .col(Metric::HostId)
+                    .col(Metric::RecordedAt)
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Metric::Table).to_owned())
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,53 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum Alert {
+    Table,
+    Id,
+    Kind,
+    HostId,
+    Message,
+    CreatedAt,
+    ResolvedAt,
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Alert::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(Alert::Id))
+                    .col(string_len(Alert::Kind, 64))
+                    .col(uuid_null(Alert::HostId))
+                    .col(text(Alert::Message))
+                    .col(timestamp(Alert::CreatedAt))
+                    .col(timestamp_null(Alert::ResolvedAt))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_alert_created_at")
+                    .table(Alert::Table)
+                    .col(Alert::CreatedAt)
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Alert::Table).to_owned())
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,49 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum HostLog {
+    Table,
+    Id,
+    HostId,
+    Content,
+    CreatedAt,
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(HostLog::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(HostLog::Id))
+                    .col(uuid(HostLog::HostId))
+                    .col(text(HostLog::Content))
+                    .col(timestamp(HostLog::CreatedAt))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_host_log_host_id")
+                    .table(HostLog::Table)
+                    .col(HostLog::HostId)
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(HostLog::Table).to_owned())
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,53 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum HardwareChange {
+    Table,
+    Id,
+    HostId,
+    Column,
+    Old,
+    New,
+    ChangedAt,
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(HardwareChange::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(HardwareChange::Id))
+                    .col(uuid(HardwareChange::HostId))
+                    .col(string_len(HardwareChange::Column, 32))
+                    .col(integer(HardwareChange::Old))
+                    .col(integer(HardwareChange::New))
+                    .col(timestamp(HardwareChange::ChangedAt))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_hardware_change_changed_at")
+                    .table(HardwareChange::Table)
+                    .col(HardwareChange::ChangedAt)
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(HardwareChange::Table).to_owned())
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,55 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum Session {
+    Table,
+    Id,
+    UserId,
+    RefreshToken,
+    UserAgent,
+    Ip,
+    CreatedAt,
+    LastUsedAt,
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Session::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(Session::Id))
+                    .col(uuid(Session::UserId))
+                    .col(string_uniq(Session::RefreshToken).string_len(64))
+                    .col(string_len_null(Session::UserAgent, 256))
+                    .col(string_len_null(Session::Ip, 64))
+                    .col(timestamp(Session::CreatedAt))
+                    .col(timestamp(Session::LastUsedAt))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_session_user_id")
+                    .table(Session::Table)
+                    .col(Session::UserId)
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Session::Table).to_owned())
+            .await?;
+        Ok(())
+    }
+}
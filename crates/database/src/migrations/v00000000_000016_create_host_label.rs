@@ -0,0 +1,53 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum HostLabel {
+    Table,
+    Id,
+    HostId,
+    Name,
+    Value,
+    UpdatedAt,
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(HostLabel::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(HostLabel::Id))
+                    .col(uuid(HostLabel::HostId))
+                    .col(string_len(HostLabel::Name, 64))
+                    .col(string_len(HostLabel::Value, 256))
+                    .col(timestamp(HostLabel::UpdatedAt))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_host_label_host_id_name")
+                    .table(HostLabel::Table)
+                    .col(HostLabel::HostId)
+                    .col(HostLabel::Name)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(HostLabel::Table).to_owned())
+            .await?;
+        Ok(())
+    }
+}
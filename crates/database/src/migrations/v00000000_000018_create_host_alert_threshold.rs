@@ -0,0 +1,41 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum HostAlertThreshold {
+    Table,
+    HostId,
+    CpuPercent,
+    MemoryPercent,
+    DiskPercent,
+    UpdatedAt,
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(HostAlertThreshold::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(HostAlertThreshold::HostId))
+                    .col(small_integer_null(HostAlertThreshold::CpuPercent))
+                    .col(small_integer_null(HostAlertThreshold::MemoryPercent))
+                    .col(small_integer_null(HostAlertThreshold::DiskPercent))
+                    .col(timestamp(HostAlertThreshold::UpdatedAt))
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(HostAlertThreshold::Table).to_owned())
+            .await?;
+        Ok(())
+    }
+}
@@ -22,6 +22,18 @@ pub struct Model {
     pub hashed_memory: i32,
     pub hashed_disk: i32,
     pub hashed_network: i32,
+    pub deleted_at: Option<DateTimeUtc>,
+    pub agent_token: Option<String>,
+    pub last_seen: Option<DateTimeUtc>,
+    pub agent_version: Option<String>,
+    pub os_arch_raw: Option<String>,
+    pub display_name: Option<String>,
+    pub os_virtualization_platform: Option<String>,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub last_error: Option<String>,
+    pub last_error_at: Option<DateTimeUtc>,
+    pub maintenance_until: Option<DateTimeUtc>,
+    pub merged_into: Option<Uuid>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -2,6 +2,17 @@
 
 pub mod prelude;
 
+pub mod alert;
 pub mod captcha;
+pub mod enrollment;
+pub mod event_log;
+pub mod fleet_snapshot;
+pub mod hardware_change;
 pub mod host;
+pub mod host_alert_threshold;
+pub mod host_command;
+pub mod host_label;
+pub mod host_log;
+pub mod metric;
+pub mod session;
 pub mod user;
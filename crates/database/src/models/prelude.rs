@@ -1,5 +1,16 @@
 //! `SeaORM` Entity, @generated by sea-orm-codegen 1.1.7
 
+pub use super::alert::Entity as Alert;
 pub use super::captcha::Entity as Captcha;
+pub use super::enrollment::Entity as Enrollment;
+pub use super::event_log::Entity as EventLog;
+pub use super::fleet_snapshot::Entity as FleetSnapshot;
+pub use super::hardware_change::Entity as HardwareChange;
 pub use super::host::Entity as Host;
+pub use super::host_alert_threshold::Entity as HostAlertThreshold;
+pub use super::host_command::Entity as HostCommand;
+pub use super::host_label::Entity as HostLabel;
+pub use super::host_log::Entity as HostLog;
+pub use super::metric::Entity as Metric;
+pub use super::session::Entity as Session;
 pub use super::user::Entity as User;
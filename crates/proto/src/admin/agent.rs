@@ -0,0 +1,16 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct AgentErrorListReq {
+    pub range: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AgentErrorResp {
+    pub host_id: String,
+    pub machine_id: String,
+    pub display_name: Option<String>,
+    pub error: String,
+    pub errored_at: String,
+}
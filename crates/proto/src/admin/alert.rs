@@ -0,0 +1,32 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct AlertListReq {
+    pub resolved: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AlertResp {
+    pub id: String,
+    pub kind: String,
+    pub host_id: Option<String>,
+    pub message: String,
+    pub created_at: String,
+    pub resolved_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct HostAlertThresholdReq {
+    pub cpu_percent: Option<u8>,
+    pub memory_percent: Option<u8>,
+    pub disk_percent: Option<u8>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HostAlertThresholdResp {
+    pub host_id: String,
+    pub cpu_percent: Option<u8>,
+    pub memory_percent: Option<u8>,
+    pub disk_percent: Option<u8>,
+}
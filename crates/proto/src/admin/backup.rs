@@ -0,0 +1,7 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BackupResp {
+    pub path: String,
+}
@@ -0,0 +1,16 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HostCommandResp {
+    pub id: String,
+    pub host_id: String,
+    pub command: serde_json::Value,
+    pub created_at: String,
+    pub delivered_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CommandBroadcastResp {
+    pub notified: usize,
+}
@@ -0,0 +1,24 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigSource {
+    Default,
+    Args,
+    Env,
+    /// Changed by a reload on SIGHUP, after the server was started.
+    Reload,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ConfigEntry {
+    pub name: String,
+    pub value: Option<String>,
+    pub source: ConfigSource,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EffectiveConfigResp {
+    pub entries: Vec<ConfigEntry>,
+}
@@ -0,0 +1,13 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct EnrollmentCreateReq {
+    pub ttl: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EnrollmentCreateResp {
+    pub token: String,
+    pub expired_at: String,
+}
@@ -0,0 +1,21 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct EventListReq {
+    pub event_type: Option<String>,
+    pub host_id: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub page: Option<u64>,
+    pub size: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EventResp {
+    pub id: String,
+    pub host_id: String,
+    pub event_type: String,
+    pub payload: serde_json::Value,
+    pub received_at: String,
+}
@@ -0,0 +1,18 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct HardwareChangeListReq {
+    pub host_id: Option<String>,
+    pub limit: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HardwareChangeResp {
+    pub id: String,
+    pub host_id: String,
+    pub column: String,
+    pub old: i32,
+    pub new: i32,
+    pub changed_at: String,
+}
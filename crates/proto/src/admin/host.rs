@@ -0,0 +1,107 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct HostListReq {
+    pub page: Option<u64>,
+    pub size: Option<u64>,
+    pub pending: Option<bool>,
+    pub q: Option<String>,
+    pub os_family: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct HostByHardwareReq {
+    pub hashed_cpu: Option<i32>,
+    pub hashed_gpu: Option<i32>,
+    pub hashed_memory: Option<i32>,
+    pub hashed_disk: Option<i32>,
+    pub hashed_network: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct HostExportReq {
+    pub format: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct HostImportReq {
+    pub machine_id: String,
+    pub machine_ip: String,
+    pub machine_country: String,
+    pub os_family: String,
+    pub os_name: String,
+    pub os_version: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HostImportStatus {
+    Imported,
+    Conflict,
+    Invalid,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HostImportRow {
+    pub row: usize,
+    pub machine_id: String,
+    pub status: HostImportStatus,
+    pub message: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct HostImportResp {
+    pub imported: usize,
+    pub conflicts: usize,
+    pub invalid: usize,
+    pub rows: Vec<HostImportRow>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct HostDisplayNameReq {
+    pub display_name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct HostMaintenanceReq {
+    pub until: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HostDisconnectResp {
+    pub connected: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HostMergeReq {
+    pub source: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HostResp {
+    pub id: String,
+    pub machine_id: String,
+    pub display_name: Option<String>,
+    pub machine_ip: String,
+    pub machine_country: String,
+    pub machine_geo: String,
+    pub os_family: String,
+    pub os_name: String,
+    pub os_version: String,
+    pub os_arch: String,
+    pub os_arch_raw: Option<String>,
+    pub os_build: String,
+    pub os_virtualization: bool,
+    pub os_virtualization_platform: Option<String>,
+    pub hashed_cpu: i32,
+    pub hashed_gpu: i32,
+    pub hashed_memory: i32,
+    pub hashed_disk: i32,
+    pub hashed_network: i32,
+    pub last_seen: Option<String>,
+    pub agent_version: Option<String>,
+    pub maintenance_until: Option<String>,
+    pub in_maintenance: bool,
+}
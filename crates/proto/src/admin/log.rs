@@ -0,0 +1,10 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HostLogResp {
+    pub id: String,
+    pub host_id: String,
+    pub content: String,
+    pub created_at: String,
+}
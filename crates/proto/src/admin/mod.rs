@@ -0,0 +1,15 @@
+pub mod agent;
+pub mod alert;
+pub mod backup;
+pub mod command;
+pub mod config;
+pub mod enrollment;
+pub mod event;
+pub mod hardware;
+pub mod host;
+pub mod label;
+pub mod log;
+pub mod schema;
+pub mod stats;
+pub mod user;
+pub mod webhook;
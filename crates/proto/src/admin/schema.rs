@@ -0,0 +1,8 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SchemaResp {
+    pub applied: Vec<String>,
+    pub pending: Vec<String>,
+}
@@ -0,0 +1,25 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Event ingestion latency percentiles in milliseconds, `None` until an event was processed.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LatencyStatsResp {
+    pub count: u64,
+    pub p50_ms: Option<f64>,
+    pub p95_ms: Option<f64>,
+    pub p99_ms: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CaptchaStatsReq {
+    pub range: Option<String>,
+}
+
+/// Captchas generated and answered within `range_secs`, counted by this server.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CaptchaStatsResp {
+    pub range_secs: i64,
+    pub generated: u64,
+    pub verified: u64,
+    pub failed: u64,
+}
@@ -0,0 +1,24 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UserCreateReq {
+    pub email: String,
+    pub password: String,
+    pub nickname: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UserResp {
+    pub id: String,
+    pub sa: bool,
+    pub nickname: String,
+    pub email: String,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InitializedResp {
+    pub initialized: bool,
+}
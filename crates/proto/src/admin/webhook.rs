@@ -0,0 +1,9 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WebhookTestResp {
+    pub status: Option<u16>,
+    pub latency_ms: u64,
+    pub error: Option<String>,
+}
@@ -0,0 +1,16 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Event schema versions the server understands, the current one last.
+pub const EVENT_SCHEMA_VERSIONS: &[u32] = &[1];
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CapabilitiesResp {
+    pub schema_versions: Vec<u32>,
+    pub event_types: Vec<String>,
+    pub max_report_size: usize,
+    pub max_report_events: usize,
+    pub max_log_size: usize,
+    pub max_upload_size: usize,
+    pub ws_frame_format: String,
+}
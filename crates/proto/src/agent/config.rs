@@ -1,5 +1,16 @@
 use serde::Deserialize;
 use serde::Serialize;
 
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ConfigReq {
+    pub enrollment_token: Option<String>,
+    pub agent_version: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
-pub struct Config {}
+pub struct Config {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub commands: Vec<serde_json::Value>,
+}
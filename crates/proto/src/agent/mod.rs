@@ -1,5 +1,11 @@
+mod capabilities;
 mod config;
 mod report;
+mod upload;
+mod whoami;
 
+pub use self::capabilities::*;
 pub use self::config::*;
 pub use self::report::*;
+pub use self::upload::*;
+pub use self::whoami::*;
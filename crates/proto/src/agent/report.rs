@@ -5,6 +5,18 @@ use serde::Serialize;
 pub enum Events {
     EvtMachineEmit(EvtMachineEmit),
     EvtOsEmit(EvtOsEmit),
+    EvtHardwareEmit(EvtHardwareEmit),
+    EvtMetricsEmit(EvtMetricsEmit),
+}
+
+impl Events {
+    /// Names of all event types, as used in the serialized form.
+    pub const TYPES: &'static [&'static str] = &[
+        "EvtMachineEmit",
+        "EvtOsEmit",
+        "EvtHardwareEmit",
+        "EvtMetricsEmit",
+    ];
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -21,4 +33,75 @@ pub struct EvtOsEmit {
     pub arch: Option<String>,
     pub build: Option<String>,
     pub virtualization: Option<bool>,
+    pub virtualization_platform: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EvtHardwareEmit {
+    pub cpu: Option<String>,
+    pub gpu: Option<String>,
+    pub memory: Option<String>,
+    pub disk: Option<String>,
+    pub network: Option<String>,
+}
+
+/// Resource usage sample. `cpu_usage` is a percentage, the other fields are bytes.
+///
+/// Besides the object form, a sample can be sent as the compact positional array
+/// `[cpu_usage, memory_used, memory_total, disk_used, disk_total]`, with `null` for
+/// unknown values. Samples are always serialized as objects.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(from = "EvtMetricsEmitRepr")]
+pub struct EvtMetricsEmit {
+    pub cpu_usage: Option<f64>,
+    pub memory_used: Option<u64>,
+    pub memory_total: Option<u64>,
+    pub disk_used: Option<u64>,
+    pub disk_total: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum EvtMetricsEmitRepr {
+    Object {
+        cpu_usage: Option<f64>,
+        memory_used: Option<u64>,
+        memory_total: Option<u64>,
+        disk_used: Option<u64>,
+        disk_total: Option<u64>,
+    },
+    Compact(
+        Option<f64>,
+        Option<u64>,
+        Option<u64>,
+        Option<u64>,
+        Option<u64>,
+    ),
+}
+
+impl From<EvtMetricsEmitRepr> for EvtMetricsEmit {
+    fn from(value: EvtMetricsEmitRepr) -> Self {
+        match value {
+            EvtMetricsEmitRepr::Object {
+                cpu_usage,
+                memory_used,
+                memory_total,
+                disk_used,
+                disk_total,
+            }
+            | EvtMetricsEmitRepr::Compact(
+                cpu_usage,
+                memory_used,
+                memory_total,
+                disk_used,
+                disk_total,
+            ) => Self {
+                cpu_usage,
+                memory_used,
+                memory_total,
+                disk_used,
+                disk_total,
+            },
+        }
+    }
 }
@@ -0,0 +1,19 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UploadCreateReq {
+    pub purpose: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UploadCreateResp {
+    pub url: String,
+    pub expired_at: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UploadResp {
+    pub name: String,
+    pub size: usize,
+}
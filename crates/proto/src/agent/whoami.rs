@@ -0,0 +1,9 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WhoamiResp {
+    pub id: String,
+    pub machine_id: String,
+    pub display_name: Option<String>,
+}
@@ -0,0 +1,15 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AuthorizeReq {
+    pub email: String,
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AuthorizeResp {
+    pub token: String,
+    pub expired_at: String,
+    pub refresh_token: String,
+}
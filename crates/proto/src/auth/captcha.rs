@@ -11,4 +11,6 @@ pub struct CaptchaGenerateReq {
 pub struct CaptchaGenerateResp {
     pub id: String,
     pub base64: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub answer: Option<String>,
 }
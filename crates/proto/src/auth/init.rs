@@ -3,10 +3,14 @@ use serde::Serialize;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct InitReq {
+    #[serde(default)]
     pub captcha_id: String,
+    #[serde(default)]
     pub captcha_answer: String,
     pub email: String,
     pub password: String,
+    #[serde(default)]
+    pub nickname: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -1,2 +1,5 @@
+pub mod authorize;
 pub mod captcha;
 pub mod init;
+pub mod profile;
+pub mod session;
@@ -0,0 +1,8 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ProfileUpdateReq {
+    pub nickname: Option<String>,
+    pub email: Option<String>,
+}
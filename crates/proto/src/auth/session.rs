@@ -0,0 +1,16 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RefreshReq {
+    pub refresh_token: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SessionResp {
+    pub id: String,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: String,
+    pub last_used_at: String,
+}
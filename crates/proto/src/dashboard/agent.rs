@@ -0,0 +1,8 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AgentVersionResp {
+    pub agent_version: Option<String>,
+    pub count: i64,
+}
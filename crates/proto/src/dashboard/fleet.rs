@@ -0,0 +1,15 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct FleetSnapshotReq {
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FleetSnapshotResp {
+    pub online: i32,
+    pub total: i32,
+    pub recorded_at: String,
+}
@@ -0,0 +1,9 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GeoHealthResp {
+    pub country: String,
+    pub online: i64,
+    pub offline: i64,
+}
@@ -0,0 +1,37 @@
+use crate::admin::host::HostResp;
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct StaleHostReq {
+    pub limit: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StaleHostResp {
+    pub id: String,
+    pub machine_id: String,
+    pub display_name: Option<String>,
+    pub last_seen: String,
+    pub stale_seconds: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct HostCompareReq {
+    pub a: Option<String>,
+    pub b: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HostFieldDiff {
+    pub field: String,
+    pub a: serde_json::Value,
+    pub b: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HostCompareResp {
+    pub a: HostResp,
+    pub b: HostResp,
+    pub differences: Vec<HostFieldDiff>,
+}
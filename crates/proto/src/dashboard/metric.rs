@@ -0,0 +1,48 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct MissingMetricsReq {
+    pub max_age: Option<u64>,
+    pub limit: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MissingMetricsResp {
+    pub id: String,
+    pub machine_id: String,
+    pub last_seen: Option<String>,
+    pub last_metrics_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct MetricAggregateReq {
+    pub range: Option<String>,
+    pub bucket: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MetricAggregateResp {
+    pub bucket_start: String,
+    pub hosts: i64,
+    pub cpu_usage: Option<f64>,
+    pub memory_used: Option<i64>,
+    pub memory_total: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct MetricExportReq {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub format: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MetricResp {
+    pub recorded_at: String,
+    pub cpu_usage: Option<f64>,
+    pub memory_used: Option<i64>,
+    pub memory_total: Option<i64>,
+    pub disk_used: Option<i64>,
+    pub disk_total: Option<i64>,
+}
@@ -0,0 +1,9 @@
+pub mod agent;
+pub mod fleet;
+pub mod geo;
+pub mod host;
+pub mod metric;
+pub mod os;
+pub mod prefix;
+pub mod uptime;
+pub mod virtualization;
@@ -0,0 +1,23 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct OsVersionReq {
+    pub family: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct OsVersionResp {
+    pub os_version: String,
+    pub count: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct OutdatedBuildResp {
+    pub id: String,
+    pub machine_id: String,
+    pub os_family: String,
+    pub os_name: String,
+    pub os_build: String,
+    pub baseline: String,
+}
@@ -0,0 +1,13 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct IdPrefixReq {
+    pub len: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct IdPrefixResp {
+    pub prefix: String,
+    pub count: i64,
+}
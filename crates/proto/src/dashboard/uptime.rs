@@ -0,0 +1,16 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct UptimeReq {
+    pub range: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UptimeResp {
+    pub id: String,
+    pub from: String,
+    pub to: String,
+    pub uptime: f64,
+    pub heartbeats: u64,
+}
@@ -0,0 +1,9 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct VirtualizationResp {
+    pub virtualization: bool,
+    pub platform: Option<String>,
+    pub count: i64,
+}
@@ -1,2 +1,7 @@
+pub mod admin;
 pub mod agent;
 pub mod auth;
+pub mod dashboard;
+pub mod error;
+pub mod server;
+pub mod webhook;
@@ -0,0 +1,15 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ServerCapabilitiesResp {
+    pub version: String,
+    pub tls: bool,
+    pub geoip: bool,
+    pub default_country: Option<String>,
+    pub webhooks: bool,
+    pub metrics: bool,
+    pub admin_api: bool,
+    pub captcha: bool,
+    pub read_only: bool,
+}
@@ -0,0 +1,20 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookChange {
+    Online,
+    Machine,
+    Os,
+    Hardware,
+    Test,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WebhookEvent {
+    pub host_id: String,
+    pub machine_id: String,
+    pub change: WebhookChange,
+    pub occurred_at: String,
+}